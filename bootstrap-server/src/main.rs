@@ -3,8 +3,11 @@
 //! This server helps peers discover each other across the internet by maintaining
 //! a registry of connected peers and their addresses.
 
+mod probe;
 mod protocol;
 mod registry;
+mod relay;
+mod rendezvous;
 mod server;
 
 use clap::Parser;
@@ -31,6 +34,14 @@ struct Args {
     /// Path to TLS private key file (optional, for WSS support)
     #[arg(long)]
     key: Option<String>,
+
+    /// Relay traffic between peers that cannot connect directly
+    #[arg(long)]
+    relay: bool,
+
+    /// Relay bandwidth each peer may use, in bytes per second
+    #[arg(long, default_value_t = relay::DEFAULT_RELAY_QUOTA)]
+    relay_quota: u64,
 }
 
 #[tokio::main]
@@ -57,7 +68,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tracing::info!("Starting bootstrap server on {}", bind_addr);
 
-    let server = server::BootstrapServer::new(bind_addr).await?;
+    let mut server = server::BootstrapServer::new(bind_addr).await?;
+    if args.relay {
+        server = server.with_relay(args.relay_quota);
+    }
 
     let shutdown = async {
         tokio::signal::ctrl_c()
@@ -0,0 +1,137 @@
+//! Reflexive address probes for the bootstrap server.
+//!
+//! Clients learn the address their NAT maps them to by sending a
+//! [`ProbeRequest`] to one of two UDP probe ports; the answer carries the
+//! address the request arrived from. A request may ask for the answer to
+//! come from the other port, which tells the client whether its NAT lets in
+//! datagrams from a port it never sent to. Comparing the mapped addresses
+//! seen by both ports tells whether the NAT reuses one mapping for every
+//! destination.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+/// Largest probe datagram accepted.
+const MAX_PROBE_SIZE: usize = 512;
+
+/// Datagram a client sends to a probe port.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProbeRequest {
+    /// Client-chosen identifier echoed in the response.
+    pub id: String,
+    /// Answer from the other probe port instead of the one probed.
+    #[serde(default)]
+    pub change_port: bool,
+}
+
+/// Answer to a [`ProbeRequest`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProbeResponse {
+    /// Identifier from the request.
+    pub id: String,
+    /// Address the request arrived from.
+    pub mapped_addr: String,
+}
+
+/// Answers probes arriving on `socket`.
+///
+/// Requests that ask to change ports are answered from `other`.
+pub async fn serve_probe(socket: Arc<UdpSocket>, other: Arc<UdpSocket>) {
+    let mut buf = [0u8; MAX_PROBE_SIZE];
+
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::debug!(error = %e, "Failed to receive probe");
+                continue;
+            }
+        };
+
+        let request = match serde_json::from_slice::<ProbeRequest>(&buf[..len]) {
+            Ok(request) => request,
+            Err(e) => {
+                tracing::debug!(addr = %addr, error = %e, "Invalid probe datagram");
+                continue;
+            }
+        };
+
+        let response = ProbeResponse {
+            id: request.id,
+            mapped_addr: addr.to_string(),
+        };
+        let Ok(json) = serde_json::to_vec(&response) else {
+            continue;
+        };
+        let from = if request.change_port { &other } else { &socket };
+        if let Err(e) = from.send_to(&json, addr).await {
+            tracing::debug!(addr = %addr, error = %e, "Failed to answer probe");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    async fn probe_ports() -> (Arc<UdpSocket>, Arc<UdpSocket>) {
+        let primary = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let alternate = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        tokio::spawn(serve_probe(primary.clone(), alternate.clone()));
+        tokio::spawn(serve_probe(alternate.clone(), primary.clone()));
+        (primary, alternate)
+    }
+
+    async fn ask(
+        client: &UdpSocket,
+        to: SocketAddr,
+        change_port: bool,
+    ) -> (ProbeResponse, SocketAddr) {
+        let request = ProbeRequest {
+            id: "p1".to_string(),
+            change_port,
+        };
+        client
+            .send_to(&serde_json::to_vec(&request).unwrap(), to)
+            .await
+            .unwrap();
+        let mut buf = [0u8; MAX_PROBE_SIZE];
+        let (len, from) = client.recv_from(&mut buf).await.unwrap();
+        (serde_json::from_slice(&buf[..len]).unwrap(), from)
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_mapped_address() {
+        let (primary, _alternate) = probe_ports().await;
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let primary_addr = primary.local_addr().unwrap();
+
+        let (response, from) = ask(&client, primary_addr, false).await;
+        assert_eq!(response.id, "p1");
+        assert_eq!(
+            response.mapped_addr,
+            client.local_addr().unwrap().to_string()
+        );
+        assert_eq!(from, primary_addr);
+    }
+
+    #[tokio::test]
+    async fn test_probe_answers_from_other_port() {
+        let (primary, alternate) = probe_ports().await;
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let (_, from) = ask(&client, primary.local_addr().unwrap(), true).await;
+        assert_eq!(from, alternate.local_addr().unwrap());
+
+        let (_, from) = ask(&client, alternate.local_addr().unwrap(), true).await;
+        assert_eq!(from, primary.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_probe_request_defaults_to_same_port() {
+        let request: ProbeRequest = serde_json::from_str(r#"{"id":"p1"}"#).unwrap();
+        assert!(!request.change_port);
+    }
+}
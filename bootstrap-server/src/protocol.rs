@@ -5,6 +5,48 @@
 
 use serde::{Deserialize, Serialize};
 
+/// Protocol version spoken by this server.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Oldest client protocol version this server accepts.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Length of an identity public key in bytes.
+pub const PUBLIC_KEY_LENGTH: usize = 32;
+
+/// Parses a hex-encoded identity public key into its lowercase form.
+///
+/// Returns `None` unless the string is exactly one key's worth of hex.
+pub fn parse_public_key(key: &str) -> Option<String> {
+    let valid = key.len() == PUBLIC_KEY_LENGTH * 2 && key.bytes().all(|b| b.is_ascii_hexdigit());
+    valid.then(|| key.to_ascii_lowercase())
+}
+
+/// Protocol versions and features advertised by a client.
+///
+/// Clients from before versioning send none of these fields, which reads
+/// as version 0.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Hello {
+    /// Protocol version the client speaks.
+    #[serde(default)]
+    pub protocol_version: u16,
+    /// Oldest protocol version the client understands.
+    #[serde(default)]
+    pub min_protocol_version: u16,
+    /// Optional features the client supports.
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl Hello {
+    /// Checks whether the client's versions overlap with the server's.
+    pub fn is_compatible(&self) -> bool {
+        let version = self.protocol_version.min(PROTOCOL_VERSION);
+        version >= self.min_protocol_version.max(MIN_PROTOCOL_VERSION)
+    }
+}
+
 /// Messages sent from client to server.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -15,6 +57,11 @@ pub enum ClientMessage {
         nickname: String,
         /// The peer's local network address (e.g., "192.168.1.100:5000").
         local_addr: String,
+        /// The peer's hex-encoded Ed25519 identity public key.
+        public_key: String,
+        /// The peer's protocol versions and features.
+        #[serde(flatten)]
+        hello: Hello,
     },
     /// Request the current list of registered peers.
     ListPeers,
@@ -22,6 +69,31 @@ pub enum ClientMessage {
     Heartbeat,
     /// Unregister from the server.
     Unregister,
+    /// Ask the server to coordinate a hole punch with another peer.
+    ConnectRequest {
+        /// Peer ID of the peer to connect to.
+        target_peer_id: String,
+    },
+    /// Forward an opaque payload to another peer through the relay.
+    Relay {
+        /// Peer ID of the recipient.
+        to: String,
+        /// Identifier of the relayed stream the payload belongs to.
+        circuit: String,
+        /// Base64-encoded payload, end-to-end encrypted by the peers; empty
+        /// to close the circuit.
+        payload: String,
+    },
+    /// Replace the registered public address with one the client probed.
+    UpdateAddress {
+        /// Address peers can reach the client at; must use the IP address
+        /// the client connects from.
+        public_addr: String,
+        /// Set if the client's NAT lets no connections in, so peers should
+        /// punch or relay instead of dialing `public_addr`.
+        #[serde(default)]
+        unreachable: bool,
+    },
 }
 
 /// Messages sent from server to client.
@@ -34,12 +106,71 @@ pub enum ServerMessage {
         peer_id: String,
         /// The peer's public address as seen by the server.
         public_addr: String,
+        /// UDP ports answering address probes.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        probe_ports: Vec<u16>,
     },
     /// List of currently registered peers.
     PeerList {
         /// Vector of peer information.
         peers: Vec<PeerInfo>,
     },
+    /// The client's protocol version is not supported.
+    Incompatible {
+        /// Protocol version spoken by the server.
+        protocol_version: u16,
+        /// Oldest protocol version the server accepts.
+        min_protocol_version: u16,
+    },
+    /// A hole punch session was opened; bind a UDP socket for it.
+    PunchPrepare {
+        /// Session identifier to include in the UDP bind datagram.
+        session: String,
+        /// Peer ID of the other side.
+        peer_id: String,
+        /// Whether this side asked for the connection.
+        initiator: bool,
+    },
+    /// Both sides are bound; punch towards the other side's candidates.
+    PunchStart {
+        /// Session identifier.
+        session: String,
+        /// Peer ID of the other side.
+        peer_id: String,
+        /// Hex-encoded identity public key of the other side.
+        public_key: String,
+        /// The other side's UDP address as seen by the server.
+        public_addr: String,
+        /// The other side's UDP address on its local network.
+        local_addr: String,
+        /// Unix time in milliseconds at which both sides start punching.
+        start_at: i64,
+    },
+    /// A hole punch session could not be opened.
+    PunchFailed {
+        /// Peer ID the client asked to connect to.
+        peer_id: String,
+        /// Why the session could not be opened.
+        message: String,
+    },
+    /// A payload relayed from another peer.
+    Relay {
+        /// Peer ID of the sender.
+        from: String,
+        /// Identifier of the relayed stream the payload belongs to.
+        circuit: String,
+        /// Base64-encoded payload as sent by the peer.
+        payload: String,
+    },
+    /// A payload could not be relayed.
+    RelayFailed {
+        /// Peer ID the payload was addressed to.
+        peer_id: String,
+        /// Circuit the payload belonged to.
+        circuit: String,
+        /// Why the payload was not relayed.
+        message: String,
+    },
     /// Error message.
     Error {
         /// Description of the error.
@@ -47,6 +178,20 @@ pub enum ServerMessage {
     },
 }
 
+/// Datagram a client sends to the server's UDP port during a hole punch.
+///
+/// The server records the address the datagram arrived from as the
+/// client's public UDP candidate.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PunchBind {
+    /// Session identifier from `PunchPrepare`.
+    pub session: String,
+    /// Peer ID of the sender.
+    pub peer_id: String,
+    /// The sender's UDP address on its local network.
+    pub local_addr: String,
+}
+
 /// Information about a registered peer.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PeerInfo {
@@ -58,8 +203,16 @@ pub struct PeerInfo {
     pub public_addr: String,
     /// Local address reported by the peer.
     pub local_addr: String,
+    /// Hex-encoded identity public key reported by the peer.
+    pub public_key: String,
     /// Unix timestamp of last activity.
     pub last_seen: i64,
+    /// Whether the peer reported that it cannot accept connections.
+    #[serde(default)]
+    pub unreachable: bool,
+    /// Protocol versions and features reported by the peer.
+    #[serde(flatten)]
+    pub hello: Hello,
 }
 
 impl PeerInfo {
@@ -69,14 +222,19 @@ impl PeerInfo {
         nickname: String,
         public_addr: String,
         local_addr: String,
+        public_key: String,
         last_seen: i64,
+        hello: Hello,
     ) -> Self {
         Self {
             peer_id,
             nickname,
             public_addr,
             local_addr,
+            public_key,
             last_seen,
+            unreachable: false,
+            hello,
         }
     }
 }
@@ -90,9 +248,16 @@ mod tests {
         let msg = ClientMessage::Register {
             nickname: "alice".to_string(),
             local_addr: "192.168.1.100:5000".to_string(),
+            public_key: "ab".repeat(32),
+            hello: Hello {
+                protocol_version: PROTOCOL_VERSION,
+                min_protocol_version: MIN_PROTOCOL_VERSION,
+                features: vec!["groups".to_string()],
+            },
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("\"type\":\"register\""));
+        assert!(json.contains("\"protocol_version\":1"));
         assert!(json.contains("\"nickname\":\"alice\""));
 
         let deserialized: ClientMessage = serde_json::from_str(&json).unwrap();
@@ -129,11 +294,72 @@ mod tests {
         assert_eq!(msg, deserialized);
     }
 
+    #[test]
+    fn test_client_message_connect_request_serialization() {
+        let msg = ClientMessage::ConnectRequest {
+            target_peer_id: "id2".to_string(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"connect_request\""));
+        assert!(json.contains("\"target_peer_id\":\"id2\""));
+
+        let deserialized: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg, deserialized);
+    }
+
+    #[test]
+    fn test_client_message_relay_serialization() {
+        let msg = ClientMessage::Relay {
+            to: "id2".to_string(),
+            circuit: "c1".to_string(),
+            payload: "aGVsbG8=".to_string(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"relay\""));
+        assert!(json.contains("\"payload\":\"aGVsbG8=\""));
+
+        let deserialized: ClientMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg, deserialized);
+    }
+
+    #[test]
+    fn test_server_message_relay_failed_serialization() {
+        let msg = ServerMessage::RelayFailed {
+            peer_id: "id2".to_string(),
+            circuit: "c1".to_string(),
+            message: "Relay disabled".to_string(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"relay_failed\""));
+
+        let deserialized: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg, deserialized);
+    }
+
+    #[test]
+    fn test_server_message_punch_start_serialization() {
+        let msg = ServerMessage::PunchStart {
+            session: "s1".to_string(),
+            peer_id: "id2".to_string(),
+            public_key: "ab".repeat(32),
+            public_addr: "1.2.3.4:40000".to_string(),
+            local_addr: "192.168.1.100:40000".to_string(),
+            start_at: 1699564800000,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"punch_start\""));
+        assert!(json.contains("\"start_at\":1699564800000"));
+
+        let deserialized: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(msg, deserialized);
+    }
+
     #[test]
     fn test_server_message_registered_serialization() {
         let msg = ServerMessage::Registered {
             peer_id: "test-id".to_string(),
             public_addr: "1.2.3.4:5000".to_string(),
+            probe_ports: vec![8081, 8082],
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("\"type\":\"registered\""));
@@ -150,7 +376,9 @@ mod tests {
             "alice".to_string(),
             "1.2.3.4:5000".to_string(),
             "192.168.1.100:5000".to_string(),
+            "ab".repeat(32),
             1699564800,
+            Hello::default(),
         )];
         let msg = ServerMessage::PeerList { peers };
         let json = serde_json::to_string(&msg).unwrap();
@@ -160,6 +388,43 @@ mod tests {
         assert_eq!(msg, deserialized);
     }
 
+    #[test]
+    fn test_register_without_version_deserializes_as_legacy() {
+        let json = r#"{"type":"register","nickname":"alice","local_addr":"192.168.1.100:5000","public_key":"ab"}"#;
+        match serde_json::from_str::<ClientMessage>(json).unwrap() {
+            ClientMessage::Register { hello, .. } => {
+                assert_eq!(hello.protocol_version, 0);
+                assert!(!hello.is_compatible());
+            }
+            _ => panic!("Expected Register message"),
+        }
+    }
+
+    #[test]
+    fn test_hello_compatibility() {
+        let current = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            features: Vec::new(),
+        };
+        assert!(current.is_compatible());
+
+        // A newer client that still understands our version
+        let newer = Hello {
+            protocol_version: PROTOCOL_VERSION + 1,
+            min_protocol_version: PROTOCOL_VERSION,
+            features: Vec::new(),
+        };
+        assert!(newer.is_compatible());
+
+        let too_new = Hello {
+            protocol_version: PROTOCOL_VERSION + 1,
+            min_protocol_version: PROTOCOL_VERSION + 1,
+            features: Vec::new(),
+        };
+        assert!(!too_new.is_compatible());
+    }
+
     #[test]
     fn test_server_message_error_serialization() {
         let msg = ServerMessage::Error {
@@ -180,12 +445,15 @@ mod tests {
             "bob".to_string(),
             "5.6.7.8:9000".to_string(),
             "10.0.0.5:9000".to_string(),
+            "cd".repeat(32),
             1699564900,
+            Hello::default(),
         );
         assert_eq!(peer.peer_id, "id1");
         assert_eq!(peer.nickname, "bob");
         assert_eq!(peer.public_addr, "5.6.7.8:9000");
         assert_eq!(peer.local_addr, "10.0.0.5:9000");
+        assert_eq!(peer.public_key, "cd".repeat(32));
         assert_eq!(peer.last_seen, 1699564900);
     }
 }
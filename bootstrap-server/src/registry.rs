@@ -3,7 +3,7 @@
 //! This module provides thread-safe management of registered peers,
 //! including registration, lookup, timeout handling, and cleanup.
 
-use crate::protocol::PeerInfo;
+use crate::protocol::{Hello, PeerInfo};
 use chrono::Utc;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -33,13 +33,23 @@ impl PeerRegistry {
         }
     }
 
-    /// Registers a new peer and returns the assigned peer ID and public address.
+    /// Registers a new peer and returns the assigned peer ID.
+    ///
+    /// Returns `None` if another peer is already registered with the same
+    /// public key, so a key cannot be claimed away from its live owner.
     pub async fn register(
         &self,
         nickname: String,
         local_addr: String,
         public_addr: String,
-    ) -> Uuid {
+        public_key: String,
+        hello: Hello,
+    ) -> Option<Uuid> {
+        let mut peers = self.peers.write().await;
+        if peers.values().any(|p| p.info.public_key == public_key) {
+            return None;
+        }
+
         let peer_id = Uuid::new_v4();
         let now = Utc::now().timestamp();
 
@@ -49,11 +59,12 @@ impl PeerRegistry {
                 nickname,
                 public_addr,
                 local_addr,
+                public_key,
                 now,
+                hello,
             ),
         };
 
-        let mut peers = self.peers.write().await;
         peers.insert(peer_id, peer);
 
         tracing::info!(
@@ -61,7 +72,7 @@ impl PeerRegistry {
             "Peer registered"
         );
 
-        peer_id
+        Some(peer_id)
     }
 
     /// Updates the last_seen timestamp for a peer.
@@ -75,6 +86,23 @@ impl PeerRegistry {
         }
     }
 
+    /// Replaces the public address of a peer and whether it can be dialed.
+    pub async fn update_public_addr(
+        &self,
+        peer_id: Uuid,
+        public_addr: String,
+        unreachable: bool,
+    ) -> bool {
+        let mut peers = self.peers.write().await;
+        if let Some(peer) = peers.get_mut(&peer_id) {
+            peer.info.public_addr = public_addr;
+            peer.info.unreachable = unreachable;
+            true
+        } else {
+            false
+        }
+    }
+
     /// Unregisters a peer by ID.
     pub async fn unregister(&self, peer_id: Uuid) -> bool {
         let mut peers = self.peers.write().await;
@@ -87,11 +115,18 @@ impl PeerRegistry {
     }
 
     /// Gets the public address for a peer.
+    #[allow(dead_code)]
     pub async fn get_public_addr(&self, peer_id: Uuid) -> Option<String> {
         let peers = self.peers.read().await;
         peers.get(&peer_id).map(|p| p.info.public_addr.clone())
     }
 
+    /// Gets the information a peer registered with.
+    pub async fn get_peer(&self, peer_id: Uuid) -> Option<PeerInfo> {
+        let peers = self.peers.read().await;
+        peers.get(&peer_id).map(|p| p.info.clone())
+    }
+
     /// Returns a list of all registered peers.
     pub async fn list_peers(&self) -> Vec<PeerInfo> {
         let peers = self.peers.read().await;
@@ -119,6 +154,7 @@ impl PeerRegistry {
     }
 
     /// Returns the total number of registered peers.
+    #[allow(dead_code)]
     pub async fn peer_count(&self) -> usize {
         let peers = self.peers.read().await;
         peers.len()
@@ -144,8 +180,11 @@ mod tests {
                 "alice".to_string(),
                 "192.168.1.100:5000".to_string(),
                 "1.2.3.4:5000".to_string(),
+                "ab".repeat(32),
+                Hello::default(),
             )
-            .await;
+            .await
+            .unwrap();
 
         let public_addr = registry.get_public_addr(peer_id).await;
         assert_eq!(public_addr, Some("1.2.3.4:5000".to_string()));
@@ -164,8 +203,11 @@ mod tests {
                 "bob".to_string(),
                 "192.168.1.101:5000".to_string(),
                 "5.6.7.8:5000".to_string(),
+                "ab".repeat(32),
+                Hello::default(),
             )
-            .await;
+            .await
+            .unwrap();
 
         assert_eq!(registry.peer_count().await, 1);
 
@@ -193,8 +235,11 @@ mod tests {
                 "charlie".to_string(),
                 "192.168.1.102:5000".to_string(),
                 "9.10.11.12:5000".to_string(),
+                "ab".repeat(32),
+                Hello::default(),
             )
-            .await;
+            .await
+            .unwrap();
 
         let peers_before = registry.list_peers().await;
         let last_seen_before = peers_before[0].last_seen;
@@ -219,6 +264,44 @@ mod tests {
         assert!(!result);
     }
 
+    #[tokio::test]
+    async fn test_update_public_addr() {
+        let registry = PeerRegistry::new();
+        let peer_id = registry
+            .register(
+                "dave".to_string(),
+                "192.168.1.103:5000".to_string(),
+                "13.14.15.16:5000".to_string(),
+                "ab".repeat(32),
+                Hello::default(),
+            )
+            .await
+            .unwrap();
+
+        assert!(
+            registry
+                .update_public_addr(peer_id, "13.14.15.16:41000".to_string(), false)
+                .await
+        );
+        assert_eq!(
+            registry.get_public_addr(peer_id).await.unwrap(),
+            "13.14.15.16:41000"
+        );
+        assert!(!registry.get_peer(peer_id).await.unwrap().unreachable);
+
+        assert!(
+            registry
+                .update_public_addr(peer_id, "13.14.15.16:5000".to_string(), true)
+                .await
+        );
+        assert!(registry.get_peer(peer_id).await.unwrap().unreachable);
+        assert!(
+            !registry
+                .update_public_addr(Uuid::new_v4(), "1.1.1.1:1".to_string(), false)
+                .await
+        );
+    }
+
     #[tokio::test]
     async fn test_multiple_peers() {
         let registry = PeerRegistry::new();
@@ -228,24 +311,33 @@ mod tests {
                 "peer1".to_string(),
                 "192.168.1.1:5000".to_string(),
                 "1.1.1.1:5000".to_string(),
+                "ab".repeat(32),
+                Hello::default(),
             )
-            .await;
+            .await
+            .unwrap();
 
         registry
             .register(
                 "peer2".to_string(),
                 "192.168.1.2:5000".to_string(),
                 "2.2.2.2:5000".to_string(),
+                "cd".repeat(32),
+                Hello::default(),
             )
-            .await;
+            .await
+            .unwrap();
 
         registry
             .register(
                 "peer3".to_string(),
                 "192.168.1.3:5000".to_string(),
                 "3.3.3.3:5000".to_string(),
+                "ef".repeat(32),
+                Hello::default(),
             )
-            .await;
+            .await
+            .unwrap();
 
         assert_eq!(registry.peer_count().await, 3);
 
@@ -258,6 +350,46 @@ mod tests {
         assert!(nicknames.contains(&"peer3".to_string()));
     }
 
+    #[tokio::test]
+    async fn test_register_rejects_key_in_use() {
+        let registry = PeerRegistry::new();
+        let owner = registry
+            .register(
+                "alice".to_string(),
+                "192.168.1.1:5000".to_string(),
+                "1.1.1.1:5000".to_string(),
+                "ab".repeat(32),
+                Hello::default(),
+            )
+            .await
+            .unwrap();
+
+        let impostor = registry
+            .register(
+                "mallory".to_string(),
+                "192.168.1.2:5000".to_string(),
+                "2.2.2.2:5000".to_string(),
+                "ab".repeat(32),
+                Hello::default(),
+            )
+            .await;
+        assert!(impostor.is_none());
+        assert_eq!(registry.peer_count().await, 1);
+
+        // The key is free again once its owner leaves
+        registry.unregister(owner).await;
+        assert!(registry
+            .register(
+                "alice".to_string(),
+                "192.168.1.1:5000".to_string(),
+                "1.1.1.1:5000".to_string(),
+                "ab".repeat(32),
+                Hello::default(),
+            )
+            .await
+            .is_some());
+    }
+
     #[tokio::test]
     async fn test_list_peers_empty() {
         let registry = PeerRegistry::new();
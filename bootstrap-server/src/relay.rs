@@ -0,0 +1,115 @@
+//! Bandwidth quotas for relayed traffic.
+//!
+//! Peers that can neither reach each other directly nor punch a path can
+//! exchange their end-to-end encrypted frames through the server. Each
+//! sending connection gets a token bucket refilled at the configured rate;
+//! a peer that sends faster than its quota is made to wait, which throttles
+//! its WebSocket connection instead of buffering its traffic on the server.
+//!
+//! Buckets belong to the WebSocket connection rather than the registration,
+//! so registering again on the same connection does not refill the quota.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Default relay quota per connection in bytes per second.
+pub const DEFAULT_RELAY_QUOTA: u64 = 64 * 1024;
+
+/// Token bucket of a single connection.
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes the peer may send right away; negative while in debt.
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-connection relay bandwidth quotas.
+#[derive(Debug)]
+pub struct RelayQuota {
+    /// Bytes per second each connection may relay.
+    rate: u64,
+    /// Buckets by the remote address of the WebSocket connection.
+    buckets: Mutex<HashMap<SocketAddr, Bucket>>,
+}
+
+impl RelayQuota {
+    /// Creates quotas allowing each peer `rate` bytes per second.
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Charges `bytes` to a connection and returns how long it must wait.
+    ///
+    /// Up to one second of traffic may be sent in a burst. Larger payloads
+    /// are accepted but put the connection in debt until the bucket refills.
+    pub async fn reserve(&self, connection: SocketAddr, bytes: usize) -> Duration {
+        let rate = self.rate as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().await;
+        let bucket = buckets.entry(connection).or_insert(Bucket {
+            tokens: rate,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate) - bytes as f64;
+        bucket.updated = now;
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+
+    /// Forgets a closed connection's bucket.
+    pub async fn forget(&self, connection: SocketAddr) {
+        self.buckets.lock().await.remove(&connection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(port: u16) -> SocketAddr {
+        SocketAddr::from(([203, 0, 113, 7], port))
+    }
+
+    #[tokio::test]
+    async fn test_burst_within_quota() {
+        let quota = RelayQuota::new(1000);
+        let peer = connection(40000);
+
+        assert_eq!(quota.reserve(peer, 600).await, Duration::ZERO);
+        assert_eq!(quota.reserve(peer, 400).await, Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_exceeding_quota_waits() {
+        let quota = RelayQuota::new(1000);
+        let peer = connection(40000);
+
+        quota.reserve(peer, 1000).await;
+        let wait = quota.reserve(peer, 500).await;
+        assert!(wait > Duration::from_millis(400), "waited {:?}", wait);
+        assert!(wait <= Duration::from_millis(500), "waited {:?}", wait);
+    }
+
+    #[tokio::test]
+    async fn test_quotas_are_per_connection() {
+        let quota = RelayQuota::new(1000);
+        let (alice, bob) = (connection(40000), connection(40001));
+
+        quota.reserve(alice, 3000).await;
+        assert_eq!(quota.reserve(bob, 1000).await, Duration::ZERO);
+
+        quota.forget(alice).await;
+        assert_eq!(quota.reserve(alice, 1000).await, Duration::ZERO);
+    }
+}
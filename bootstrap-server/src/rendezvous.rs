@@ -0,0 +1,587 @@
+//! Hole punching rendezvous for the bootstrap server.
+//!
+//! A client that cannot reach a peer directly sends a `ConnectRequest`. The
+//! server opens a session and tells both peers to prepare. Each side then
+//! sends a [`PunchBind`] datagram to the server's UDP port from the socket
+//! it will punch with, which reveals the public address its NAT mapped that
+//! socket to. Once both sides are bound, each receives the other's public
+//! and local candidates together with a shared start time, and both start
+//! sending to each other at the same moment.
+//!
+//! When the relay is enabled, the same connections also carry `Relay`
+//! payloads for peers that could not connect at all, subject to each
+//! sending connection's [`RelayQuota`].
+
+use crate::protocol::{PunchBind, ServerMessage};
+use crate::registry::PeerRegistry;
+use crate::relay::RelayQuota;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, RwLock};
+use uuid::Uuid;
+
+/// How long a session may wait for both sides to bind.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Delay between announcing the candidates and the punch start time.
+///
+/// Leaves room for the announcement to reach both peers before either
+/// starts sending.
+const PUNCH_DELAY_MS: i64 = 500;
+
+/// Largest UDP bind datagram accepted.
+const MAX_BIND_SIZE: usize = 1024;
+
+/// Largest encoded payload accepted for relaying.
+const MAX_RELAY_PAYLOAD: usize = 256 * 1024;
+
+/// Longest circuit ID accepted for relaying; clients use UUIDs.
+const MAX_CIRCUIT_LEN: usize = 64;
+
+/// One side of a punch session.
+#[derive(Debug)]
+struct Side {
+    peer_id: Uuid,
+    /// Observed public address and reported local address, once bound.
+    endpoint: Option<(SocketAddr, String)>,
+}
+
+/// A punch session between two peers.
+#[derive(Debug)]
+struct Session {
+    sides: [Side; 2],
+    created: Instant,
+}
+
+/// Routes hole punching signals between connected peers.
+#[derive(Debug, Default)]
+pub struct Rendezvous {
+    /// Outgoing message queue of each registered connection.
+    connections: RwLock<HashMap<Uuid, mpsc::UnboundedSender<ServerMessage>>>,
+    sessions: RwLock<HashMap<Uuid, Session>>,
+    /// Relay quotas, if relaying is enabled.
+    relay: Option<RelayQuota>,
+    /// UDP ports answering address probes, announced on registration.
+    probe_ports: Vec<u16>,
+}
+
+impl Rendezvous {
+    /// Creates an empty rendezvous.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also relays payloads between peers, `quota` bytes per second each.
+    pub fn with_relay(mut self, quota: u64) -> Self {
+        self.relay = Some(RelayQuota::new(quota));
+        self
+    }
+
+    /// Announces the UDP ports that answer address probes.
+    pub fn with_probe_ports(mut self, ports: Vec<u16>) -> Self {
+        self.probe_ports = ports;
+        self
+    }
+
+    /// UDP ports that answer address probes, empty if probing is unavailable.
+    pub fn probe_ports(&self) -> Vec<u16> {
+        self.probe_ports.clone()
+    }
+
+    /// Makes a registered peer reachable for signaling.
+    pub async fn attach(&self, peer_id: Uuid, sender: mpsc::UnboundedSender<ServerMessage>) {
+        self.connections.write().await.insert(peer_id, sender);
+    }
+
+    /// Forgets a peer's connection.
+    pub async fn detach(&self, peer_id: Uuid) {
+        self.connections.write().await.remove(&peer_id);
+    }
+
+    /// Releases the state of a closed WebSocket connection from `addr`.
+    pub async fn close(&self, addr: SocketAddr) {
+        if let Some(relay) = &self.relay {
+            relay.forget(addr).await;
+        }
+    }
+
+    /// Sends a message to a registered peer's connection.
+    async fn notify(&self, peer_id: Uuid, msg: ServerMessage) -> bool {
+        match self.connections.read().await.get(&peer_id) {
+            Some(sender) => sender.send(msg).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Opens a punch session between `from` and `target`.
+    ///
+    /// Tells the target to prepare and returns the message for the
+    /// requesting side.
+    pub async fn open(
+        &self,
+        registry: &PeerRegistry,
+        from: Uuid,
+        target: Uuid,
+    ) -> Result<ServerMessage, String> {
+        if from == target {
+            return Err("Cannot connect to yourself".to_string());
+        }
+        if registry.get_peer(target).await.is_none() {
+            return Err("Unknown peer".to_string());
+        }
+
+        let session = Uuid::new_v4();
+        self.sessions.write().await.insert(
+            session,
+            Session {
+                sides: [
+                    Side {
+                        peer_id: from,
+                        endpoint: None,
+                    },
+                    Side {
+                        peer_id: target,
+                        endpoint: None,
+                    },
+                ],
+                created: Instant::now(),
+            },
+        );
+
+        let prepare = ServerMessage::PunchPrepare {
+            session: session.to_string(),
+            peer_id: from.to_string(),
+            initiator: false,
+        };
+        if !self.notify(target, prepare).await {
+            self.sessions.write().await.remove(&session);
+            return Err("Unknown peer".to_string());
+        }
+
+        tracing::info!(session = %session, from = %from, target = %target, "Punch session opened");
+
+        Ok(ServerMessage::PunchPrepare {
+            session: session.to_string(),
+            peer_id: target.to_string(),
+            initiator: true,
+        })
+    }
+
+    /// Records the public address a bind datagram arrived from.
+    ///
+    /// When both sides are bound, sends each the other's candidates and
+    /// closes the session. Returns false if the datagram matched no session.
+    pub async fn bind(
+        &self,
+        registry: &PeerRegistry,
+        bind: PunchBind,
+        observed: SocketAddr,
+    ) -> bool {
+        let (Ok(session_id), Ok(peer_id)) =
+            (bind.session.parse::<Uuid>(), bind.peer_id.parse::<Uuid>())
+        else {
+            return false;
+        };
+
+        let sides = {
+            let mut sessions = self.sessions.write().await;
+            let Some(session) = sessions.get_mut(&session_id) else {
+                return false;
+            };
+            let Some(side) = session
+                .sides
+                .iter_mut()
+                .find(|side| side.peer_id == peer_id)
+            else {
+                return false;
+            };
+            side.endpoint = Some((observed, bind.local_addr));
+
+            if session.sides.iter().any(|side| side.endpoint.is_none()) {
+                return true;
+            }
+            match sessions.remove(&session_id) {
+                Some(session) => session.sides,
+                None => return true,
+            }
+        };
+
+        let start_at = Utc::now().timestamp_millis() + PUNCH_DELAY_MS;
+        for (this, other) in [(&sides[0], &sides[1]), (&sides[1], &sides[0])] {
+            let Some(info) = registry.get_peer(other.peer_id).await else {
+                continue;
+            };
+            let Some((public_addr, local_addr)) = &other.endpoint else {
+                continue;
+            };
+            let start = ServerMessage::PunchStart {
+                session: session_id.to_string(),
+                peer_id: other.peer_id.to_string(),
+                public_key: info.public_key,
+                public_addr: public_addr.to_string(),
+                local_addr: local_addr.clone(),
+                start_at,
+            };
+            self.notify(this.peer_id, start).await;
+        }
+
+        tracing::info!(session = %session_id, "Punch session started");
+        true
+    }
+
+    /// Forwards a payload from `from`, connected from `addr`, to `to`.
+    ///
+    /// Waits while the sender's connection is over its quota, which holds
+    /// back the sender's further messages. The circuit ID counts towards the
+    /// quota along with the payload.
+    pub async fn relay(
+        &self,
+        addr: SocketAddr,
+        from: Uuid,
+        to: Uuid,
+        circuit: String,
+        payload: String,
+    ) -> Result<(), String> {
+        let Some(quota) = &self.relay else {
+            return Err("Relay disabled".to_string());
+        };
+        if from == to {
+            return Err("Cannot relay to yourself".to_string());
+        }
+        if payload.len() > MAX_RELAY_PAYLOAD {
+            return Err("Payload too large".to_string());
+        }
+        if circuit.len() > MAX_CIRCUIT_LEN {
+            return Err("Circuit ID too long".to_string());
+        }
+        if !self.connections.read().await.contains_key(&to) {
+            return Err("Unknown peer".to_string());
+        }
+
+        let wait = quota.reserve(addr, payload.len() + circuit.len()).await;
+        if !wait.is_zero() {
+            tracing::debug!(
+                peer_id = %from,
+                wait_ms = wait.as_millis() as u64,
+                "Relay quota exceeded, throttling"
+            );
+            tokio::time::sleep(wait).await;
+        }
+
+        let relayed = ServerMessage::Relay {
+            from: from.to_string(),
+            circuit,
+            payload,
+        };
+        if !self.notify(to, relayed).await {
+            return Err("Unknown peer".to_string());
+        }
+        Ok(())
+    }
+
+    /// Drops sessions whose peers never bound.
+    pub async fn remove_expired_sessions(&self) -> usize {
+        let mut sessions = self.sessions.write().await;
+        let before = sessions.len();
+        sessions.retain(|_, session| session.created.elapsed() <= SESSION_TIMEOUT);
+        before - sessions.len()
+    }
+}
+
+/// Receives bind datagrams on the server's UDP port.
+pub async fn serve_udp(
+    socket: UdpSocket,
+    rendezvous: Arc<Rendezvous>,
+    registry: Arc<PeerRegistry>,
+) {
+    let mut buf = [0u8; MAX_BIND_SIZE];
+
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to receive UDP datagram");
+                continue;
+            }
+        };
+
+        match serde_json::from_slice::<PunchBind>(&buf[..len]) {
+            Ok(bind) => {
+                if !rendezvous.bind(&registry, bind, addr).await {
+                    tracing::debug!(addr = %addr, "Bind datagram for unknown session");
+                }
+            }
+            Err(e) => {
+                tracing::debug!(addr = %addr, error = %e, "Invalid UDP datagram");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Hello;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const ALICE_ADDR: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 50000));
+    const BOB_ADDR: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(2, 2, 2, 2), 50000));
+
+    async fn register(registry: &PeerRegistry, nickname: &str, key: &str) -> Uuid {
+        registry
+            .register(
+                nickname.to_string(),
+                "192.168.1.1:5000".to_string(),
+                "1.1.1.1:5000".to_string(),
+                key.repeat(32),
+                Hello::default(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_open_rejects_unknown_peer() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
+        let alice = register(&registry, "alice", "aa").await;
+
+        let result = rendezvous.open(&registry, alice, Uuid::new_v4()).await;
+        assert_eq!(result, Err("Unknown peer".to_string()));
+        assert_eq!(
+            rendezvous.open(&registry, alice, alice).await,
+            Err("Cannot connect to yourself".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_punch_session_exchanges_candidates() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
+        let alice = register(&registry, "alice", "aa").await;
+        let bob = register(&registry, "bob", "bb").await;
+
+        let (alice_tx, mut alice_rx) = mpsc::unbounded_channel();
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(alice, alice_tx).await;
+        rendezvous.attach(bob, bob_tx).await;
+
+        let session = match rendezvous.open(&registry, alice, bob).await.unwrap() {
+            ServerMessage::PunchPrepare {
+                session,
+                peer_id,
+                initiator,
+            } => {
+                assert_eq!(peer_id, bob.to_string());
+                assert!(initiator);
+                session
+            }
+            other => panic!("Expected PunchPrepare, got {:?}", other),
+        };
+
+        assert_eq!(
+            bob_rx.try_recv().unwrap(),
+            ServerMessage::PunchPrepare {
+                session: session.clone(),
+                peer_id: alice.to_string(),
+                initiator: false,
+            }
+        );
+
+        let alice_bind = PunchBind {
+            session: session.clone(),
+            peer_id: alice.to_string(),
+            local_addr: "192.168.1.10:40000".to_string(),
+        };
+        assert!(
+            rendezvous
+                .bind(&registry, alice_bind, "1.1.1.1:50000".parse().unwrap())
+                .await
+        );
+        assert!(alice_rx.try_recv().is_err());
+
+        let bob_bind = PunchBind {
+            session: session.clone(),
+            peer_id: bob.to_string(),
+            local_addr: "10.0.0.20:41000".to_string(),
+        };
+        assert!(
+            rendezvous
+                .bind(&registry, bob_bind, "2.2.2.2:60000".parse().unwrap())
+                .await
+        );
+
+        let (alice_start, bob_start) = (alice_rx.try_recv().unwrap(), bob_rx.try_recv().unwrap());
+        match (alice_start, bob_start) {
+            (
+                ServerMessage::PunchStart {
+                    peer_id: alice_sees,
+                    public_key,
+                    public_addr: bob_public,
+                    local_addr: bob_local,
+                    start_at: alice_start_at,
+                    ..
+                },
+                ServerMessage::PunchStart {
+                    peer_id: bob_sees,
+                    public_addr: alice_public,
+                    local_addr: alice_local,
+                    start_at: bob_start_at,
+                    ..
+                },
+            ) => {
+                assert_eq!(alice_sees, bob.to_string());
+                assert_eq!(bob_sees, alice.to_string());
+                assert_eq!(public_key, "bb".repeat(32));
+                assert_eq!(bob_public, "2.2.2.2:60000");
+                assert_eq!(bob_local, "10.0.0.20:41000");
+                assert_eq!(alice_public, "1.1.1.1:50000");
+                assert_eq!(alice_local, "192.168.1.10:40000");
+                assert_eq!(alice_start_at, bob_start_at);
+                assert!(alice_start_at > Utc::now().timestamp_millis());
+            }
+            other => panic!("Expected PunchStart messages, got {:?}", other),
+        }
+
+        // The session is closed once both sides started
+        let late_bind = PunchBind {
+            session,
+            peer_id: alice.to_string(),
+            local_addr: "192.168.1.10:40000".to_string(),
+        };
+        assert!(
+            !rendezvous
+                .bind(&registry, late_bind, "1.1.1.1:50000".parse().unwrap())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_relay_forwards_payload() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new().with_relay(1024);
+        let alice = register(&registry, "alice", "aa").await;
+        let bob = register(&registry, "bob", "bb").await;
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(bob, bob_tx).await;
+
+        rendezvous
+            .relay(ALICE_ADDR, alice, bob, "c1".to_string(), "aGk=".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            bob_rx.try_recv().unwrap(),
+            ServerMessage::Relay {
+                from: alice.to_string(),
+                circuit: "c1".to_string(),
+                payload: "aGk=".to_string(),
+            }
+        );
+
+        assert_eq!(
+            rendezvous
+                .relay(
+                    ALICE_ADDR,
+                    alice,
+                    Uuid::new_v4(),
+                    "c1".to_string(),
+                    String::new()
+                )
+                .await,
+            Err("Unknown peer".to_string())
+        );
+        assert_eq!(
+            rendezvous
+                .relay(BOB_ADDR, bob, bob, "c1".to_string(), String::new())
+                .await,
+            Err("Cannot relay to yourself".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_relay_disabled_by_default() {
+        let rendezvous = Rendezvous::new();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(bob, bob_tx).await;
+
+        assert_eq!(
+            rendezvous
+                .relay(ALICE_ADDR, alice, bob, "c1".to_string(), "aGk=".to_string())
+                .await,
+            Err("Relay disabled".to_string())
+        );
+        assert!(bob_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_relay_throttles_sender_over_quota() {
+        let rendezvous = Rendezvous::new().with_relay(1000);
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(bob, bob_tx).await;
+
+        let started = Instant::now();
+        for _ in 0..2 {
+            rendezvous
+                .relay(ALICE_ADDR, alice, bob, "c1".to_string(), "a".repeat(700))
+                .await
+                .unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(350));
+        assert!(bob_rx.try_recv().is_ok());
+        assert!(bob_rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_relay_charges_circuit_to_quota() {
+        let rendezvous = Rendezvous::new().with_relay(100);
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(bob, bob_tx).await;
+
+        let started = Instant::now();
+        for _ in 0..2 {
+            rendezvous
+                .relay(ALICE_ADDR, alice, bob, "c".repeat(60), String::new())
+                .await
+                .unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert!(bob_rx.try_recv().is_ok());
+        assert!(bob_rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bind_rejects_outsider() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
+        let alice = register(&registry, "alice", "aa").await;
+        let bob = register(&registry, "bob", "bb").await;
+        let (bob_tx, _bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(bob, bob_tx).await;
+
+        let session = match rendezvous.open(&registry, alice, bob).await {
+            Err(message) => panic!("open failed: {}", message),
+            Ok(ServerMessage::PunchPrepare { session, .. }) => session,
+            Ok(other) => panic!("Expected PunchPrepare, got {:?}", other),
+        };
+
+        let bind = PunchBind {
+            session,
+            peer_id: Uuid::new_v4().to_string(),
+            local_addr: "10.0.0.1:1".to_string(),
+        };
+        assert!(
+            !rendezvous
+                .bind(&registry, bind, "3.3.3.3:1".parse().unwrap())
+                .await
+        );
+    }
+}
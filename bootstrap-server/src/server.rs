@@ -1,15 +1,23 @@
 //! WebSocket server implementation for the bootstrap server.
 //!
 //! This module handles incoming WebSocket connections, processes client messages,
-//! and manages peer state through the registry.
-
-use crate::protocol::{ClientMessage, ServerMessage};
+//! and manages peer state through the registry. A UDP socket on the same port
+//! receives the bind datagrams used to coordinate hole punching, and the two
+//! ports after it answer the probes clients use to learn their mapped
+//! address. When the relay is enabled, registered peers may also forward
+//! payloads to each other through their connections.
+
+use crate::probe::serve_probe;
+use crate::protocol::{
+    parse_public_key, ClientMessage, ServerMessage, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
 use crate::registry::PeerRegistry;
+use crate::rendezvous::{serve_udp, Rendezvous};
 use futures_util::{SinkExt, StreamExt};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::RwLock;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, RwLock};
 use tokio_tungstenite::tungstenite::Message;
 use uuid::Uuid;
 
@@ -17,6 +25,11 @@ use uuid::Uuid;
 pub struct BootstrapServer {
     registry: PeerRegistry,
     listener: TcpListener,
+    udp_socket: Option<UdpSocket>,
+    /// Primary and alternate address probe sockets.
+    probe_sockets: Option<(UdpSocket, UdpSocket)>,
+    /// Per-connection relay quota in bytes per second, if relaying is enabled.
+    relay_quota: Option<u64>,
 }
 
 impl BootstrapServer {
@@ -25,27 +38,76 @@ impl BootstrapServer {
         let listener = TcpListener::bind(addr).await?;
         tracing::info!("Bootstrap server listening on {}", addr);
 
+        // Hole punching needs the UDP port, but discovery works without it
+        let udp_socket = match UdpSocket::bind(listener.local_addr()?).await {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to bind UDP port, hole punching disabled");
+                None
+            }
+        };
+
+        let probe_sockets = match bind_probe_sockets(listener.local_addr()?).await {
+            Ok(sockets) => Some(sockets),
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to bind probe ports, address probing disabled");
+                None
+            }
+        };
+
         Ok(Self {
             registry: PeerRegistry::new(),
             listener,
+            udp_socket,
+            probe_sockets,
+            relay_quota: None,
         })
     }
 
+    /// Enables relaying with a per-connection quota in bytes per second.
+    pub fn with_relay(mut self, quota: u64) -> Self {
+        self.relay_quota = Some(quota);
+        self
+    }
+
     /// Runs the bootstrap server, accepting and handling connections.
     pub async fn run(self) -> Result<(), std::io::Error> {
         let registry = Arc::new(self.registry);
 
+        let mut rendezvous = Rendezvous::new();
+        if let Some(quota) = self.relay_quota {
+            tracing::info!(quota_bytes_per_sec = quota, "Relay enabled");
+            rendezvous = rendezvous.with_relay(quota);
+        }
+        if let Some((primary, alternate)) = self.probe_sockets {
+            let ports = vec![primary.local_addr()?.port(), alternate.local_addr()?.port()];
+            tracing::info!(ports = ?ports, "Answering address probes");
+            rendezvous = rendezvous.with_probe_ports(ports);
+
+            let (primary, alternate) = (Arc::new(primary), Arc::new(alternate));
+            tokio::spawn(serve_probe(primary.clone(), alternate.clone()));
+            tokio::spawn(serve_probe(alternate, primary));
+        }
+        let rendezvous = Arc::new(rendezvous);
+
         let cleanup_registry = registry.clone();
+        let cleanup_rendezvous = rendezvous.clone();
         tokio::spawn(async move {
-            cleanup_task(cleanup_registry).await;
+            cleanup_task(cleanup_registry, cleanup_rendezvous).await;
         });
 
+        if let Some(socket) = self.udp_socket {
+            tokio::spawn(serve_udp(socket, rendezvous.clone(), registry.clone()));
+        }
+
         loop {
             match self.listener.accept().await {
                 Ok((stream, addr)) => {
                     let registry = registry.clone();
+                    let rendezvous = rendezvous.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = handle_connection(stream, addr, registry).await {
+                        if let Err(e) = handle_connection(stream, addr, registry, rendezvous).await
+                        {
                             tracing::error!(addr = %addr, error = %e, "Connection handler error");
                         }
                     });
@@ -58,16 +120,33 @@ impl BootstrapServer {
     }
 
     /// Returns the local address the server is bound to.
+    #[allow(dead_code)]
     pub fn local_addr(&self) -> Result<SocketAddr, std::io::Error> {
         self.listener.local_addr()
     }
 }
 
+/// Binds the two probe ports following the server's port.
+async fn bind_probe_sockets(addr: SocketAddr) -> Result<(UdpSocket, UdpSocket), std::io::Error> {
+    let port = |offset: u16| {
+        addr.port().checked_add(offset).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no ports left for probing",
+            )
+        })
+    };
+    let primary = UdpSocket::bind(SocketAddr::new(addr.ip(), port(1)?)).await?;
+    let alternate = UdpSocket::bind(SocketAddr::new(addr.ip(), port(2)?)).await?;
+    Ok((primary, alternate))
+}
+
 /// Handles a single WebSocket connection.
 async fn handle_connection(
     stream: TcpStream,
     addr: SocketAddr,
     registry: Arc<PeerRegistry>,
+    rendezvous: Arc<Rendezvous>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!(addr = %addr, "New connection");
 
@@ -77,37 +156,60 @@ async fn handle_connection(
     let peer_id: Arc<RwLock<Option<Uuid>>> = Arc::new(RwLock::new(None));
     let connection_peer_id = peer_id.clone();
 
-    while let Some(msg) = read.next().await {
-        match msg {
-            Ok(Message::Text(text)) => {
-                let response = process_message(&text, addr, &registry, &peer_id).await;
+    // Messages other connections route to this peer
+    let (signal_tx, mut signal_rx) = mpsc::unbounded_channel::<ServerMessage>();
 
-                if let Some(response_msg) = response {
-                    let json = serde_json::to_string(&response_msg)?;
-                    write.send(Message::Text(json)).await?;
+    loop {
+        tokio::select! {
+            msg = read.next() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+                match msg {
+                    Ok(Message::Text(text)) => {
+                        let response =
+                            process_message(&text, addr, &registry, &rendezvous, &peer_id).await;
+
+                        if let Some(ServerMessage::Registered { .. }) = &response {
+                            if let Some(id) = *peer_id.read().await {
+                                rendezvous.attach(id, signal_tx.clone()).await;
+                            }
+                        }
+
+                        if let Some(response_msg) = response {
+                            let json = serde_json::to_string(&response_msg)?;
+                            write.send(Message::Text(json)).await?;
+                        }
+                    }
+                    Ok(Message::Close(_)) => {
+                        tracing::info!(addr = %addr, "Connection closed by client");
+                        break;
+                    }
+                    Ok(Message::Ping(data)) => {
+                        write.send(Message::Pong(data)).await?;
+                    }
+                    Ok(_) => {
+                        // Ignore other message types
+                    }
+                    Err(e) => {
+                        tracing::error!(addr = %addr, error = %e, "WebSocket error");
+                        break;
+                    }
                 }
             }
-            Ok(Message::Close(_)) => {
-                tracing::info!(addr = %addr, "Connection closed by client");
-                break;
-            }
-            Ok(Message::Ping(data)) => {
-                write.send(Message::Pong(data)).await?;
-            }
-            Ok(_) => {
-                // Ignore other message types
-            }
-            Err(e) => {
-                tracing::error!(addr = %addr, error = %e, "WebSocket error");
-                break;
+            Some(signal) = signal_rx.recv() => {
+                let json = serde_json::to_string(&signal)?;
+                write.send(Message::Text(json)).await?;
             }
         }
     }
 
     if let Some(id) = *connection_peer_id.read().await {
+        rendezvous.detach(id).await;
         registry.unregister(id).await;
         tracing::info!(addr = %addr, peer_id = %id, "Connection closed, peer unregistered");
     }
+    rendezvous.close(addr).await;
 
     Ok(())
 }
@@ -117,6 +219,7 @@ async fn process_message(
     text: &str,
     addr: SocketAddr,
     registry: &PeerRegistry,
+    rendezvous: &Rendezvous,
     peer_id: &Arc<RwLock<Option<Uuid>>>,
 ) -> Option<ServerMessage> {
     let client_msg: ClientMessage = match serde_json::from_str(text) {
@@ -133,25 +236,74 @@ async fn process_message(
         ClientMessage::Register {
             nickname,
             local_addr,
+            public_key,
+            hello,
         } => {
-            let public_addr = if let Ok(local_socket_addr) = local_addr.parse::<std::net::SocketAddr>() {
-                let public_ip = addr.ip();
-                let tcp_port = local_socket_addr.port();
-                format!("{}:{}", public_ip, tcp_port)
-            } else {
-                tracing::warn!("Failed to parse local_addr: {}, using WebSocket addr", local_addr);
-                addr.to_string()
+            // A registration lives as long as its connection
+            if peer_id.read().await.is_some() {
+                return Some(ServerMessage::Error {
+                    message: "Already registered".to_string(),
+                });
+            }
+
+            if !hello.is_compatible() {
+                tracing::warn!(
+                    addr = %addr,
+                    protocol_version = hello.protocol_version,
+                    "Rejected client with incompatible protocol version"
+                );
+                // Clients from before versioning only understand errors
+                if hello.protocol_version == 0 {
+                    return Some(ServerMessage::Error {
+                        message: format!(
+                            "Incompatible protocol: server requires protocol version {} or newer, please upgrade",
+                            MIN_PROTOCOL_VERSION
+                        ),
+                    });
+                }
+                return Some(ServerMessage::Incompatible {
+                    protocol_version: PROTOCOL_VERSION,
+                    min_protocol_version: MIN_PROTOCOL_VERSION,
+                });
+            }
+
+            let Some(public_key) = parse_public_key(&public_key) else {
+                return Some(ServerMessage::Error {
+                    message: "Invalid public key".to_string(),
+                });
             };
 
-            let id = registry
-                .register(nickname, local_addr, public_addr.clone())
-                .await;
+            // Only a guess: NATs may map the listener to another port. Clients
+            // that probe their mapping correct it with `update_address`.
+            let public_addr =
+                if let Ok(local_socket_addr) = local_addr.parse::<std::net::SocketAddr>() {
+                    let public_ip = addr.ip();
+                    let tcp_port = local_socket_addr.port();
+                    format!("{}:{}", public_ip, tcp_port)
+                } else {
+                    tracing::warn!(
+                        "Failed to parse local_addr: {}, using WebSocket addr",
+                        local_addr
+                    );
+                    addr.to_string()
+                };
+
+            let Some(id) = registry
+                .register(nickname, local_addr, public_addr.clone(), public_key, hello)
+                .await
+            else {
+                tracing::warn!(addr = %addr, "Rejected registration of a key already in use");
+                return Some(ServerMessage::Error {
+                    message: "Public key already registered".to_string(),
+                });
+            };
 
             *peer_id.write().await = Some(id);
 
             Some(ServerMessage::Registered {
                 peer_id: id.to_string(),
                 public_addr,
+                probe_ports: rendezvous.probe_ports(),
             })
         }
         ClientMessage::ListPeers => {
@@ -174,9 +326,10 @@ async fn process_message(
             }
         }
         ClientMessage::Unregister => {
-            if let Some(id) = *peer_id.write().await {
+            let id = peer_id.write().await.take();
+            if let Some(id) = id {
+                rendezvous.detach(id).await;
                 registry.unregister(id).await;
-                *peer_id.write().await = None;
                 None
             } else {
                 Some(ServerMessage::Error {
@@ -184,11 +337,102 @@ async fn process_message(
                 })
             }
         }
+        ClientMessage::ConnectRequest { target_peer_id } => {
+            let Some(id) = *peer_id.read().await else {
+                return Some(ServerMessage::Error {
+                    message: "Not registered".to_string(),
+                });
+            };
+            let Ok(target) = target_peer_id.parse::<Uuid>() else {
+                return Some(ServerMessage::PunchFailed {
+                    peer_id: target_peer_id,
+                    message: "Unknown peer".to_string(),
+                });
+            };
+
+            match rendezvous.open(registry, id, target).await {
+                Ok(prepare) => Some(prepare),
+                Err(message) => Some(ServerMessage::PunchFailed {
+                    peer_id: target_peer_id,
+                    message,
+                }),
+            }
+        }
+        ClientMessage::UpdateAddress {
+            public_addr,
+            unreachable,
+        } => {
+            let Some(id) = *peer_id.read().await else {
+                return Some(ServerMessage::Error {
+                    message: "Not registered".to_string(),
+                });
+            };
+
+            // Only accept addresses on the connection's own IP, so peers
+            // cannot point others at third parties
+            match public_addr.parse::<SocketAddr>() {
+                Ok(claimed) if claimed.ip() == addr.ip() => {
+                    registry
+                        .update_public_addr(id, claimed.to_string(), unreachable)
+                        .await;
+                    tracing::info!(
+                        peer_id = %id,
+                        public_addr = %claimed,
+                        unreachable,
+                        "Public address updated"
+                    );
+                }
+                _ => {
+                    tracing::warn!(
+                        peer_id = %id,
+                        public_addr = %public_addr,
+                        "Rejected public address from another IP"
+                    );
+                }
+            }
+
+            let peer = registry.get_peer(id).await?;
+            Some(ServerMessage::Registered {
+                peer_id: id.to_string(),
+                public_addr: peer.public_addr,
+                probe_ports: rendezvous.probe_ports(),
+            })
+        }
+        ClientMessage::Relay {
+            to,
+            circuit,
+            payload,
+        } => {
+            let Some(id) = *peer_id.read().await else {
+                return Some(ServerMessage::Error {
+                    message: "Not registered".to_string(),
+                });
+            };
+            let Ok(target) = to.parse::<Uuid>() else {
+                return Some(ServerMessage::RelayFailed {
+                    peer_id: to,
+                    circuit,
+                    message: "Unknown peer".to_string(),
+                });
+            };
+
+            match rendezvous
+                .relay(addr, id, target, circuit.clone(), payload)
+                .await
+            {
+                Ok(()) => None,
+                Err(message) => Some(ServerMessage::RelayFailed {
+                    peer_id: to,
+                    circuit,
+                    message,
+                }),
+            }
+        }
     }
 }
 
-/// Background task that periodically removes stale peers.
-async fn cleanup_task(registry: Arc<PeerRegistry>) {
+/// Background task that periodically removes stale peers and punch sessions.
+async fn cleanup_task(registry: Arc<PeerRegistry>, rendezvous: Arc<Rendezvous>) {
     let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(10));
 
     loop {
@@ -197,12 +441,26 @@ async fn cleanup_task(registry: Arc<PeerRegistry>) {
         if removed > 0 {
             tracing::info!(count = removed, "Removed stale peers");
         }
+        let expired = rendezvous.remove_expired_sessions().await;
+        if expired > 0 {
+            tracing::info!(count = expired, "Removed expired punch sessions");
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::Hello;
+    use std::time::{Duration, Instant};
+
+    fn current_hello() -> Hello {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            features: Vec::new(),
+        }
+    }
 
     #[tokio::test]
     async fn test_server_creation() {
@@ -215,22 +473,26 @@ mod tests {
     #[tokio::test]
     async fn test_process_register_message() {
         let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
         let peer_id = Arc::new(RwLock::new(None));
         let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
 
         let msg = ClientMessage::Register {
             nickname: "test".to_string(),
             local_addr: "192.168.1.1:5000".to_string(),
+            public_key: "ab".repeat(32),
+            hello: current_hello(),
         };
         let json = serde_json::to_string(&msg).unwrap();
 
-        let response = process_message(&json, addr, &registry, &peer_id).await;
+        let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
 
         assert!(response.is_some());
         match response.unwrap() {
             ServerMessage::Registered {
                 peer_id: id,
                 public_addr,
+                ..
             } => {
                 assert!(!id.is_empty());
                 assert_eq!(public_addr, "127.0.0.1:5000");
@@ -241,9 +503,139 @@ mod tests {
         assert!(peer_id.read().await.is_some());
     }
 
+    #[tokio::test]
+    async fn test_process_register_rejects_incompatible_version() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
+        let peer_id = Arc::new(RwLock::new(None));
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        let msg = ClientMessage::Register {
+            nickname: "test".to_string(),
+            local_addr: "192.168.1.1:5000".to_string(),
+            public_key: "ab".repeat(32),
+            hello: Hello {
+                protocol_version: PROTOCOL_VERSION + 1,
+                min_protocol_version: PROTOCOL_VERSION + 1,
+                features: Vec::new(),
+            },
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+
+        let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+
+        assert_eq!(
+            response,
+            Some(ServerMessage::Incompatible {
+                protocol_version: PROTOCOL_VERSION,
+                min_protocol_version: MIN_PROTOCOL_VERSION,
+            })
+        );
+        assert!(peer_id.read().await.is_none());
+        assert_eq!(registry.peer_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_process_register_rejects_invalid_key() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
+        let peer_id = Arc::new(RwLock::new(None));
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        for key in ["ab".to_string(), "zz".repeat(32), "ab".repeat(33)] {
+            let msg = ClientMessage::Register {
+                nickname: "test".to_string(),
+                local_addr: "192.168.1.1:5000".to_string(),
+                public_key: key,
+                hello: current_hello(),
+            };
+            let json = serde_json::to_string(&msg).unwrap();
+
+            let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+            assert_eq!(
+                response,
+                Some(ServerMessage::Error {
+                    message: "Invalid public key".to_string(),
+                })
+            );
+        }
+        assert!(peer_id.read().await.is_none());
+        assert_eq!(registry.peer_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_process_register_rejects_key_registered_elsewhere() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
+        let register = |key: String| {
+            serde_json::to_string(&ClientMessage::Register {
+                nickname: "alice".to_string(),
+                local_addr: "192.168.1.1:5000".to_string(),
+                public_key: key,
+                hello: current_hello(),
+            })
+            .unwrap()
+        };
+
+        let owner = Arc::new(RwLock::new(None));
+        let owner_addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let response = process_message(
+            &register("ab".repeat(32)),
+            owner_addr,
+            &registry,
+            &rendezvous,
+            &owner,
+        )
+        .await;
+        assert!(matches!(response, Some(ServerMessage::Registered { .. })));
+
+        // Changing the case of the hex does not make it another key
+        let impostor = Arc::new(RwLock::new(None));
+        let impostor_addr: SocketAddr = "127.0.0.1:23456".parse().unwrap();
+        let response = process_message(
+            &register("AB".repeat(32)),
+            impostor_addr,
+            &registry,
+            &rendezvous,
+            &impostor,
+        )
+        .await;
+        assert_eq!(
+            response,
+            Some(ServerMessage::Error {
+                message: "Public key already registered".to_string(),
+            })
+        );
+        assert!(impostor.read().await.is_none());
+
+        let peers = registry.list_peers().await;
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].peer_id, owner.read().await.unwrap().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_process_register_rejects_unversioned_client() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
+        let peer_id = Arc::new(RwLock::new(None));
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        let json = r#"{"type":"register","nickname":"test","local_addr":"192.168.1.1:5000","public_key":"ab"}"#;
+        let response = process_message(json, addr, &registry, &rendezvous, &peer_id).await;
+
+        match response {
+            Some(ServerMessage::Error { message }) => {
+                assert!(message.contains("Incompatible protocol"))
+            }
+            other => panic!("Expected Error message, got {:?}", other),
+        }
+        assert!(peer_id.read().await.is_none());
+    }
+
     #[tokio::test]
     async fn test_process_list_peers_message() {
         let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
         let peer_id = Arc::new(RwLock::new(None));
         let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
 
@@ -252,13 +644,16 @@ mod tests {
                 "peer1".to_string(),
                 "192.168.1.1:5000".to_string(),
                 "1.1.1.1:5000".to_string(),
+                "ab".repeat(32),
+                Hello::default(),
             )
-            .await;
+            .await
+            .unwrap();
 
         let msg = ClientMessage::ListPeers;
         let json = serde_json::to_string(&msg).unwrap();
 
-        let response = process_message(&json, addr, &registry, &peer_id).await;
+        let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
 
         assert!(response.is_some());
         match response.unwrap() {
@@ -273,13 +668,14 @@ mod tests {
     #[tokio::test]
     async fn test_process_heartbeat_not_registered() {
         let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
         let peer_id = Arc::new(RwLock::new(None));
         let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
 
         let msg = ClientMessage::Heartbeat;
         let json = serde_json::to_string(&msg).unwrap();
 
-        let response = process_message(&json, addr, &registry, &peer_id).await;
+        let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
 
         assert!(response.is_some());
         match response.unwrap() {
@@ -293,10 +689,12 @@ mod tests {
     #[tokio::test]
     async fn test_process_invalid_message() {
         let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
         let peer_id = Arc::new(RwLock::new(None));
         let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
 
-        let response = process_message("invalid json", addr, &registry, &peer_id).await;
+        let response =
+            process_message("invalid json", addr, &registry, &rendezvous, &peer_id).await;
 
         assert!(response.is_some());
         match response.unwrap() {
@@ -306,4 +704,314 @@ mod tests {
             _ => panic!("Expected Error message"),
         }
     }
+
+    #[tokio::test]
+    async fn test_process_connect_request() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
+        let peer_id = Arc::new(RwLock::new(None));
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        let msg = ClientMessage::Register {
+            nickname: "alice".to_string(),
+            local_addr: "192.168.1.1:5000".to_string(),
+            public_key: "aa".repeat(32),
+            hello: current_hello(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+
+        let msg = ClientMessage::ConnectRequest {
+            target_peer_id: "nobody".to_string(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+        assert_eq!(
+            response,
+            Some(ServerMessage::PunchFailed {
+                peer_id: "nobody".to_string(),
+                message: "Unknown peer".to_string(),
+            })
+        );
+
+        let bob = registry
+            .register(
+                "bob".to_string(),
+                "192.168.1.2:5000".to_string(),
+                "2.2.2.2:5000".to_string(),
+                "bb".repeat(32),
+                Hello::default(),
+            )
+            .await
+            .unwrap();
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(bob, bob_tx).await;
+
+        let msg = ClientMessage::ConnectRequest {
+            target_peer_id: bob.to_string(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+
+        match response {
+            Some(ServerMessage::PunchPrepare {
+                peer_id: target,
+                initiator,
+                ..
+            }) => {
+                assert_eq!(target, bob.to_string());
+                assert!(initiator);
+            }
+            other => panic!("Expected PunchPrepare message, got {:?}", other),
+        }
+        assert!(matches!(
+            bob_rx.try_recv(),
+            Ok(ServerMessage::PunchPrepare {
+                initiator: false,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_process_connect_request_not_registered() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new();
+        let peer_id = Arc::new(RwLock::new(None));
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        let msg = ClientMessage::ConnectRequest {
+            target_peer_id: Uuid::new_v4().to_string(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+
+        assert_eq!(
+            response,
+            Some(ServerMessage::Error {
+                message: "Not registered".to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_relay_message() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new().with_relay(64 * 1024);
+        let peer_id = Arc::new(RwLock::new(None));
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        let msg = ClientMessage::Register {
+            nickname: "alice".to_string(),
+            local_addr: "192.168.1.1:5000".to_string(),
+            public_key: "aa".repeat(32),
+            hello: current_hello(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+        let alice = peer_id.read().await.unwrap();
+
+        let bob = Uuid::new_v4();
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(bob, bob_tx).await;
+
+        let msg = ClientMessage::Relay {
+            to: bob.to_string(),
+            circuit: "c1".to_string(),
+            payload: "aGk=".to_string(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+        assert_eq!(response, None);
+        assert_eq!(
+            bob_rx.try_recv().unwrap(),
+            ServerMessage::Relay {
+                from: alice.to_string(),
+                circuit: "c1".to_string(),
+                payload: "aGk=".to_string(),
+            }
+        );
+
+        let msg = ClientMessage::Relay {
+            to: "nobody".to_string(),
+            circuit: "c2".to_string(),
+            payload: String::new(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        let response = process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+        assert_eq!(
+            response,
+            Some(ServerMessage::RelayFailed {
+                peer_id: "nobody".to_string(),
+                circuit: "c2".to_string(),
+                message: "Unknown peer".to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reregistering_keeps_relay_quota() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new().with_relay(1000);
+        let peer_id = Arc::new(RwLock::new(None));
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        let bob = Uuid::new_v4();
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(bob, bob_tx).await;
+
+        let register = serde_json::to_string(&ClientMessage::Register {
+            nickname: "alice".to_string(),
+            local_addr: "192.168.1.1:5000".to_string(),
+            public_key: "aa".repeat(32),
+            hello: current_hello(),
+        })
+        .unwrap();
+        let unregister = serde_json::to_string(&ClientMessage::Unregister).unwrap();
+        let relay = serde_json::to_string(&ClientMessage::Relay {
+            to: bob.to_string(),
+            circuit: "c1".to_string(),
+            payload: "a".repeat(700),
+        })
+        .unwrap();
+
+        process_message(&register, addr, &registry, &rendezvous, &peer_id).await;
+        let started = Instant::now();
+        process_message(&relay, addr, &registry, &rendezvous, &peer_id).await;
+
+        // A second registration on a live connection is refused
+        let response = process_message(&register, addr, &registry, &rendezvous, &peer_id).await;
+        assert_eq!(
+            response,
+            Some(ServerMessage::Error {
+                message: "Already registered".to_string(),
+            })
+        );
+
+        // Registering afresh on the same connection does not refill the quota
+        process_message(&unregister, addr, &registry, &rendezvous, &peer_id).await;
+        assert!(peer_id.read().await.is_none());
+        process_message(&register, addr, &registry, &rendezvous, &peer_id).await;
+        process_message(&relay, addr, &registry, &rendezvous, &peer_id).await;
+
+        assert!(started.elapsed() >= Duration::from_millis(350));
+        assert!(bob_rx.try_recv().is_ok());
+        assert!(bob_rx.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_process_relay_rejects_oversized_circuit() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new().with_relay(1024);
+        let peer_id = Arc::new(RwLock::new(None));
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        let bob = Uuid::new_v4();
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        rendezvous.attach(bob, bob_tx).await;
+
+        let register = serde_json::to_string(&ClientMessage::Register {
+            nickname: "alice".to_string(),
+            local_addr: "192.168.1.1:5000".to_string(),
+            public_key: "aa".repeat(32),
+            hello: current_hello(),
+        })
+        .unwrap();
+        process_message(&register, addr, &registry, &rendezvous, &peer_id).await;
+
+        let circuit = "c".repeat(1024 * 1024);
+        let relay = serde_json::to_string(&ClientMessage::Relay {
+            to: bob.to_string(),
+            circuit: circuit.clone(),
+            payload: String::new(),
+        })
+        .unwrap();
+        let response = process_message(&relay, addr, &registry, &rendezvous, &peer_id).await;
+        assert_eq!(
+            response,
+            Some(ServerMessage::RelayFailed {
+                peer_id: bob.to_string(),
+                circuit,
+                message: "Circuit ID too long".to_string(),
+            })
+        );
+        assert!(bob_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_process_update_address() {
+        let registry = PeerRegistry::new();
+        let rendezvous = Rendezvous::new().with_probe_ports(vec![8081, 8082]);
+        let peer_id = Arc::new(RwLock::new(None));
+        let addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+
+        let msg = ClientMessage::Register {
+            nickname: "alice".to_string(),
+            local_addr: "192.168.1.1:5000".to_string(),
+            public_key: "aa".repeat(32),
+            hello: current_hello(),
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        match process_message(&json, addr, &registry, &rendezvous, &peer_id).await {
+            Some(ServerMessage::Registered { probe_ports, .. }) => {
+                assert_eq!(probe_ports, vec![8081, 8082]);
+            }
+            other => panic!("Expected Registered message, got {:?}", other),
+        }
+        let id = peer_id.read().await.unwrap();
+
+        let update = |public_addr: &str| {
+            serde_json::to_string(&ClientMessage::UpdateAddress {
+                public_addr: public_addr.to_string(),
+                unreachable: false,
+            })
+            .unwrap()
+        };
+
+        let response = process_message(
+            &update("127.0.0.1:41000"),
+            addr,
+            &registry,
+            &rendezvous,
+            &peer_id,
+        )
+        .await;
+        match response {
+            Some(ServerMessage::Registered { public_addr, .. }) => {
+                assert_eq!(public_addr, "127.0.0.1:41000");
+            }
+            other => panic!("Expected Registered message, got {:?}", other),
+        }
+
+        // Addresses on other IPs are ignored
+        let response = process_message(
+            &update("8.8.8.8:53"),
+            addr,
+            &registry,
+            &rendezvous,
+            &peer_id,
+        )
+        .await;
+        match response {
+            Some(ServerMessage::Registered { public_addr, .. }) => {
+                assert_eq!(public_addr, "127.0.0.1:41000");
+            }
+            other => panic!("Expected Registered message, got {:?}", other),
+        }
+        assert_eq!(
+            registry.get_public_addr(id).await.unwrap(),
+            "127.0.0.1:41000"
+        );
+
+        // Peers behind a NAT that lets nothing in are listed as such
+        let json = serde_json::to_string(&ClientMessage::UpdateAddress {
+            public_addr: "127.0.0.1:5000".to_string(),
+            unreachable: true,
+        })
+        .unwrap();
+        process_message(&json, addr, &registry, &rendezvous, &peer_id).await;
+        let peer = registry.get_peer(id).await.unwrap();
+        assert_eq!(peer.public_addr, "127.0.0.1:5000");
+        assert!(peer.unreachable);
+    }
 }
@@ -1,7 +1,14 @@
 //! Command parsing and representation.
 
+use chrono::NaiveDate;
 use std::fmt;
 
+/// Number of messages `/history` shows when no count is given
+pub const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+/// Maximum number of results `/search` shows
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+
 /// User commands
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Command {
@@ -9,6 +16,54 @@ pub enum Command {
     Send { to: String, content: String },
     /// List discovered peers
     Peers,
+    /// Approve a peer's key (including a changed key)
+    Trust { nickname: String },
+    /// Revoke trust in a peer's key
+    Untrust { nickname: String },
+    /// List pinned peer keys
+    Keys,
+    /// Show a peer's safety number, or mark it verified with `confirm`
+    Verify { nickname: String, confirm: bool },
+    /// Show the last `limit` messages exchanged with a peer
+    History { nickname: String, limit: usize },
+    /// Search the message history
+    Search {
+        query: String,
+        /// Only messages sent by this nickname
+        from: Option<String>,
+        /// Only messages on or after this day
+        since: Option<NaiveDate>,
+        /// Only messages on or before this day
+        until: Option<NaiveDate>,
+    },
+    /// List messages waiting for offline peers
+    Outbox,
+    /// Cancel a message waiting in the outbox
+    OutboxCancel { id: String },
+    /// Create a group
+    GroupCreate { name: String },
+    /// Add a peer to a group
+    GroupInvite { group: String, nickname: String },
+    /// Leave a group
+    GroupLeave { group: String },
+    /// List our groups
+    GroupList,
+    /// Send a message to a group
+    GroupSend { group: String, content: String },
+    /// Offer a file to a peer
+    SendFile { to: String, path: String },
+    /// Accept a file offer, or resume an interrupted transfer
+    Accept { id: String },
+    /// Join a LAN channel
+    Join { channel: String },
+    /// Leave a LAN channel
+    Leave { channel: String },
+    /// List the members of a LAN channel
+    Who { channel: String },
+    /// Post a message to a LAN channel
+    ChannelSend { channel: String, content: String },
+    /// Show our public address and NAT type
+    NetInfo,
     /// Quit the application
     Quit,
     /// Display help
@@ -61,6 +116,12 @@ impl Command {
 
                 let rest = parts[1];
                 if let Some((to, content)) = rest.split_once(' ') {
+                    if to.starts_with('#') {
+                        return Ok(Command::ChannelSend {
+                            channel: to.to_string(),
+                            content: content.to_string(),
+                        });
+                    }
                     Ok(Command::Send {
                         to: to.to_string(),
                         content: content.to_string(),
@@ -73,17 +134,253 @@ impl Command {
                 }
             }
             "peers" => Ok(Command::Peers),
+            "netinfo" => Ok(Command::NetInfo),
+            "trust" => Ok(Command::Trust {
+                nickname: Self::parse_nickname(&parts, "/trust")?,
+            }),
+            "untrust" => Ok(Command::Untrust {
+                nickname: Self::parse_nickname(&parts, "/untrust")?,
+            }),
+            "keys" => Ok(Command::Keys),
+            "verify" => {
+                let usage = || CommandParseError::MissingArguments {
+                    command: "/verify".to_string(),
+                    usage: "<nickname> [confirm]".to_string(),
+                };
+                let args: Vec<&str> = parts
+                    .get(1)
+                    .map_or(Vec::new(), |rest| rest.split_whitespace().collect());
+
+                match args.as_slice() {
+                    [nickname] => Ok(Command::Verify {
+                        nickname: nickname.to_string(),
+                        confirm: false,
+                    }),
+                    [nickname, "confirm"] => Ok(Command::Verify {
+                        nickname: nickname.to_string(),
+                        confirm: true,
+                    }),
+                    _ => Err(usage()),
+                }
+            }
+            "history" => {
+                let usage = || CommandParseError::MissingArguments {
+                    command: "/history".to_string(),
+                    usage: "<nickname> [count]".to_string(),
+                };
+                let args: Vec<&str> = parts
+                    .get(1)
+                    .map_or(Vec::new(), |rest| rest.split_whitespace().collect());
+
+                match args.as_slice() {
+                    [nickname] => Ok(Command::History {
+                        nickname: nickname.to_string(),
+                        limit: DEFAULT_HISTORY_LIMIT,
+                    }),
+                    [nickname, count] => match count.parse() {
+                        Ok(limit) if limit > 0 => Ok(Command::History {
+                            nickname: nickname.to_string(),
+                            limit,
+                        }),
+                        _ => Err(usage()),
+                    },
+                    _ => Err(usage()),
+                }
+            }
+            "search" => Self::parse_search(parts.get(1).copied().unwrap_or("")),
+            "outbox" => {
+                let args: Vec<&str> = parts
+                    .get(1)
+                    .map_or(Vec::new(), |rest| rest.split_whitespace().collect());
+
+                match args.as_slice() {
+                    [] => Ok(Command::Outbox),
+                    ["cancel", id] => Ok(Command::OutboxCancel { id: id.to_string() }),
+                    _ => Err(CommandParseError::MissingArguments {
+                        command: "/outbox".to_string(),
+                        usage: "[cancel <id>]".to_string(),
+                    }),
+                }
+            }
+            "group" => {
+                let args: Vec<&str> = parts
+                    .get(1)
+                    .map_or(Vec::new(), |rest| rest.split_whitespace().collect());
+
+                match args.as_slice() {
+                    ["create", name] => Ok(Command::GroupCreate {
+                        name: name.to_string(),
+                    }),
+                    ["invite", group, nickname] => Ok(Command::GroupInvite {
+                        group: group.to_string(),
+                        nickname: nickname.to_string(),
+                    }),
+                    ["leave", group] => Ok(Command::GroupLeave {
+                        group: group.to_string(),
+                    }),
+                    ["list"] | [] => Ok(Command::GroupList),
+                    _ => Err(CommandParseError::MissingArguments {
+                        command: "/group".to_string(),
+                        usage: "create <name> | invite <group> <nickname> | leave <group> | list"
+                            .to_string(),
+                    }),
+                }
+            }
+            "gsend" => {
+                let usage = || CommandParseError::MissingArguments {
+                    command: "/gsend".to_string(),
+                    usage: "<group> <message>".to_string(),
+                };
+
+                let (group, content) = parts
+                    .get(1)
+                    .and_then(|rest| rest.split_once(' '))
+                    .ok_or_else(usage)?;
+                if group.is_empty() || content.trim().is_empty() {
+                    return Err(usage());
+                }
+
+                Ok(Command::GroupSend {
+                    group: group.to_string(),
+                    content: content.to_string(),
+                })
+            }
+            "sendfile" => {
+                let usage = || CommandParseError::MissingArguments {
+                    command: "/sendfile".to_string(),
+                    usage: "<nickname> <path>".to_string(),
+                };
+
+                let (to, path) = parts
+                    .get(1)
+                    .and_then(|rest| rest.split_once(' '))
+                    .ok_or_else(usage)?;
+                let path = path.trim();
+                if to.is_empty() || path.is_empty() {
+                    return Err(usage());
+                }
+
+                Ok(Command::SendFile {
+                    to: to.to_string(),
+                    path: path.to_string(),
+                })
+            }
+            "accept" => match parts.get(1).map(|s| s.trim()) {
+                Some(id) if !id.is_empty() && !id.contains(' ') => {
+                    Ok(Command::Accept { id: id.to_string() })
+                }
+                _ => Err(CommandParseError::MissingArguments {
+                    command: "/accept".to_string(),
+                    usage: "<id>".to_string(),
+                }),
+            },
+            "join" => Ok(Command::Join {
+                channel: Self::parse_channel(&parts, "/join")?,
+            }),
+            "leave" => Ok(Command::Leave {
+                channel: Self::parse_channel(&parts, "/leave")?,
+            }),
+            "who" => Ok(Command::Who {
+                channel: Self::parse_channel(&parts, "/who")?,
+            }),
             "quit" | "exit" | "q" => Ok(Command::Quit),
             "help" | "h" => Ok(Command::Help),
             unknown => Err(CommandParseError::UnknownCommand(unknown.to_string())),
         }
     }
 
+    /// Parse the single `<nickname>` argument of a command
+    fn parse_nickname(parts: &[&str], command: &str) -> Result<String, CommandParseError> {
+        match parts.get(1).map(|s| s.trim()) {
+            Some(nickname) if !nickname.is_empty() => Ok(nickname.to_string()),
+            _ => Err(CommandParseError::MissingArguments {
+                command: command.to_string(),
+                usage: "<nickname>".to_string(),
+            }),
+        }
+    }
+
+    /// Parse the single `<#channel>` argument of a command
+    fn parse_channel(parts: &[&str], command: &str) -> Result<String, CommandParseError> {
+        match parts.get(1).map(|s| s.trim()) {
+            Some(channel) if channel.starts_with('#') && !channel.contains(' ') => {
+                Ok(channel.to_string())
+            }
+            _ => Err(CommandParseError::MissingArguments {
+                command: command.to_string(),
+                usage: "<#channel>".to_string(),
+            }),
+        }
+    }
+
+    /// Parse the arguments of `/search`
+    fn parse_search(args: &str) -> Result<Self, CommandParseError> {
+        let usage = || CommandParseError::MissingArguments {
+            command: "/search".to_string(),
+            usage: "<query> [--from <nickname>] [--since YYYY-MM-DD] [--until YYYY-MM-DD]"
+                .to_string(),
+        };
+        let date = |value: Option<&str>| {
+            value
+                .and_then(|v| NaiveDate::parse_from_str(v, "%Y-%m-%d").ok())
+                .ok_or_else(usage)
+        };
+
+        let mut words = Vec::new();
+        let mut from = None;
+        let mut since = None;
+        let mut until = None;
+
+        let mut args = args.split_whitespace();
+        while let Some(arg) = args.next() {
+            match arg {
+                "--from" => from = Some(args.next().ok_or_else(usage)?.to_string()),
+                "--since" => since = Some(date(args.next())?),
+                "--until" => until = Some(date(args.next())?),
+                word => words.push(word),
+            }
+        }
+
+        if words.is_empty() {
+            return Err(usage());
+        }
+
+        Ok(Command::Search {
+            query: words.join(" "),
+            from,
+            since,
+            until,
+        })
+    }
+
     /// Get help text for a command
     pub fn help_text() -> &'static str {
         r#"Available commands:
   /send <nickname> <message>  Send a message to a peer
   /peers                      List discovered peers
+  /keys                       List pinned peer keys
+  /trust <nickname>           Trust a peer's key (approves key changes)
+  /untrust <nickname>         Stop trusting a peer's key
+  /verify <nickname>          Show the safety number to compare with a peer
+  /verify <nickname> confirm  Mark a peer as verified
+  /history <nickname> [n]     Show the last n messages with a peer (default 20)
+  /search <query> [--from <nickname>] [--since YYYY-MM-DD] [--until YYYY-MM-DD]
+                              Search the message history
+  /outbox                     List messages waiting for offline peers
+  /outbox cancel <id>         Cancel a waiting message
+  /group create <name>        Create a group
+  /group invite <group> <nickname>
+                              Add an online peer to a group
+  /group leave <group>        Leave a group
+  /group list                 List your groups and their members
+  /gsend <group> <message>    Send a message to every member of a group
+  /sendfile <nickname> <path> Offer a file to a peer
+  /accept <id>                Accept a file offer (again to resume it)
+  /join <#channel>            Join a channel on the local network
+  /leave <#channel>           Leave a channel
+  /who <#channel>             List the members of a channel
+  /send <#channel> <message>  Post a message to a channel you joined
+  /netinfo                    Show your public address and NAT type
   /quit                       Exit the application
   /help                       Show this help"#
     }
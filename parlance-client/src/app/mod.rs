@@ -5,34 +5,64 @@
 
 pub mod command;
 pub mod output;
+pub mod tui;
 
-use command::Command;
-use output::Output;
+use command::{Command, DEFAULT_SEARCH_LIMIT};
+use output::{Event, GroupRow, HistoryRow, KeyRow, OutboxRow, Output, PeerRow, SearchResultRow};
+use tui::{StatusInfo, Tui, TuiHandle};
 
 use crate::core::config::{Config, DiscoveryMode};
 use crate::core::error::Result;
-use crate::core::peer::PeerRegistry;
-use crate::network::bootstrap::BootstrapClient;
+use crate::core::group::Groups;
+use crate::core::history::{Direction, History, HistoryEntry};
+use crate::core::identity::{safety_number, Identity, PublicKey};
+use crate::core::known_peers::{KeyChangeWarning, KnownPeers, TrustStatus};
+use crate::core::peer::{PeerEvent, PeerRegistry};
+use crate::core::search::SearchQuery;
+use crate::core::vault::Vault;
+use crate::network::bootstrap::{BootstrapClient, BootstrapStatus, NetInfo};
+use crate::network::channels::{ChannelConfig, ChannelMessage, ChannelService};
+use crate::network::connection::{ConnectionManager, PoolConfig};
 use crate::network::discovery::{DiscoveryConfig, DiscoveryService};
-use crate::network::messaging::{MessageEvent, MessagingConfig, MessagingService};
+use crate::network::messaging::{
+    DeliveryConfig, GroupChange, MessageEvent, MessagingConfig, MessagingService,
+};
+use crate::network::outbox::Outbox;
+use crate::network::portmap::{PortMapConfig, PortMapper, PortMapping};
+use crate::network::transfer::Transfers;
+use chrono::{Local, NaiveDate, TimeZone};
+use std::io::{self, IsTerminal};
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::signal;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot, watch};
 use tracing::{error, info};
 
+/// How long shutdown waits for the router to remove our port mapping
+const PORT_MAPPING_REMOVAL_TIMEOUT: Duration = Duration::from_secs(3);
+
 /// Application configuration
 pub struct AppConfig {
     pub nickname: String,
+    pub identity: Identity,
     pub tcp_port: u16,
+    /// Key for the encrypted profile data, if the profile is encrypted
+    pub vault: Option<Vault>,
+    /// Read commands line by line instead of running the full-screen UI
+    pub plain: bool,
 }
 
 impl AppConfig {
-    /// Create a new config with a nickname and dynamic port
-    pub fn new(nickname: String) -> Self {
+    /// Create a new config with a nickname, identity and dynamic port
+    pub fn new(nickname: String, identity: Identity) -> Self {
         Self {
             nickname,
+            identity,
             tcp_port: 0,
+            vault: None,
+            plain: false,
         }
     }
 }
@@ -42,28 +72,97 @@ pub struct App {
     app_config: AppConfig,
     config: Config,
     registry: PeerRegistry,
+    known_peers: KnownPeers,
+    outbox: Outbox,
+    history: History,
+    groups: Groups,
+    transfers: Transfers,
+    warning_rx: Option<mpsc::UnboundedReceiver<KeyChangeWarning>>,
+    output: Arc<dyn Output>,
 }
 
 impl App {
     /// Create a new application instance
-    pub fn new(app_config: AppConfig, config: Config) -> Self {
-        Self {
+    ///
+    /// Loads the known-peers store, the groups, the outbox and the
+    /// conversation history from the nickname's profile directory,
+    /// decrypting the latter two with the vault if there is one. Everything
+    /// shown to the user goes to `output`.
+    pub fn new(app_config: AppConfig, config: Config, output: Arc<dyn Output>) -> Result<Self> {
+        let (warning_tx, warning_rx) = mpsc::unbounded_channel();
+        let known_peers =
+            KnownPeers::load(config.known_peers_path(&app_config.nickname), warning_tx)?;
+        let outbox = Outbox::load(
+            config.outbox_path(&app_config.nickname),
+            config.outbox_expiry(),
+            app_config.vault.clone(),
+        )?;
+        let history = History::load(
+            config.history_path(&app_config.nickname),
+            config.history_retention(),
+            app_config.vault.clone(),
+        )?;
+        let groups = Groups::load(config.groups_path(&app_config.nickname))?;
+        let transfers = Transfers::new(config.downloads_path(&app_config.nickname));
+
+        Ok(Self {
             app_config,
             config,
-            registry: PeerRegistry::new(),
-        }
+            registry: PeerRegistry::with_known_peers(known_peers.clone()),
+            known_peers,
+            outbox,
+            history,
+            groups,
+            transfers,
+            warning_rx: Some(warning_rx),
+            output,
+        })
     }
 
     /// Run the application
+    ///
+    /// Reads commands from the full-screen UI, or from stdin in plain mode.
     pub async fn run(self) -> Result<()> {
-        info!(nickname = %self.app_config.nickname, "Starting Parlance");
+        self.run_with(None).await
+    }
+
+    /// Run the application, reading commands from `lines` instead of the
+    /// terminal
+    ///
+    /// Returns after `/quit` or once `lines` is closed.
+    #[allow(dead_code)]
+    pub async fn run_with_input(self, lines: mpsc::UnboundedReceiver<String>) -> Result<()> {
+        self.run_with(Some(lines)).await
+    }
+
+    async fn run_with(mut self, lines: Option<mpsc::UnboundedReceiver<String>>) -> Result<()> {
+        let output = self.output.clone();
+
+        info!(
+            nickname = %self.app_config.nickname,
+            peer_id = %self.app_config.identity.peer_id(),
+            "Starting Parlance"
+        );
 
         let (event_tx, event_rx) = mpsc::unbounded_channel::<MessageEvent>();
 
         let messaging_config = MessagingConfig {
             nickname: self.app_config.nickname.clone(),
+            identity: self.app_config.identity.clone(),
             tcp_port: self.app_config.tcp_port,
             registry: self.registry.clone(),
+            pool: PoolConfig {
+                idle_timeout: self.config.idle_timeout(),
+                max_backoff: self.config.max_backoff(),
+                max_frame_size: self.config.max_frame_size(),
+                ..PoolConfig::default()
+            },
+            delivery: DeliveryConfig::default(),
+            outbox: Some(self.outbox.clone()),
+            history: Some(self.history.clone()),
+            groups: Some(self.groups.clone()),
+            transfers: Some(self.transfers.clone()),
+            transport: self.config.network.transport,
         };
 
         let messaging_service = MessagingService::new(messaging_config, event_tx.clone()).await?;
@@ -73,53 +172,127 @@ impl App {
 
         let discovery_config = DiscoveryConfig {
             nickname: self.app_config.nickname.clone(),
+            identity: self.app_config.identity.clone(),
             tcp_port: actual_tcp_port,
             registry: self.registry.clone(),
             announce_interval: self.config.announce_interval(),
             peer_timeout: self.config.peer_timeout(),
+            hello: messaging_service.hello(),
         };
 
-        let discovery_service = DiscoveryService::new(discovery_config).await?;
+        let discovery_service = Arc::new(DiscoveryService::new(discovery_config).await?);
+        let discovery_stats = discovery_service.stats();
 
-        let mode_str = match self.config.network.mode {
+        let mode = self.config.network.mode;
+        let mode_str = match mode {
             DiscoveryMode::Local => "Local network only",
             DiscoveryMode::Internet => "Internet only",
+            DiscoveryMode::Hybrid => "Local network and internet",
         };
         info!(mode = mode_str, "Discovery mode");
 
-        Output::welcome_banner(&self.app_config.nickname, actual_tcp_port);
+        // Internet and hybrid modes: ask the router to forward the messaging port
+        let port_mapper = (mode.uses_bootstrap() && self.config.network.port_mapping)
+            .then(|| PortMapper::new(PortMapConfig::default(), actual_tcp_port));
+        let port_mapping = port_mapper.as_ref().map(PortMapper::subscribe);
 
-        let (discovery_task, bootstrap_task) = match self.config.network.mode {
-            DiscoveryMode::Local => {
-                // Local mode: UDP multicast discovery
-                let task = tokio::spawn(async move {
-                    if let Err(e) = discovery_service.run().await {
-                        error!(error = ?e, "Discovery service error");
-                    }
-                });
-                (Some(task), None)
-            }
-            DiscoveryMode::Internet => {
-                // Internet mode: bootstrap server
-                let local_addr = format!("0.0.0.0:{}", actual_tcp_port)
-                    .parse()
-                    .expect("Valid socket address");
-
-                let mut bootstrap_client = BootstrapClient::new(
-                    self.config.network.bootstrap_server.clone(),
-                    self.app_config.nickname.clone(),
-                    local_addr,
-                    Arc::new(self.registry.clone()),
-                );
+        // Internet and hybrid modes: bootstrap server
+        let bootstrap_client = mode.uses_bootstrap().then(|| {
+            let local_addr = format!("0.0.0.0:{}", actual_tcp_port)
+                .parse()
+                .expect("Valid socket address");
 
-                let task = tokio::spawn(async move {
-                    if let Err(e) = bootstrap_client.run().await {
-                        error!(error = ?e, "Bootstrap client error");
-                    }
-                });
-                (None, Some(task))
+            let mut client = BootstrapClient::new(
+                self.config.network.bootstrap_server.clone(),
+                self.app_config.nickname.clone(),
+                self.app_config.identity.public_key(),
+                local_addr,
+                Arc::new(self.registry.clone()),
+            );
+            client.set_hello(messaging_service.hello());
+            if let Some(port_mapping) = &port_mapping {
+                client.set_port_mapping(port_mapping.clone());
             }
+            client
+        });
+
+        let port_mapping_task = port_mapper.map(|port_mapper| {
+            let (stop_tx, stop_rx) = oneshot::channel();
+            (stop_tx, tokio::spawn(port_mapper.run(stop_rx)))
+        });
+
+        // Peers we cannot dial over TCP are reached by hole punching
+        if let Some(bootstrap_client) = &bootstrap_client {
+            messaging_service
+                .connections()
+                .set_rendezvous(bootstrap_client.rendezvous());
+        }
+
+        // Local and hybrid modes: UDP multicast discovery
+        let discovery_task = mode.uses_multicast().then(|| {
+            let discovery_service = discovery_service.clone();
+            tokio::spawn(async move {
+                if let Err(e) = discovery_service.run().await {
+                    error!(error = ?e, "Discovery service error");
+                }
+            })
+        });
+
+        // Local and hybrid modes: LAN channels over their own multicast group
+        let (channel_tx, channel_rx) = mpsc::unbounded_channel::<ChannelMessage>();
+        let channel_service = if mode.uses_multicast() {
+            let channel_config = ChannelConfig {
+                nickname: self.app_config.nickname.clone(),
+                identity: self.app_config.identity.clone(),
+                registry: self.registry.clone(),
+                presence_interval: self.config.announce_interval(),
+                member_timeout: self.config.peer_timeout(),
+            };
+            Some(Arc::new(
+                ChannelService::new(channel_config, channel_tx).await?,
+            ))
+        } else {
+            None
         };
+        let channel_task = channel_service.clone().map(|channel_service| {
+            tokio::spawn(async move {
+                if let Err(e) = channel_service.run().await {
+                    error!(error = ?e, "Channel service error");
+                }
+            })
+        });
+
+        let (line_rx, screen) = match lines {
+            Some(lines) => (lines, None),
+            None => {
+                let (line_tx, line_rx) = mpsc::unbounded_channel::<String>();
+                let screen = self.start_screen(
+                    mode,
+                    actual_tcp_port,
+                    bootstrap_client.as_ref().map(BootstrapClient::status),
+                    messaging_service.connections().clone(),
+                    line_tx,
+                );
+                (line_rx, screen)
+            }
+        };
+
+        output.emit(Event::Welcome {
+            nickname: self.app_config.nickname.clone(),
+            tcp_port: actual_tcp_port,
+        });
+
+        let net_info = bootstrap_client.as_ref().map(BootstrapClient::net_info);
+
+        let bootstrap_task = bootstrap_client.map(|mut bootstrap_client| {
+            let output = output.clone();
+            tokio::spawn(async move {
+                if let Err(e) = bootstrap_client.run().await {
+                    error!(error = ?e, "Bootstrap client error");
+                    output.error(&format!("Bootstrap server unavailable: {}", e));
+                }
+            })
+        });
 
         let msg_service = Arc::new(messaging_service);
         let msg_service_for_task = msg_service.clone();
@@ -130,9 +303,31 @@ impl App {
             }
         });
 
-        let input_task = self.spawn_input_handler(msg_service.clone());
+        let input_task = self.spawn_input_handler(
+            line_rx,
+            msg_service.clone(),
+            channel_service.clone(),
+            actual_tcp_port,
+            net_info,
+            port_mapping,
+        );
+
+        let event_task =
+            Self::spawn_event_handler(event_rx, self.known_peers.clone(), output.clone());
 
-        let event_task = Self::spawn_event_handler(event_rx);
+        let channel_message_task = Self::spawn_channel_message_handler(
+            channel_rx,
+            self.known_peers.clone(),
+            output.clone(),
+        );
+
+        let warning_task = self
+            .warning_rx
+            .take()
+            .map(|warning_rx| Self::spawn_warning_handler(warning_rx, output.clone()));
+
+        let peer_event_task =
+            Self::spawn_peer_event_handler(self.registry.subscribe(), output.clone());
 
         tokio::select! {
             _ = signal::ctrl_c() => {
@@ -143,35 +338,119 @@ impl App {
             }
         }
 
-        Output::info("\nShutting down...");
+        if let Some(screen) = screen {
+            screen.stop().await;
+        }
+
+        output.info("\nShutting down...");
 
         if let Some(task) = discovery_task {
             task.abort();
+            if let Err(e) = discovery_service.send_goodbye().await {
+                error!(error = ?e, "Failed to send goodbye");
+            }
+        }
+        if let Some(task) = channel_task {
+            task.abort();
         }
         if let Some(task) = bootstrap_task {
             task.abort();
         }
+        if let Some((stop_tx, task)) = port_mapping_task {
+            // Give the router a moment to drop the mapping
+            let _ = stop_tx.send(());
+            let _ = tokio::time::timeout(PORT_MAPPING_REMOVAL_TIMEOUT, task).await;
+        }
         messaging_task.abort();
+        channel_message_task.abort();
         event_task.abort();
+        if let Some(task) = warning_task {
+            task.abort();
+        }
+        peer_event_task.abort();
+
+        let rejected = discovery_stats.snapshot();
+        if rejected.total() > 0 {
+            info!(
+                malformed = rejected.malformed,
+                unsigned = rejected.unsigned,
+                bad_signature = rejected.bad_signature,
+                stale = rejected.stale,
+                replayed = rejected.replayed,
+                incompatible = rejected.incompatible,
+                "Rejected discovery packets"
+            );
+        }
 
-        Output::info("Goodbye!");
+        output.info("Goodbye!");
 
         Ok(())
     }
 
+    /// Start the full-screen UI, or read lines from stdin in plain mode
+    ///
+    /// Submitted lines are sent to `line_tx` either way. Falls back to
+    /// plain mode if stdout is not a terminal or the UI fails to start.
+    fn start_screen(
+        &self,
+        mode: DiscoveryMode,
+        tcp_port: u16,
+        bootstrap: Option<watch::Receiver<BootstrapStatus>>,
+        connections: ConnectionManager,
+        line_tx: mpsc::UnboundedSender<String>,
+    ) -> Option<TuiHandle> {
+        if !self.app_config.plain && io::stdout().is_terminal() {
+            let status = StatusInfo {
+                nickname: self.app_config.nickname.clone(),
+                mode,
+                tcp_port,
+                bootstrap,
+            };
+            match Tui::start(
+                status,
+                self.registry.clone(),
+                connections,
+                self.output.clone(),
+                line_tx.clone(),
+            ) {
+                Ok(handle) => return Some(handle),
+                Err(e) => error!(error = %e, "Failed to start terminal UI, using plain mode"),
+            }
+        }
+
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(tokio::io::stdin()).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line_tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        None
+    }
+
     /// Spawn the input handler task
     fn spawn_input_handler(
         &self,
+        mut line_rx: mpsc::UnboundedReceiver<String>,
         msg_service: Arc<MessagingService>,
+        channels: Option<Arc<ChannelService>>,
+        tcp_port: u16,
+        net_info: Option<watch::Receiver<NetInfo>>,
+        port_mapping: Option<watch::Receiver<Option<PortMapping>>>,
     ) -> tokio::task::JoinHandle<()> {
         let registry = self.registry.clone();
+        let known_peers = self.known_peers.clone();
+        let own_key = self.app_config.identity.public_key();
+        let outbox = self.outbox.clone();
+        let history = self.history.clone();
+        let groups = self.groups.clone();
+        let nickname = self.app_config.nickname.clone();
+        let output = self.output.clone();
 
         tokio::spawn(async move {
-            let stdin = tokio::io::stdin();
-            let reader = BufReader::new(stdin);
-            let mut lines = reader.lines();
-
-            while let Ok(Some(line)) = lines.next_line().await {
+            let output = &*output;
+            while let Some(line) = line_rx.recv().await {
                 let line = line.trim();
 
                 if line.is_empty() {
@@ -181,28 +460,167 @@ impl App {
                 match Command::parse(line) {
                     Ok(Command::Send { to, content }) => {
                         match msg_service.send_message(&to, content).await {
-                            Ok(_) => {
-                                Output::success(&format!("Message sent to {}", to));
+                            Ok(id) => {
+                                output.info(&format!("Sending message {} to {}...", id, to));
                             }
                             Err(e) => {
-                                Output::error(&format!("Failed to send message: {}", e));
+                                output.error(&format!("Failed to send message: {}", e));
                             }
                         }
                     }
                     Ok(Command::Peers) => {
-                        Self::handle_peers_command(&registry).await;
+                        Self::handle_peers_command(
+                            output,
+                            &registry,
+                            &known_peers,
+                            msg_service.connections(),
+                        )
+                        .await;
+                    }
+                    Ok(Command::Trust { nickname }) => {
+                        Self::handle_trust_command(
+                            output,
+                            &known_peers,
+                            &registry,
+                            msg_service.connections(),
+                            &nickname,
+                        )
+                        .await;
+                    }
+                    Ok(Command::Untrust { nickname }) => {
+                        Self::handle_untrust_command(
+                            output,
+                            &known_peers,
+                            &registry,
+                            msg_service.connections(),
+                            &nickname,
+                        )
+                        .await;
+                    }
+                    Ok(Command::Keys) => {
+                        Self::handle_keys_command(output, &known_peers).await;
+                    }
+                    Ok(Command::Verify { nickname, confirm }) => {
+                        Self::handle_verify_command(
+                            output,
+                            &known_peers,
+                            &own_key,
+                            &nickname,
+                            confirm,
+                        )
+                        .await;
+                    }
+                    Ok(Command::History {
+                        nickname: peer,
+                        limit,
+                    }) => {
+                        Self::handle_history_command(output, &history, &nickname, &peer, limit)
+                            .await;
+                    }
+                    Ok(Command::Search {
+                        query,
+                        from,
+                        since,
+                        until,
+                    }) => {
+                        Self::handle_search_command(
+                            output, &history, &nickname, query, from, since, until,
+                        )
+                        .await;
+                    }
+                    Ok(Command::Outbox) => {
+                        Self::handle_outbox_command(output, &outbox).await;
+                    }
+                    Ok(Command::OutboxCancel { id }) => {
+                        Self::handle_outbox_cancel_command(output, &outbox, &id).await;
+                    }
+                    Ok(Command::GroupCreate { name }) => {
+                        match msg_service.create_group(&name).await {
+                            Ok(group) => output
+                                .success(&format!("Created group {} ({})", group.name, group.id)),
+                            Err(e) => output.error(&format!("Failed to create group: {}", e)),
+                        }
+                    }
+                    Ok(Command::GroupInvite {
+                        group,
+                        nickname: peer,
+                    }) => match msg_service.invite_to_group(&group, &peer).await {
+                        Ok(group) => {
+                            output.success(&format!("Invited {} to {}", peer, group.name));
+                        }
+                        Err(e) => output.error(&format!("Failed to invite {}: {}", peer, e)),
+                    },
+                    Ok(Command::GroupLeave { group }) => {
+                        match msg_service.leave_group(&group).await {
+                            Ok(group) => output.success(&format!("Left group {}", group.name)),
+                            Err(e) => output.error(&format!("Failed to leave group: {}", e)),
+                        }
+                    }
+                    Ok(Command::GroupList) => {
+                        Self::handle_group_list_command(output, &groups).await;
+                    }
+                    Ok(Command::GroupSend { group, content }) => {
+                        match msg_service.send_group_message(&group, content).await {
+                            Ok(id) => {
+                                output.info(&format!("Sending message {} to {}...", id, group));
+                            }
+                            Err(e) => {
+                                output.error(&format!("Failed to send message: {}", e));
+                            }
+                        }
+                    }
+                    Ok(Command::SendFile { to, path }) => {
+                        match msg_service.send_file(&to, Path::new(&path)).await {
+                            Ok(offer) => output.info(&format!(
+                                "Offered {} to {} (transfer {}), waiting for them to accept...",
+                                offer.name, to, offer.id
+                            )),
+                            Err(e) => output.error(&format!("Failed to send file: {}", e)),
+                        }
+                    }
+                    Ok(Command::Accept { id }) => match msg_service.accept_file(&id).await {
+                        Ok(offer) => {
+                            output.info(&format!("Receiving {} from {}...", offer.name, offer.from))
+                        }
+                        Err(e) => output.error(&format!("Failed to accept file: {}", e)),
+                    },
+                    Ok(Command::Join { channel }) => {
+                        Self::handle_join_command(output, channels.as_deref(), &channel).await;
+                    }
+                    Ok(Command::Leave { channel }) => {
+                        Self::handle_leave_command(output, channels.as_deref(), &channel).await;
+                    }
+                    Ok(Command::Who { channel }) => {
+                        Self::handle_who_command(output, channels.as_deref(), &channel);
+                    }
+                    Ok(Command::ChannelSend { channel, content }) => {
+                        Self::handle_channel_send_command(
+                            output,
+                            channels.as_deref(),
+                            &channel,
+                            content,
+                        )
+                        .await;
+                    }
+                    Ok(Command::NetInfo) => {
+                        Self::handle_net_info_command(
+                            output,
+                            tcp_port,
+                            net_info.as_ref(),
+                            port_mapping.as_ref(),
+                        );
                     }
                     Ok(Command::Quit) => {
                         info!("User requested quit");
                         break;
                     }
                     Ok(Command::Help) => {
-                        Output::info(&format!("\n{}", Command::help_text()));
+                        output.info(&format!("\n{}", Command::help_text()));
                     }
                     Err(e) => {
-                        Output::error(&e.to_string());
+                        output.error(&e.to_string());
                         if matches!(e, command::CommandParseError::UnknownCommand(_)) {
-                            Output::info("Type /help for available commands");
+                            output.info("Type /help for available commands");
                         }
                     }
                 }
@@ -213,35 +631,557 @@ impl App {
     }
 
     /// Handle the /peers command
-    async fn handle_peers_command(registry: &PeerRegistry) {
+    async fn handle_peers_command(
+        output: &dyn Output,
+        registry: &PeerRegistry,
+        known_peers: &KnownPeers,
+        connections: &ConnectionManager,
+    ) {
         let peers = registry.get_all().await;
-        let peer_list: Vec<(String, String)> = peers
+        let mut peer_list = Vec::with_capacity(peers.len());
+        for p in peers {
+            let verified = known_peers.is_verified(&p.nickname, &p.public_key).await;
+            let sources = p
+                .sources()
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut connection = connections.state(&p.public_key).to_string();
+            if connections.uses_quic(&p.public_key) {
+                connection.push_str(" over QUIC");
+            }
+            peer_list.push(PeerRow {
+                connection,
+                nickname: p.nickname,
+                addr: p.addr.to_string(),
+                sources,
+                verified,
+            });
+        }
+
+        output.emit(Event::PeerList { peers: peer_list });
+    }
+
+    /// Handle the /trust command
+    async fn handle_trust_command(
+        output: &dyn Output,
+        known_peers: &KnownPeers,
+        registry: &PeerRegistry,
+        connections: &ConnectionManager,
+        nickname: &str,
+    ) {
+        match known_peers.trust(nickname).await {
+            Ok(Some(old_key)) => {
+                // Drop any entry still registered under the old key
+                registry.remove_by_nickname(nickname).await;
+                connections.close(&old_key).await;
+                output.success(&format!(
+                    "Trusted new key for {} (replaced {})",
+                    nickname,
+                    old_key.fingerprint()
+                ));
+            }
+            Ok(None) => {
+                output.success(&format!("Trusted {}", nickname));
+            }
+            Err(e) => {
+                output.error(&format!("Failed to trust {}: {}", nickname, e));
+            }
+        }
+    }
+
+    /// Handle the /untrust command
+    async fn handle_untrust_command(
+        output: &dyn Output,
+        known_peers: &KnownPeers,
+        registry: &PeerRegistry,
+        connections: &ConnectionManager,
+        nickname: &str,
+    ) {
+        match known_peers.untrust(nickname).await {
+            Ok(()) => {
+                for peer in registry.remove_by_nickname(nickname).await {
+                    connections.close(&peer.public_key).await;
+                }
+                output.success(&format!("No longer trusting {}", nickname));
+            }
+            Err(e) => {
+                output.error(&format!("Failed to untrust {}: {}", nickname, e));
+            }
+        }
+    }
+
+    /// Handle the /keys command
+    async fn handle_keys_command(output: &dyn Output, known_peers: &KnownPeers) {
+        let keys: Vec<KeyRow> = known_peers
+            .list()
+            .await
+            .into_iter()
+            .map(|p| {
+                let mut status = match p.status {
+                    TrustStatus::Trusted => "trusted".to_string(),
+                    TrustStatus::Untrusted => "untrusted".to_string(),
+                };
+                if p.verified {
+                    status.push_str(", verified");
+                }
+                if let Some(pending) = p.pending_key {
+                    status.push_str(&format!(", key change pending: {}", pending.fingerprint()));
+                }
+                KeyRow {
+                    peer_id: p.peer_id().to_string(),
+                    nickname: p.nickname,
+                    fingerprint: p.public_key.fingerprint(),
+                    status,
+                }
+            })
+            .collect();
+
+        output.emit(Event::KnownKeys { keys });
+    }
+
+    /// Handle the /verify command
+    async fn handle_verify_command(
+        output: &dyn Output,
+        known_peers: &KnownPeers,
+        own_key: &PublicKey,
+        nickname: &str,
+        confirm: bool,
+    ) {
+        let Some(known) = known_peers.get(nickname).await else {
+            output.error(&format!("No key known for {}", nickname));
+            return;
+        };
+
+        if !confirm {
+            output.emit(Event::SafetyNumber {
+                peer: nickname.to_string(),
+                number: safety_number(own_key, &known.public_key),
+            });
+            return;
+        }
+
+        match known_peers.mark_verified(nickname).await {
+            Ok(()) => output.success(&format!("{} is now verified ✔", nickname)),
+            Err(e) => output.error(&format!("Failed to verify {}: {}", nickname, e)),
+        }
+    }
+
+    /// Handle the /history command
+    async fn handle_history_command(
+        output: &dyn Output,
+        history: &History,
+        own_nickname: &str,
+        peer: &str,
+        limit: usize,
+    ) {
+        let messages: Vec<HistoryRow> = history
+            .conversation(peer, limit)
+            .await
             .into_iter()
-            .map(|p| (p.nickname, p.addr.to_string()))
+            .map(|entry| Self::history_row(entry, own_nickname))
             .collect();
 
-        Output::peer_list(&peer_list);
+        output.emit(Event::History {
+            peer: peer.to_string(),
+            messages,
+        });
+    }
+
+    /// Handle the /search command
+    async fn handle_search_command(
+        output: &dyn Output,
+        history: &History,
+        own_nickname: &str,
+        text: String,
+        from: Option<String>,
+        since: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) {
+        let mut query = SearchQuery {
+            since: since.and_then(start_of_day),
+            until: until.and_then(|day| day.succ_opt()).and_then(start_of_day),
+            limit: DEFAULT_SEARCH_LIMIT,
+            ..SearchQuery::new(text.clone())
+        };
+        match from {
+            Some(from) if from == own_nickname => query.direction = Some(Direction::Sent),
+            Some(from) => {
+                query.peer = Some(from);
+                query.direction = Some(Direction::Received);
+            }
+            None => {}
+        }
+
+        let results: Vec<SearchResultRow> = history
+            .search(&query)
+            .await
+            .into_iter()
+            .map(|hit| SearchResultRow {
+                before: hit
+                    .before
+                    .into_iter()
+                    .map(|e| Self::history_row(e, own_nickname))
+                    .collect(),
+                hit: Self::history_row(hit.entry, own_nickname),
+                after: hit
+                    .after
+                    .into_iter()
+                    .map(|e| Self::history_row(e, own_nickname))
+                    .collect(),
+            })
+            .collect();
+
+        output.emit(Event::SearchResults {
+            query: text,
+            results,
+        });
+    }
+
+    /// Convert a history entry into a row for display
+    fn history_row(entry: HistoryEntry, own_nickname: &str) -> HistoryRow {
+        let outgoing = entry.direction == Direction::Sent;
+        HistoryRow {
+            time: chrono::DateTime::from_timestamp(entry.timestamp, 0)
+                .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| "?".to_string()),
+            outgoing,
+            from: if outgoing {
+                own_nickname.to_string()
+            } else {
+                entry.peer
+            },
+            content: entry.content,
+            state: entry.state.to_string(),
+        }
+    }
+
+    /// Handle the /outbox command
+    async fn handle_outbox_command(output: &dyn Output, outbox: &Outbox) {
+        let messages: Vec<OutboxRow> = outbox
+            .list()
+            .await
+            .into_iter()
+            .map(|entry| OutboxRow {
+                id: entry.id().to_string(),
+                queued_at: chrono::DateTime::from_timestamp(entry.queued_at, 0)
+                    .map(|dt| dt.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_else(|| "?".to_string()),
+                to: entry.to,
+                content: entry.message.content,
+            })
+            .collect();
+
+        output.emit(Event::Outbox { messages });
+    }
+
+    /// Handle the /outbox cancel command
+    async fn handle_outbox_cancel_command(output: &dyn Output, outbox: &Outbox, id: &str) {
+        match outbox.cancel(id).await {
+            Ok(Some(entry)) => {
+                output.success(&format!("Cancelled message {} to {}", entry.id(), entry.to));
+            }
+            Ok(None) => {
+                output.error(&format!("No single queued message matches {}", id));
+            }
+            Err(e) => {
+                output.error(&format!("Failed to cancel message: {}", e));
+            }
+        }
+    }
+
+    /// Handle the /group list command
+    async fn handle_group_list_command(output: &dyn Output, groups: &Groups) {
+        let rows: Vec<GroupRow> = groups
+            .list()
+            .await
+            .into_iter()
+            .map(|group| GroupRow {
+                id: group.id.to_string(),
+                name: group.name,
+                members: group.members.into_iter().map(|m| m.nickname).collect(),
+            })
+            .collect();
+
+        output.emit(Event::GroupList { groups: rows });
+    }
+
+    /// Handle the /netinfo command
+    fn handle_net_info_command(
+        output: &dyn Output,
+        tcp_port: u16,
+        net_info: Option<&watch::Receiver<NetInfo>>,
+        port_mapping: Option<&watch::Receiver<Option<PortMapping>>>,
+    ) {
+        let Some(net_info) = net_info else {
+            output.error("Network info needs internet or hybrid discovery mode");
+            return;
+        };
+        let info = net_info.borrow().clone();
+        output.emit(Event::NetInfo {
+            tcp_port,
+            public_addr: info.public_addr,
+            mapped_addr: info.nat.map(|nat| nat.mapped_addr.to_string()),
+            nat_type: info.nat.map(|nat| nat.nat_type.to_string()),
+            port_mapping: port_mapping
+                .and_then(|mapping| *mapping.borrow())
+                .map(|mapping| format!("{} ({})", mapping.external_addr, mapping.protocol)),
+        });
+    }
+
+    /// Get the channel service, or report that channels are unavailable
+    fn channel_service<'a>(
+        output: &dyn Output,
+        channels: Option<&'a ChannelService>,
+    ) -> Option<&'a ChannelService> {
+        if channels.is_none() {
+            output.error("Channels need local or hybrid discovery mode");
+        }
+        channels
+    }
+
+    /// Handle the /join command
+    async fn handle_join_command(
+        output: &dyn Output,
+        channels: Option<&ChannelService>,
+        channel: &str,
+    ) {
+        let Some(channels) = Self::channel_service(output, channels) else {
+            return;
+        };
+        match channels.join(channel).await {
+            Ok(true) => output.success(&format!("Joined {}", channel)),
+            Ok(false) => output.info(&format!("Already in {}", channel)),
+            Err(e) => output.error(&format!("Failed to join {}: {}", channel, e)),
+        }
+    }
+
+    /// Handle the /leave command
+    async fn handle_leave_command(
+        output: &dyn Output,
+        channels: Option<&ChannelService>,
+        channel: &str,
+    ) {
+        let Some(channels) = Self::channel_service(output, channels) else {
+            return;
+        };
+        match channels.leave(channel).await {
+            Ok(true) => output.success(&format!("Left {}", channel)),
+            Ok(false) => output.info(&format!("Not in {}", channel)),
+            Err(e) => output.error(&format!("Failed to leave {}: {}", channel, e)),
+        }
+    }
+
+    /// Handle the /who command
+    fn handle_who_command(output: &dyn Output, channels: Option<&ChannelService>, channel: &str) {
+        let Some(channels) = Self::channel_service(output, channels) else {
+            return;
+        };
+        match channels.members(channel) {
+            Ok(members) => output.emit(Event::ChannelMembers {
+                channel: channel.to_string(),
+                members,
+            }),
+            Err(e) => output.error(&e.to_string()),
+        }
+    }
+
+    /// Handle /send to a channel
+    async fn handle_channel_send_command(
+        output: &dyn Output,
+        channels: Option<&ChannelService>,
+        channel: &str,
+        content: String,
+    ) {
+        let Some(channels) = Self::channel_service(output, channels) else {
+            return;
+        };
+        if let Err(e) = channels.post(channel, content).await {
+            output.error(&format!("Failed to post to {}: {}", channel, e));
+        }
+    }
+
+    /// Spawn the channel message handler task
+    fn spawn_channel_message_handler(
+        mut message_rx: mpsc::UnboundedReceiver<ChannelMessage>,
+        known_peers: KnownPeers,
+        output: Arc<dyn Output>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Some(message) = message_rx.recv().await {
+                let verified = known_peers
+                    .is_verified(&message.from, &message.public_key)
+                    .await;
+                output.emit(Event::ChannelMessage { message, verified });
+            }
+        })
+    }
+
+    /// Spawn the key change warning handler task
+    fn spawn_warning_handler(
+        mut warning_rx: mpsc::UnboundedReceiver<KeyChangeWarning>,
+        output: Arc<dyn Output>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Some(warning) = warning_rx.recv().await {
+                output.emit(Event::KeyChanged {
+                    nickname: warning.nickname,
+                    pinned: warning.pinned.fingerprint(),
+                    presented: warning.presented.fingerprint(),
+                    known_as: warning.known_as,
+                });
+            }
+        })
+    }
+
+    /// Spawn the peer event handler task
+    fn spawn_peer_event_handler(
+        mut peer_rx: mpsc::UnboundedReceiver<PeerEvent>,
+        output: Arc<dyn Output>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Some(event) = peer_rx.recv().await {
+                output.emit(match event {
+                    PeerEvent::PeerJoined(peer) => Event::PeerJoined {
+                        nickname: peer.nickname,
+                        addr: peer.addr.to_string(),
+                    },
+                    PeerEvent::PeerLeft(peer) => Event::PeerLeft {
+                        nickname: peer.nickname,
+                    },
+                    PeerEvent::PeerTimedOut(peer) => Event::PeerTimedOut {
+                        nickname: peer.nickname,
+                    },
+                    PeerEvent::PeerAddressChanged { peer, old_addr } => Event::PeerAddressChanged {
+                        nickname: peer.nickname,
+                        old_addr: old_addr.to_string(),
+                        new_addr: peer.addr.to_string(),
+                    },
+                });
+            }
+        })
     }
 
     /// Spawn the event handler task
     fn spawn_event_handler(
         mut event_rx: mpsc::UnboundedReceiver<MessageEvent>,
+        known_peers: KnownPeers,
+        output: Arc<dyn Output>,
     ) -> tokio::task::JoinHandle<()> {
         tokio::spawn(async move {
             while let Some(event) = event_rx.recv().await {
-                match event {
-                    MessageEvent::Received(msg) => {
-                        Output::message_received(&msg.format());
+                let event = match event {
+                    MessageEvent::Received { msg, relayed } => {
+                        let verified = known_peers.is_verified(&msg.from, &msg.public_key).await;
+                        Event::Message {
+                            message: msg,
+                            group: None,
+                            verified,
+                            relayed,
+                        }
                     }
-                    MessageEvent::Sent { to, content: _ } => {
-                        tracing::debug!(to = %to, "Message sent event");
+                    MessageEvent::GroupReceived {
+                        group,
+                        msg,
+                        relayed,
+                    } => {
+                        let verified = known_peers.is_verified(&msg.from, &msg.public_key).await;
+                        Event::Message {
+                            message: msg,
+                            group: Some(group),
+                            verified,
+                            relayed,
+                        }
                     }
-                    MessageEvent::SendError { to, error } => {
-                        Output::error(&format!("Error sending to {}: {}", to, error));
-                        Output::prompt("> ");
+                    MessageEvent::GroupChanged { group, change } => {
+                        let notice = match change {
+                            GroupChange::Invited { by } => format!("{} added you to the group", by),
+                            GroupChange::MemberJoined(nickname) => format!("{} joined", nickname),
+                            GroupChange::MemberLeft(nickname) => format!("{} left", nickname),
+                        };
+                        Event::GroupNotice { group, notice }
                     }
-                }
+                    MessageEvent::FileOffered(offer) => Event::FileOffer {
+                        id: offer.id.to_string(),
+                        from: offer.from,
+                        name: offer.name,
+                        size: offer.size,
+                    },
+                    MessageEvent::TransferProgress {
+                        peer,
+                        id,
+                        name,
+                        outgoing,
+                        transferred,
+                        size,
+                    } => Event::TransferProgress {
+                        id: id.to_string(),
+                        name,
+                        peer,
+                        outgoing,
+                        transferred,
+                        size,
+                    },
+                    MessageEvent::TransferInterrupted {
+                        peer,
+                        id,
+                        name,
+                        error,
+                    } => Event::TransferInterrupted {
+                        id: id.to_string(),
+                        name,
+                        peer,
+                        error,
+                    },
+                    MessageEvent::TransferComplete {
+                        peer,
+                        id,
+                        name,
+                        path,
+                    } => Event::TransferComplete {
+                        id: id.to_string(),
+                        name,
+                        peer,
+                        path,
+                    },
+                    MessageEvent::TransferFailed {
+                        peer,
+                        id,
+                        name,
+                        error,
+                    } => Event::TransferFailed {
+                        id: id.to_string(),
+                        name,
+                        peer,
+                        error,
+                    },
+                    MessageEvent::Queued { to, id } => Event::MessageQueued {
+                        to,
+                        id: id.to_string(),
+                    },
+                    MessageEvent::Sent { to, id } => {
+                        tracing::debug!(to = %to, id = %id, "Message sent event");
+                        continue;
+                    }
+                    MessageEvent::Delivered { to, id } => Event::MessageDelivered {
+                        to,
+                        id: id.to_string(),
+                    },
+                    MessageEvent::Failed { to, id, error } => Event::MessageFailed {
+                        to,
+                        id: id.to_string(),
+                        error,
+                    },
+                };
+                output.emit(event);
             }
         })
     }
 }
+
+/// Unix timestamp of local midnight at the start of `day`
+fn start_of_day(day: NaiveDate) -> Option<i64> {
+    let midnight = day.and_hms_opt(0, 0, 0)?;
+    Some(Local.from_local_datetime(&midnight).earliest()?.timestamp())
+}
@@ -1,75 +1,945 @@
 //! Output abstraction for user-facing messages.
 //!
-//! Centralizes all user output to make it easier to test and potentially
-//! redirect output (e.g., to a GUI or different terminal).
+//! Everything the application shows the user is an [`Event`], handed to an
+//! [`Output`] injected into the [`App`](super::App). Three outputs ship with
+//! Parlance:
+//!
+//! - [`TerminalOutput`] renders events as text on stdout, or on the
+//!   full-screen UI once a screen is attached
+//! - [`JsonOutput`] writes one JSON object per event, for driving Parlance
+//!   from another program (`--output json`)
+//! - [`MemoryOutput`] keeps the events, for tests
 
+use crate::network::channels::ChannelMessage;
+use crate::network::messaging::TextMessage;
+use serde::Serialize;
 use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::mpsc;
 
-/// Output interface for user messages
-pub struct Output;
+/// Kind of a line shown on the screen, used to pick its color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    /// Messages, listings and command output
+    Normal,
+    /// Peer and group notices
+    Notice,
+    /// Successful outcomes
+    Success,
+    /// Warnings
+    Warning,
+    /// Errors
+    Error,
+}
 
-impl Output {
-    /// Print a regular informational message
-    pub fn info(message: &str) {
-        println!("{}", message);
+/// A line of output sent to an attached screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScreenLine {
+    pub tone: Tone,
+    pub text: String,
+}
+
+/// How events are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable text
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "Invalid output format '{}'. Valid options: text, json",
+                s
+            )),
+        }
     }
+}
 
-    /// Print a success message
-    pub fn success(message: &str) {
-        println!("{}", message);
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+        }
     }
+}
+
+/// A row of the `/peers` listing
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerRow {
+    pub nickname: String,
+    pub addr: String,
+    /// Comma-separated discovery sources
+    pub sources: String,
+    /// Connection state
+    pub connection: String,
+    /// Whether the peer's safety number was verified
+    pub verified: bool,
+}
+
+/// A row of the `/keys` listing
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyRow {
+    pub nickname: String,
+    /// Peer ID derived from the pinned key
+    pub peer_id: String,
+    pub fingerprint: String,
+    /// Trust status, verification and pending key changes
+    pub status: String,
+}
+
+/// A row of the `/history` listing
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryRow {
+    /// Formatted time the message was sent
+    pub time: String,
+    /// Whether we sent the message
+    pub outgoing: bool,
+    /// Sender nickname
+    pub from: String,
+    pub content: String,
+    /// Delivery state of sent messages
+    pub state: String,
+}
+
+/// A `/search` result with the messages around it
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResultRow {
+    pub before: Vec<HistoryRow>,
+    pub hit: HistoryRow,
+    pub after: Vec<HistoryRow>,
+}
+
+/// A row of the `/outbox` listing
+#[derive(Debug, Clone, Serialize)]
+pub struct OutboxRow {
+    pub id: String,
+    pub to: String,
+    /// When the message was queued
+    pub queued_at: String,
+    pub content: String,
+}
+
+/// A row of the `/group list` listing
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupRow {
+    pub id: String,
+    pub name: String,
+    /// Member nicknames
+    pub members: Vec<String>,
+}
 
-    /// Print an error message
-    pub fn error(message: &str) {
-        eprintln!("{}", message);
+/// Something shown to the user
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Parlance started
+    Welcome { nickname: String, tcp_port: u16 },
+    /// A regular informational message
+    Info { message: String },
+    /// A command succeeded
+    Success { message: String },
+    /// A command failed or something went wrong
+    Error { message: String },
+    /// A direct or group message from a peer
+    Message {
+        message: TextMessage,
+        /// Name of the group it was sent to
+        #[serde(skip_serializing_if = "Option::is_none")]
+        group: Option<String>,
+        /// Whether the sender's safety number was verified
+        verified: bool,
+        /// Whether it came through the bootstrap server's relay
+        relayed: bool,
+    },
+    /// A message posted to a channel
+    ChannelMessage {
+        message: ChannelMessage,
+        /// Whether the sender's safety number was verified
+        verified: bool,
+    },
+    /// A message could not be delivered yet and waits in the outbox
+    MessageQueued { to: String, id: String },
+    /// A peer acknowledged a message
+    MessageDelivered { to: String, id: String },
+    /// A message could not be delivered
+    MessageFailed {
+        to: String,
+        id: String,
+        error: String,
+    },
+    /// A peer was discovered
+    PeerJoined { nickname: String, addr: String },
+    /// A peer said goodbye
+    PeerLeft { nickname: String },
+    /// A peer stopped responding
+    PeerTimedOut { nickname: String },
+    /// A peer moved to a different address
+    PeerAddressChanged {
+        nickname: String,
+        old_addr: String,
+        new_addr: String,
+    },
+    /// A peer presented a different identity key than the pinned one
+    KeyChanged {
+        nickname: String,
+        pinned: String,
+        presented: String,
+        /// Nickname the presented key is already pinned to
+        known_as: Option<String>,
+    },
+    /// A group's membership changed
+    GroupNotice { group: String, notice: String },
+    /// A peer offered a file
+    FileOffer {
+        id: String,
+        from: String,
+        name: String,
+        size: u64,
+    },
+    /// A file transfer made progress
+    TransferProgress {
+        id: String,
+        name: String,
+        peer: String,
+        outgoing: bool,
+        transferred: u64,
+        size: u64,
+    },
+    /// A file transfer stopped and resumes when the peer reconnects
+    TransferInterrupted {
+        id: String,
+        name: String,
+        peer: String,
+        error: String,
+    },
+    /// A file transfer finished
+    TransferComplete {
+        id: String,
+        name: String,
+        peer: String,
+        /// Where a received file was saved
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<PathBuf>,
+    },
+    /// A file transfer failed for good
+    TransferFailed {
+        id: String,
+        name: String,
+        peer: String,
+        error: String,
+    },
+    /// The `/peers` listing
+    PeerList { peers: Vec<PeerRow> },
+    /// The `/keys` listing
+    KnownKeys { keys: Vec<KeyRow> },
+    /// The `/history` listing
+    History {
+        peer: String,
+        messages: Vec<HistoryRow>,
+    },
+    /// The `/search` results
+    SearchResults {
+        query: String,
+        results: Vec<SearchResultRow>,
+    },
+    /// The `/outbox` listing
+    Outbox { messages: Vec<OutboxRow> },
+    /// The `/group list` listing
+    GroupList { groups: Vec<GroupRow> },
+    /// The `/who` listing
+    ChannelMembers {
+        channel: String,
+        members: Vec<String>,
+    },
+    /// The safety number shared with a peer
+    SafetyNumber { peer: String, number: String },
+    /// The `/netinfo` report
+    NetInfo {
+        tcp_port: u16,
+        /// Address the bootstrap server lists us at
+        #[serde(skip_serializing_if = "Option::is_none")]
+        public_addr: Option<String>,
+        /// Address our NAT mapped the probing socket to
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mapped_addr: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        nat_type: Option<String>,
+        /// Port the router forwards to us, and over which protocol
+        #[serde(skip_serializing_if = "Option::is_none")]
+        port_mapping: Option<String>,
+    },
+}
+
+impl Event {
+    /// Check whether the event arrives on its own rather than in reply to
+    /// a command, possibly while the user is typing
+    pub fn is_notice(&self) -> bool {
+        matches!(
+            self,
+            Event::Message { .. }
+                | Event::ChannelMessage { .. }
+                | Event::MessageQueued { .. }
+                | Event::MessageDelivered { .. }
+                | Event::MessageFailed { .. }
+                | Event::PeerJoined { .. }
+                | Event::PeerLeft { .. }
+                | Event::PeerTimedOut { .. }
+                | Event::PeerAddressChanged { .. }
+                | Event::KeyChanged { .. }
+                | Event::GroupNotice { .. }
+                | Event::FileOffer { .. }
+                | Event::TransferProgress { .. }
+                | Event::TransferInterrupted { .. }
+                | Event::TransferComplete { .. }
+                | Event::TransferFailed { .. }
+        )
     }
+}
+
+/// Where user-facing events go
+pub trait Output: Send + Sync {
+    /// Show an event
+    fn emit(&self, event: Event);
 
-    #[allow(dead_code)]
-    /// Print a warning message
-    pub fn warning(message: &str) {
-        println!("{}", message);
+    /// Send rendered lines to the full-screen UI instead
+    ///
+    /// Returns false for outputs that cannot be shown on a screen.
+    fn attach_screen(&self, _screen: mpsc::UnboundedSender<ScreenLine>) -> bool {
+        false
     }
 
-    /// Print without a newline and flush
-    pub fn prompt(message: &str) {
-        print!("{}", message);
-        let _ = io::stdout().flush();
+    /// Stop sending lines to the full-screen UI
+    fn detach_screen(&self) {}
+
+    /// Show a regular informational message
+    fn info(&self, message: &str) {
+        self.emit(Event::Info {
+            message: message.to_string(),
+        });
     }
 
-    /// Print a message received from a peer
-    pub fn message_received(formatted: &str) {
-        println!("\n{}", formatted);
-        Self::prompt("> ");
+    /// Show a success message
+    fn success(&self, message: &str) {
+        self.emit(Event::Success {
+            message: message.to_string(),
+        });
     }
 
-    /// Print the welcome banner
-    pub fn welcome_banner(nickname: &str, tcp_port: u16) {
-        println!("\n╔═══════════════════════════════════════╗");
-        println!("║         Parlance Started!             ║");
-        println!("╚═══════════════════════════════════════╝");
-        println!();
-        println!("Nickname: {}", nickname);
-        println!("TCP Port: {}", tcp_port);
-        println!();
-        println!("Type /help for available commands");
-        println!();
-        println!("Waiting for peers...");
-        println!();
+    /// Show an error message
+    fn error(&self, message: &str) {
+        self.emit(Event::Error {
+            message: message.to_string(),
+        });
     }
+}
 
-    /// Print the peer list
-    pub fn peer_list(peers: &[(String, String)]) {
-        println!("\n╔═══════════════════════════════════════╗");
-        println!("║     Discovered Peers ({:2})             ║", peers.len());
-        println!("╚═══════════════════════════════════════╝");
+/// Renders events as text on the terminal
+#[derive(Default)]
+pub struct TerminalOutput {
+    screen: Mutex<Option<mpsc::UnboundedSender<ScreenLine>>>,
+}
+
+impl TerminalOutput {
+    /// Create an output printing to stdout
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        if peers.is_empty() {
-            println!("  No peers found yet...");
+    /// Render an event as text
+    ///
+    /// The text may span several lines.
+    pub fn render(event: &Event) -> ScreenLine {
+        let (tone, text) = match event {
+            Event::Welcome { nickname, tcp_port } => {
+                (Tone::Normal, render_welcome(nickname, *tcp_port))
+            }
+            Event::Info { message } => (Tone::Normal, message.clone()),
+            Event::Success { message } => (Tone::Success, message.clone()),
+            Event::Error { message } => (Tone::Error, message.clone()),
+            Event::Message {
+                message,
+                group,
+                verified,
+                relayed,
+            } => (
+                Tone::Normal,
+                match group {
+                    Some(group) => message.format_in_group(group, *verified, *relayed),
+                    None => message.format(*verified, *relayed),
+                },
+            ),
+            Event::ChannelMessage { message, verified } => {
+                (Tone::Normal, message.format(*verified))
+            }
+            Event::MessageQueued { to, id } => (
+                Tone::Normal,
+                format!("{} is offline, message {} queued in the outbox", to, id),
+            ),
+            Event::MessageDelivered { to, id } => {
+                (Tone::Success, format!("Message {} delivered to {}", id, to))
+            }
+            Event::MessageFailed { to, id, error } => (
+                Tone::Error,
+                format!("Message {} to {} failed: {}", id, to, error),
+            ),
+            Event::PeerJoined { nickname, addr } => {
+                (Tone::Notice, format!("* {} joined ({})", nickname, addr))
+            }
+            Event::PeerLeft { nickname } => (Tone::Notice, format!("* {} left", nickname)),
+            Event::PeerTimedOut { nickname } => (Tone::Notice, format!("* {} timed out", nickname)),
+            Event::PeerAddressChanged {
+                nickname,
+                old_addr,
+                new_addr,
+            } => (
+                Tone::Notice,
+                format!("* {} moved from {} to {}", nickname, old_addr, new_addr),
+            ),
+            Event::KeyChanged {
+                nickname,
+                pinned,
+                presented,
+                known_as,
+            } => (
+                Tone::Warning,
+                render_key_change(nickname, pinned, presented, known_as.as_deref()),
+            ),
+            Event::GroupNotice { group, notice } => {
+                (Tone::Notice, format!("* [{}] {}", group, notice))
+            }
+            Event::FileOffer {
+                id,
+                from,
+                name,
+                size,
+            } => (
+                Tone::Notice,
+                format!(
+                    "* {} offers {} ({}). Type /accept {} to receive it",
+                    from,
+                    name,
+                    format_size(*size),
+                    id
+                ),
+            ),
+            Event::TransferProgress {
+                id,
+                name,
+                peer,
+                outgoing,
+                transferred,
+                size,
+            } => {
+                let direction = if *outgoing { "to" } else { "from" };
+                let percent = (transferred.saturating_mul(100) / (*size).max(1)).min(100);
+                (
+                    Tone::Notice,
+                    format!(
+                        "* [{}] {} {} {}: {}% ({} of {})",
+                        id,
+                        name,
+                        direction,
+                        peer,
+                        percent,
+                        format_size(*transferred),
+                        format_size(*size)
+                    ),
+                )
+            }
+            Event::TransferInterrupted {
+                id,
+                name,
+                peer,
+                error,
+            } => (
+                Tone::Warning,
+                format!(
+                    "Transfer {} of {} to {} interrupted ({}); it resumes when {} reconnects",
+                    id, name, peer, error, peer
+                ),
+            ),
+            Event::TransferComplete {
+                id,
+                name,
+                peer,
+                path,
+            } => (
+                Tone::Success,
+                match path {
+                    Some(path) => format!(
+                        "Transfer {}: received {} from {}, saved to {}",
+                        id,
+                        name,
+                        peer,
+                        path.display()
+                    ),
+                    None => format!("Transfer {}: {} delivered to {}", id, name, peer),
+                },
+            ),
+            Event::TransferFailed {
+                id,
+                name,
+                peer,
+                error,
+            } => (
+                Tone::Error,
+                format!(
+                    "Transfer {} of {} with {} failed: {}",
+                    id, name, peer, error
+                ),
+            ),
+            Event::PeerList { peers } => (Tone::Normal, render_peer_list(peers)),
+            Event::KnownKeys { keys } => (Tone::Normal, render_known_keys(keys)),
+            Event::History { peer, messages } => (Tone::Normal, render_history(peer, messages)),
+            Event::SearchResults { query, results } => {
+                (Tone::Normal, render_search_results(query, results))
+            }
+            Event::Outbox { messages } => (Tone::Normal, render_outbox(messages)),
+            Event::GroupList { groups } => (Tone::Normal, render_group_list(groups)),
+            Event::ChannelMembers { channel, members } => {
+                (Tone::Normal, render_channel_members(channel, members))
+            }
+            Event::SafetyNumber { peer, number } => {
+                (Tone::Normal, render_safety_number(peer, number))
+            }
+            Event::NetInfo {
+                tcp_port,
+                public_addr,
+                mapped_addr,
+                nat_type,
+                port_mapping,
+            } => (
+                Tone::Normal,
+                render_net_info(
+                    *tcp_port,
+                    public_addr.as_deref(),
+                    mapped_addr.as_deref(),
+                    nat_type.as_deref(),
+                    port_mapping.as_deref(),
+                ),
+            ),
+        };
+        ScreenLine { tone, text }
+    }
+
+    fn lock_screen(&self) -> MutexGuard<'_, Option<mpsc::UnboundedSender<ScreenLine>>> {
+        self.screen.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Send a line to the attached screen, one entry per line of text
+    ///
+    /// Returns false if no screen is attached or it is gone.
+    fn send_to_screen(&self, line: &ScreenLine) -> bool {
+        let mut screen = self.lock_screen();
+        let Some(tx) = screen.as_ref() else {
+            return false;
+        };
+        let sent = line.text.split('\n').all(|text| {
+            tx.send(ScreenLine {
+                tone: line.tone,
+                text: text.to_string(),
+            })
+            .is_ok()
+        });
+        if !sent {
+            // The screen is gone; fall back to the terminal
+            *screen = None;
+        }
+        sent
+    }
+}
+
+impl Output for TerminalOutput {
+    fn emit(&self, event: Event) {
+        let line = Self::render(&event);
+        if self.send_to_screen(&line) {
+            return;
+        }
+
+        if event.is_notice() {
+            // Set off from whatever the user is typing, then prompt again
+            print!("\n{}\n> ", line.text);
+            let _ = io::stdout().flush();
+        } else if line.tone == Tone::Error {
+            eprintln!("{}", line.text);
         } else {
-            for (nickname, addr) in peers {
-                println!("  • {} ({})", nickname, addr);
+            println!("{}", line.text);
+        }
+    }
+
+    fn attach_screen(&self, screen: mpsc::UnboundedSender<ScreenLine>) -> bool {
+        *self.lock_screen() = Some(screen);
+        true
+    }
+
+    fn detach_screen(&self) {
+        *self.lock_screen() = None;
+    }
+}
+
+/// Writes every event as a line of JSON
+pub struct JsonOutput {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonOutput {
+    /// Create an output writing to `writer`
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Create an output writing to stdout
+    pub fn stdout() -> Self {
+        Self::new(Box::new(io::stdout()))
+    }
+}
+
+impl Output for JsonOutput {
+    fn emit(&self, event: Event) {
+        let line = match serde_json::to_string(&event) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to serialize output event");
+                return;
             }
+        };
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            tracing::warn!(error = %e, "Failed to write output event");
         }
-        println!();
     }
 }
+
+/// Keeps every event in memory
+#[allow(dead_code)]
+#[derive(Clone, Default)]
+pub struct MemoryOutput {
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+#[allow(dead_code)]
+impl MemoryOutput {
+    /// Create an empty output
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the events emitted so far
+    pub fn events(&self) -> Vec<Event> {
+        self.lock().clone()
+    }
+
+    /// Take the events emitted so far, leaving none
+    pub fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Event>> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Output for MemoryOutput {
+    fn emit(&self, event: Event) {
+        self.lock().push(event);
+    }
+}
+
+/// Format a byte count for display, e.g. `1.5 MiB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Render a boxed title, preceded by a blank line
+fn title(lines: &mut Vec<String>, title: String) {
+    lines.push(String::new());
+    lines.push("╔═══════════════════════════════════════╗".to_string());
+    lines.push(title);
+    lines.push("╚═══════════════════════════════════════╝".to_string());
+}
+
+fn render_welcome(nickname: &str, tcp_port: u16) -> String {
+    let mut lines = Vec::new();
+    title(
+        &mut lines,
+        "║         Parlance Started!             ║".to_string(),
+    );
+    lines.push(String::new());
+    lines.push(format!("Nickname: {}", nickname));
+    lines.push(format!("TCP Port: {}", tcp_port));
+    lines.push(String::new());
+    lines.push("Type /help for available commands".to_string());
+    lines.push(String::new());
+    lines.push("Waiting for peers...".to_string());
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_peer_list(peers: &[PeerRow]) -> String {
+    let mut lines = Vec::new();
+    title(
+        &mut lines,
+        format!("║     Discovered Peers ({:2})             ║", peers.len()),
+    );
+
+    if peers.is_empty() {
+        lines.push("  No peers found yet...".to_string());
+    }
+    for peer in peers {
+        let sources = if peer.sources.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", peer.sources)
+        };
+        let badge = if peer.verified { " ✔ verified" } else { "" };
+        lines.push(format!(
+            "  • {} ({}){} - {}{}",
+            peer.nickname, peer.addr, sources, peer.connection, badge
+        ));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_key_change(
+    nickname: &str,
+    pinned: &str,
+    presented: &str,
+    known_as: Option<&str>,
+) -> String {
+    let mut lines = vec![
+        "╔═══════════════════════════════════════╗".to_string(),
+        "║   WARNING: IDENTITY KEY CHANGED!      ║".to_string(),
+        "╚═══════════════════════════════════════╝".to_string(),
+    ];
+    lines.push(format!(
+        "  '{}' is using a different key than before.",
+        nickname
+    ));
+    lines.push(format!("  Pinned key:    {}", pinned));
+    lines.push(format!("  Presented key: {}", presented));
+    match known_as {
+        Some(other) => lines.push(format!(
+            "  The presented key belongs to '{}', who is using this nickname too.",
+            other
+        )),
+        None => lines.push("  Someone may be impersonating this peer.".to_string()),
+    }
+    lines.push(format!(
+        "  Messages are blocked until you run: /trust {}",
+        nickname
+    ));
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_known_keys(keys: &[KeyRow]) -> String {
+    let mut lines = Vec::new();
+    title(
+        &mut lines,
+        format!("║     Known Peer Keys ({:2})              ║", keys.len()),
+    );
+
+    if keys.is_empty() {
+        lines.push("  No keys pinned yet...".to_string());
+    }
+    for key in keys {
+        lines.push(format!(
+            "  • {} ({}) [{}] {}",
+            key.nickname, key.peer_id, key.fingerprint, key.status
+        ));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_history(nickname: &str, messages: &[HistoryRow]) -> String {
+    let mut lines = vec![String::new(), format!("Conversation with {}:", nickname)];
+
+    if messages.is_empty() {
+        lines.push("  No messages yet...".to_string());
+    }
+    for msg in messages {
+        lines.push(format!("  {}", history_line(msg)));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_search_results(query: &str, results: &[SearchResultRow]) -> String {
+    let mut lines = vec![
+        String::new(),
+        format!("Search results for \"{}\" ({}):", query, results.len()),
+    ];
+
+    if results.is_empty() {
+        lines.push("  No matching messages.".to_string());
+    }
+    for result in results {
+        lines.push(String::new());
+        for msg in &result.before {
+            lines.push(format!("    {}", history_line(msg)));
+        }
+        lines.push(format!("  ▶ {}", history_line(&result.hit)));
+        for msg in &result.after {
+            lines.push(format!("    {}", history_line(msg)));
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// Format a history message, with the delivery state of sent messages
+fn history_line(msg: &HistoryRow) -> String {
+    if msg.outgoing {
+        format!(
+            "[{}] {}: {} ({})",
+            msg.time, msg.from, msg.content, msg.state
+        )
+    } else {
+        format!("[{}] {}: {}", msg.time, msg.from, msg.content)
+    }
+}
+
+fn render_outbox(messages: &[OutboxRow]) -> String {
+    let mut lines = Vec::new();
+    title(
+        &mut lines,
+        format!(
+            "║     Outbox ({:2})                       ║",
+            messages.len()
+        ),
+    );
+
+    if messages.is_empty() {
+        lines.push("  No messages waiting...".to_string());
+    }
+    for msg in messages {
+        lines.push(format!(
+            "  • {} to {} (queued {}): {}",
+            msg.id, msg.to, msg.queued_at, msg.content
+        ));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_group_list(groups: &[GroupRow]) -> String {
+    let mut lines = Vec::new();
+    title(
+        &mut lines,
+        format!("║     Groups ({:2})                       ║", groups.len()),
+    );
+
+    if groups.is_empty() {
+        lines.push("  Not in any group yet...".to_string());
+    }
+    for group in groups {
+        lines.push(format!(
+            "  • {} ({}): {}",
+            group.name,
+            group.id,
+            group.members.join(", ")
+        ));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_channel_members(channel: &str, members: &[String]) -> String {
+    let mut lines = vec![
+        String::new(),
+        format!("{} ({} members)", channel, members.len()),
+    ];
+
+    if members.is_empty() {
+        lines.push("  Nobody has joined yet...".to_string());
+    }
+    for member in members {
+        lines.push(format!("  • {}", member));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_safety_number(nickname: &str, number: &str) -> String {
+    let groups: Vec<&str> = number.split(' ').collect();
+
+    let mut lines = vec![
+        String::new(),
+        format!("Safety number with {}:", nickname),
+        String::new(),
+    ];
+    for row in groups.chunks(4) {
+        lines.push(format!("    {}", row.join("  ")));
+    }
+    lines.push(String::new());
+    lines.push(format!(
+        "Compare this number with {} in person or over a trusted",
+        nickname
+    ));
+    lines.push(format!(
+        "channel. If it matches, run: /verify {} confirm",
+        nickname
+    ));
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_net_info(
+    tcp_port: u16,
+    public_addr: Option<&str>,
+    mapped_addr: Option<&str>,
+    nat_type: Option<&str>,
+    port_mapping: Option<&str>,
+) -> String {
+    let mut lines = Vec::new();
+    title(
+        &mut lines,
+        "║     Network Info                      ║".to_string(),
+    );
+
+    lines.push(format!("  TCP port:        {}", tcp_port));
+    lines.push(format!(
+        "  Public address:  {}",
+        public_addr.unwrap_or("not registered")
+    ));
+    lines.push(format!(
+        "  Mapped address:  {}",
+        mapped_addr.unwrap_or("not probed yet")
+    ));
+    lines.push(format!(
+        "  NAT type:        {}",
+        nat_type.unwrap_or("unknown")
+    ));
+    lines.push(format!(
+        "  Port mapping:    {}",
+        port_mapping.unwrap_or("none")
+    ));
+    lines.push(String::new());
+    lines.join("\n")
+}
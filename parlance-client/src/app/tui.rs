@@ -0,0 +1,653 @@
+//! Full-screen terminal UI.
+//!
+//! The screen is split into a scrolling message pane, a sidebar listing the
+//! peers in the [`PeerRegistry`], a status bar and an input line:
+//!
+//! ```text
+//! +--------------------------------+-----------+
+//! | message pane                   | Peers (2) |
+//! |                                | ● alice   |
+//! |                                | ○ bob     |
+//! +--------------------------------+-----------+
+//! | status: nickname, mode, port, bootstrap    |
+//! | > input line                               |
+//! +--------------------------------------------+
+//! ```
+//!
+//! Everything sent to the [`Output`] lands in the message pane, so
+//! incoming messages never clobber what the user is typing. Submitted lines
+//! are handed to the command loop like lines read from stdin in plain mode.
+
+use super::output::{Output, ScreenLine, Tone};
+use crate::core::config::DiscoveryMode;
+use crate::core::peer::PeerRegistry;
+use crate::network::bootstrap::BootstrapStatus;
+use crate::network::connection::{ConnectionManager, ConnectionState};
+use crossterm::cursor::{MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::terminal::{
+    self, BeginSynchronizedUpdate, EndSynchronizedUpdate, EnterAlternateScreen,
+    LeaveAlternateScreen,
+};
+use crossterm::{execute, queue};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+
+/// Number of lines kept in the message pane
+pub const MAX_SCROLLBACK: usize = 1000;
+
+/// Width of the peer sidebar, including its border
+const SIDEBAR_WIDTH: usize = 22;
+
+/// Narrower terminals hide the sidebar
+const MIN_WIDTH_FOR_SIDEBAR: usize = 60;
+
+/// How often the sidebar and status bar are refreshed
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the input thread waits for a key before checking for shutdown
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Prompt shown in front of the input line
+const PROMPT: &str = "> ";
+
+/// What the status bar shows besides the bootstrap state
+#[derive(Debug, Clone)]
+pub struct StatusInfo {
+    pub nickname: String,
+    pub mode: DiscoveryMode,
+    pub tcp_port: u16,
+    /// Bootstrap connection state, in modes that use the server
+    pub bootstrap: Option<watch::Receiver<BootstrapStatus>>,
+}
+
+/// Text being edited on the input line
+#[derive(Debug, Default)]
+pub struct InputLine {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl InputLine {
+    /// Create an empty input line
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the current text
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// Insert a character at the cursor
+    pub fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    /// Delete the character before the cursor
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    /// Delete the character under the cursor
+    pub fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    /// Move the cursor one character left
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    /// Move the cursor one character right
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.chars.len());
+    }
+
+    /// Move the cursor to the start of the line
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Move the cursor to the end of the line
+    pub fn end(&mut self) {
+        self.cursor = self.chars.len();
+    }
+
+    /// Replace the text, putting the cursor at the end
+    pub fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    /// Take the text, leaving the line empty
+    pub fn take(&mut self) -> String {
+        let text = self.text();
+        self.set("");
+        text
+    }
+
+    /// Get the part of the line that fits into `width` columns, and the
+    /// cursor column within it
+    pub fn visible(&self, width: usize) -> (String, usize) {
+        if width == 0 {
+            return (String::new(), 0);
+        }
+        let offset = (self.cursor + 1).saturating_sub(width);
+        let text = self.chars.iter().skip(offset).take(width).collect();
+        (text, self.cursor - offset)
+    }
+}
+
+/// Lines shown in the message pane
+#[derive(Debug)]
+pub struct Scrollback {
+    lines: VecDeque<ScreenLine>,
+    capacity: usize,
+    /// Number of lines scrolled up from the bottom
+    scroll: usize,
+}
+
+impl Scrollback {
+    /// Create a pane keeping up to `capacity` lines
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            capacity,
+            scroll: 0,
+        }
+    }
+
+    /// Add a line at the bottom
+    ///
+    /// While scrolled up, the view stays where it is.
+    pub fn push(&mut self, line: ScreenLine) {
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.lines.len().saturating_sub(1));
+        }
+    }
+
+    /// Scroll towards older lines
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll = (self.scroll + lines).min(self.lines.len().saturating_sub(1));
+    }
+
+    /// Scroll towards newer lines
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    /// Jump back to the newest line
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll = 0;
+    }
+
+    /// Check whether the view is scrolled up
+    pub fn is_scrolled(&self) -> bool {
+        self.scroll > 0
+    }
+
+    /// Get the rows filling a pane of `width` by `height`, top to bottom
+    pub fn visible(&self, width: usize, height: usize) -> Vec<(Tone, String)> {
+        let mut rows = VecDeque::new();
+        for line in self.lines.iter().rev().skip(self.scroll) {
+            for row in wrap(&line.text, width).into_iter().rev() {
+                if rows.len() == height {
+                    return rows.into();
+                }
+                rows.push_front((line.tone, row));
+            }
+        }
+        rows.into()
+    }
+}
+
+/// Wrap text into rows of at most `width` characters
+///
+/// Breaks at spaces where possible and keeps the line's indentation.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let body = text.trim_start_matches(' ');
+    let indent = (text.len() - body.len()).min(width - 1);
+
+    let mut rows = Vec::new();
+    let mut row = " ".repeat(indent);
+    let mut len = indent;
+    let mut has_word = false;
+
+    for word in body.split(' ') {
+        let word_len = word.chars().count();
+        if has_word && len + 1 + word_len > width {
+            rows.push(std::mem::take(&mut row));
+            len = 0;
+            has_word = false;
+        }
+        if has_word {
+            row.push(' ');
+            len += 1;
+        }
+        for c in word.chars() {
+            if len == width {
+                rows.push(std::mem::take(&mut row));
+                len = 0;
+            }
+            row.push(c);
+            len += 1;
+        }
+        has_word = true;
+    }
+    rows.push(row);
+    rows
+}
+
+/// A peer in the sidebar
+#[derive(Debug, Clone)]
+struct SidebarEntry {
+    nickname: String,
+    connection: ConnectionState,
+}
+
+/// Handle to the running UI
+pub struct TuiHandle {
+    stop: oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl TuiHandle {
+    /// Close the UI and give the terminal back to line output
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.task.await;
+    }
+}
+
+/// Full-screen terminal UI
+pub struct Tui {
+    status: StatusInfo,
+    registry: PeerRegistry,
+    connections: ConnectionManager,
+    messages: Scrollback,
+    input: InputLine,
+    /// Lines submitted earlier, oldest first
+    recall: Vec<String>,
+    /// Position while browsing `recall` with the arrow keys
+    recall_pos: Option<usize>,
+    peers: Vec<SidebarEntry>,
+    bootstrap: Option<BootstrapStatus>,
+}
+
+impl Tui {
+    /// Take over the terminal and start the UI
+    ///
+    /// Lines the user submits are sent to `lines`. `output` is shown in the
+    /// message pane until the returned handle is stopped; fails if it
+    /// cannot be shown on a screen.
+    pub fn start(
+        status: StatusInfo,
+        registry: PeerRegistry,
+        connections: ConnectionManager,
+        output: Arc<dyn Output>,
+        lines: mpsc::UnboundedSender<String>,
+    ) -> io::Result<TuiHandle> {
+        let (screen_tx, screen_rx) = mpsc::unbounded_channel();
+        if !output.attach_screen(screen_tx) {
+            return Err(io::Error::other("output cannot be shown on a screen"));
+        }
+        let guard = match TerminalGuard::enter() {
+            Ok(guard) => guard,
+            Err(e) => {
+                output.detach_screen();
+                return Err(e);
+            }
+        };
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let key_rx = spawn_input_thread(stopped.clone());
+
+        let tui = Tui {
+            status,
+            registry,
+            connections,
+            messages: Scrollback::new(MAX_SCROLLBACK),
+            input: InputLine::new(),
+            recall: Vec::new(),
+            recall_pos: None,
+            peers: Vec::new(),
+            bootstrap: None,
+        };
+
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            if let Err(e) = tui.run(screen_rx, key_rx, lines, stop_rx).await {
+                tracing::error!(error = %e, "Terminal UI error");
+            }
+            stopped.store(true, Ordering::Relaxed);
+            output.detach_screen();
+            drop(guard);
+        });
+
+        Ok(TuiHandle {
+            stop: stop_tx,
+            task,
+        })
+    }
+
+    async fn run(
+        mut self,
+        mut screen_rx: mpsc::UnboundedReceiver<ScreenLine>,
+        mut key_rx: mpsc::UnboundedReceiver<Event>,
+        lines: mpsc::UnboundedSender<String>,
+        mut stop_rx: oneshot::Receiver<()>,
+    ) -> io::Result<()> {
+        let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+        let mut stdout = io::stdout();
+
+        loop {
+            tokio::select! {
+                _ = &mut stop_rx => break,
+                Some(line) = screen_rx.recv() => {
+                    self.messages.push(line);
+                    while let Ok(line) = screen_rx.try_recv() {
+                        self.messages.push(line);
+                    }
+                }
+                Some(event) = key_rx.recv() => {
+                    self.handle_event(event, &lines);
+                }
+                _ = refresh.tick() => self.refresh().await,
+            }
+            self.draw(&mut stdout)?;
+        }
+
+        // Keep output that arrived while shutting down
+        while let Ok(line) = screen_rx.try_recv() {
+            self.messages.push(line);
+        }
+        Ok(())
+    }
+
+    /// Reload the sidebar and status bar state
+    async fn refresh(&mut self) {
+        let mut peers: Vec<SidebarEntry> = self
+            .registry
+            .get_all()
+            .await
+            .into_iter()
+            .map(|peer| SidebarEntry {
+                connection: self.connections.state(&peer.public_key),
+                nickname: peer.nickname,
+            })
+            .collect();
+        peers.sort_by(|a, b| a.nickname.cmp(&b.nickname));
+        self.peers = peers;
+
+        self.bootstrap = self.status.bootstrap.as_ref().map(|rx| *rx.borrow());
+    }
+
+    fn handle_event(&mut self, event: Event, lines: &mpsc::UnboundedSender<String>) {
+        match event {
+            Event::Key(key) if key.kind != KeyEventKind::Release => self.handle_key(key, lines),
+            Event::Paste(text) => text
+                .chars()
+                .filter(|c| !c.is_control())
+                .for_each(|c| self.input.insert(c)),
+            _ => {}
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, lines: &mpsc::UnboundedSender<String>) {
+        let page = terminal::size()
+            .map(|(_, rows)| (rows as usize).saturating_sub(3).max(1))
+            .unwrap_or(10);
+
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            match key.code {
+                // Raw mode swallows the interrupt signal, so quit by hand
+                KeyCode::Char('c') | KeyCode::Char('d') => {
+                    let _ = lines.send("/quit".to_string());
+                }
+                KeyCode::Char('u') => self.input.set(""),
+                KeyCode::Char('a') => self.input.home(),
+                KeyCode::Char('e') => self.input.end(),
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Enter => {
+                let line = self.input.take();
+                self.recall_pos = None;
+                if line.trim().is_empty() {
+                    return;
+                }
+                self.messages.scroll_to_bottom();
+                self.messages.push(ScreenLine {
+                    tone: Tone::Notice,
+                    text: format!("{}{}", PROMPT, line),
+                });
+                self.recall.push(line.clone());
+                let _ = lines.send(line);
+            }
+            KeyCode::Char(c) => self.input.insert(c),
+            KeyCode::Backspace => self.input.backspace(),
+            KeyCode::Delete => self.input.delete(),
+            KeyCode::Left => self.input.left(),
+            KeyCode::Right => self.input.right(),
+            KeyCode::Home => self.input.home(),
+            KeyCode::End => self.input.end(),
+            KeyCode::Up => self.recall_previous(),
+            KeyCode::Down => self.recall_next(),
+            KeyCode::PageUp => self.messages.scroll_up(page),
+            KeyCode::PageDown => self.messages.scroll_down(page),
+            _ => {}
+        }
+    }
+
+    /// Show the previously submitted line
+    fn recall_previous(&mut self) {
+        let pos = match self.recall_pos {
+            Some(0) => return,
+            Some(pos) => pos - 1,
+            None if self.recall.is_empty() => return,
+            None => self.recall.len() - 1,
+        };
+        self.recall_pos = Some(pos);
+        self.input.set(&self.recall[pos]);
+    }
+
+    /// Show the next submitted line, or an empty line after the last one
+    fn recall_next(&mut self) {
+        let Some(pos) = self.recall_pos else {
+            return;
+        };
+        if pos + 1 < self.recall.len() {
+            self.recall_pos = Some(pos + 1);
+            self.input.set(&self.recall[pos + 1]);
+        } else {
+            self.recall_pos = None;
+            self.input.set("");
+        }
+    }
+
+    fn status_line(&self) -> String {
+        let mut parts = vec![
+            self.status.nickname.clone(),
+            format!("mode: {}", self.status.mode),
+            format!("port: {}", self.status.tcp_port),
+        ];
+        if let Some(bootstrap) = self.bootstrap {
+            parts.push(format!("bootstrap: {}", bootstrap));
+        }
+        parts.push(format!("peers: {}", self.peers.len()));
+        if self.messages.is_scrolled() {
+            parts.push("scrolled (PgDn)".to_string());
+        }
+        format!(" {}", parts.join(" │ "))
+    }
+
+    fn sidebar_rows(&self, height: usize) -> Vec<String> {
+        let mut rows = vec![format!("Peers ({})", self.peers.len())];
+        if self.peers.is_empty() {
+            rows.push("waiting...".to_string());
+        }
+        for peer in &self.peers {
+            let marker = match peer.connection {
+                ConnectionState::Connected => '●',
+                ConnectionState::Relayed => '◐',
+                _ => '○',
+            };
+            rows.push(format!("{} {}", marker, peer.nickname));
+        }
+        rows.truncate(height);
+        rows
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let (cols, rows) = terminal::size()?;
+        let (cols, rows) = (cols as usize, rows as usize);
+        if rows < 3 || cols < 10 {
+            return Ok(());
+        }
+
+        let pane_height = rows - 2;
+        let show_sidebar = cols >= MIN_WIDTH_FOR_SIDEBAR;
+        let pane_width = if show_sidebar {
+            cols - SIDEBAR_WIDTH
+        } else {
+            cols
+        };
+
+        queue!(out, BeginSynchronizedUpdate)?;
+
+        let messages = self.messages.visible(pane_width, pane_height);
+        let sidebar = self.sidebar_rows(pane_height);
+        for row in 0..pane_height {
+            queue!(out, MoveTo(0, row as u16))?;
+            match messages.get(row) {
+                Some((tone, text)) => queue!(
+                    out,
+                    SetForegroundColor(tone_color(*tone)),
+                    Print(pad(text, pane_width)),
+                    ResetColor
+                )?,
+                None => queue!(out, Print(pad("", pane_width)))?,
+            }
+            if show_sidebar {
+                let entry = sidebar.get(row).map(String::as_str).unwrap_or("");
+                queue!(
+                    out,
+                    SetForegroundColor(Color::DarkGrey),
+                    Print("│ "),
+                    ResetColor,
+                    Print(pad(entry, SIDEBAR_WIDTH - 2))
+                )?;
+            }
+        }
+
+        queue!(
+            out,
+            MoveTo(0, pane_height as u16),
+            SetAttribute(Attribute::Reverse),
+            Print(pad(&self.status_line(), cols)),
+            SetAttribute(Attribute::Reset)
+        )?;
+
+        let prompt_width = PROMPT.chars().count();
+        let (input, cursor) = self.input.visible(cols - prompt_width);
+        queue!(
+            out,
+            MoveTo(0, (rows - 1) as u16),
+            Print(PROMPT),
+            Print(pad(&input, cols - prompt_width)),
+            MoveTo((prompt_width + cursor) as u16, (rows - 1) as u16),
+            Show,
+            EndSynchronizedUpdate
+        )?;
+        out.flush()
+    }
+}
+
+/// Color used for each kind of line
+fn tone_color(tone: Tone) -> Color {
+    match tone {
+        Tone::Normal => Color::Reset,
+        Tone::Notice => Color::DarkGrey,
+        Tone::Success => Color::Green,
+        Tone::Warning => Color::Yellow,
+        Tone::Error => Color::Red,
+    }
+}
+
+/// Cut or pad text to exactly `width` characters
+fn pad(text: &str, width: usize) -> String {
+    let mut padded: String = text.chars().take(width).collect();
+    let len = padded.chars().count();
+    padded.extend(std::iter::repeat_n(' ', width - len));
+    padded
+}
+
+/// Read terminal events on a blocking thread until `stopped` is set
+fn spawn_input_thread(stopped: Arc<AtomicBool>) -> mpsc::UnboundedReceiver<Event> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while !stopped.load(Ordering::Relaxed) {
+            match event::poll(INPUT_POLL_INTERVAL) {
+                Ok(true) => match event::read() {
+                    Ok(event) => {
+                        if tx.send(event).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!(error = %e, "Failed to read terminal input");
+                        break;
+                    }
+                },
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::error!(error = %e, "Failed to poll terminal input");
+                    break;
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// Puts the terminal into raw mode on the alternate screen, and restores
+/// it when dropped, including on panic
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        if let Err(e) = execute!(io::stdout(), EnterAlternateScreen) {
+            let _ = terminal::disable_raw_mode();
+            return Err(e);
+        }
+        Ok(Self)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
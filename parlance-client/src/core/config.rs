@@ -2,22 +2,31 @@
 
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 /// Discovery mode for finding peers
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum DiscoveryMode {
     /// Local network discovery only (UDP multicast)
+    #[default]
     Local,
     /// Internet discovery only (bootstrap server)
     Internet,
+    /// Both multicast and bootstrap discovery, preferring LAN addresses
+    Hybrid,
 }
 
-impl Default for DiscoveryMode {
-    fn default() -> Self {
-        DiscoveryMode::Local
+impl DiscoveryMode {
+    /// Whether this mode runs UDP multicast discovery
+    pub fn uses_multicast(self) -> bool {
+        matches!(self, DiscoveryMode::Local | DiscoveryMode::Hybrid)
+    }
+
+    /// Whether this mode connects to the bootstrap server
+    pub fn uses_bootstrap(self) -> bool {
+        matches!(self, DiscoveryMode::Internet | DiscoveryMode::Hybrid)
     }
 }
 
@@ -28,8 +37,9 @@ impl std::str::FromStr for DiscoveryMode {
         match s.to_lowercase().as_str() {
             "local" => Ok(DiscoveryMode::Local),
             "internet" => Ok(DiscoveryMode::Internet),
+            "hybrid" => Ok(DiscoveryMode::Hybrid),
             _ => Err(format!(
-                "Invalid discovery mode '{}'. Valid options: local, internet",
+                "Invalid discovery mode '{}'. Valid options: local, internet, hybrid",
                 s
             )),
         }
@@ -41,6 +51,42 @@ impl std::fmt::Display for DiscoveryMode {
         match self {
             DiscoveryMode::Local => write!(f, "local"),
             DiscoveryMode::Internet => write!(f, "internet"),
+            DiscoveryMode::Hybrid => write!(f, "hybrid"),
+        }
+    }
+}
+
+/// Transport used for peer connections
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Encrypted channels over TCP
+    #[default]
+    Tcp,
+    /// Encrypted channels over QUIC to peers that support it, TCP otherwise
+    Quic,
+}
+
+impl std::str::FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tcp" => Ok(Transport::Tcp),
+            "quic" => Ok(Transport::Quic),
+            _ => Err(format!(
+                "Invalid transport '{}'. Valid options: tcp, quic",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "tcp"),
+            Transport::Quic => write!(f, "quic"),
         }
     }
 }
@@ -57,6 +103,17 @@ pub struct NetworkConfig {
     /// Default: ws://localhost:8080
     #[serde(default = "default_bootstrap_server")]
     pub bootstrap_server: String,
+
+    /// Ask the router to forward the messaging port over NAT-PMP or UPnP
+    /// Only used in internet and hybrid modes
+    /// Default: false
+    #[serde(default)]
+    pub port_mapping: bool,
+
+    /// Transport for peer connections
+    /// Default: tcp
+    #[serde(default)]
+    pub transport: Transport,
 }
 
 /// Discovery configuration
@@ -87,8 +144,61 @@ pub struct PeerConfig {
     pub announce_interval_secs: u64,
 }
 
-/// Complete application configuration
+/// Peer connection configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionConfig {
+    /// Close a peer connection after this many seconds without traffic
+    /// Default: 300 seconds
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+
+    /// Upper bound in seconds for the delay between reconnect attempts
+    /// Default: 60 seconds
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+
+    /// Largest frame in bytes sent to or accepted from a peer
+    /// Default: 1048576 bytes (1 MiB)
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+}
+
+/// Offline outbox configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxConfig {
+    /// Drop queued messages that could not be delivered within this many seconds
+    /// Default: 604800 seconds (7 days)
+    #[serde(default = "default_outbox_expiry_secs")]
+    pub expiry_secs: u64,
+}
+
+/// Conversation history configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    /// Delete messages older than this many days when the history is loaded
+    /// (0 keeps messages forever)
+    /// Default: 90 days
+    #[serde(default = "default_history_retention_days")]
+    pub retention_days: u64,
+}
+
+/// Persistent storage configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Directory holding identity keys and other persistent data
+    /// Default: platform data directory (e.g. ~/.local/share/parlance)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Encrypt the identity key, outbox and history with a passphrase
+    /// Once a profile is encrypted it stays encrypted regardless of this flag
+    /// Default: false
+    #[serde(default)]
+    pub encrypt: bool,
+}
+
+/// Complete application configuration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default)]
     pub network: NetworkConfig,
@@ -98,6 +208,18 @@ pub struct Config {
 
     #[serde(default)]
     pub peer: PeerConfig,
+
+    #[serde(default)]
+    pub connection: ConnectionConfig,
+
+    #[serde(default)]
+    pub outbox: OutboxConfig,
+
+    #[serde(default)]
+    pub history: HistoryConfig,
+
+    #[serde(default)]
+    pub storage: StorageConfig,
 }
 
 impl Config {
@@ -124,6 +246,98 @@ impl Config {
         Duration::from_secs(self.peer.announce_interval_secs)
     }
 
+    /// Get connection idle timeout as Duration
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.connection.idle_timeout_secs)
+    }
+
+    /// Get the maximum reconnect delay as Duration
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_secs(self.connection.max_backoff_secs)
+    }
+
+    /// Get the maximum size of a peer frame in bytes
+    pub fn max_frame_size(&self) -> usize {
+        self.connection.max_frame_size
+    }
+
+    /// Get the outbox expiry as Duration
+    pub fn outbox_expiry(&self) -> Duration {
+        Duration::from_secs(self.outbox.expiry_secs)
+    }
+
+    /// Get the history retention period, or `None` to keep messages forever
+    pub fn history_retention(&self) -> Option<Duration> {
+        (self.history.retention_days > 0)
+            .then(|| Duration::from_secs(self.history.retention_days * 24 * 60 * 60))
+    }
+
+    /// Get the root data directory
+    pub fn data_dir(&self) -> PathBuf {
+        self.storage
+            .data_dir
+            .clone()
+            .unwrap_or_else(default_data_dir)
+    }
+
+    /// Get the per-nickname profile directory
+    ///
+    /// Every nickname gets its own profile so that several instances can run
+    /// side by side on one machine without sharing an identity.
+    pub fn profile_dir(&self, nickname: &str) -> PathBuf {
+        let name: String = nickname
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.data_dir().join("profiles").join(name)
+    }
+
+    /// Get the identity key file for a nickname
+    pub fn identity_path(&self, nickname: &str) -> PathBuf {
+        self.profile_dir(nickname).join("identity.key")
+    }
+
+    /// Get the known-peers store for a nickname
+    pub fn known_peers_path(&self, nickname: &str) -> PathBuf {
+        self.profile_dir(nickname).join("known_peers.json")
+    }
+
+    /// Get the offline outbox for a nickname
+    pub fn outbox_path(&self, nickname: &str) -> PathBuf {
+        self.profile_dir(nickname).join("outbox.json")
+    }
+
+    /// Get the conversation history log for a nickname
+    pub fn history_path(&self, nickname: &str) -> PathBuf {
+        self.profile_dir(nickname).join("history.jsonl")
+    }
+
+    /// Get the group store file for a nickname
+    pub fn groups_path(&self, nickname: &str) -> PathBuf {
+        self.profile_dir(nickname).join("groups.json")
+    }
+
+    /// Get the directory received files are saved in for a nickname
+    pub fn downloads_path(&self, nickname: &str) -> PathBuf {
+        self.profile_dir(nickname).join("downloads")
+    }
+
+    /// Get the vault file holding a nickname's key derivation parameters
+    pub fn vault_path(&self, nickname: &str) -> PathBuf {
+        self.profile_dir(nickname).join("vault.json")
+    }
+
+    /// Get the log file written while the full-screen UI owns the terminal
+    pub fn log_path(&self, nickname: &str) -> PathBuf {
+        self.profile_dir(nickname).join("parlance.log")
+    }
+
     /// Create a default configuration and write it to a file
     pub fn write_default<P: AsRef<Path>>(path: P) -> Result<(), ConfigError> {
         let config = Config::default();
@@ -139,21 +353,13 @@ impl Config {
     }
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            network: NetworkConfig::default(),
-            discovery: DiscoveryConfig::default(),
-            peer: PeerConfig::default(),
-        }
-    }
-}
-
 impl Default for NetworkConfig {
     fn default() -> Self {
         Self {
             mode: DiscoveryMode::default(),
             bootstrap_server: default_bootstrap_server(),
+            port_mapping: false,
+            transport: Transport::default(),
         }
     }
 }
@@ -176,7 +382,39 @@ impl Default for PeerConfig {
     }
 }
 
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_secs: default_idle_timeout_secs(),
+            max_backoff_secs: default_max_backoff_secs(),
+            max_frame_size: default_max_frame_size(),
+        }
+    }
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            expiry_secs: default_outbox_expiry_secs(),
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: default_history_retention_days(),
+        }
+    }
+}
+
 // Default value functions for serde
+fn default_data_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join("parlance"))
+        .unwrap_or_else(|| PathBuf::from(".parlance"))
+}
+
 fn default_bootstrap_server() -> String {
     "ws://localhost:8080".to_string()
 }
@@ -197,8 +435,29 @@ fn default_announce_interval_secs() -> u64 {
     5
 }
 
+fn default_idle_timeout_secs() -> u64 {
+    300
+}
+
+fn default_max_backoff_secs() -> u64 {
+    60
+}
+
+fn default_max_frame_size() -> usize {
+    1024 * 1024
+}
+
+fn default_outbox_expiry_secs() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+fn default_history_retention_days() -> u64 {
+    90
+}
+
 /// Configuration errors
 #[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum ConfigError {
     #[error("Failed to read config file {path}: {source}")]
     IoError {
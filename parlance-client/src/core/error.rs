@@ -35,7 +35,6 @@ pub enum ParlanceError {
 
     /// Invalid message format received
     #[error("Invalid message format: {0}")]
-    #[allow(dead_code)]
     InvalidMessage(String),
 
     /// Channel send error
@@ -61,6 +60,58 @@ pub enum ParlanceError {
     /// Bootstrap server returned an error
     #[error("Bootstrap server error: {0}")]
     BootstrapServerError(String),
+
+    /// Identity key could not be loaded or stored
+    #[error("Identity error: {0}")]
+    Identity(String),
+
+    /// Persistent storage could not be read or written
+    #[error("Storage error: {0}")]
+    Storage(String),
+
+    /// A signature did not verify against the claimed identity key
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    /// The encrypted channel handshake with a peer failed
+    #[error("Secure handshake failed: {0}")]
+    Handshake(String),
+
+    /// No connection to a peer could be established
+    #[error("Connection unavailable: {0}")]
+    ConnectionUnavailable(String),
+
+    /// No path through the NATs between us and a peer could be opened
+    #[error("NAT traversal failed: {0}")]
+    Traversal(String),
+
+    /// A peer or server speaks a protocol version we cannot talk to
+    #[error("Incompatible protocol: {0}")]
+    IncompatibleProtocol(String),
+
+    /// A peer frame exceeded the maximum frame size
+    #[error("Frame of {size} bytes exceeds the {max} byte limit")]
+    FrameTooLarge { size: usize, max: usize },
+
+    /// A peer sent a frame type this build does not know
+    #[error("Unknown frame type: {0}")]
+    UnknownFrameType(u8),
+
+    /// A group operation could not be performed
+    #[error("Group error: {0}")]
+    Group(String),
+
+    /// A channel operation could not be performed
+    #[error("Channel error: {0}")]
+    Channel(String),
+
+    /// A file transfer could not be performed
+    #[error("Transfer error: {0}")]
+    Transfer(String),
+
+    /// The passphrase did not unlock the encrypted storage
+    #[error("Incorrect passphrase")]
+    IncorrectPassphrase,
 }
 
 /// Convenience type alias for Results using our custom error type.
@@ -0,0 +1,337 @@
+//! Group chat membership.
+//!
+//! A group has a random ID, a name chosen by its creator and a member list
+//! of nicknames and identity keys. Every member keeps a copy of the group
+//! in the profile's `groups.json`. Membership changes bump the group's
+//! version and are sent to the other members, who replace their copy when
+//! the update supersedes it, so all copies converge on the same list.
+
+use super::error::{ParlanceError, Result};
+use super::identity::{PublicKey, KEY_LENGTH};
+use super::storage;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// Maximum number of members in a group
+pub const MAX_GROUP_MEMBERS: usize = 64;
+
+/// Furthest an update may move a group's version past our copy
+///
+/// Each change bumps the version by one, so a bigger jump means a member
+/// is inflating it to win every future conflict.
+pub const MAX_VERSION_STEP: u64 = 64;
+
+/// Unique identifier of a group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GroupId(Uuid);
+
+impl GroupId {
+    /// Create a new random group ID
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    /// Check whether the full ID starts with `prefix`
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        !prefix.is_empty() && self.0.to_string().starts_with(&prefix.to_lowercase())
+    }
+}
+
+impl Default for GroupId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for GroupId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self.0.to_string()[..8])
+    }
+}
+
+/// A member of a group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    /// Member's nickname
+    pub nickname: String,
+    /// Member's identity key
+    pub public_key: PublicKey,
+}
+
+/// A group chat
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    /// Group ID
+    pub id: GroupId,
+    /// Group name
+    pub name: String,
+    /// Current members, including ourselves
+    pub members: Vec<Member>,
+    /// Incremented on every membership change
+    pub version: u64,
+}
+
+impl Group {
+    /// Create a group whose only member is its creator
+    pub fn new(name: String, creator: Member) -> Self {
+        Self {
+            id: GroupId::new(),
+            name,
+            members: vec![creator],
+            version: 1,
+        }
+    }
+
+    /// Get the member with the given key
+    pub fn member(&self, key: &PublicKey) -> Option<&Member> {
+        self.members.iter().find(|m| m.public_key == *key)
+    }
+
+    /// Check whether a key belongs to a member
+    pub fn is_member(&self, key: &PublicKey) -> bool {
+        self.member(key).is_some()
+    }
+
+    /// Add a member, bumping the version
+    pub fn add_member(&mut self, member: Member) -> Result<()> {
+        if self.is_member(&member.public_key) {
+            return Err(ParlanceError::Group(format!(
+                "{} is already in {}",
+                member.nickname, self.name
+            )));
+        }
+        if self.members.len() >= MAX_GROUP_MEMBERS {
+            return Err(ParlanceError::Group(format!(
+                "{} already has {} members",
+                self.name, MAX_GROUP_MEMBERS
+            )));
+        }
+
+        self.bump_version()?;
+        self.members.push(member);
+        Ok(())
+    }
+
+    /// Remove a member, bumping the version
+    ///
+    /// Returns the removed member, if the key belonged to one.
+    pub fn remove_member(&mut self, key: &PublicKey) -> Result<Option<Member>> {
+        let Some(index) = self.members.iter().position(|m| m.public_key == *key) else {
+            return Ok(None);
+        };
+        self.bump_version()?;
+        Ok(Some(self.members.remove(index)))
+    }
+
+    fn bump_version(&mut self) -> Result<()> {
+        self.version = self.version.checked_add(1).ok_or_else(|| {
+            ParlanceError::Group(format!("{} cannot be changed any further", self.name))
+        })?;
+        Ok(())
+    }
+
+    /// Check whether this copy of the group should replace `other`
+    ///
+    /// The higher version wins. Concurrent changes with the same version
+    /// are ordered by their member keys, so every member picks the same one.
+    pub fn supersedes(&self, other: &Group) -> bool {
+        self.version > other.version
+            || (self.version == other.version && self.member_keys() > other.member_keys())
+    }
+
+    fn member_keys(&self) -> Vec<[u8; KEY_LENGTH]> {
+        let mut keys: Vec<[u8; KEY_LENGTH]> = self
+            .members
+            .iter()
+            .map(|m| *m.public_key.as_bytes())
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+}
+
+/// Result of applying a group update received from a member
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// We were invited to a group we did not know
+    Joined,
+    /// Our copy was replaced; holds the copy before the update
+    Updated { previous: Group },
+    /// The update was stale or not allowed
+    Ignored,
+}
+
+/// On-disk representation of the store
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct GroupsFile {
+    #[serde(default)]
+    groups: Vec<Group>,
+}
+
+/// Persistent, thread-safe group store
+#[derive(Clone)]
+pub struct Groups {
+    path: PathBuf,
+    groups: Arc<RwLock<Vec<Group>>>,
+}
+
+impl Groups {
+    /// Load the store from `path`, starting empty if the file does not exist
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let file = if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|e| {
+                ParlanceError::Storage(format!("Failed to read {}: {}", path.display(), e))
+            })?;
+            serde_json::from_str(&contents)?
+        } else {
+            GroupsFile::default()
+        };
+
+        Ok(Self {
+            path,
+            groups: Arc::new(RwLock::new(file.groups)),
+        })
+    }
+
+    /// Get all groups
+    pub async fn list(&self) -> Vec<Group> {
+        self.groups.read().await.clone()
+    }
+
+    /// Get a group by ID
+    pub async fn get(&self, id: &GroupId) -> Option<Group> {
+        self.groups
+            .read()
+            .await
+            .iter()
+            .find(|g| g.id == *id)
+            .cloned()
+    }
+
+    /// Find a group by name or ID prefix
+    ///
+    /// Fails if nothing or more than one group matches.
+    pub async fn find(&self, name_or_id: &str) -> Result<Group> {
+        let groups = self.groups.read().await;
+
+        let by_name: Vec<&Group> = groups.iter().filter(|g| g.name == name_or_id).collect();
+        let matches = if by_name.is_empty() {
+            groups
+                .iter()
+                .filter(|g| g.id.starts_with(name_or_id))
+                .collect()
+        } else {
+            by_name
+        };
+
+        match matches.as_slice() {
+            [group] => Ok((*group).clone()),
+            [] => Err(ParlanceError::Group(format!(
+                "No group named {}",
+                name_or_id
+            ))),
+            _ => Err(ParlanceError::Group(format!(
+                "{} matches several groups; use the group ID",
+                name_or_id
+            ))),
+        }
+    }
+
+    /// Create a group with ourselves as the only member
+    pub async fn create(&self, name: &str, creator: Member) -> Result<Group> {
+        let mut groups = self.groups.write().await;
+        if groups.iter().any(|g| g.name == name) {
+            return Err(ParlanceError::Group(format!(
+                "A group named {} already exists",
+                name
+            )));
+        }
+
+        let group = Group::new(name.to_string(), creator);
+        groups.push(group.clone());
+        self.save(&groups).await?;
+        Ok(group)
+    }
+
+    /// Store a changed copy of a group we are in
+    pub async fn update(&self, group: Group) -> Result<()> {
+        let mut groups = self.groups.write().await;
+        match groups.iter_mut().find(|g| g.id == group.id) {
+            Some(existing) => *existing = group,
+            None => groups.push(group),
+        }
+        self.save(&groups).await
+    }
+
+    /// Forget a group
+    pub async fn remove(&self, id: &GroupId) -> Result<Option<Group>> {
+        let mut groups = self.groups.write().await;
+        let Some(index) = groups.iter().position(|g| g.id == *id) else {
+            return Ok(None);
+        };
+
+        let group = groups.remove(index);
+        self.save(&groups).await?;
+        Ok(Some(group))
+    }
+
+    /// Apply a group update sent by the member with key `from`
+    ///
+    /// Updates to a known group are accepted from its members when they
+    /// supersede our copy without jumping more than [`MAX_VERSION_STEP`]
+    /// versions ahead, and still include us. An unknown group is only
+    /// accepted when the update is an invitation addressed to us. Updates
+    /// listing more than [`MAX_GROUP_MEMBERS`] members are ignored.
+    pub async fn apply(
+        &self,
+        update: Group,
+        from: &PublicKey,
+        own_key: &PublicKey,
+        invited: bool,
+    ) -> Result<SyncOutcome> {
+        let mut groups = self.groups.write().await;
+
+        if update.members.len() > MAX_GROUP_MEMBERS || !update.is_member(own_key) {
+            return Ok(SyncOutcome::Ignored);
+        }
+
+        let outcome = match groups.iter_mut().find(|g| g.id == update.id) {
+            Some(existing) => {
+                let max_version = existing.version.saturating_add(MAX_VERSION_STEP);
+                if !existing.is_member(from)
+                    || !update.supersedes(existing)
+                    || update.version > max_version
+                {
+                    return Ok(SyncOutcome::Ignored);
+                }
+                let previous = std::mem::replace(existing, update);
+                SyncOutcome::Updated { previous }
+            }
+            None => {
+                if !invited || !update.is_member(from) {
+                    return Ok(SyncOutcome::Ignored);
+                }
+                groups.push(update);
+                SyncOutcome::Joined
+            }
+        };
+
+        self.save(&groups).await?;
+        Ok(outcome)
+    }
+
+    /// Write the store to disk
+    async fn save(&self, groups: &[Group]) -> Result<()> {
+        let file = GroupsFile {
+            groups: groups.to_vec(),
+        };
+        let json = serde_json::to_vec_pretty(&file)?;
+        storage::atomic_write_async(self.path.clone(), json).await
+    }
+}
@@ -0,0 +1,245 @@
+//! Persistent conversation history.
+//!
+//! Every sent and received message is appended to a JSON-lines log in the
+//! profile directory. Delivery state changes are appended as separate
+//! records and folded into the message they refer to when the log is
+//! loaded. Loading also drops messages older than the retention period and
+//! rewrites the log in compacted form.
+//!
+//! With a [`Vault`] every line is sealed separately, so appending stays
+//! cheap. Plaintext lines left from before encryption was enabled are
+//! sealed by the next compaction, which loading forces when it finds any.
+//!
+//! The messages are kept in memory together with a [`SearchIndex`] for
+//! `/search`.
+
+use super::error::{ParlanceError, Result};
+use super::identity::PublicKey;
+use super::search::{SearchHit, SearchIndex, SearchQuery};
+use super::storage;
+use super::vault::{self, Vault};
+use crate::network::messaging::MessageId;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Whether a message was sent or received by us
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Delivery state of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+    /// Handed to the messaging service, not yet written to the peer
+    Pending,
+    /// Waiting in the outbox for the peer to come online
+    Queued,
+    /// Written to the peer, not yet acknowledged
+    Sent,
+    /// Acknowledged by the peer (or received by us)
+    Delivered,
+    /// Gave up on delivery
+    Failed,
+}
+
+impl std::fmt::Display for DeliveryState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryState::Pending => write!(f, "pending"),
+            DeliveryState::Queued => write!(f, "queued"),
+            DeliveryState::Sent => write!(f, "sent"),
+            DeliveryState::Delivered => write!(f, "delivered"),
+            DeliveryState::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// A message in the conversation history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Message ID
+    pub id: MessageId,
+    /// Nickname of the other side of the conversation
+    pub peer: String,
+    /// Identity key of the other side of the conversation
+    pub peer_key: PublicKey,
+    /// Whether we sent or received the message
+    pub direction: Direction,
+    /// Message content
+    pub content: String,
+    /// Unix timestamp (seconds since epoch)
+    pub timestamp: i64,
+    /// Delivery state
+    pub state: DeliveryState,
+}
+
+/// A line of the history log
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "lowercase")]
+enum LogRecord {
+    /// A new message
+    Message(HistoryEntry),
+    /// A delivery state change of an earlier message
+    State { id: MessageId, state: DeliveryState },
+}
+
+/// Loaded messages and their index
+struct Log {
+    entries: Vec<HistoryEntry>,
+    index: SearchIndex,
+}
+
+/// Persistent, thread-safe conversation history
+#[derive(Clone)]
+pub struct History {
+    path: PathBuf,
+    vault: Option<Vault>,
+    log: Arc<RwLock<Log>>,
+}
+
+impl History {
+    /// Load the history from `path`, starting empty if the file does not exist
+    ///
+    /// Messages older than `retention` are dropped; `None` keeps everything.
+    /// Fails if the log is sealed and no vault is given.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        retention: Option<Duration>,
+        vault: Option<Vault>,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries: Vec<HistoryEntry> = Vec::new();
+        let mut lines = 0;
+        let mut plaintext = false;
+
+        if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|e| {
+                ParlanceError::Storage(format!("Failed to read {}: {}", path.display(), e))
+            })?;
+
+            let mut index: HashMap<MessageId, usize> = HashMap::new();
+            for line in contents.lines().filter(|l| !l.trim().is_empty()) {
+                lines += 1;
+                if vault::is_sealed(line) {
+                    if vault.is_none() {
+                        return Err(ParlanceError::Storage(format!(
+                            "{} is encrypted; a passphrase is required",
+                            path.display()
+                        )));
+                    }
+                } else {
+                    plaintext = true;
+                }
+
+                // A torn final line from a crash should not lose the whole log
+                let Ok(record) = vault::open_text(vault.as_ref(), line, &path)
+                    .and_then(|line| Ok(serde_json::from_str::<LogRecord>(&line)?))
+                else {
+                    tracing::warn!(path = %path.display(), "Skipping corrupt history record");
+                    continue;
+                };
+                match record {
+                    LogRecord::Message(entry) => {
+                        index.insert(entry.id, entries.len());
+                        entries.push(entry);
+                    }
+                    LogRecord::State { id, state } => {
+                        if let Some(&i) = index.get(&id) {
+                            entries[i].state = state;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(retention) = retention {
+            let cutoff = Utc::now().timestamp() - retention.as_secs() as i64;
+            entries.retain(|e| e.timestamp >= cutoff);
+        }
+
+        if lines > entries.len() || (plaintext && vault.is_some()) {
+            compact(&path, vault.as_ref(), &entries)?;
+        }
+
+        let index = SearchIndex::build(&entries);
+        Ok(Self {
+            path,
+            vault,
+            log: Arc::new(RwLock::new(Log { entries, index })),
+        })
+    }
+
+    /// Record a new message
+    pub async fn record(&self, entry: HistoryEntry) -> Result<()> {
+        let mut log = self.log.write().await;
+        self.append(&LogRecord::Message(entry.clone())).await?;
+        let position = log.entries.len();
+        log.index.insert(position, &entry);
+        log.entries.push(entry);
+        Ok(())
+    }
+
+    /// Update the delivery state of a recorded message
+    ///
+    /// Unknown IDs are ignored.
+    pub async fn set_state(&self, id: MessageId, state: DeliveryState) -> Result<()> {
+        let mut log = self.log.write().await;
+        let Some(entry) = log.entries.iter_mut().rev().find(|e| e.id == id) else {
+            return Ok(());
+        };
+        if entry.state == state {
+            return Ok(());
+        }
+
+        entry.state = state;
+        self.append(&LogRecord::State { id, state }).await
+    }
+
+    /// Get the last `limit` messages exchanged with a nickname, oldest first
+    pub async fn conversation(&self, nickname: &str, limit: usize) -> Vec<HistoryEntry> {
+        let log = self.log.read().await;
+        let positions = log.index.conversation(nickname);
+        positions[positions.len().saturating_sub(limit)..]
+            .iter()
+            .filter_map(|&p| log.entries.get(p).cloned())
+            .collect()
+    }
+
+    /// Find messages matching a query, newest first
+    pub async fn search(&self, query: &SearchQuery) -> Vec<SearchHit> {
+        let log = self.log.read().await;
+        log.index.search(&log.entries, query)
+    }
+
+    /// Append a record to the log
+    ///
+    /// Callers hold the write lock, which keeps records in order.
+    async fn append(&self, record: &LogRecord) -> Result<()> {
+        let json = serde_json::to_string(record)?;
+        let mut line = vault::seal_text(self.vault.as_ref(), &json)?;
+        line.push('\n');
+        storage::append_async(self.path.clone(), line.into_bytes()).await
+    }
+}
+
+/// Rewrite the log with one record per message
+fn compact(path: &Path, vault: Option<&Vault>, entries: &[HistoryEntry]) -> Result<()> {
+    let mut contents = String::new();
+    for entry in entries {
+        let json = serde_json::to_string(&LogRecord::Message(entry.clone()))?;
+        contents.push_str(&vault::seal_text(vault, &json)?);
+        contents.push('\n');
+    }
+
+    storage::atomic_write(path, contents.as_bytes())
+}
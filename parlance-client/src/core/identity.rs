@@ -0,0 +1,296 @@
+//! Cryptographic peer identity.
+//!
+//! Each client owns an Ed25519 keypair that is generated on first run and
+//! persisted to disk. The public key is carried in every announcement and
+//! message, and the `PeerId` is derived from it, so a peer keeps the same
+//! identity across restarts, ports and networks. With a [`Vault`] the secret
+//! key file is sealed.
+
+use super::error::{ParlanceError, Result};
+use super::peer::PeerId;
+use super::storage;
+use super::vault::{self, Vault};
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha512};
+use std::fs;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Length of an Ed25519 public or secret key in bytes
+pub const KEY_LENGTH: usize = 32;
+
+/// Length of an Ed25519 signature in bytes
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// Domain separation prefix for safety number derivation
+const SAFETY_NUMBER_CONTEXT: &[u8] = b"parlance-safety-number:";
+
+/// Number of five-digit groups in a safety number
+const SAFETY_NUMBER_GROUPS: usize = 12;
+
+/// A peer's Ed25519 public key
+///
+/// Serialized as a lowercase hex string on the wire.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey([u8; KEY_LENGTH]);
+
+impl PublicKey {
+    /// Create a public key from raw bytes, rejecting invalid curve points
+    pub fn from_bytes(bytes: &[u8; KEY_LENGTH]) -> Result<Self> {
+        VerifyingKey::from_bytes(bytes)
+            .map_err(|e| ParlanceError::InvalidMessage(format!("Invalid public key: {}", e)))?;
+        Ok(Self(*bytes))
+    }
+
+    /// Get the raw key bytes
+    pub fn as_bytes(&self) -> &[u8; KEY_LENGTH] {
+        &self.0
+    }
+
+    /// Encode the key as a hex string
+    pub fn to_hex(self) -> String {
+        hex::encode(self.0)
+    }
+
+    /// Short human-readable fingerprint (first 8 bytes in groups of four hex digits)
+    pub fn fingerprint(&self) -> String {
+        self.0[..8]
+            .chunks(2)
+            .map(hex::encode)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Derive the peer ID belonging to this key
+    pub fn peer_id(&self) -> PeerId {
+        PeerId::from_public_key(self)
+    }
+
+    /// Verify a signature made by the matching identity over `message`
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<()> {
+        let key = VerifyingKey::from_bytes(&self.0)
+            .map_err(|e| ParlanceError::InvalidMessage(format!("Invalid public key: {}", e)))?;
+        let signature = ed25519_dalek::Signature::from_bytes(&signature.0);
+
+        key.verify(message, &signature)
+            .map_err(|_| ParlanceError::InvalidSignature)
+    }
+}
+
+impl std::str::FromStr for PublicKey {
+    type Err = ParlanceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut bytes = [0u8; KEY_LENGTH];
+        hex::decode_to_slice(s, &mut bytes)
+            .map_err(|e| ParlanceError::InvalidMessage(format!("Invalid public key: {}", e)))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl std::fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl std::fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PublicKey({})", self.to_hex())
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// An Ed25519 signature
+///
+/// Serialized as a lowercase hex string on the wire.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Signature([u8; SIGNATURE_LENGTH]);
+
+impl Signature {
+    /// Encode the signature as a hex string
+    pub fn to_hex(self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl std::str::FromStr for Signature {
+    type Err = ParlanceError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut bytes = [0u8; SIGNATURE_LENGTH];
+        hex::decode_to_slice(s, &mut bytes)
+            .map_err(|e| ParlanceError::InvalidMessage(format!("Invalid signature: {}", e)))?;
+        Ok(Self(bytes))
+    }
+}
+
+impl std::fmt::Debug for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Signature({})", self.to_hex())
+    }
+}
+
+impl Serialize for Signature {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Derive the safety number shared by two identities
+///
+/// The result does not depend on argument order, so both peers compute the
+/// same twelve groups of five digits and can compare them out of band.
+pub fn safety_number(a: &PublicKey, b: &PublicKey) -> String {
+    let (first, second) = if a.0 <= b.0 { (a, b) } else { (b, a) };
+
+    let digest = Sha512::new()
+        .chain_update(SAFETY_NUMBER_CONTEXT)
+        .chain_update(first.0)
+        .chain_update(second.0)
+        .finalize();
+
+    digest
+        .chunks(5)
+        .take(SAFETY_NUMBER_GROUPS)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+            format!("{:05}", value % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Our own long-term identity keypair
+#[derive(Clone)]
+pub struct Identity {
+    signing_key: SigningKey,
+}
+
+impl Identity {
+    /// Generate a fresh random identity
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Restore an identity from its secret key bytes
+    pub fn from_secret_bytes(bytes: &[u8; KEY_LENGTH]) -> Self {
+        Self {
+            signing_key: SigningKey::from_bytes(bytes),
+        }
+    }
+
+    /// Load the identity stored at `path`, generating and saving a new one
+    /// if the file does not exist yet
+    ///
+    /// With a vault, a plaintext key file is re-written sealed.
+    pub fn load_or_generate<P: AsRef<Path>>(path: P, vault: Option<&Vault>) -> Result<Self> {
+        let path = path.as_ref();
+
+        if path.exists() {
+            let identity = Self::load(path, vault)?;
+            if vault.is_some() && !Self::is_sealed(path)? {
+                identity.save(path, vault)?;
+                tracing::info!(path = %path.display(), "Encrypted identity key");
+            }
+            return Ok(identity);
+        }
+
+        let identity = Self::generate();
+        identity.save(path, vault)?;
+
+        tracing::info!(
+            path = %path.display(),
+            peer_id = %identity.peer_id(),
+            "Generated new identity"
+        );
+
+        Ok(identity)
+    }
+
+    /// Load an identity from a key file
+    ///
+    /// Fails if the file is sealed and no vault is given.
+    pub fn load<P: AsRef<Path>>(path: P, vault: Option<&Vault>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = Self::read(path)?;
+        let contents = Zeroizing::new(
+            vault::open_text(vault, &contents, path)
+                .map_err(|e| ParlanceError::Identity(e.to_string()))?,
+        );
+
+        let mut bytes = [0u8; KEY_LENGTH];
+        hex::decode_to_slice(contents.trim(), &mut bytes).map_err(|e| {
+            ParlanceError::Identity(format!("Invalid key file {}: {}", path.display(), e))
+        })?;
+
+        Ok(Self::from_secret_bytes(&bytes))
+    }
+
+    /// Check whether the key file at `path` is sealed
+    pub fn is_sealed<P: AsRef<Path>>(path: P) -> Result<bool> {
+        Ok(vault::is_sealed(&Self::read(path.as_ref())?))
+    }
+
+    fn read(path: &Path) -> Result<Zeroizing<String>> {
+        fs::read_to_string(path).map(Zeroizing::new).map_err(|e| {
+            ParlanceError::Identity(format!("Failed to read {}: {}", path.display(), e))
+        })
+    }
+
+    /// Write the secret key to `path`, creating parent directories as needed
+    ///
+    /// The key is sealed if a vault is given. The file is replaced
+    /// atomically and on Unix is readable by its owner only.
+    pub fn save<P: AsRef<Path>>(&self, path: P, vault: Option<&Vault>) -> Result<()> {
+        let key = Zeroizing::new(hex::encode(self.signing_key.to_bytes()));
+        let contents = Zeroizing::new(vault::seal_text(vault, &key)?);
+        storage::atomic_write(path.as_ref(), contents.as_bytes())
+    }
+
+    /// Get our public key
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.signing_key.verifying_key().to_bytes())
+    }
+
+    /// Get our peer ID
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key().peer_id()
+    }
+
+    /// Sign `message` with our secret key
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        Signature(self.signing_key.sign(message).to_bytes())
+    }
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("public_key", &self.public_key())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,274 @@
+//! Trust-on-first-use key pinning.
+//!
+//! The known-peers store remembers the first identity key seen for every
+//! nickname. Later announcements or messages that use the same nickname
+//! with a different key are rejected until the user approves the change
+//! with `/trust`, so a peer cannot be silently impersonated on the LAN.
+//!
+//! Entries are pinned by nickname and identified by the [`PeerId`] derived
+//! from their key. When a nickname shows up with a key that is already
+//! pinned under another nickname, the warning names that peer, so a
+//! nickname collision between two known peers is not mistaken for a key
+//! change.
+
+use super::error::{ParlanceError, Result};
+use super::identity::PublicKey;
+use super::peer::PeerId;
+use super::storage;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+
+/// Trust state of a pinned key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrustStatus {
+    /// Key is accepted (pinned on first use or approved by the user)
+    Trusted,
+    /// User revoked trust; the peer is ignored until trusted again
+    Untrusted,
+}
+
+/// A remembered peer identity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownPeer {
+    /// Nickname the key is pinned to
+    pub nickname: String,
+    /// Pinned identity key
+    pub public_key: PublicKey,
+    /// Trust state of the pinned key
+    pub status: TrustStatus,
+    /// Unix timestamp when the key was first pinned
+    pub first_seen: i64,
+    /// Whether the user confirmed the pinned key's safety number
+    #[serde(default)]
+    pub verified: bool,
+    /// A different key announced for this nickname, awaiting approval
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending_key: Option<PublicKey>,
+}
+
+/// Warning raised the first time a nickname shows up with a new key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyChangeWarning {
+    /// Nickname whose key changed
+    pub nickname: String,
+    /// Key we have pinned
+    pub pinned: PublicKey,
+    /// Key the peer is now presenting
+    pub presented: PublicKey,
+    /// Nickname the presented key is already pinned to, if any
+    ///
+    /// Set when a known peer uses another peer's nickname rather than the
+    /// peer's key having changed.
+    pub known_as: Option<String>,
+}
+
+impl KnownPeer {
+    /// Peer ID derived from the pinned key
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key.peer_id()
+    }
+}
+
+/// Result of checking a nickname/key pair against the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustDecision {
+    /// Key matches the trusted pin (or was just pinned)
+    Accept,
+    /// Key is untrusted or differs from the pin
+    Reject,
+}
+
+/// On-disk representation of the store
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KnownPeersFile {
+    #[serde(default)]
+    peers: BTreeMap<String, KnownPeer>,
+}
+
+/// Persistent, thread-safe known-peers store
+#[derive(Clone)]
+pub struct KnownPeers {
+    path: PathBuf,
+    peers: Arc<RwLock<BTreeMap<String, KnownPeer>>>,
+    warning_tx: mpsc::UnboundedSender<KeyChangeWarning>,
+}
+
+impl KnownPeers {
+    /// Load the store from `path`, starting empty if the file does not exist
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        warning_tx: mpsc::UnboundedSender<KeyChangeWarning>,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+
+        let file = if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|e| {
+                ParlanceError::Storage(format!("Failed to read {}: {}", path.display(), e))
+            })?;
+            serde_json::from_str(&contents)?
+        } else {
+            KnownPeersFile::default()
+        };
+
+        Ok(Self {
+            path,
+            peers: Arc::new(RwLock::new(file.peers)),
+            warning_tx,
+        })
+    }
+
+    /// Check a nickname/key pair, pinning it if the nickname is new
+    pub async fn check(&self, nickname: &str, key: &PublicKey) -> TrustDecision {
+        let mut peers = self.peers.write().await;
+        let known_as = find_by_id(&peers, &key.peer_id())
+            .filter(|p| p.nickname != nickname)
+            .map(|p| p.nickname.clone());
+
+        let Some(entry) = peers.get_mut(nickname) else {
+            peers.insert(
+                nickname.to_string(),
+                KnownPeer {
+                    nickname: nickname.to_string(),
+                    public_key: *key,
+                    status: TrustStatus::Trusted,
+                    first_seen: Utc::now().timestamp(),
+                    verified: false,
+                    pending_key: None,
+                },
+            );
+            tracing::info!(nickname = %nickname, key = %key.fingerprint(), "Pinned new peer key");
+            self.save_or_log(&peers).await;
+            return TrustDecision::Accept;
+        };
+
+        if entry.public_key != *key {
+            if entry.pending_key != Some(*key) {
+                entry.pending_key = Some(*key);
+                let warning = KeyChangeWarning {
+                    nickname: nickname.to_string(),
+                    pinned: entry.public_key,
+                    presented: *key,
+                    known_as,
+                };
+                tracing::warn!(
+                    nickname = %nickname,
+                    pinned = %warning.pinned.fingerprint(),
+                    presented = %warning.presented.fingerprint(),
+                    known_as = ?warning.known_as,
+                    "Peer presented a different identity key"
+                );
+                self.save_or_log(&peers).await;
+                let _ = self.warning_tx.send(warning);
+            }
+            return TrustDecision::Reject;
+        }
+
+        match entry.status {
+            TrustStatus::Trusted => TrustDecision::Accept,
+            TrustStatus::Untrusted => TrustDecision::Reject,
+        }
+    }
+
+    /// Trust a nickname, approving a pending key change if there is one
+    ///
+    /// Returns the previously pinned key when it was replaced.
+    pub async fn trust(&self, nickname: &str) -> Result<Option<PublicKey>> {
+        let mut peers = self.peers.write().await;
+        let entry = peers
+            .get_mut(nickname)
+            .ok_or_else(|| ParlanceError::PeerNotFound(nickname.to_string()))?;
+
+        let replaced = entry.pending_key.take().map(|new_key| {
+            let old_key = entry.public_key;
+            entry.public_key = new_key;
+            entry.verified = false;
+            old_key
+        });
+        entry.status = TrustStatus::Trusted;
+
+        self.save(&peers).await?;
+        Ok(replaced)
+    }
+
+    /// Revoke trust in a nickname's pinned key
+    pub async fn untrust(&self, nickname: &str) -> Result<()> {
+        let mut peers = self.peers.write().await;
+        let entry = peers
+            .get_mut(nickname)
+            .ok_or_else(|| ParlanceError::PeerNotFound(nickname.to_string()))?;
+
+        entry.status = TrustStatus::Untrusted;
+        entry.verified = false;
+        entry.pending_key = None;
+
+        self.save(&peers).await
+    }
+
+    /// Mark a nickname's pinned key as verified
+    ///
+    /// Fails if the key is untrusted or a key change is awaiting approval,
+    /// since the safety number the user compared would be for the old key.
+    pub async fn mark_verified(&self, nickname: &str) -> Result<()> {
+        let mut peers = self.peers.write().await;
+        let entry = peers
+            .get_mut(nickname)
+            .ok_or_else(|| ParlanceError::PeerNotFound(nickname.to_string()))?;
+
+        if entry.status != TrustStatus::Trusted || entry.pending_key.is_some() {
+            return Err(ParlanceError::Identity(format!(
+                "{}'s key must be trusted before it can be verified",
+                nickname
+            )));
+        }
+
+        entry.verified = true;
+        self.save(&peers).await
+    }
+
+    /// Check whether a nickname's verified key matches `key`
+    pub async fn is_verified(&self, nickname: &str, key: &PublicKey) -> bool {
+        let peers = self.peers.read().await;
+        peers
+            .get(nickname)
+            .is_some_and(|p| p.verified && p.public_key == *key && p.status == TrustStatus::Trusted)
+    }
+
+    /// Get the entry for a nickname
+    pub async fn get(&self, nickname: &str) -> Option<KnownPeer> {
+        let peers = self.peers.read().await;
+        peers.get(nickname).cloned()
+    }
+
+    /// Get all known peers, sorted by nickname
+    pub async fn list(&self) -> Vec<KnownPeer> {
+        let peers = self.peers.read().await;
+        peers.values().cloned().collect()
+    }
+
+    /// Persist while already holding the lock, logging failures
+    async fn save_or_log(&self, peers: &BTreeMap<String, KnownPeer>) {
+        if let Err(e) = self.save(peers).await {
+            tracing::error!(error = %e, "Failed to save known peers");
+        }
+    }
+
+    /// Write the store to disk
+    async fn save(&self, peers: &BTreeMap<String, KnownPeer>) -> Result<()> {
+        let file = KnownPeersFile {
+            peers: peers.clone(),
+        };
+        let json = serde_json::to_vec_pretty(&file)?;
+        storage::atomic_write_async(self.path.clone(), json).await
+    }
+}
+
+/// Find the entry pinned to the key `id` is derived from
+fn find_by_id<'a>(peers: &'a BTreeMap<String, KnownPeer>, id: &PeerId) -> Option<&'a KnownPeer> {
+    peers.values().find(|p| p.peer_id() == *id)
+}
@@ -2,5 +2,13 @@
 
 pub mod config;
 pub mod error;
+pub mod group;
+pub mod history;
+pub mod identity;
+pub mod known_peers;
 pub mod peer;
+pub mod search;
+pub mod storage;
+pub mod sync;
 pub mod validation;
+pub mod vault;
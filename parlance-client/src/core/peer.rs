@@ -3,12 +3,14 @@
 //! This module handles peer representation and the peer registry,
 //! which tracks all discovered peers on the local network.
 
+use super::identity::PublicKey;
+use super::known_peers::{KnownPeers, TrustDecision, TrustStatus};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 use uuid::Uuid;
 
 /// Unique identifier for a peer
@@ -21,10 +23,9 @@ impl PeerId {
         Self(Uuid::new_v4())
     }
 
-    /// Create a peer ID from a socket address (deterministic)
-    pub fn from_addr(addr: &SocketAddr) -> Self {
-        let hash = format!("{}", addr);
-        Self(Uuid::new_v5(&Uuid::NAMESPACE_DNS, hash.as_bytes()))
+    /// Derive a peer ID from an identity public key (deterministic)
+    pub fn from_public_key(key: &PublicKey) -> Self {
+        Self(Uuid::new_v5(&Uuid::NAMESPACE_OID, key.as_bytes()))
     }
 }
 
@@ -40,6 +41,27 @@ impl std::fmt::Display for PeerId {
     }
 }
 
+/// Where a peer was discovered
+///
+/// Variants are ordered by preference: when a peer is seen through several
+/// sources, the address from the first one is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DiscoverySource {
+    /// UDP multicast on the local network
+    Multicast,
+    /// Bootstrap server peer list
+    Bootstrap,
+}
+
+impl std::fmt::Display for DiscoverySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiscoverySource::Multicast => write!(f, "lan"),
+            DiscoverySource::Bootstrap => write!(f, "bootstrap"),
+        }
+    }
+}
+
 /// Represents a peer on the network
 #[derive(Debug, Clone)]
 pub struct Peer {
@@ -49,21 +71,52 @@ pub struct Peer {
     pub nickname: String,
     /// Socket address for TCP connections
     pub addr: SocketAddr,
+    /// Identity public key the peer ID is derived from
+    pub public_key: PublicKey,
     /// Last time we received an announcement from this peer
     pub last_seen: Instant,
+    /// Address and last sighting per discovery source
+    sightings: BTreeMap<DiscoverySource, (SocketAddr, Instant)>,
+    /// Optional protocol features the peer advertises
+    features: Vec<String>,
 }
 
 impl Peer {
     /// Create a new peer
-    pub fn new(nickname: String, addr: SocketAddr) -> Self {
+    pub fn new(nickname: String, addr: SocketAddr, public_key: PublicKey) -> Self {
         Self {
-            id: PeerId::from_addr(&addr),
+            id: public_key.peer_id(),
             nickname,
             addr,
+            public_key,
             last_seen: Instant::now(),
+            sightings: BTreeMap::new(),
+            features: Vec::new(),
         }
     }
 
+    /// Set the optional protocol features the peer advertises
+    pub fn with_features(mut self, features: Vec<String>) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// Check whether the peer advertises `feature`
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Record that the peer was seen at its address through `source`
+    pub fn via(mut self, source: DiscoverySource) -> Self {
+        self.sightings.insert(source, (self.addr, self.last_seen));
+        self
+    }
+
+    /// Get the sources this peer is currently known through, most preferred first
+    pub fn sources(&self) -> Vec<DiscoverySource> {
+        self.sightings.keys().copied().collect()
+    }
+
     /// Update the last_seen timestamp
     pub fn refresh(&mut self) {
         self.last_seen = Instant::now();
@@ -73,15 +126,72 @@ impl Peer {
     pub fn is_timed_out(&self, timeout: Duration) -> bool {
         self.last_seen.elapsed() > timeout
     }
+
+    /// Merge the sightings of a fresh announcement for the same peer
+    fn merge(&mut self, other: Peer) {
+        self.refresh();
+        self.nickname = other.nickname;
+        self.features = other.features;
+        if other.sightings.is_empty() {
+            self.addr = other.addr;
+        } else {
+            self.sightings.extend(other.sightings);
+            self.addr = self.preferred_addr();
+        }
+    }
+
+    /// Forget sources not seen within `timeout`
+    ///
+    /// Returns true if any source was dropped.
+    fn expire_sightings(&mut self, timeout: Duration) -> bool {
+        let before = self.sightings.len();
+        self.sightings
+            .retain(|_, (_, seen)| seen.elapsed() <= timeout);
+        if self.sightings.len() == before {
+            return false;
+        }
+        self.addr = self.preferred_addr();
+        true
+    }
+
+    /// Address from the most preferred source, falling back to the current one
+    fn preferred_addr(&self) -> SocketAddr {
+        self.sightings
+            .values()
+            .next()
+            .map(|(addr, _)| *addr)
+            .unwrap_or(self.addr)
+    }
+}
+
+/// Change in the set of known peers
+#[derive(Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
+pub enum PeerEvent {
+    /// A peer was seen for the first time
+    PeerJoined(Peer),
+    /// A peer said goodbye or was removed
+    PeerLeft(Peer),
+    /// A peer stopped announcing and was dropped
+    PeerTimedOut(Peer),
+    /// A known peer is now reachable at a different address
+    PeerAddressChanged { peer: Peer, old_addr: SocketAddr },
 }
 
 /// Thread-safe peer registry
 ///
 /// Maintains a list of all discovered peers and provides methods
-/// to add, update, and remove peers based on timeouts.
+/// to add, update, and remove peers based on timeouts. When backed by a
+/// known-peers store, peers whose key does not match the pinned key for
+/// their nickname are never added.
+///
+/// Changes to the set of peers are published as [`PeerEvent`]s to every
+/// receiver obtained from [`PeerRegistry::subscribe`].
 #[derive(Clone)]
 pub struct PeerRegistry {
     peers: Arc<RwLock<HashMap<PeerId, Peer>>>,
+    known_peers: Option<KnownPeers>,
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<PeerEvent>>>>,
 }
 
 impl PeerRegistry {
@@ -89,16 +199,82 @@ impl PeerRegistry {
     pub fn new() -> Self {
         Self {
             peers: Arc::new(RwLock::new(HashMap::new())),
+            known_peers: None,
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Create a new empty peer registry that enforces key pinning
+    pub fn with_known_peers(known_peers: KnownPeers) -> Self {
+        Self {
+            peers: Arc::new(RwLock::new(HashMap::new())),
+            known_peers: Some(known_peers),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Subscribe to peer events
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<PeerEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(tx);
+        rx
+    }
+
+    /// Publish an event to all subscribers, dropping closed ones
+    fn emit(&self, event: PeerEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    /// Check whether a nickname/key pair is trusted
+    ///
+    /// Always true when the registry has no known-peers store.
+    pub async fn is_trusted(&self, nickname: &str, key: &PublicKey) -> bool {
+        match &self.known_peers {
+            Some(known) => known.check(nickname, key).await == TrustDecision::Accept,
+            None => true,
         }
     }
 
+    /// Get the trusted key pinned for a nickname, even if the peer is offline
+    ///
+    /// Always `None` when the registry has no known-peers store.
+    pub async fn pinned_key(&self, nickname: &str) -> Option<PublicKey> {
+        let known = self.known_peers.as_ref()?.get(nickname).await?;
+        (known.status == TrustStatus::Trusted).then_some(known.public_key)
+    }
+
     /// Add or update a peer in the registry
     pub async fn upsert(&self, peer: Peer) {
+        if !self.is_trusted(&peer.nickname, &peer.public_key).await {
+            tracing::debug!(
+                nickname = %peer.nickname,
+                key = %peer.public_key.fingerprint(),
+                "Ignoring peer with untrusted key"
+            );
+            return;
+        }
+
         let mut peers = self.peers.write().await;
         if let Some(existing) = peers.get_mut(&peer.id) {
-            existing.refresh();
-            existing.nickname = peer.nickname;
-            existing.addr = peer.addr;
+            let old_addr = existing.addr;
+            existing.merge(peer);
+            if existing.addr != old_addr {
+                tracing::info!(
+                    peer_id = %existing.id,
+                    nickname = %existing.nickname,
+                    old_addr = %old_addr,
+                    addr = %existing.addr,
+                    "Peer address changed"
+                );
+                self.emit(PeerEvent::PeerAddressChanged {
+                    peer: existing.clone(),
+                    old_addr,
+                });
+            }
         } else {
             tracing::info!(
                 peer_id = %peer.id,
@@ -106,21 +282,38 @@ impl PeerRegistry {
                 addr = %peer.addr,
                 "New peer discovered"
             );
-            peers.insert(peer.id, peer);
+            peers.insert(peer.id, peer.clone());
+            self.emit(PeerEvent::PeerJoined(peer));
         }
     }
 
     /// Remove a peer by ID
-    #[allow(dead_code)]
-    pub async fn remove(&self, id: &PeerId) {
+    pub async fn remove(&self, id: &PeerId) -> Option<Peer> {
         let mut peers = self.peers.write().await;
-        if let Some(peer) = peers.remove(id) {
-            tracing::info!(
-                peer_id = %peer.id,
-                nickname = %peer.nickname,
-                "Peer removed"
-            );
+        let peer = peers.remove(id)?;
+        tracing::info!(
+            peer_id = %peer.id,
+            nickname = %peer.nickname,
+            "Peer removed"
+        );
+        self.emit(PeerEvent::PeerLeft(peer.clone()));
+        Some(peer)
+    }
+
+    /// Remove every peer using the given nickname
+    pub async fn remove_by_nickname(&self, nickname: &str) -> Vec<Peer> {
+        let mut peers = self.peers.write().await;
+        let ids: Vec<_> = peers
+            .values()
+            .filter(|p| p.nickname == nickname)
+            .map(|p| p.id)
+            .collect();
+
+        let removed: Vec<Peer> = ids.iter().filter_map(|id| peers.remove(id)).collect();
+        for peer in &removed {
+            self.emit(PeerEvent::PeerLeft(peer.clone()));
         }
+        removed
     }
 
     /// Remove all timed-out peers
@@ -132,6 +325,21 @@ impl PeerRegistry {
             .map(|(id, _)| *id)
             .collect();
 
+        // Peers still alive may have lost a source (e.g. left the LAN) and
+        // fall back to the address from the remaining one
+        for peer in peers.values_mut() {
+            let old_addr = peer.addr;
+            if !peer.is_timed_out(timeout)
+                && peer.expire_sightings(timeout)
+                && peer.addr != old_addr
+            {
+                self.emit(PeerEvent::PeerAddressChanged {
+                    peer: peer.clone(),
+                    old_addr,
+                });
+            }
+        }
+
         let mut removed = Vec::new();
         for id in timed_out {
             if let Some(peer) = peers.remove(&id) {
@@ -140,6 +348,7 @@ impl PeerRegistry {
                     nickname = %peer.nickname,
                     "Peer timed out"
                 );
+                self.emit(PeerEvent::PeerTimedOut(peer.clone()));
                 removed.push(peer);
             }
         }
@@ -153,7 +362,6 @@ impl PeerRegistry {
     }
 
     /// Get a peer by ID
-    #[allow(dead_code)]
     pub async fn get(&self, id: &PeerId) -> Option<Peer> {
         let peers = self.peers.read().await;
         peers.get(id).cloned()
@@ -0,0 +1,160 @@
+//! Full-text search over the conversation history.
+//!
+//! The [`SearchIndex`] maps every lowercase word of every message to the
+//! positions of the messages containing it, so a query only touches the
+//! messages that contain all of its words instead of scanning the whole
+//! history. It also keeps the positions of each conversation to look up the
+//! messages around a hit.
+
+use super::history::{Direction, HistoryEntry};
+use std::collections::HashMap;
+
+/// Number of messages shown before and after each search hit
+pub const SEARCH_CONTEXT: usize = 1;
+
+/// Split text into lowercase search terms
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// A history search
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Words that must all appear in a message
+    pub text: String,
+    /// Only messages exchanged with this nickname
+    pub peer: Option<String>,
+    /// Only messages in this direction
+    pub direction: Option<Direction>,
+    /// Only messages at or after this Unix timestamp
+    pub since: Option<i64>,
+    /// Only messages before this Unix timestamp
+    pub until: Option<i64>,
+    /// Maximum number of hits, newest first
+    pub limit: usize,
+}
+
+impl SearchQuery {
+    /// Create a query for messages containing every word of `text`
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            limit: usize::MAX,
+            ..Self::default()
+        }
+    }
+
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.peer.as_ref().is_none_or(|peer| entry.peer == *peer)
+            && self.direction.is_none_or(|d| entry.direction == d)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+    }
+}
+
+/// A message matching a search, with the surrounding conversation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    /// Messages with the same peer just before the hit, oldest first
+    pub before: Vec<HistoryEntry>,
+    /// The matching message
+    pub entry: HistoryEntry,
+    /// Messages with the same peer just after the hit, oldest first
+    pub after: Vec<HistoryEntry>,
+}
+
+/// Inverted index over an append-only list of history entries
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// Positions of the entries containing each term, ascending
+    terms: HashMap<String, Vec<usize>>,
+    /// Positions of the entries of each conversation, ascending
+    peers: HashMap<String, Vec<usize>>,
+}
+
+impl SearchIndex {
+    /// Build an index over `entries`
+    pub fn build(entries: &[HistoryEntry]) -> Self {
+        let mut index = Self::default();
+        for (position, entry) in entries.iter().enumerate() {
+            index.insert(position, entry);
+        }
+        index
+    }
+
+    /// Index the entry at `position`, which must follow all indexed entries
+    pub fn insert(&mut self, position: usize, entry: &HistoryEntry) {
+        let mut terms = tokenize(&entry.content);
+        terms.sort_unstable();
+        terms.dedup();
+
+        for term in terms {
+            self.terms.entry(term).or_default().push(position);
+        }
+        self.peers
+            .entry(entry.peer.clone())
+            .or_default()
+            .push(position);
+    }
+
+    /// Get the positions of a conversation's entries, oldest first
+    pub fn conversation(&self, peer: &str) -> &[usize] {
+        self.peers.get(peer).map_or(&[], Vec::as_slice)
+    }
+
+    /// Run a query against the indexed `entries`
+    pub fn search(&self, entries: &[HistoryEntry], query: &SearchQuery) -> Vec<SearchHit> {
+        let mut terms = tokenize(&query.text);
+        terms.sort_unstable();
+        terms.dedup();
+
+        let Some(mut postings) = terms
+            .iter()
+            .map(|term| self.terms.get(term).map(Vec::as_slice))
+            .collect::<Option<Vec<&[usize]>>>()
+        else {
+            // Some word appears in no message at all
+            return Vec::new();
+        };
+
+        // Walk the rarest term's postings, newest first, and probe the rest
+        postings.sort_by_key(|p| p.len());
+        let Some((rarest, others)) = postings.split_first() else {
+            return Vec::new();
+        };
+
+        rarest
+            .iter()
+            .rev()
+            .filter(|position| others.iter().all(|p| p.binary_search(position).is_ok()))
+            .filter_map(|&position| Some((position, entries.get(position)?)))
+            .filter(|(_, entry)| query.matches(entry))
+            .take(query.limit)
+            .map(|(position, entry)| self.hit(entries, position, entry))
+            .collect()
+    }
+
+    /// Collect the context around a hit
+    fn hit(&self, entries: &[HistoryEntry], position: usize, entry: &HistoryEntry) -> SearchHit {
+        let conversation = self.conversation(&entry.peer);
+        let index = conversation.binary_search(&position).unwrap_or(0);
+        let context = |positions: &[usize]| -> Vec<HistoryEntry> {
+            positions
+                .iter()
+                .filter_map(|&p| entries.get(p).cloned())
+                .collect()
+        };
+
+        let start = index.saturating_sub(SEARCH_CONTEXT);
+        let end = (index + 1 + SEARCH_CONTEXT).min(conversation.len());
+
+        SearchHit {
+            before: context(&conversation[start..index]),
+            entry: entry.clone(),
+            after: context(&conversation[(index + 1).min(end)..end]),
+        }
+    }
+}
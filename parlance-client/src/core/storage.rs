@@ -0,0 +1,97 @@
+//! Crash-safe file writes.
+//!
+//! Every store rewrites its whole file on change. Writing in place would
+//! leave a truncated file behind if the process dies or the disk fills up
+//! mid-write, so [`atomic_write`] writes a temporary file next to the
+//! target, syncs it and renames it over the old one. Readers see either the
+//! old contents or the new, never a mix.
+//!
+//! Append-only logs are extended with [`append_async`] instead, which also
+//! keeps the file owner-only and runs off the async runtime.
+
+use super::error::{ParlanceError, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Replace the file at `path` with `contents`
+///
+/// The file is readable by its owner only, since the stores hold keys and
+/// private conversations. Missing parent directories are created.
+pub fn atomic_write(path: &Path, contents: &[u8]) -> Result<()> {
+    let write_err = |e: std::io::Error| {
+        ParlanceError::Storage(format!("Failed to write {}: {}", path.display(), e))
+    };
+
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+    if let Some(parent) = parent {
+        fs::create_dir_all(parent).map_err(write_err)?;
+    }
+
+    // A stale temporary file would keep its old permissions
+    let tmp_path = path.with_extension("tmp");
+    let _ = fs::remove_file(&tmp_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&tmp_path).map_err(write_err)?;
+    file.write_all(contents).map_err(write_err)?;
+    file.sync_all().map_err(write_err)?;
+    drop(file);
+    fs::rename(&tmp_path, path).map_err(write_err)?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(parent) = parent {
+        if let Ok(dir) = fs::File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    Ok(())
+}
+
+/// Replace the file at `path` with `contents` without blocking the runtime
+///
+/// Runs [`atomic_write`] on Tokio's blocking thread pool.
+pub async fn atomic_write_async(path: PathBuf, contents: Vec<u8>) -> Result<()> {
+    tokio::task::spawn_blocking(move || atomic_write(&path, &contents))
+        .await
+        .map_err(|e| ParlanceError::Storage(format!("Write task failed: {}", e)))?
+}
+
+/// Append `contents` to the file at `path` without blocking the runtime
+///
+/// A missing file is created readable by its owner only, along with any
+/// missing parent directories.
+pub async fn append_async(path: PathBuf, contents: Vec<u8>) -> Result<()> {
+    tokio::task::spawn_blocking(move || append(&path, &contents))
+        .await
+        .map_err(|e| ParlanceError::Storage(format!("Write task failed: {}", e)))?
+}
+
+fn append(path: &Path, contents: &[u8]) -> Result<()> {
+    let write_err = |e: std::io::Error| {
+        ParlanceError::Storage(format!("Failed to write {}: {}", path.display(), e))
+    };
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).map_err(write_err)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path).map_err(write_err)?;
+    file.write_all(contents).map_err(write_err)
+}
@@ -0,0 +1,11 @@
+//! Helpers for the standard library's synchronization primitives.
+
+use std::sync::{Mutex, MutexGuard};
+
+/// Lock a mutex, recovering the data if a panicking thread poisoned it
+///
+/// The state guarded by these mutexes stays consistent between statements,
+/// so a panic elsewhere is no reason to take the rest of the client down.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -48,6 +48,86 @@ impl NicknameValidator {
             return Err(NicknameValidationError::InvalidCharacters);
         }
 
+        // `#` introduces channel names in `/send`
+        if nickname.starts_with(ChannelNameValidator::PREFIX) {
+            return Err(NicknameValidationError::ReservedPrefix);
+        }
+
+        Ok(())
+    }
+}
+
+/// Group name validation rules
+///
+/// Group names are typed as the first argument of `/gsend`, so unlike
+/// nicknames they cannot contain whitespace.
+pub struct GroupNameValidator;
+
+impl GroupNameValidator {
+    /// Maximum group name length
+    pub const MAX_LENGTH: usize = 32;
+
+    /// Validate a group name
+    pub fn validate(name: &str) -> Result<(), GroupNameValidationError> {
+        if name.is_empty() {
+            return Err(GroupNameValidationError::Empty);
+        }
+
+        if name.len() > Self::MAX_LENGTH {
+            return Err(GroupNameValidationError::TooLong {
+                max: Self::MAX_LENGTH,
+                actual: name.len(),
+            });
+        }
+
+        if name.chars().any(char::is_whitespace) {
+            return Err(GroupNameValidationError::ContainsWhitespace);
+        }
+
+        if name.chars().any(|c| c.is_control()) {
+            return Err(GroupNameValidationError::InvalidCharacters);
+        }
+
+        Ok(())
+    }
+}
+
+/// Channel name validation rules
+///
+/// Channel names start with `#`, followed by ASCII letters, digits, `-` or `_`.
+pub struct ChannelNameValidator;
+
+impl ChannelNameValidator {
+    /// Prefix every channel name starts with
+    pub const PREFIX: char = '#';
+
+    /// Maximum channel name length, including the prefix
+    pub const MAX_LENGTH: usize = 32;
+
+    /// Validate a channel name
+    pub fn validate(name: &str) -> Result<(), ChannelNameValidationError> {
+        let Some(rest) = name.strip_prefix(Self::PREFIX) else {
+            return Err(ChannelNameValidationError::MissingPrefix);
+        };
+
+        if rest.is_empty() {
+            return Err(ChannelNameValidationError::Empty);
+        }
+
+        if name.len() > Self::MAX_LENGTH {
+            return Err(ChannelNameValidationError::TooLong {
+                max: Self::MAX_LENGTH,
+                actual: name.len(),
+            });
+        }
+
+        if !rest
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(ChannelNameValidationError::InvalidCharacters);
+        }
+
         Ok(())
     }
 }
@@ -75,4 +155,39 @@ pub enum NicknameValidationError {
 
     #[error("Nickname cannot contain newlines")]
     ContainsNewline,
+
+    #[error("Nickname cannot start with '#'")]
+    ReservedPrefix,
+}
+
+/// Group name validation errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum GroupNameValidationError {
+    #[error("Group name cannot be empty")]
+    Empty,
+
+    #[error("Group name must be at most {max} characters long (got {actual})")]
+    TooLong { max: usize, actual: usize },
+
+    #[error("Group name cannot contain whitespace")]
+    ContainsWhitespace,
+
+    #[error("Group name contains invalid characters")]
+    InvalidCharacters,
+}
+
+/// Channel name validation errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ChannelNameValidationError {
+    #[error("Channel name must start with '#'")]
+    MissingPrefix,
+
+    #[error("Channel name cannot be empty")]
+    Empty,
+
+    #[error("Channel name must be at most {max} characters long (got {actual})")]
+    TooLong { max: usize, actual: usize },
+
+    #[error("Channel name may only contain letters, digits, '-' and '_'")]
+    InvalidCharacters,
 }
@@ -0,0 +1,235 @@
+//! Passphrase-based encryption at rest.
+//!
+//! A [`Vault`] holds a key derived from the user's passphrase with Argon2id.
+//! The salt and KDF parameters live in `vault.json` in the profile
+//! directory, next to a sealed check value used to reject a wrong
+//! passphrase before any data is touched.
+//!
+//! Sealed data is XChaCha20-Poly1305 ciphertext with a random nonce. Text
+//! files store it base64-encoded behind a `sealed:` prefix, so stores can
+//! tell encrypted content from plaintext written before encryption was
+//! turned on.
+
+use super::error::{ParlanceError, Result};
+use super::storage;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Prefix marking sealed text
+pub const SEALED_PREFIX: &str = "sealed:";
+
+const KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+
+/// Plaintext of the check value stored in the vault file
+const CHECK_PLAINTEXT: &[u8] = b"parlance vault";
+
+/// Argon2id cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of passes
+    pub iterations: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// On-disk representation of the vault
+#[derive(Debug, Serialize, Deserialize)]
+struct VaultFile {
+    kdf: KdfParams,
+    /// Hex-encoded salt
+    salt: String,
+    /// Sealed [`CHECK_PLAINTEXT`]
+    check: String,
+}
+
+/// Key used to encrypt a profile's data at rest
+#[derive(Clone)]
+pub struct Vault {
+    key: Zeroizing<[u8; KEY_LENGTH]>,
+}
+
+impl Vault {
+    /// Check whether a vault file exists at `path`
+    pub fn exists<P: AsRef<Path>>(path: P) -> bool {
+        path.as_ref().exists()
+    }
+
+    /// Create a vault protected by `passphrase` and write it to `path`
+    pub fn create<P: AsRef<Path>>(path: P, passphrase: &str, params: KdfParams) -> Result<Self> {
+        let mut salt = [0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+
+        let vault = Self::derive(passphrase, &salt, params)?;
+        let file = VaultFile {
+            kdf: params,
+            salt: hex::encode(salt),
+            check: vault.seal_str(&String::from_utf8_lossy(CHECK_PLAINTEXT))?,
+        };
+
+        storage::atomic_write(
+            path.as_ref(),
+            serde_json::to_string_pretty(&file)?.as_bytes(),
+        )?;
+
+        Ok(vault)
+    }
+
+    /// Unlock the vault at `path` with `passphrase`
+    pub fn unlock<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| {
+            ParlanceError::Storage(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        let file: VaultFile = serde_json::from_str(&contents)?;
+
+        let salt = hex::decode(&file.salt).map_err(|e| {
+            ParlanceError::Storage(format!("Invalid salt in {}: {}", path.display(), e))
+        })?;
+
+        let vault = Self::derive(passphrase, &salt, file.kdf)?;
+        match vault.open_str(&file.check) {
+            Ok(check) if check.as_bytes() == CHECK_PLAINTEXT => Ok(vault),
+            _ => Err(ParlanceError::IncorrectPassphrase),
+        }
+    }
+
+    /// Derive the key from a passphrase
+    fn derive(passphrase: &str, salt: &[u8], params: KdfParams) -> Result<Self> {
+        let kdf_err = |e: argon2::Error| ParlanceError::Storage(format!("Key derivation: {}", e));
+
+        let params = Params::new(
+            params.memory_kib,
+            params.iterations,
+            params.parallelism,
+            Some(KEY_LENGTH),
+        )
+        .map_err(kdf_err)?;
+
+        let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+            .map_err(kdf_err)?;
+
+        Ok(Self { key })
+    }
+
+    /// Encrypt `plaintext`, returning the nonce followed by the ciphertext
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LENGTH];
+        OsRng.fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher()
+            .encrypt(&XNonce::from(nonce), plaintext)
+            .map_err(|_| ParlanceError::Storage("Encryption failed".to_string()))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt data produced by [`Vault::seal`]
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LENGTH {
+            return Err(ParlanceError::Storage(
+                "Sealed data is truncated".to_string(),
+            ));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        let mut nonce_bytes = [0u8; NONCE_LENGTH];
+        nonce_bytes.copy_from_slice(nonce);
+
+        self.cipher()
+            .decrypt(&XNonce::from(nonce_bytes), ciphertext)
+            .map_err(|_| ParlanceError::Storage("Decryption failed".to_string()))
+    }
+
+    /// Encrypt text into a `sealed:` line
+    pub fn seal_str(&self, plaintext: &str) -> Result<String> {
+        Ok(format!(
+            "{}{}",
+            SEALED_PREFIX,
+            BASE64.encode(self.seal(plaintext.as_bytes())?)
+        ))
+    }
+
+    /// Decrypt a `sealed:` line
+    pub fn open_str(&self, sealed: &str) -> Result<String> {
+        let encoded = sealed
+            .trim()
+            .strip_prefix(SEALED_PREFIX)
+            .ok_or_else(|| ParlanceError::Storage("Data is not sealed".to_string()))?;
+        let data = BASE64
+            .decode(encoded)
+            .map_err(|e| ParlanceError::Storage(format!("Invalid sealed data: {}", e)))?;
+
+        String::from_utf8(self.open(&data)?).map_err(ParlanceError::from)
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(self.key.as_ref().into())
+    }
+}
+
+impl std::fmt::Debug for Vault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vault").finish_non_exhaustive()
+    }
+}
+
+/// Check whether stored text is sealed
+pub fn is_sealed(contents: &str) -> bool {
+    contents.trim_start().starts_with(SEALED_PREFIX)
+}
+
+/// Seal text with the vault if there is one, otherwise return it unchanged
+pub fn seal_text(vault: Option<&Vault>, plaintext: &str) -> Result<String> {
+    match vault {
+        Some(vault) => vault.seal_str(plaintext),
+        None => Ok(plaintext.to_string()),
+    }
+}
+
+/// Read stored text that may be sealed
+///
+/// Fails if the text is sealed and no vault is available. Plaintext is
+/// returned as-is, so stores written before encryption was enabled still
+/// load.
+pub fn open_text(vault: Option<&Vault>, contents: &str, path: &Path) -> Result<String> {
+    if !is_sealed(contents) {
+        return Ok(contents.to_string());
+    }
+
+    let vault = vault.ok_or_else(|| {
+        ParlanceError::Storage(format!(
+            "{} is encrypted; a passphrase is required",
+            path.display()
+        ))
+    })?;
+    vault
+        .open_str(contents)
+        .map_err(|e| ParlanceError::Storage(format!("Failed to decrypt {}: {}", path.display(), e)))
+}
@@ -7,13 +7,20 @@ mod app;
 mod core;
 mod network;
 
+use app::output::{JsonOutput, Output, OutputFormat, TerminalOutput};
 use app::{App, AppConfig};
 use clap::Parser;
-use core::config::DiscoveryMode;
-use core::error::Result;
+use core::config::{Config, DiscoveryMode};
+use core::error::{ParlanceError, Result};
+use core::identity::Identity;
 use core::validation::NicknameValidator;
-use std::path::PathBuf;
+use core::vault::{KdfParams, Vault};
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use tracing_subscriber::fmt;
+use zeroize::Zeroizing;
 
 /// Parlance - Local Network P2P Messaging
 #[derive(Parser, Debug)]
@@ -38,15 +45,26 @@ struct Args {
     /// Bootstrap server URL (overrides config file)
     #[arg(long, value_name = "URL")]
     bootstrap_server: Option<String>,
+
+    /// Read the storage passphrase from a file instead of prompting
+    /// (enables encryption for a new profile)
+    #[arg(long, value_name = "FILE")]
+    passphrase_file: Option<PathBuf>,
+
+    /// Plain line-by-line output instead of the full-screen UI
+    #[arg(long)]
+    plain: bool,
+
+    /// Output format: text, or json for one JSON object per event
+    #[arg(long, value_name = "FORMAT", default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
 }
 
+/// Number of passphrase prompts before giving up
+const PASSPHRASE_ATTEMPTS: usize = 3;
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    fmt()
-        .with_max_level(tracing::Level::INFO)
-        .with_target(false)
-        .init();
-
     let args = Args::parse();
 
     if let Some(config_path) = args.generate_config {
@@ -74,6 +92,12 @@ async fn main() -> Result<()> {
         }
     };
 
+    let plain = args.plain || args.output == OutputFormat::Json || !io::stdout().is_terminal();
+    let encrypted = config.storage.encrypt
+        || args.passphrase_file.is_some()
+        || Vault::exists(config.vault_path(&args.nickname));
+    init_logging(&config, &args.nickname, plain, encrypted, args.output)?;
+
     if let Some(mode) = args.mode {
         tracing::info!("Overriding discovery mode from CLI: {}", mode);
         config.network.mode = mode;
@@ -87,8 +111,133 @@ async fn main() -> Result<()> {
     NicknameValidator::validate(&args.nickname)
         .map_err(|e| core::error::ParlanceError::ConfigError(format!("Invalid nickname: {}", e)))?;
 
-    let app_config = AppConfig::new(args.nickname);
-    let app = App::new(app_config, config);
+    let vault = unlock_storage(&config, &args.nickname, args.passphrase_file.as_deref())?;
+    let identity =
+        Identity::load_or_generate(config.identity_path(&args.nickname), vault.as_ref())?;
+
+    let mut app_config = AppConfig::new(args.nickname, identity);
+    app_config.vault = vault;
+    app_config.plain = plain;
+    let output: Arc<dyn Output> = match args.output {
+        OutputFormat::Text => Arc::new(TerminalOutput::new()),
+        OutputFormat::Json => Arc::new(JsonOutput::stdout()),
+    };
+    let app = App::new(app_config, config, output)?;
 
     app.run().await
 }
+
+/// Set up logging
+///
+/// Logs go to stdout in plain mode, and to stderr when stdout carries JSON
+/// events. The full-screen UI owns the terminal, so there they are appended
+/// to the profile's log file instead, unless the profile is encrypted: the
+/// log would sit next to the sealed stores in plaintext, so nothing is
+/// logged at all.
+fn init_logging(
+    config: &Config,
+    nickname: &str,
+    plain: bool,
+    encrypted: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    let subscriber = fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false);
+
+    if format == OutputFormat::Json {
+        subscriber.with_writer(io::stderr).init();
+        return Ok(());
+    }
+    if plain {
+        subscriber.init();
+        return Ok(());
+    }
+    if encrypted {
+        return Ok(());
+    }
+
+    let path = config.log_path(nickname);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    subscriber
+        .with_ansi(false)
+        .with_writer(Mutex::new(file))
+        .init();
+    Ok(())
+}
+
+/// Unlock the profile's encrypted storage, creating it if encryption is
+/// requested and the profile is not encrypted yet
+///
+/// Returns `None` for an unencrypted profile.
+fn unlock_storage(
+    config: &Config,
+    nickname: &str,
+    passphrase_file: Option<&Path>,
+) -> Result<Option<Vault>> {
+    let vault_path = config.vault_path(nickname);
+
+    if Vault::exists(&vault_path) {
+        if let Some(file) = passphrase_file {
+            return Vault::unlock(&vault_path, &read_passphrase_file(file)?).map(Some);
+        }
+
+        for attempt in 1..=PASSPHRASE_ATTEMPTS {
+            let passphrase = prompt_passphrase("Passphrase: ")?;
+            match Vault::unlock(&vault_path, &passphrase) {
+                Ok(vault) => return Ok(Some(vault)),
+                Err(ParlanceError::IncorrectPassphrase) if attempt < PASSPHRASE_ATTEMPTS => {
+                    eprintln!("Incorrect passphrase, try again.");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        return Err(ParlanceError::IncorrectPassphrase);
+    }
+
+    if !config.storage.encrypt && passphrase_file.is_none() {
+        return Ok(None);
+    }
+
+    let passphrase = match passphrase_file {
+        Some(file) => read_passphrase_file(file)?,
+        None => {
+            let passphrase = prompt_passphrase("New passphrase: ")?;
+            if *prompt_passphrase("Confirm passphrase: ")? != *passphrase {
+                return Err(ParlanceError::ConfigError(
+                    "Passphrases do not match".to_string(),
+                ));
+            }
+            passphrase
+        }
+    };
+    if passphrase.is_empty() {
+        return Err(ParlanceError::ConfigError(
+            "Passphrase must not be empty".to_string(),
+        ));
+    }
+
+    let vault = Vault::create(&vault_path, &passphrase, KdfParams::default())?;
+    tracing::info!(path = %vault_path.display(), "Encrypted storage enabled");
+    Ok(Some(vault))
+}
+
+/// Read a passphrase from the first line of a file
+fn read_passphrase_file(path: &Path) -> Result<Zeroizing<String>> {
+    let contents = Zeroizing::new(std::fs::read_to_string(path).map_err(|e| {
+        ParlanceError::ConfigError(format!("Failed to read {}: {}", path.display(), e))
+    })?);
+    Ok(Zeroizing::new(
+        contents.lines().next().unwrap_or_default().to_string(),
+    ))
+}
+
+/// Ask for a passphrase on the terminal without echoing it
+fn prompt_passphrase(prompt: &str) -> Result<Zeroizing<String>> {
+    rpassword::prompt_password(prompt)
+        .map(Zeroizing::new)
+        .map_err(|e| ParlanceError::ConfigError(format!("Failed to read passphrase: {}", e)))
+}
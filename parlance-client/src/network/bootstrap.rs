@@ -2,16 +2,55 @@
 //!
 //! This module implements a WebSocket client that connects to a bootstrap server
 //! to discover peers across the internet, complementing local network discovery.
+//!
+//! Registration carries our protocol [`Hello`]. A server that cannot serve
+//! our version answers with `incompatible`, which stops the client instead of
+//! reconnecting, and listed peers whose versions do not overlap with ours are
+//! skipped.
+//!
+//! The server also coordinates UDP hole punching. A [`Rendezvous`] handle
+//! asks it to pair us with a peer; both sides then report the address their
+//! punching socket appears from, receive each other's candidates and a start
+//! time, and punch a path with [`punch`].
+//!
+//! After registering, the client probes the server's probe ports to learn
+//! the address its NAT maps it to and the kind of NAT (see [`probe`]). A
+//! NAT's UDP mapping says nothing about our TCP listener, so unless the
+//! probe finds no NAT at all, the client registers itself as unreachable.
+//! Peers then skip dialing it and punch or relay straight away.
+//!
+//! When the router forwards our port (see [`crate::network::portmap`]), the
+//! forwarded address is registered instead, and the peer is reachable.
+//!
+//! If the server runs a relay, the same handle also opens [`RelayStream`]s:
+//! circuits whose chunks travel base64-encoded in `relay` messages over the
+//! WebSocket connection, addressed by peer ID.
 
 use crate::core::error::{ParlanceError, Result};
-use crate::core::peer::{Peer, PeerRegistry};
+use crate::core::identity::PublicKey;
+use crate::core::peer::{DiscoverySource, Peer, PeerRegistry};
+use crate::core::sync::lock;
+use crate::network::nat::{probe, NatProbe, PROBE_TIMEOUT};
+use crate::network::portmap::PortMapping;
+use crate::network::protocol::Hello;
+use crate::network::punch::{punch, Datagram, PUNCH_TIMEOUT};
+use crate::network::relay::RelayStream;
+use crate::network::udp_stream::UdpStream;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::time::{sleep, Duration};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{sleep, timeout, Duration};
+use tokio_tungstenite::tungstenite::http::Uri;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use uuid::Uuid;
 
 type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
 
@@ -24,6 +63,12 @@ const INITIAL_RECONNECT_DELAY_SECS: u64 = 1;
 /// Maximum reconnection delay in seconds.
 const MAX_RECONNECT_DELAY_SECS: u64 = 30;
 
+/// How long to wait for the server to pair up a hole punch.
+const PUNCH_SIGNAL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval between UDP bind datagrams sent to the server.
+const PUNCH_BIND_INTERVAL: Duration = Duration::from_millis(250);
+
 /// Messages sent from client to server.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -31,10 +76,26 @@ enum ClientMessage {
     Register {
         nickname: String,
         local_addr: String,
+        public_key: PublicKey,
+        #[serde(flatten)]
+        hello: Hello,
     },
     ListPeers,
     Heartbeat,
     Unregister,
+    ConnectRequest {
+        target_peer_id: String,
+    },
+    Relay {
+        to: String,
+        circuit: String,
+        payload: String,
+    },
+    UpdateAddress {
+        public_addr: String,
+        #[serde(default)]
+        unreachable: bool,
+    },
 }
 
 /// Messages sent from server to client.
@@ -44,15 +105,64 @@ enum ServerMessage {
     Registered {
         peer_id: String,
         public_addr: String,
+        #[serde(default)]
+        probe_ports: Vec<u16>,
     },
     PeerList {
         peers: Vec<PeerInfo>,
     },
+    Incompatible {
+        protocol_version: u16,
+        min_protocol_version: u16,
+    },
+    PunchPrepare {
+        session: String,
+        peer_id: String,
+        initiator: bool,
+    },
+    PunchStart {
+        session: String,
+        peer_id: String,
+        public_key: String,
+        public_addr: String,
+        local_addr: String,
+        start_at: i64,
+    },
+    PunchFailed {
+        peer_id: String,
+        message: String,
+    },
+    Relay {
+        from: String,
+        circuit: String,
+        payload: String,
+    },
+    RelayFailed {
+        peer_id: String,
+        circuit: String,
+        message: String,
+    },
     Error {
         message: String,
     },
 }
 
+/// Datagram sent to the server's UDP port from a punching socket.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct PunchBind {
+    session: String,
+    peer_id: String,
+    local_addr: String,
+}
+
+/// The other side's candidates for a punch session.
+#[derive(Debug)]
+struct PunchCandidates {
+    public_addr: String,
+    local_addr: String,
+    start_at: i64,
+}
+
 /// Information about a peer from the bootstrap server.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 struct PeerInfo {
@@ -60,18 +170,368 @@ struct PeerInfo {
     nickname: String,
     public_addr: String,
     local_addr: String,
+    public_key: String,
     last_seen: i64,
+    #[serde(default)]
+    unreachable: bool,
+    #[serde(flatten)]
+    hello: Hello,
+}
+
+/// State of the connection to the bootstrap server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootstrapStatus {
+    /// Connecting or waiting for the registration to be confirmed.
+    Connecting,
+    /// Registered with the server.
+    Connected,
+    /// Not connected; the client retries after a delay.
+    Disconnected,
+}
+
+impl std::fmt::Display for BootstrapStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BootstrapStatus::Connecting => write!(f, "connecting"),
+            BootstrapStatus::Connected => write!(f, "connected"),
+            BootstrapStatus::Disconnected => write!(f, "disconnected"),
+        }
+    }
+}
+
+/// What the bootstrap server tells us about our network address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetInfo {
+    /// The address the server lists us at, while registered
+    pub public_addr: Option<String>,
+    /// Result of probing the server, once done
+    pub nat: Option<NatProbe>,
+}
+
+/// A peer stream opened through the bootstrap server.
+pub enum RendezvousStream {
+    /// A UDP path punched through both sides' NATs
+    Punched(UdpStream),
+    /// A circuit relayed by the server
+    Relayed(RelayStream),
+}
+
+/// Peer ID of the other side and circuit ID of a relay circuit.
+type CircuitKey = (String, String);
+
+/// Hole punching and relay state shared between the client and its [`Rendezvous`] handles.
+struct RendezvousState {
+    server_url: String,
+    /// Address of the server, once connected.
+    server_addr: Mutex<Option<SocketAddr>>,
+    /// Our peer ID, while registered.
+    peer_id: Mutex<Option<String>>,
+    /// Peer IDs of listed peers by identity key.
+    peer_ids: Mutex<HashMap<PublicKey, String>>,
+    /// Listed peers that cannot accept connections, by identity key.
+    unreachable: Mutex<HashSet<PublicKey>>,
+    /// Connect requests waiting for their session, by target peer ID.
+    requests: Mutex<HashMap<String, oneshot::Sender<std::result::Result<String, String>>>>,
+    /// Sessions waiting for the other side's candidates, by session ID.
+    sessions: Mutex<HashMap<String, oneshot::Sender<PunchCandidates>>>,
+    /// Open relay circuits, by peer ID and circuit ID.
+    circuits: Mutex<HashMap<CircuitKey, mpsc::UnboundedSender<Vec<u8>>>>,
+    /// Messages for the server, sent by the client's event loop.
+    outgoing_tx: mpsc::UnboundedSender<ClientMessage>,
+    outgoing_rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<ClientMessage>>,
+    /// Streams punched or relayed at another peer's request.
+    incoming_tx: mpsc::UnboundedSender<RendezvousStream>,
+    incoming_rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<RendezvousStream>>,
+}
+
+/// Handle for opening peer connections through the bootstrap server.
+#[derive(Clone)]
+pub struct Rendezvous {
+    state: Arc<RendezvousState>,
+}
+
+impl Rendezvous {
+    fn new(server_url: String) -> Self {
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        Self {
+            state: Arc::new(RendezvousState {
+                server_url,
+                server_addr: Mutex::new(None),
+                peer_id: Mutex::new(None),
+                peer_ids: Mutex::new(HashMap::new()),
+                unreachable: Mutex::new(HashSet::new()),
+                requests: Mutex::new(HashMap::new()),
+                sessions: Mutex::new(HashMap::new()),
+                circuits: Mutex::new(HashMap::new()),
+                outgoing_tx,
+                outgoing_rx: tokio::sync::Mutex::new(outgoing_rx),
+                incoming_tx,
+                incoming_rx: tokio::sync::Mutex::new(incoming_rx),
+            }),
+        }
+    }
+
+    /// Punches a path to a peer listed by the bootstrap server.
+    pub async fn connect(&self, key: &PublicKey) -> Result<UdpStream> {
+        let target = self.state.listed_peer_id(key)?;
+
+        let (tx, rx) = oneshot::channel();
+        lock(&self.state.requests).insert(target.clone(), tx);
+        let request = ClientMessage::ConnectRequest {
+            target_peer_id: target.clone(),
+        };
+        if self.state.outgoing_tx.send(request).is_err() {
+            lock(&self.state.requests).remove(&target);
+            return Err(ParlanceError::Traversal(
+                "bootstrap client is not running".to_string(),
+            ));
+        }
+
+        let session = match timeout(PUNCH_SIGNAL_TIMEOUT, rx).await {
+            Ok(Ok(Ok(session))) => session,
+            Ok(Ok(Err(message))) => return Err(ParlanceError::Traversal(message)),
+            _ => {
+                lock(&self.state.requests).remove(&target);
+                return Err(ParlanceError::Traversal(
+                    "bootstrap server did not open a punch session".to_string(),
+                ));
+            }
+        };
+
+        self.state.run_session(session).await
+    }
+
+    /// Opens a relay circuit to a peer listed by the bootstrap server.
+    ///
+    /// Fails only if the peer is unknown or we are not registered. If the
+    /// server does not relay, it rejects the circuit and the stream ends
+    /// once the rejection arrives.
+    pub fn relay(&self, key: &PublicKey) -> Result<RelayStream> {
+        let target = self.state.listed_peer_id(key)?;
+        if lock(&self.state.peer_id).is_none() {
+            return Err(ParlanceError::Traversal(
+                "not registered with the bootstrap server".to_string(),
+            ));
+        }
+        Ok(self.state.open_circuit(target, Uuid::new_v4().to_string()))
+    }
+
+    /// Whether the bootstrap server lists a peer as able to accept connections.
+    ///
+    /// Peers behind a NAT that lets nothing in can only be punched or relayed to.
+    pub fn accepts_connections(&self, key: &PublicKey) -> bool {
+        !lock(&self.state.unreachable).contains(key)
+    }
+
+    /// Waits for a stream punched or relayed at another peer's request.
+    pub async fn accept(&self) -> Option<RendezvousStream> {
+        self.state.incoming_rx.lock().await.recv().await
+    }
+}
+
+impl RendezvousState {
+    /// Peer ID the bootstrap server lists for an identity key.
+    fn listed_peer_id(&self, key: &PublicKey) -> Result<String> {
+        lock(&self.peer_ids).get(key).cloned().ok_or_else(|| {
+            ParlanceError::Traversal("peer is not listed by the bootstrap server".to_string())
+        })
+    }
+
+    /// Starts a relay circuit and forwards the stream's chunks to the server.
+    fn open_circuit(&self, peer_id: String, circuit: String) -> RelayStream {
+        let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+        lock(&self.circuits).insert((peer_id.clone(), circuit.clone()), inbound_tx);
+
+        let outgoing = self.outgoing_tx.clone();
+        tokio::spawn(async move {
+            while let Some(chunk) = outbound_rx.recv().await {
+                let fin = chunk.is_empty();
+                let msg = ClientMessage::Relay {
+                    to: peer_id.clone(),
+                    circuit: circuit.clone(),
+                    payload: BASE64.encode(chunk),
+                };
+                if outgoing.send(msg).is_err() || fin {
+                    break;
+                }
+            }
+        });
+
+        let server_addr =
+            lock(&self.server_addr).unwrap_or(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0));
+        RelayStream::open(server_addr, outbound_tx, inbound_rx)
+    }
+
+    /// Routes a relayed chunk to its circuit, opening the circuit if the peer started it.
+    fn relay_received(&self, from: String, circuit: String, chunk: Vec<u8>) {
+        let key = (from, circuit);
+        let existing = lock(&self.circuits).get(&key).cloned();
+        match existing {
+            Some(inbound) => {
+                if chunk.is_empty() || inbound.send(chunk).is_err() {
+                    lock(&self.circuits).remove(&key);
+                }
+            }
+            // A closing chunk for a circuit we no longer know needs no answer
+            None if chunk.is_empty() => {}
+            None => {
+                tracing::debug!(peer_id = %key.0, "Peer opened a relay circuit");
+                let stream = self.open_circuit(key.0.clone(), key.1.clone());
+                if let Some(inbound) = lock(&self.circuits).get(&key) {
+                    let _ = inbound.send(chunk);
+                }
+                let _ = self.incoming_tx.send(RendezvousStream::Relayed(stream));
+            }
+        }
+    }
+
+    /// Binds a socket for a session, waits for the candidates and punches.
+    async fn run_session(&self, session: String) -> Result<UdpStream> {
+        let session_id = session
+            .parse::<Uuid>()
+            .map_err(|e| ParlanceError::InvalidMessage(format!("invalid punch session: {}", e)))?;
+        let peer_id = lock(&self.peer_id).clone().ok_or_else(|| {
+            ParlanceError::Traversal("not registered with the bootstrap server".to_string())
+        })?;
+
+        let server = server_udp_addr(&self.server_url).await?;
+        let socket = UdpSocket::bind(unspecified(server)).await?;
+        let local_addr = local_candidate(&socket, server).await?;
+
+        let (tx, mut rx) = oneshot::channel();
+        lock(&self.sessions).insert(session.clone(), tx);
+        let bind = serde_json::to_vec(&PunchBind {
+            session: session.clone(),
+            peer_id,
+            local_addr: local_addr.to_string(),
+        })?;
+
+        // Datagrams may be lost, so keep binding until the server answers
+        let mut interval = tokio::time::interval(PUNCH_BIND_INTERVAL);
+        let deadline = sleep(PUNCH_SIGNAL_TIMEOUT);
+        tokio::pin!(deadline);
+        let candidates = loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    socket.send_to(&bind, server).await?;
+                }
+                result = &mut rx => break result.ok(),
+                _ = &mut deadline => break None,
+            }
+        };
+        let Some(candidates) = candidates else {
+            lock(&self.sessions).remove(&session);
+            return Err(ParlanceError::Traversal(
+                "bootstrap server did not send the peer's candidates".to_string(),
+            ));
+        };
+
+        let mut addrs = Vec::new();
+        for candidate in [&candidates.public_addr, &candidates.local_addr] {
+            if let Ok(addr) = candidate.parse::<SocketAddr>() {
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+        let start_at = UNIX_EPOCH + Duration::from_millis(candidates.start_at.max(0) as u64);
+
+        let socket: Arc<dyn Datagram> = Arc::new(socket);
+        let remote = punch(&*socket, session_id, &addrs, start_at, PUNCH_TIMEOUT).await?;
+        Ok(UdpStream::open(socket, remote, session_id))
+    }
+}
+
+/// Resolves the UDP address of the bootstrap server from its URL.
+async fn server_udp_addr(server_url: &str) -> Result<SocketAddr> {
+    let uri = server_url
+        .parse::<Uri>()
+        .map_err(|e| ParlanceError::BootstrapConnection(e.to_string()))?;
+    let host = uri
+        .host()
+        .ok_or_else(|| ParlanceError::BootstrapConnection("server URL has no host".to_string()))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = match (uri.port_u16(), uri.scheme_str()) {
+        (Some(port), _) => port,
+        (None, Some("wss")) => 443,
+        (None, _) => 80,
+    };
+
+    let addr = tokio::net::lookup_host((host, port)).await?.next();
+    addr.ok_or_else(|| ParlanceError::BootstrapConnection(format!("cannot resolve {}", host)))
+}
+
+/// Probes the server's first two probe ports from a fresh UDP socket.
+async fn probe_nat(server_url: &str, probe_ports: &[u16]) -> Result<NatProbe> {
+    let [primary, alternate, ..] = probe_ports else {
+        return Err(ParlanceError::Traversal(
+            "server announced fewer than two probe ports".to_string(),
+        ));
+    };
+    let server = server_udp_addr(server_url).await?;
+    let socket = UdpSocket::bind(unspecified(server)).await?;
+    let local_addr = local_candidate(&socket, server).await?;
+
+    probe(
+        &socket,
+        local_addr,
+        SocketAddr::new(server.ip(), *primary),
+        SocketAddr::new(server.ip(), *alternate),
+        PROBE_TIMEOUT,
+    )
+    .await
+}
+
+/// The address update to send after probing, if the registration needs one.
+///
+/// Without a NAT the address the server guessed is right. Behind one, the
+/// registered address stays, but peers are told not to dial it.
+fn address_update(nat: &NatProbe, registered: String) -> Option<ClientMessage> {
+    if nat.directly_reachable() {
+        return None;
+    }
+    Some(ClientMessage::UpdateAddress {
+        public_addr: registered,
+        unreachable: true,
+    })
+}
+
+/// Unspecified address of the same family as `addr`, with any port.
+fn unspecified(addr: SocketAddr) -> SocketAddr {
+    let ip = match addr {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    SocketAddr::new(ip, 0)
+}
+
+/// Address of `socket` on the local network, using the interface that routes to `server`.
+async fn local_candidate(socket: &UdpSocket, server: SocketAddr) -> Result<SocketAddr> {
+    let probe = UdpSocket::bind(unspecified(server)).await?;
+    probe.connect(server).await?;
+    Ok(SocketAddr::new(
+        probe.local_addr()?.ip(),
+        socket.local_addr()?.port(),
+    ))
 }
 
 /// Bootstrap client for connecting to the bootstrap server.
 pub struct BootstrapClient {
     server_url: String,
     nickname: String,
+    public_key: PublicKey,
     local_addr: SocketAddr,
     peer_registry: Arc<PeerRegistry>,
     ws_stream: Option<WsStream>,
     peer_id: Option<String>,
-    public_addr: Option<String>,
+    status: watch::Sender<BootstrapStatus>,
+    net_info: Arc<watch::Sender<NetInfo>>,
+    port_mapping: watch::Receiver<Option<PortMapping>>,
+    hello: Hello,
+    rendezvous: Rendezvous,
 }
 
 impl BootstrapClient {
@@ -79,20 +539,51 @@ impl BootstrapClient {
     pub fn new(
         server_url: String,
         nickname: String,
+        public_key: PublicKey,
         local_addr: SocketAddr,
         peer_registry: Arc<PeerRegistry>,
     ) -> Self {
         Self {
+            rendezvous: Rendezvous::new(server_url.clone()),
             server_url,
             nickname,
+            public_key,
             local_addr,
             peer_registry,
             ws_stream: None,
             peer_id: None,
-            public_addr: None,
+            status: watch::channel(BootstrapStatus::Connecting).0,
+            net_info: Arc::new(watch::channel(NetInfo::default()).0),
+            port_mapping: watch::channel(None).1,
+            hello: Hello::current(),
         }
     }
 
+    /// Registers the address the router forwards to us, whenever it changes.
+    pub fn set_port_mapping(&mut self, port_mapping: watch::Receiver<Option<PortMapping>>) {
+        self.port_mapping = port_mapping;
+    }
+
+    /// Sets the protocol versions and features to register with.
+    pub fn set_hello(&mut self, hello: Hello) {
+        self.hello = hello;
+    }
+
+    /// Subscribes to changes of the connection state.
+    pub fn status(&self) -> watch::Receiver<BootstrapStatus> {
+        self.status.subscribe()
+    }
+
+    /// Subscribes to our public address and NAT type as learned from the server.
+    pub fn net_info(&self) -> watch::Receiver<NetInfo> {
+        self.net_info.subscribe()
+    }
+
+    /// Gets a handle for hole punching through this client's server.
+    pub fn rendezvous(&self) -> Rendezvous {
+        self.rendezvous.clone()
+    }
+
     /// Connects to the bootstrap server.
     pub async fn connect(&mut self) -> Result<()> {
         tracing::info!(url = %self.server_url, "Connecting to bootstrap server");
@@ -102,6 +593,7 @@ impl BootstrapClient {
             .map_err(|e| ParlanceError::BootstrapConnection(e.to_string()))?;
 
         self.ws_stream = Some(ws_stream);
+        *lock(&self.rendezvous.state.server_addr) = server_udp_addr(&self.server_url).await.ok();
 
         tracing::info!("Connected to bootstrap server");
         Ok(())
@@ -112,6 +604,8 @@ impl BootstrapClient {
         let msg = ClientMessage::Register {
             nickname: self.nickname.clone(),
             local_addr: self.local_addr.to_string(),
+            public_key: self.public_key,
+            hello: self.hello.clone(),
         };
 
         self.send_message(&msg).await?;
@@ -172,19 +666,113 @@ impl BootstrapClient {
             ServerMessage::Registered {
                 peer_id,
                 public_addr,
+                probe_ports,
             } => {
                 tracing::info!(
                     peer_id = %peer_id,
                     public_addr = %public_addr,
                     "Registered with bootstrap server"
                 );
+                // Address updates are confirmed with another registration
+                let first = self.peer_id.is_none();
+                *lock(&self.rendezvous.state.peer_id) = Some(peer_id.clone());
                 self.peer_id = Some(peer_id);
-                self.public_addr = Some(public_addr);
+                self.status.send_replace(BootstrapStatus::Connected);
+                self.net_info
+                    .send_modify(|info| info.public_addr = Some(public_addr));
+                if first {
+                    let mapping = *self.port_mapping.borrow();
+                    if let Some(mapping) = mapping {
+                        self.announce_mapping(mapping).await?;
+                    }
+                    if probe_ports.len() >= 2 {
+                        self.spawn_probe(probe_ports);
+                    }
+                }
             }
             ServerMessage::PeerList { peers } => {
-                tracing::debug!(count = peers.len(), "Received peer list from bootstrap server");
+                tracing::debug!(
+                    count = peers.len(),
+                    "Received peer list from bootstrap server"
+                );
                 self.update_peer_registry(peers).await?;
             }
+            ServerMessage::Incompatible {
+                protocol_version,
+                min_protocol_version,
+            } => {
+                let hello = Hello::current();
+                return Err(ParlanceError::IncompatibleProtocol(format!(
+                    "bootstrap server accepts protocol {}-{}, we speak {}-{}",
+                    min_protocol_version,
+                    protocol_version,
+                    hello.min_protocol_version,
+                    hello.protocol_version
+                )));
+            }
+            ServerMessage::PunchPrepare {
+                session,
+                peer_id,
+                initiator,
+            } => {
+                if initiator {
+                    if let Some(request) = lock(&self.rendezvous.state.requests).remove(&peer_id) {
+                        let _ = request.send(Ok(session));
+                    }
+                } else {
+                    tracing::debug!(peer_id = %peer_id, "Peer asked to punch a connection");
+                    let state = self.rendezvous.state.clone();
+                    tokio::spawn(async move {
+                        match state.run_session(session).await {
+                            Ok(stream) => {
+                                let _ = state.incoming_tx.send(RendezvousStream::Punched(stream));
+                            }
+                            Err(e) => {
+                                tracing::warn!(peer_id = %peer_id, error = %e, "Hole punch failed");
+                            }
+                        }
+                    });
+                }
+            }
+            ServerMessage::PunchStart {
+                session,
+                public_addr,
+                local_addr,
+                start_at,
+                ..
+            } => {
+                if let Some(waiting) = lock(&self.rendezvous.state.sessions).remove(&session) {
+                    let _ = waiting.send(PunchCandidates {
+                        public_addr,
+                        local_addr,
+                        start_at,
+                    });
+                }
+            }
+            ServerMessage::PunchFailed { peer_id, message } => {
+                if let Some(request) = lock(&self.rendezvous.state.requests).remove(&peer_id) {
+                    let _ = request.send(Err(message));
+                }
+            }
+            ServerMessage::Relay {
+                from,
+                circuit,
+                payload,
+            } => match BASE64.decode(payload) {
+                Ok(chunk) => self.rendezvous.state.relay_received(from, circuit, chunk),
+                Err(e) => {
+                    tracing::warn!(peer_id = %from, error = %e, "Invalid relay payload");
+                }
+            },
+            ServerMessage::RelayFailed {
+                peer_id,
+                circuit,
+                message,
+            } => {
+                tracing::warn!(peer_id = %peer_id, error = %message, "Relay failed");
+                // Dropping the circuit ends the stream, failing the connection on top
+                lock(&self.rendezvous.state.circuits).remove(&(peer_id, circuit));
+            }
             ServerMessage::Error { message } => {
                 tracing::error!(error = %message, "Bootstrap server error");
                 return Err(ParlanceError::BootstrapServerError(message));
@@ -193,6 +781,55 @@ impl BootstrapClient {
         Ok(())
     }
 
+    /// Probes our NAT in the background and corrects the registered address.
+    fn spawn_probe(&self, probe_ports: Vec<u16>) {
+        let server_url = self.server_url.clone();
+        let net_info = self.net_info.clone();
+        let port_mapping = self.port_mapping.clone();
+        let outgoing = self.rendezvous.state.outgoing_tx.clone();
+
+        tokio::spawn(async move {
+            let nat = match probe_nat(&server_url, &probe_ports).await {
+                Ok(nat) => nat,
+                Err(e) => {
+                    tracing::warn!(error = %e, "Address probe failed");
+                    return;
+                }
+            };
+            tracing::info!(
+                mapped_addr = %nat.mapped_addr,
+                nat_type = %nat.nat_type,
+                "Probed NAT"
+            );
+
+            let registered = net_info.borrow().public_addr.clone();
+            net_info.send_modify(|info| info.nat = Some(nat));
+
+            // A forwarded port is registered as soon as it is mapped
+            if port_mapping.borrow().is_some() {
+                return;
+            }
+            if let Some(update) = registered.and_then(|addr| address_update(&nat, addr)) {
+                let _ = outgoing.send(update);
+            }
+        });
+    }
+
+    /// Registers the router's forwarded address, unless already listed there.
+    async fn announce_mapping(&mut self, mapping: PortMapping) -> Result<()> {
+        let public_addr = mapping.external_addr.to_string();
+        let listed = self.net_info.borrow().public_addr.clone();
+        if self.peer_id.is_none() || listed.as_deref() == Some(public_addr.as_str()) {
+            return Ok(());
+        }
+        tracing::info!(public_addr = %public_addr, "Registering forwarded address");
+        self.send_message(&ClientMessage::UpdateAddress {
+            public_addr,
+            unreachable: false,
+        })
+        .await
+    }
+
     /// Updates the peer registry with peers from the bootstrap server.
     async fn update_peer_registry(&self, peers: Vec<PeerInfo>) -> Result<()> {
         for peer_info in peers {
@@ -202,6 +839,38 @@ impl BootstrapClient {
                 }
             }
 
+            let public_key = match peer_info.public_key.parse::<PublicKey>() {
+                Ok(key) => key,
+                Err(e) => {
+                    tracing::warn!(
+                        peer_id = %peer_info.peer_id,
+                        error = %e,
+                        "Invalid peer public key, skipping"
+                    );
+                    continue;
+                }
+            };
+
+            if public_key == self.public_key {
+                continue;
+            }
+
+            if let Err(e) = Hello::current().check(&peer_info.hello) {
+                tracing::debug!(
+                    peer_id = %peer_info.peer_id,
+                    error = %e,
+                    "Skipping peer with incompatible protocol"
+                );
+                continue;
+            }
+
+            lock(&self.rendezvous.state.peer_ids).insert(public_key, peer_info.peer_id.clone());
+            if peer_info.unreachable {
+                lock(&self.rendezvous.state.unreachable).insert(public_key);
+            } else {
+                lock(&self.rendezvous.state.unreachable).remove(&public_key);
+            }
+
             let addr = if let Ok(addr) = peer_info.public_addr.parse::<SocketAddr>() {
                 addr
             } else if let Ok(addr) = peer_info.local_addr.parse::<SocketAddr>() {
@@ -214,7 +883,9 @@ impl BootstrapClient {
                 continue;
             };
 
-            let peer = Peer::new(peer_info.nickname, addr);
+            let peer = Peer::new(peer_info.nickname, addr, public_key)
+                .via(DiscoverySource::Bootstrap)
+                .with_features(peer_info.hello.features);
             self.peer_registry.upsert(peer).await;
         }
 
@@ -236,6 +907,7 @@ impl BootstrapClient {
         let mut reconnect_delay = INITIAL_RECONNECT_DELAY_SECS;
 
         loop {
+            self.status.send_replace(BootstrapStatus::Connecting);
             match self.connect().await {
                 Ok(()) => {
                     if let Err(e) = self.register().await {
@@ -248,6 +920,11 @@ impl BootstrapClient {
 
                     if let Err(e) = self.run_loop().await {
                         tracing::error!(error = %e, "Bootstrap client error");
+                        // Reconnecting cannot fix a version mismatch
+                        if matches!(e, ParlanceError::IncompatibleProtocol(_)) {
+                            self.status.send_replace(BootstrapStatus::Disconnected);
+                            return Err(e);
+                        }
                     }
                 }
                 Err(e) => {
@@ -259,19 +936,24 @@ impl BootstrapClient {
                 }
             }
 
+            self.status.send_replace(BootstrapStatus::Disconnected);
             sleep(Duration::from_secs(reconnect_delay)).await;
 
             reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY_SECS);
 
             self.ws_stream = None;
             self.peer_id = None;
-            self.public_addr = None;
+            self.net_info.send_replace(NetInfo::default());
+            *lock(&self.rendezvous.state.peer_id) = None;
+            // Circuits are addressed by peer IDs the server has now forgotten
+            lock(&self.rendezvous.state.circuits).clear();
         }
     }
 
     /// Main event loop for the bootstrap client.
     async fn run_loop(&mut self) -> Result<()> {
-        let mut heartbeat_interval = tokio::time::interval(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
+        let mut heartbeat_interval =
+            tokio::time::interval(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
         let mut peer_list_interval = tokio::time::interval(Duration::from_secs(5));
 
         heartbeat_interval.tick().await;
@@ -279,8 +961,33 @@ impl BootstrapClient {
 
         self.request_peer_list().await?;
 
+        let mut port_mapping = self.port_mapping.clone();
+        port_mapping.borrow_and_update();
+
+        let state = self.rendezvous.state.clone();
+        let mut outgoing = state.outgoing_rx.lock().await;
+        // Requests queued while disconnected refer to stale peer IDs
+        while outgoing.try_recv().is_ok() {}
+
         loop {
             tokio::select! {
+                Some(msg) = outgoing.recv() => {
+                    if let Err(e) = self.send_message(&msg).await {
+                        tracing::error!(error = %e, "Failed to send rendezvous message");
+                        return Err(e);
+                    }
+                }
+
+                Ok(()) = port_mapping.changed() => {
+                    let mapping = *port_mapping.borrow_and_update();
+                    if let Some(mapping) = mapping {
+                        if let Err(e) = self.announce_mapping(mapping).await {
+                            tracing::error!(error = %e, "Failed to register forwarded address");
+                            return Err(e);
+                        }
+                    }
+                }
+
                 _ = heartbeat_interval.tick() => {
                     if let Err(e) = self.send_heartbeat().await {
                         tracing::error!(error = %e, "Failed to send heartbeat");
@@ -317,6 +1024,7 @@ impl BootstrapClient {
     }
 
     /// Disconnects from the bootstrap server.
+    #[allow(dead_code)]
     pub async fn disconnect(&mut self) -> Result<()> {
         if let Some(mut ws) = self.ws_stream.take() {
             let _ = self.send_message(&ClientMessage::Unregister).await;
@@ -330,12 +1038,15 @@ impl BootstrapClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::identity::Identity;
 
     #[test]
     fn test_client_message_serialization() {
         let msg = ClientMessage::Register {
             nickname: "test".to_string(),
             local_addr: "192.168.1.1:5000".to_string(),
+            public_key: Identity::generate().public_key(),
+            hello: Hello::current(),
         };
         let json = serde_json::to_string(&msg).unwrap();
         assert!(json.contains("\"type\":\"register\""));
@@ -353,14 +1064,114 @@ mod tests {
             ServerMessage::Registered {
                 peer_id,
                 public_addr,
+                probe_ports,
             } => {
                 assert_eq!(peer_id, "123");
                 assert_eq!(public_addr, "1.2.3.4:5000");
+                assert!(probe_ports.is_empty());
             }
             _ => panic!("Expected Registered message"),
         }
     }
 
+    #[test]
+    fn test_address_probe_messages() {
+        let json = r#"{"type":"registered","peer_id":"123","public_addr":"1.2.3.4:5000","probe_ports":[8081,8082]}"#;
+        let msg: ServerMessage = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            msg,
+            ServerMessage::Registered { probe_ports, .. } if probe_ports == [8081, 8082]
+        ));
+
+        let msg = ClientMessage::UpdateAddress {
+            public_addr: "1.2.3.4:5000".to_string(),
+            unreachable: false,
+        };
+        let json = serde_json::to_string(&msg).unwrap();
+        assert!(json.contains("\"type\":\"update_address\""));
+    }
+
+    #[test]
+    fn test_incompatible_message_deserialization() {
+        let json = r#"{"type":"incompatible","protocol_version":3,"min_protocol_version":2}"#;
+        let msg: ServerMessage = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            msg,
+            ServerMessage::Incompatible {
+                protocol_version: 3,
+                min_protocol_version: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_punch_messages_deserialization() {
+        let json = r#"{"type":"punch_start","session":"s1","peer_id":"id2","public_key":"ab","public_addr":"1.2.3.4:40000","local_addr":"192.168.1.100:40000","start_at":1699564800000}"#;
+        match serde_json::from_str::<ServerMessage>(json).unwrap() {
+            ServerMessage::PunchStart {
+                public_addr,
+                start_at,
+                ..
+            } => {
+                assert_eq!(public_addr, "1.2.3.4:40000");
+                assert_eq!(start_at, 1699564800000);
+            }
+            other => panic!("Expected PunchStart message, got {:?}", other),
+        }
+
+        let json = r#"{"type":"punch_failed","peer_id":"id2","message":"Unknown peer"}"#;
+        assert_eq!(
+            serde_json::from_str::<ServerMessage>(json).unwrap(),
+            ServerMessage::PunchFailed {
+                peer_id: "id2".to_string(),
+                message: "Unknown peer".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_relay_circuit_opened_by_peer() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let rendezvous = Rendezvous::new("ws://127.0.0.1:8080".to_string());
+        let state = rendezvous.state.clone();
+        state.relay_received("id2".to_string(), "c1".to_string(), b"hello".to_vec());
+
+        let mut stream = match rendezvous.accept().await {
+            Some(RendezvousStream::Relayed(stream)) => stream,
+            _ => panic!("Expected a relayed stream"),
+        };
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        stream.write_all(b"hi").await.unwrap();
+        let reply = state.outgoing_rx.lock().await.recv().await.unwrap();
+        assert_eq!(
+            reply,
+            ClientMessage::Relay {
+                to: "id2".to_string(),
+                circuit: "c1".to_string(),
+                payload: BASE64.encode(b"hi"),
+            }
+        );
+
+        // The peer closing the circuit ends the stream
+        state.relay_received("id2".to_string(), "c1".to_string(), Vec::new());
+        assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+        assert!(lock(&state.circuits).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_server_udp_addr() {
+        let addr = server_udp_addr("ws://127.0.0.1:8080").await.unwrap();
+        assert_eq!(addr, "127.0.0.1:8080".parse().unwrap());
+
+        let addr = server_udp_addr("ws://[::1]").await.unwrap();
+        assert_eq!(addr, "[::1]:80".parse().unwrap());
+    }
+
     #[test]
     fn test_peer_info_deserialization() {
         let json = r#"{
@@ -368,6 +1179,7 @@ mod tests {
             "nickname": "alice",
             "public_addr": "1.2.3.4:5000",
             "local_addr": "192.168.1.100:5000",
+            "public_key": "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
             "last_seen": 1699564800
         }"#;
 
@@ -376,7 +1188,124 @@ mod tests {
         assert_eq!(peer_info.nickname, "alice");
         assert_eq!(peer_info.public_addr, "1.2.3.4:5000");
         assert_eq!(peer_info.local_addr, "192.168.1.100:5000");
+        assert!(peer_info.public_key.parse::<PublicKey>().is_ok());
         assert_eq!(peer_info.last_seen, 1699564800);
+        assert!(!peer_info.unreachable);
+        assert_eq!(peer_info.hello.protocol_version, 0);
+    }
+
+    /// Answer probes the way they look from behind a restricted cone NAT
+    ///
+    /// Every probe seems to come from the same public address, and answers
+    /// from the other port are filtered out.
+    async fn serve_probes_behind_nat(socket: UdpSocket) {
+        let mut buf = [0u8; 512];
+        loop {
+            let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+            let request: serde_json::Value = serde_json::from_slice(&buf[..len]).unwrap();
+            if request["change_port"] == true {
+                continue;
+            }
+            let response = serde_json::json!({
+                "id": request["id"],
+                "mapped_addr": "203.0.113.7:61000",
+            });
+            let _ = socket.send_to(response.to_string().as_bytes(), from).await;
+        }
+    }
+
+    async fn next_client_message(ws: &mut WebSocketStream<tokio::net::TcpStream>) -> ClientMessage {
+        loop {
+            let message = timeout(Duration::from_secs(5), ws.next())
+                .await
+                .expect("timed out waiting for the client")
+                .unwrap()
+                .unwrap();
+            if let Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registers_as_unreachable_behind_nat() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let mut probe_ports = Vec::new();
+        for _ in 0..2 {
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            probe_ports.push(socket.local_addr().unwrap().port());
+            tokio::spawn(serve_probes_behind_nat(socket));
+        }
+
+        let mut client = BootstrapClient::new(
+            format!("ws://{}", server_addr),
+            "alice".to_string(),
+            Identity::generate().public_key(),
+            "127.0.0.1:5000".parse().unwrap(),
+            Arc::new(PeerRegistry::new()),
+        );
+        let rendezvous = client.rendezvous();
+        tokio::spawn(async move { client.run().await });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        assert!(matches!(
+            next_client_message(&mut ws).await,
+            ClientMessage::Register { .. }
+        ));
+
+        let registered = ServerMessage::Registered {
+            peer_id: "id1".to_string(),
+            public_addr: "127.0.0.1:5000".to_string(),
+            probe_ports,
+        };
+        ws.send(Message::Text(serde_json::to_string(&registered).unwrap()))
+            .await
+            .unwrap();
+
+        let update = loop {
+            let message = next_client_message(&mut ws).await;
+            if matches!(message, ClientMessage::UpdateAddress { .. }) {
+                break message;
+            }
+        };
+        // The guessed address stays, but nobody should dial it
+        assert_eq!(
+            update,
+            ClientMessage::UpdateAddress {
+                public_addr: "127.0.0.1:5000".to_string(),
+                unreachable: true,
+            }
+        );
+
+        let bob = Identity::generate().public_key();
+        let peers = ServerMessage::PeerList {
+            peers: vec![PeerInfo {
+                peer_id: "id2".to_string(),
+                nickname: "bob".to_string(),
+                public_addr: "198.51.100.2:5000".to_string(),
+                local_addr: "10.0.0.2:5000".to_string(),
+                public_key: bob.to_string(),
+                last_seen: 1699564800,
+                unreachable: true,
+                hello: Hello::current(),
+            }],
+        };
+        ws.send(Message::Text(serde_json::to_string(&peers).unwrap()))
+            .await
+            .unwrap();
+        timeout(Duration::from_secs(5), async {
+            while rendezvous.accepts_connections(&bob)
+                || rendezvous.state.listed_peer_id(&bob).is_err()
+            {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("peer list not applied");
     }
 
     #[tokio::test]
@@ -385,6 +1314,7 @@ mod tests {
         let client = BootstrapClient::new(
             "ws://localhost:8080".to_string(),
             "test".to_string(),
+            Identity::generate().public_key(),
             "127.0.0.1:5000".parse().unwrap(),
             registry,
         );
@@ -392,6 +1322,6 @@ mod tests {
         assert_eq!(client.server_url, "ws://localhost:8080");
         assert_eq!(client.nickname, "test");
         assert!(client.peer_id.is_none());
-        assert!(client.public_addr.is_none());
+        assert_eq!(*client.net_info().borrow(), NetInfo::default());
     }
 }
@@ -0,0 +1,470 @@
+//! LAN-wide public channels over UDP multicast.
+//!
+//! Channels are named `#something` and carried on their own multicast group
+//! next to the discovery group. A message posted to a channel is a single
+//! signed multicast packet that every client on the LAN receives; clients
+//! only show it if they joined the channel. Clients also multicast the list
+//! of channels they joined at the announce interval, which is how `/who`
+//! knows the members of a channel.
+//!
+//! Packets are signed, timestamped and de-duplicated the same way as
+//! discovery announcements, and must fit in [`MAX_CHANNEL_PACKET_SIZE`].
+//! Channel messages are not encrypted: anyone on the LAN can read them.
+
+use crate::core::error::{ParlanceError, Result};
+use crate::core::identity::{Identity, PublicKey};
+use crate::core::peer::PeerRegistry;
+use crate::core::sync::lock;
+use crate::core::validation::ChannelNameValidator;
+use crate::network::discovery::{bind_multicast, Rejection, MAX_ANNOUNCEMENT_AGE};
+use crate::network::messaging::MessageId;
+use crate::network::signed::SignedPayload;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time;
+
+/// Multicast group address for channels
+/// This is part of the Parlance protocol - all peers must use the same address
+pub const CHANNEL_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 251);
+
+/// Multicast port for channels
+/// This is part of the Parlance protocol - all peers must use the same port
+pub const CHANNEL_MULTICAST_PORT: u16 = 6790;
+
+/// Maximum size of a channel packet, so it fits in a single Ethernet frame
+pub const MAX_CHANNEL_PACKET_SIZE: usize = 1400;
+
+/// Maximum length of a channel message in bytes
+pub const MAX_CHANNEL_MESSAGE_LEN: usize = 1000;
+
+/// Domain separation prefix for signing channel packets
+const SIGNATURE_CONTEXT: &[u8] = b"parlance-channel:";
+
+/// A message posted to a channel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelMessage {
+    /// Unique message ID
+    pub id: MessageId,
+    /// Channel name, including the `#`
+    pub channel: String,
+    /// Sender's nickname
+    pub from: String,
+    /// Sender's identity public key
+    pub public_key: PublicKey,
+    /// Message content
+    pub content: String,
+    /// Unix timestamp (seconds since epoch)
+    pub timestamp: i64,
+}
+
+impl ChannelMessage {
+    /// Format the message for display
+    ///
+    /// Senders whose safety number was verified get a check mark badge.
+    pub fn format(&self, verified: bool) -> String {
+        let datetime = chrono::DateTime::from_timestamp(self.timestamp, 0)
+            .map(|dt| dt.format("%H:%M:%S").to_string())
+            .unwrap_or_else(|| "??:??:??".to_string());
+
+        let badge = if verified { " ✔" } else { "" };
+
+        format!(
+            "[{}] {} {}{}: {}",
+            datetime, self.channel, self.from, badge, self.content
+        )
+    }
+}
+
+/// Packets sent to the channel multicast group
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChannelPacket {
+    /// A message posted to a channel
+    Post(ChannelMessage),
+    /// The full list of channels the sender has joined
+    Presence {
+        id: MessageId,
+        from: String,
+        public_key: PublicKey,
+        channels: Vec<String>,
+        timestamp: i64,
+    },
+}
+
+impl ChannelPacket {
+    fn id(&self) -> MessageId {
+        match self {
+            Self::Post(msg) => msg.id,
+            Self::Presence { id, .. } => *id,
+        }
+    }
+
+    /// Get the identity key of the sender
+    pub fn public_key(&self) -> &PublicKey {
+        match self {
+            Self::Post(msg) => &msg.public_key,
+            Self::Presence { public_key, .. } => public_key,
+        }
+    }
+
+    fn timestamp(&self) -> i64 {
+        match self {
+            Self::Post(msg) => msg.timestamp,
+            Self::Presence { timestamp, .. } => *timestamp,
+        }
+    }
+
+    /// Sign the packet and encode it for the wire
+    fn encode(&self, identity: &Identity) -> Result<Vec<u8>> {
+        let data = serde_json::to_vec(&SignedPayload::sign(self, SIGNATURE_CONTEXT, identity)?)?;
+
+        if data.len() > MAX_CHANNEL_PACKET_SIZE {
+            return Err(ParlanceError::InvalidMessage(format!(
+                "Channel packet of {} bytes exceeds {} bytes",
+                data.len(),
+                MAX_CHANNEL_PACKET_SIZE
+            )));
+        }
+        Ok(data)
+    }
+}
+
+/// A channel member seen in presence packets
+struct Member {
+    nickname: String,
+    last_seen: Instant,
+}
+
+/// Channel membership and packet handling, independent of the socket
+pub struct ChannelRouter {
+    nickname: String,
+    identity: Identity,
+    /// Channels we joined
+    joined: Mutex<BTreeSet<String>>,
+    /// Other members of every channel, by identity key
+    members: Mutex<HashMap<String, HashMap<PublicKey, Member>>>,
+    /// Packet IDs seen within the freshness window, with their timestamps
+    seen: Mutex<HashMap<(PublicKey, MessageId), i64>>,
+}
+
+impl ChannelRouter {
+    /// Create a router for our identity
+    pub fn new(nickname: String, identity: Identity) -> Self {
+        Self {
+            nickname,
+            identity,
+            joined: Mutex::new(BTreeSet::new()),
+            members: Mutex::new(HashMap::new()),
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Join a channel, returning false if we already joined it
+    pub fn join(&self, channel: &str) -> Result<bool> {
+        let channel = normalize(channel)?;
+        Ok(lock(&self.joined).insert(channel))
+    }
+
+    /// Leave a channel, returning false if we had not joined it
+    pub fn leave(&self, channel: &str) -> Result<bool> {
+        let channel = normalize(channel)?;
+        Ok(lock(&self.joined).remove(&channel))
+    }
+
+    /// Check whether we joined a channel
+    pub fn is_joined(&self, channel: &str) -> bool {
+        normalize(channel).is_ok_and(|channel| lock(&self.joined).contains(&channel))
+    }
+
+    /// Get the channels we joined, sorted
+    pub fn joined(&self) -> Vec<String> {
+        lock(&self.joined).iter().cloned().collect()
+    }
+
+    /// Get the nicknames of a channel's members seen within `timeout`,
+    /// including ourselves if we joined it
+    pub fn members(&self, channel: &str, timeout: Duration) -> Result<Vec<String>> {
+        let channel = normalize(channel)?;
+        let mut nicknames: BTreeSet<String> = lock(&self.members)
+            .get(&channel)
+            .into_iter()
+            .flat_map(|members| members.values())
+            .filter(|m| m.last_seen.elapsed() <= timeout)
+            .map(|m| m.nickname.clone())
+            .collect();
+
+        if lock(&self.joined).contains(&channel) {
+            nicknames.insert(self.nickname.clone());
+        }
+        Ok(nicknames.into_iter().collect())
+    }
+
+    /// Build a signed post to a channel we joined
+    pub fn post(&self, channel: &str, content: String) -> Result<(ChannelMessage, Vec<u8>)> {
+        let channel = normalize(channel)?;
+        if !lock(&self.joined).contains(&channel) {
+            return Err(ParlanceError::Channel(format!(
+                "Join {} before posting to it",
+                channel
+            )));
+        }
+        if content.len() > MAX_CHANNEL_MESSAGE_LEN {
+            return Err(ParlanceError::InvalidMessage(format!(
+                "Channel messages are limited to {} bytes",
+                MAX_CHANNEL_MESSAGE_LEN
+            )));
+        }
+
+        let msg = ChannelMessage {
+            id: MessageId::new(),
+            channel,
+            from: self.nickname.clone(),
+            public_key: self.identity.public_key(),
+            content,
+            timestamp: Utc::now().timestamp(),
+        };
+        let data = ChannelPacket::Post(msg.clone()).encode(&self.identity)?;
+        Ok((msg, data))
+    }
+
+    /// Build a signed presence packet listing the channels we joined
+    pub fn presence(&self) -> Result<Vec<u8>> {
+        ChannelPacket::Presence {
+            id: MessageId::new(),
+            from: self.nickname.clone(),
+            public_key: self.identity.public_key(),
+            channels: self.joined(),
+            timestamp: Utc::now().timestamp(),
+        }
+        .encode(&self.identity)
+    }
+
+    /// Parse and verify a raw packet
+    ///
+    /// Rejects oversized, unsigned, stale and duplicate packets, and
+    /// ignores our own packets looped back by multicast.
+    pub fn receive(&self, data: &[u8]) -> std::result::Result<Option<ChannelPacket>, Rejection> {
+        if data.len() > MAX_CHANNEL_PACKET_SIZE {
+            return Err(Rejection::Malformed);
+        }
+
+        let now = Utc::now().timestamp();
+        let packet: ChannelPacket =
+            SignedPayload::open(data, SIGNATURE_CONTEXT, MAX_ANNOUNCEMENT_AGE, now)?;
+
+        if *packet.public_key() == self.identity.public_key() {
+            return Ok(None);
+        }
+
+        let mut seen = lock(&self.seen);
+        seen.retain(|_, timestamp| now.abs_diff(*timestamp) <= MAX_ANNOUNCEMENT_AGE.as_secs());
+        if seen
+            .insert((*packet.public_key(), packet.id()), packet.timestamp())
+            .is_some()
+        {
+            return Err(Rejection::Replayed);
+        }
+
+        Ok(Some(packet))
+    }
+
+    /// Replace the channels a member is in with the ones it announced
+    pub fn update_membership(&self, nickname: &str, key: &PublicKey, channels: &[String]) {
+        let channels: BTreeSet<String> =
+            channels.iter().filter_map(|c| normalize(c).ok()).collect();
+
+        let mut members = lock(&self.members);
+        for (channel, channel_members) in members.iter_mut() {
+            if !channels.contains(channel) {
+                channel_members.remove(key);
+            }
+        }
+        members.retain(|_, channel_members| !channel_members.is_empty());
+
+        for channel in channels {
+            members.entry(channel).or_default().insert(
+                *key,
+                Member {
+                    nickname: nickname.to_string(),
+                    last_seen: Instant::now(),
+                },
+            );
+        }
+    }
+}
+
+/// Channel service configuration
+pub struct ChannelConfig {
+    /// Our nickname
+    pub nickname: String,
+    /// Our identity keypair
+    pub identity: Identity,
+    /// Peer registry used to check senders against pinned keys
+    pub registry: PeerRegistry,
+    /// Interval between presence packets
+    pub presence_interval: Duration,
+    /// How long a member stays listed without a presence packet
+    pub member_timeout: Duration,
+}
+
+/// Channel service handle
+pub struct ChannelService {
+    socket: UdpSocket,
+    config: ChannelConfig,
+    multicast_addr: SocketAddr,
+    router: ChannelRouter,
+    message_tx: mpsc::UnboundedSender<ChannelMessage>,
+}
+
+impl ChannelService {
+    /// Create a new channel service
+    ///
+    /// Messages posted to joined channels are sent to `message_tx`.
+    pub async fn new(
+        config: ChannelConfig,
+        message_tx: mpsc::UnboundedSender<ChannelMessage>,
+    ) -> Result<Self> {
+        let socket = bind_multicast(CHANNEL_MULTICAST_ADDR, CHANNEL_MULTICAST_PORT)?;
+        let multicast_addr =
+            SocketAddr::new(IpAddr::V4(CHANNEL_MULTICAST_ADDR), CHANNEL_MULTICAST_PORT);
+
+        tracing::info!(
+            multicast_addr = %multicast_addr,
+            "Channel service started"
+        );
+
+        let router = ChannelRouter::new(config.nickname.clone(), config.identity.clone());
+        Ok(Self {
+            socket,
+            config,
+            multicast_addr,
+            router,
+            message_tx,
+        })
+    }
+
+    /// Join a channel and announce it
+    pub async fn join(&self, channel: &str) -> Result<bool> {
+        let joined = self.router.join(channel)?;
+        self.send_presence().await?;
+        Ok(joined)
+    }
+
+    /// Leave a channel and announce it
+    pub async fn leave(&self, channel: &str) -> Result<bool> {
+        let left = self.router.leave(channel)?;
+        self.send_presence().await?;
+        Ok(left)
+    }
+
+    /// Get the nicknames of a channel's current members
+    pub fn members(&self, channel: &str) -> Result<Vec<String>> {
+        self.router.members(channel, self.config.member_timeout)
+    }
+
+    /// Post a message to a channel we joined
+    pub async fn post(&self, channel: &str, content: String) -> Result<ChannelMessage> {
+        let (msg, data) = self.router.post(channel, content)?;
+        self.socket.send_to(&data, self.multicast_addr).await?;
+        tracing::debug!(channel = %msg.channel, id = %msg.id, "Posted to channel");
+        Ok(msg)
+    }
+
+    async fn send_presence(&self) -> Result<()> {
+        let data = self.router.presence()?;
+        self.socket.send_to(&data, self.multicast_addr).await?;
+        Ok(())
+    }
+
+    /// Handle a received channel packet
+    async fn handle_packet(&self, data: &[u8], from: SocketAddr) {
+        let packet = match self.router.receive(data) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => return,
+            Err(rejection) => {
+                tracing::debug!(from = %from, reason = %rejection, "Rejected channel packet");
+                return;
+            }
+        };
+
+        let (nickname, key) = match &packet {
+            ChannelPacket::Post(msg) => (&msg.from, &msg.public_key),
+            ChannelPacket::Presence {
+                from, public_key, ..
+            } => (from, public_key),
+        };
+        if !self.config.registry.is_trusted(nickname, key).await {
+            tracing::warn!(from = %from, nickname = %nickname, "Dropping channel packet from untrusted key");
+            return;
+        }
+
+        match packet {
+            ChannelPacket::Post(msg) => {
+                if !self.router.is_joined(&msg.channel) {
+                    return;
+                }
+                tracing::info!(channel = %msg.channel, from = %msg.from, "Channel message received");
+                if self.message_tx.send(msg).is_err() {
+                    tracing::error!("Channel message channel closed");
+                }
+            }
+            ChannelPacket::Presence {
+                from,
+                public_key,
+                channels,
+                ..
+            } => {
+                self.router.update_membership(&from, &public_key, &channels);
+            }
+        }
+    }
+
+    /// Run the channel service
+    ///
+    /// This listens for channel packets and periodically announces the
+    /// channels we joined.
+    pub async fn run(&self) -> Result<()> {
+        let presence_loop = async {
+            let mut interval = time::interval(self.config.presence_interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.send_presence().await {
+                    tracing::error!(error = ?e, "Failed to send channel presence");
+                }
+            }
+        };
+
+        let listen_loop = async {
+            // Larger than the packet limit so oversized packets are noticed
+            let mut buf = vec![0u8; 65536];
+            loop {
+                match self.socket.recv_from(&mut buf).await {
+                    Ok((len, from)) => self.handle_packet(&buf[..len], from).await,
+                    Err(e) => {
+                        tracing::error!(error = ?e, "Failed to receive on channel socket");
+                    }
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = presence_loop => {}
+            _ = listen_loop => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Validate a channel name and bring it into canonical (lowercase) form
+fn normalize(channel: &str) -> Result<String> {
+    ChannelNameValidator::validate(channel)
+        .map_err(|e| ParlanceError::Channel(format!("Invalid channel name: {}", e)))?;
+    Ok(channel.to_lowercase())
+}
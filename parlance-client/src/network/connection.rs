@@ -0,0 +1,958 @@
+//! Pooled, long-lived peer connections.
+//!
+//! The connection manager keeps at most one encrypted TCP connection per
+//! peer identity and uses it in both directions: whichever side dials first,
+//! later messages from either peer travel over the same connection. Failed
+//! dials back off exponentially, and connections that carry no traffic for
+//! the configured idle period are closed.
+//!
+//! When both peers dial each other at the same moment, both keep the
+//! connection dialed by the peer with the lower public key, so the two ends
+//! always agree on which connection survives.
+//!
+//! Connections run over any byte stream. When a peer listed by the bootstrap
+//! server cannot be reached over TCP and a [`Rendezvous`] is set, the pool
+//! punches a UDP path to it instead, and accepts the paths other peers punch
+//! to us. If punching fails too, the connection is relayed through the
+//! bootstrap server, and its state reads [`ConnectionState::Relayed`].
+//! Peers the server lists as unreachable are not dialed at all; the pool
+//! goes straight to punching.
+//!
+//! With a [`QuicEndpoint`] set, peers that advertise the `quic` feature are
+//! dialed over QUIC first, falling back to TCP if that fails, and QUIC
+//! connections from other peers are accepted into the same pool. A QUIC
+//! connection carries a second encrypted channel on a stream of its own,
+//! which [`ConnectionManager::send_bulk`] uses for file chunks.
+
+use crate::core::error::{ParlanceError, Result};
+use crate::core::identity::{Identity, PublicKey};
+use crate::core::peer::{DiscoverySource, Peer};
+use crate::network::bootstrap::{Rendezvous, RendezvousStream};
+use crate::network::protocol::{Negotiated, FEATURE_QUIC};
+use crate::network::quic::{QuicEndpoint, QuicStream};
+use crate::network::relay::RelayStream;
+use crate::network::secure::{SecureChannel, SecureReader, SecureWriter, DEFAULT_MAX_FRAME_SIZE};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+
+/// How long to wait for a TCP connection to a peer
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default time a connection may stay unused before it is closed
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Delay before retrying after the first failed dial
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Default upper bound for the reconnect delay
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A byte stream a peer connection can run over
+pub trait PeerStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> PeerStream for T {}
+
+type BoxStream = Box<dyn PeerStream>;
+
+type Reader = SecureReader<ReadHalf<BoxStream>>;
+
+type Writer = SecureWriter<WriteHalf<BoxStream>>;
+
+/// Connection pool settings
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    /// Close connections that carried no traffic for this long
+    pub idle_timeout: Duration,
+    /// Delay after the first failed dial, doubled on each further failure
+    pub initial_backoff: Duration,
+    /// Upper bound for the reconnect delay
+    pub max_backoff: Duration,
+    /// Largest frame sent or accepted on a connection
+    pub max_frame_size: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            initial_backoff: INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+/// State of the connection to a single peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// No connection is open
+    Disconnected,
+    /// A dial or handshake is in progress
+    Connecting,
+    /// An encrypted connection is open
+    Connected,
+    /// An encrypted connection is open through the bootstrap server's relay
+    Relayed,
+    /// The last dial failed; the next one waits until the delay expires
+    Backoff { failures: u32, retry_in: Duration },
+}
+
+impl std::fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionState::Disconnected => write!(f, "disconnected"),
+            ConnectionState::Connecting => write!(f, "connecting"),
+            ConnectionState::Connected => write!(f, "connected"),
+            ConnectionState::Relayed => write!(f, "relayed"),
+            ConnectionState::Backoff { retry_in, .. } => {
+                write!(f, "retrying in {}s", retry_in.as_secs().max(1))
+            }
+        }
+    }
+}
+
+/// A decrypted frame received on a pooled connection
+#[derive(Debug)]
+pub struct IncomingFrame {
+    /// Authenticated identity of the sender
+    pub from: PublicKey,
+    /// Remote address of the connection
+    pub addr: SocketAddr,
+    /// Frame payload
+    pub data: Vec<u8>,
+    /// Whether the frame came through the bootstrap server's relay
+    pub relayed: bool,
+}
+
+/// An open connection in the pool
+struct Connection {
+    id: u64,
+    /// Identity of the side that dialed this connection
+    dialer: PublicKey,
+    writer: Arc<tokio::sync::Mutex<Writer>>,
+    last_activity: Arc<Mutex<Instant>>,
+    /// Protocol version and features agreed in the handshake
+    protocol: Negotiated,
+    /// What the connection runs over
+    path: Path,
+    /// Channel for bulk data, on a QUIC stream of its own
+    bulk: Option<Arc<tokio::sync::Mutex<Writer>>>,
+}
+
+impl Connection {
+    /// Stop sending on every channel of the connection
+    async fn close(&self) {
+        let _ = self.writer.lock().await.close().await;
+        if let Some(bulk) = &self.bulk {
+            let _ = bulk.lock().await.close().await;
+        }
+    }
+}
+
+/// What a pooled connection runs over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Path {
+    /// TCP or a punched UDP path
+    Direct,
+    /// A QUIC connection
+    Quic,
+    /// The bootstrap server's relay
+    Relayed,
+}
+
+/// Pool bookkeeping for one peer
+#[derive(Default)]
+struct PeerSlot {
+    connection: Option<Connection>,
+    connecting: bool,
+    failures: u32,
+    retry_at: Option<Instant>,
+    /// Serializes dials so concurrent sends share one new connection
+    dial_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+struct Inner {
+    identity: Identity,
+    config: PoolConfig,
+    slots: Mutex<HashMap<PublicKey, PeerSlot>>,
+    incoming_tx: mpsc::UnboundedSender<IncomingFrame>,
+    next_id: AtomicU64,
+    /// Hole punching and relay fallback for peers not reachable over TCP
+    rendezvous: Mutex<Option<Rendezvous>>,
+    /// QUIC endpoint for peers that advertise QUIC support
+    quic: Mutex<Option<Arc<QuicEndpoint>>>,
+}
+
+/// Shared handle to the connection pool
+#[derive(Clone)]
+pub struct ConnectionManager {
+    inner: Arc<Inner>,
+}
+
+impl ConnectionManager {
+    /// Create a connection manager
+    ///
+    /// Frames received on any pooled connection are delivered to
+    /// `incoming_tx`. Must be called from within a Tokio runtime, since it
+    /// spawns the task that closes idle connections.
+    pub fn new(
+        identity: Identity,
+        config: PoolConfig,
+        incoming_tx: mpsc::UnboundedSender<IncomingFrame>,
+    ) -> Self {
+        let inner = Arc::new(Inner {
+            identity,
+            config,
+            slots: Mutex::new(HashMap::new()),
+            incoming_tx,
+            next_id: AtomicU64::new(0),
+            rendezvous: Mutex::new(None),
+            quic: Mutex::new(None),
+        });
+
+        tokio::spawn(reap_idle(Arc::downgrade(&inner)));
+
+        Self { inner }
+    }
+
+    /// Punch UDP paths or relay through the bootstrap server when TCP fails
+    ///
+    /// Also accepts the paths other peers punch or relay to us through it.
+    pub fn set_rendezvous(&self, rendezvous: Rendezvous) {
+        *self
+            .inner
+            .rendezvous
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = Some(rendezvous.clone());
+
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            while let Some(stream) = rendezvous.accept().await {
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                let manager = ConnectionManager { inner };
+                tokio::spawn(async move {
+                    let result = match stream {
+                        RendezvousStream::Punched(stream) => {
+                            let addr = stream.peer_addr();
+                            manager.accept_stream(stream, addr).await
+                        }
+                        RendezvousStream::Relayed(stream) => manager.accept_relayed(stream).await,
+                    };
+                    if let Err(e) = result {
+                        tracing::warn!(error = %e, "Rendezvous connection failed");
+                    }
+                });
+            }
+        });
+    }
+
+    /// Dial peers that advertise QUIC support over `endpoint`
+    ///
+    /// Also accepts the QUIC connections other peers open to it.
+    pub fn set_quic(&self, endpoint: Arc<QuicEndpoint>) {
+        *self.inner.quic.lock().unwrap_or_else(|e| e.into_inner()) = Some(endpoint.clone());
+
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                let manager = ConnectionManager { inner };
+                tokio::spawn(async move {
+                    let addr = incoming.peer_addr();
+                    let accepted = async {
+                        // Keepalives would hold open a peer that never opens its stream
+                        let stream = tokio::time::timeout(CONNECT_TIMEOUT, incoming.stream())
+                            .await
+                            .map_err(|_| {
+                                ParlanceError::ConnectionUnavailable(format!(
+                                    "{} did not open a stream",
+                                    addr
+                                ))
+                            })??;
+                        manager.accept_quic(stream, addr).await
+                    };
+                    if let Err(e) = accepted.await {
+                        tracing::warn!(peer = %addr, error = %e, "QUIC connection failed");
+                    }
+                });
+            }
+        });
+    }
+
+    /// Send a frame to a peer, reusing or opening its connection
+    ///
+    /// If writing to a pooled connection fails, the connection is dropped
+    /// and one fresh dial is attempted.
+    pub async fn send(&self, peer: &Peer, data: &[u8]) -> Result<()> {
+        self.check_frame_size(data)?;
+        if let Some((id, writer, last_activity)) = self.connection(&peer.public_key) {
+            match writer.lock().await.send(data).await {
+                Ok(()) => {
+                    touch(&last_activity);
+                    return Ok(());
+                }
+                Err(e) => {
+                    tracing::debug!(peer = %peer.nickname, error = %e, "Pooled connection failed");
+                    self.inner.remove(&peer.public_key, id);
+                }
+            }
+        }
+
+        let (_, writer, last_activity) = self.connect(peer).await?;
+        writer.lock().await.send(data).await?;
+        touch(&last_activity);
+        Ok(())
+    }
+
+    /// Send a frame over an already open connection without dialing
+    ///
+    /// Used for replies such as acknowledgements, which must travel back
+    /// over the connection the request arrived on.
+    pub async fn send_existing(&self, key: &PublicKey, data: &[u8]) -> Result<()> {
+        let handle = self.connection(key);
+        self.send_on(key, handle, data).await
+    }
+
+    /// Send bulk data, such as a file chunk, over an already open connection
+    ///
+    /// Over QUIC it travels on a stream of its own, so messages sent in the
+    /// meantime do not wait behind it. Other connections carry it like any
+    /// other frame.
+    pub async fn send_bulk(&self, key: &PublicKey, data: &[u8]) -> Result<()> {
+        let handle = self.bulk_connection(key);
+        self.send_on(key, handle, data).await
+    }
+
+    async fn send_on(
+        &self,
+        key: &PublicKey,
+        handle: Option<ConnectionHandle>,
+        data: &[u8],
+    ) -> Result<()> {
+        self.check_frame_size(data)?;
+        let (id, writer, last_activity) = handle.ok_or_else(|| {
+            ParlanceError::ConnectionUnavailable(format!("no open connection to {}", key))
+        })?;
+
+        if let Err(e) = writer.lock().await.send(data).await {
+            self.inner.remove(key, id);
+            return Err(e);
+        }
+        touch(&last_activity);
+        Ok(())
+    }
+
+    /// Reject frames the connection would refuse, without dropping it
+    fn check_frame_size(&self, data: &[u8]) -> Result<()> {
+        let max = self.inner.config.max_frame_size;
+        if data.len() > max {
+            return Err(ParlanceError::FrameTooLarge {
+                size: data.len(),
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    /// Get the pooled connection to a peer, dialing it if necessary
+    async fn connect(&self, peer: &Peer) -> Result<ConnectionHandle> {
+        let dial_lock = self
+            .inner
+            .slot_mut(&peer.public_key, |slot| slot.dial_lock.clone());
+        let _guard = dial_lock.lock().await;
+
+        // Another task may have connected while we waited
+        if let Some(handle) = self.connection(&peer.public_key) {
+            return Ok(handle);
+        }
+
+        let retry_in = self.inner.slot_mut(&peer.public_key, |slot| {
+            let retry_in = slot
+                .retry_at
+                .and_then(|at| at.checked_duration_since(Instant::now()));
+            if retry_in.is_none() {
+                slot.connecting = true;
+            }
+            retry_in
+        });
+        if let Some(retry_in) = retry_in {
+            return Err(ParlanceError::ConnectionUnavailable(format!(
+                "{} is unreachable, retrying in {}s",
+                peer.nickname,
+                retry_in.as_secs().max(1)
+            )));
+        }
+
+        let rendezvous = self.punch_rendezvous(peer);
+        let direct = self.dials_directly(peer, rendezvous.as_ref());
+
+        let mut quic = None;
+        if let Some(endpoint) = self.quic_endpoint(peer).filter(|_| direct) {
+            match quic_dial(peer, &self.inner.identity, &endpoint).await {
+                Ok(connected) => quic = Some(connected),
+                Err(e) => tracing::debug!(
+                    peer = %peer.nickname,
+                    error = %e,
+                    "QUIC dial failed, falling back to TCP"
+                ),
+            }
+        }
+
+        let mut path = Path::Direct;
+        let mut bulk = None;
+        let mut result = match quic {
+            Some((channel, bulk_channel, addr)) => {
+                path = Path::Quic;
+                bulk = Some(bulk_channel);
+                Ok((channel, addr))
+            }
+            None if direct => dial(peer, &self.inner.identity)
+                .await
+                .map(|channel| (channel, peer.addr)),
+            None => Err(ParlanceError::ConnectionUnavailable(format!(
+                "{} accepts no direct connections",
+                peer.nickname
+            ))),
+        };
+        if let Err(e) = &result {
+            if let Some(rendezvous) = rendezvous {
+                tracing::debug!(
+                    peer = %peer.nickname,
+                    error = %e,
+                    "Dial failed, punching a UDP path"
+                );
+                result = punch_dial(peer, &self.inner.identity, &rendezvous).await;
+                if let Err(e) = &result {
+                    tracing::debug!(
+                        peer = %peer.nickname,
+                        error = %e,
+                        "Punch failed, relaying through the bootstrap server"
+                    );
+                    result = relay_dial(peer, &self.inner.identity, &rendezvous).await;
+                    path = Path::Relayed;
+                }
+            }
+        }
+
+        match result {
+            Ok((channel, addr)) => {
+                tracing::debug!(
+                    peer = %peer.nickname,
+                    addr = %addr,
+                    path = ?path,
+                    "Connected to peer"
+                );
+                let dialer = self.inner.identity.public_key();
+                Ok(self.inner.register(channel, bulk, addr, dialer, path))
+            }
+            Err(e) => {
+                let delay = self.inner.slot_mut(&peer.public_key, |slot| {
+                    slot.connecting = false;
+                    slot.failures += 1;
+                    let delay = backoff_delay(&self.inner.config, slot.failures);
+                    slot.retry_at = Some(Instant::now() + delay);
+                    delay
+                });
+                tracing::warn!(
+                    peer = %peer.nickname,
+                    addr = %peer.addr,
+                    error = %e,
+                    retry_in_secs = delay.as_secs(),
+                    "Failed to connect to peer"
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// The rendezvous to punch or relay through, if the peer is listed by the bootstrap server
+    fn punch_rendezvous(&self, peer: &Peer) -> Option<Rendezvous> {
+        if !peer.sources().contains(&DiscoverySource::Bootstrap) {
+            return None;
+        }
+        self.inner
+            .rendezvous
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Whether to dial the peer's address before punching or relaying
+    ///
+    /// Not if the address came from the bootstrap server and the server
+    /// lists the peer as unreachable there.
+    fn dials_directly(&self, peer: &Peer, rendezvous: Option<&Rendezvous>) -> bool {
+        let from_bootstrap = peer.sources().first() == Some(&DiscoverySource::Bootstrap);
+        !from_bootstrap || rendezvous.is_none_or(|r| r.accepts_connections(&peer.public_key))
+    }
+
+    /// The QUIC endpoint to dial over, if the peer advertises QUIC support
+    fn quic_endpoint(&self, peer: &Peer) -> Option<Arc<QuicEndpoint>> {
+        if !peer.supports(FEATURE_QUIC) {
+            return None;
+        }
+        self.inner
+            .quic
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Get the protocol agreed with a peer, connecting to it if necessary
+    pub async fn protocol(&self, peer: &Peer) -> Result<Negotiated> {
+        if self.connection(&peer.public_key).is_none() {
+            self.connect(peer).await?;
+        }
+        self.inner
+            .lock_slots()
+            .get(&peer.public_key)
+            .and_then(|slot| slot.connection.as_ref())
+            .map(|conn| conn.protocol.clone())
+            .ok_or_else(|| {
+                ParlanceError::ConnectionUnavailable(format!(
+                    "connection to {} closed",
+                    peer.nickname
+                ))
+            })
+    }
+
+    /// Run the handshake on an inbound connection and add it to the pool
+    pub async fn accept(&self, stream: TcpStream, addr: SocketAddr) -> Result<PublicKey> {
+        self.accept_stream(stream, addr).await
+    }
+
+    /// Run the handshake on an inbound stream of any kind and add it to the pool
+    pub async fn accept_stream(
+        &self,
+        stream: impl PeerStream + 'static,
+        addr: SocketAddr,
+    ) -> Result<PublicKey> {
+        self.accept_over(Box::new(stream), addr, Path::Direct).await
+    }
+
+    /// Run the handshake on a circuit another peer opened through the relay
+    pub async fn accept_relayed(&self, stream: RelayStream) -> Result<PublicKey> {
+        let addr = stream.relay_addr();
+        self.accept_over(Box::new(stream), addr, Path::Relayed)
+            .await
+    }
+
+    async fn accept_over(
+        &self,
+        stream: BoxStream,
+        addr: SocketAddr,
+        path: Path,
+    ) -> Result<PublicKey> {
+        let channel = SecureChannel::accept(stream, &self.inner.identity).await?;
+        let remote_key = channel.remote_public_key();
+        self.inner.register(channel, None, addr, remote_key, path);
+        Ok(remote_key)
+    }
+
+    /// Run the handshakes on an inbound QUIC connection and add it to the pool
+    ///
+    /// The dialer opens the bulk stream right after the first handshake.
+    async fn accept_quic(&self, stream: QuicStream, addr: SocketAddr) -> Result<PublicKey> {
+        let connection = stream.connection();
+        let channel =
+            SecureChannel::accept(Box::new(stream) as BoxStream, &self.inner.identity).await?;
+        let remote_key = channel.remote_public_key();
+
+        let bulk_stream = tokio::time::timeout(CONNECT_TIMEOUT, connection.accept_stream())
+            .await
+            .map_err(|_| {
+                ParlanceError::ConnectionUnavailable(format!("{} did not open a bulk stream", addr))
+            })??;
+        let bulk =
+            SecureChannel::accept(Box::new(bulk_stream) as BoxStream, &self.inner.identity).await?;
+        if bulk.remote_public_key() != remote_key {
+            return Err(ParlanceError::Handshake(
+                "bulk stream presented a different identity key".to_string(),
+            ));
+        }
+
+        self.inner
+            .register(channel, Some(bulk), addr, remote_key, Path::Quic);
+        Ok(remote_key)
+    }
+
+    /// Get the state of the connection to a peer
+    pub fn state(&self, key: &PublicKey) -> ConnectionState {
+        let slots = self.inner.lock_slots();
+        let Some(slot) = slots.get(key) else {
+            return ConnectionState::Disconnected;
+        };
+
+        if let Some(conn) = &slot.connection {
+            return if conn.path == Path::Relayed {
+                ConnectionState::Relayed
+            } else {
+                ConnectionState::Connected
+            };
+        }
+        if slot.connecting {
+            return ConnectionState::Connecting;
+        }
+        match slot
+            .retry_at
+            .and_then(|at| at.checked_duration_since(Instant::now()))
+        {
+            Some(retry_in) => ConnectionState::Backoff {
+                failures: slot.failures,
+                retry_in,
+            },
+            None => ConnectionState::Disconnected,
+        }
+    }
+
+    /// Check whether the open connection to a peer runs over QUIC
+    pub fn uses_quic(&self, key: &PublicKey) -> bool {
+        self.inner
+            .lock_slots()
+            .get(key)
+            .and_then(|slot| slot.connection.as_ref())
+            .is_some_and(|conn| conn.path == Path::Quic)
+    }
+
+    /// Close the connection to a peer, if any
+    pub async fn close(&self, key: &PublicKey) {
+        let conn = self
+            .inner
+            .lock_slots()
+            .get_mut(key)
+            .and_then(|slot| slot.connection.take());
+
+        if let Some(conn) = conn {
+            conn.close().await;
+        }
+    }
+
+    fn connection(&self, key: &PublicKey) -> Option<ConnectionHandle> {
+        self.inner
+            .lock_slots()
+            .get(key)
+            .and_then(|slot| slot.connection.as_ref())
+            .map(|conn| (conn.id, conn.writer.clone(), conn.last_activity.clone()))
+    }
+
+    /// Like [`Self::connection`], but with the bulk channel's writer if there is one
+    fn bulk_connection(&self, key: &PublicKey) -> Option<ConnectionHandle> {
+        self.inner
+            .lock_slots()
+            .get(key)
+            .and_then(|slot| slot.connection.as_ref())
+            .map(|conn| {
+                let writer = conn.bulk.as_ref().unwrap_or(&conn.writer);
+                (conn.id, writer.clone(), conn.last_activity.clone())
+            })
+    }
+}
+
+type ConnectionHandle = (u64, Arc<tokio::sync::Mutex<Writer>>, Arc<Mutex<Instant>>);
+
+impl Inner {
+    fn lock_slots(&self) -> std::sync::MutexGuard<'_, HashMap<PublicKey, PeerSlot>> {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn slot_mut<T>(&self, key: &PublicKey, f: impl FnOnce(&mut PeerSlot) -> T) -> T {
+        f(self.lock_slots().entry(*key).or_default())
+    }
+
+    /// Add an established channel to the pool and start reading from it
+    fn register(
+        self: &Arc<Self>,
+        mut channel: SecureChannel<BoxStream>,
+        bulk: Option<SecureChannel<BoxStream>>,
+        addr: SocketAddr,
+        dialer: PublicKey,
+        path: Path,
+    ) -> ConnectionHandle {
+        let remote_key = channel.remote_public_key();
+        let protocol = channel.protocol().clone();
+        channel.set_max_frame_size(self.config.max_frame_size);
+        let (mut reader, writer) = channel.into_split();
+        let (bulk_reader, bulk_writer) = match bulk {
+            Some(mut bulk) => {
+                bulk.set_max_frame_size(self.config.max_frame_size);
+                let (reader, writer) = bulk.into_split();
+                (
+                    Some(reader),
+                    Some(Arc::new(tokio::sync::Mutex::new(writer))),
+                )
+            }
+            None => (None, None),
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        let last_activity = Arc::new(Mutex::new(Instant::now()));
+        let handle = (id, writer.clone(), last_activity.clone());
+
+        let superseded = {
+            let mut slots = self.lock_slots();
+            let slot = slots.entry(remote_key).or_default();
+            slot.connecting = false;
+            slot.failures = 0;
+            slot.retry_at = None;
+
+            let keep_existing = slot
+                .connection
+                .as_ref()
+                .is_some_and(|existing| prefer_existing(existing.dialer, dialer));
+
+            let new_conn = Connection {
+                id,
+                dialer,
+                writer,
+                last_activity: last_activity.clone(),
+                protocol,
+                path,
+                bulk: bulk_writer,
+            };
+            if keep_existing {
+                Some(new_conn)
+            } else {
+                slot.connection.replace(new_conn)
+            }
+        };
+
+        // The losing connection stops sending but keeps reading until the
+        // peer closes it, so frames already in flight are not lost
+        if let Some(conn) = superseded {
+            tracing::debug!(addr = %addr, "Closing duplicate connection");
+            tokio::spawn(async move { conn.close().await });
+        }
+
+        let relayed = path == Path::Relayed;
+
+        // The bulk channel is read until the connection itself closes
+        let (closed_tx, closed_rx) = oneshot::channel::<()>();
+        if let Some(mut bulk_reader) = bulk_reader {
+            let incoming_tx = self.incoming_tx.clone();
+            let last_activity = last_activity.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = read_frames(&mut bulk_reader, remote_key, addr, relayed, &last_activity, &incoming_tx) => {}
+                    _ = closed_rx => {}
+                }
+            });
+        }
+
+        let inner = Arc::downgrade(self);
+        let incoming_tx = self.incoming_tx.clone();
+        tokio::spawn(async move {
+            read_frames(
+                &mut reader,
+                remote_key,
+                addr,
+                relayed,
+                &last_activity,
+                &incoming_tx,
+            )
+            .await;
+            drop(closed_tx);
+
+            tracing::debug!(peer = %addr, "Connection closed");
+            if let Some(inner) = inner.upgrade() {
+                inner.remove(&remote_key, id);
+            }
+        });
+
+        handle
+    }
+
+    /// Drop a connection from the pool if it is still the current one
+    fn remove(&self, key: &PublicKey, id: u64) {
+        if let Some(slot) = self.lock_slots().get_mut(key) {
+            if slot.connection.as_ref().is_some_and(|c| c.id == id) {
+                slot.connection = None;
+            }
+        }
+    }
+}
+
+/// Pass the frames read from a channel on to the pool's receiver
+///
+/// Returns once the channel closes or fails.
+async fn read_frames(
+    reader: &mut Reader,
+    from: PublicKey,
+    addr: SocketAddr,
+    relayed: bool,
+    last_activity: &Mutex<Instant>,
+    incoming_tx: &mpsc::UnboundedSender<IncomingFrame>,
+) {
+    loop {
+        match reader.recv().await {
+            Ok(Some(data)) => {
+                touch(last_activity);
+                let frame = IncomingFrame {
+                    from,
+                    addr,
+                    data,
+                    relayed,
+                };
+                if incoming_tx.send(frame).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!(peer = %addr, error = %e, "Connection error");
+                return;
+            }
+        }
+    }
+}
+
+/// Decide which of two connections to the same peer survives
+///
+/// A reconnect from the same side replaces the old connection; otherwise
+/// the connection dialed by the lower key wins on both ends.
+fn prefer_existing(existing_dialer: PublicKey, new_dialer: PublicKey) -> bool {
+    existing_dialer != new_dialer && existing_dialer.as_bytes() < new_dialer.as_bytes()
+}
+
+/// Reconnect delay after `failures` consecutive failed dials
+pub fn backoff_delay(config: &PoolConfig, failures: u32) -> Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    config
+        .initial_backoff
+        .saturating_mul(1 << exponent)
+        .min(config.max_backoff)
+}
+
+/// Open a TCP connection and check the peer presented the expected identity
+async fn dial(peer: &Peer, identity: &Identity) -> Result<SecureChannel<BoxStream>> {
+    let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(peer.addr))
+        .await
+        .map_err(|_| {
+            ParlanceError::ConnectionUnavailable(format!("connection to {} timed out", peer.addr))
+        })??;
+
+    initiate(Box::new(stream), peer, identity).await
+}
+
+/// Open a QUIC connection and check the peer presented the expected identity
+///
+/// Returns the main channel and the bulk channel on a stream of its own.
+async fn quic_dial(
+    peer: &Peer,
+    identity: &Identity,
+    endpoint: &QuicEndpoint,
+) -> Result<(
+    SecureChannel<BoxStream>,
+    SecureChannel<BoxStream>,
+    SocketAddr,
+)> {
+    let stream = tokio::time::timeout(CONNECT_TIMEOUT, endpoint.connect(peer.addr))
+        .await
+        .map_err(|_| {
+            ParlanceError::ConnectionUnavailable(format!(
+                "QUIC connection to {} timed out",
+                peer.addr
+            ))
+        })??;
+
+    let addr = stream.peer_addr();
+    let connection = stream.connection();
+    let channel = initiate(Box::new(stream), peer, identity).await?;
+    let bulk = initiate(Box::new(connection.open_stream().await?), peer, identity).await?;
+    Ok((channel, bulk, addr))
+}
+
+/// Punch a UDP path to the peer and open the encrypted channel over it
+async fn punch_dial(
+    peer: &Peer,
+    identity: &Identity,
+    rendezvous: &Rendezvous,
+) -> Result<(SecureChannel<BoxStream>, SocketAddr)> {
+    let stream = rendezvous.connect(&peer.public_key).await?;
+    let addr = stream.peer_addr();
+    Ok((initiate(Box::new(stream), peer, identity).await?, addr))
+}
+
+/// Open the encrypted channel to the peer through the bootstrap server's relay
+async fn relay_dial(
+    peer: &Peer,
+    identity: &Identity,
+    rendezvous: &Rendezvous,
+) -> Result<(SecureChannel<BoxStream>, SocketAddr)> {
+    let stream = rendezvous.relay(&peer.public_key)?;
+    let addr = stream.relay_addr();
+    Ok((initiate(Box::new(stream), peer, identity).await?, addr))
+}
+
+/// Run the handshake and check the peer presented the expected identity
+async fn initiate(
+    stream: BoxStream,
+    peer: &Peer,
+    identity: &Identity,
+) -> Result<SecureChannel<BoxStream>> {
+    let channel = SecureChannel::initiate(stream, identity).await?;
+
+    if channel.remote_public_key() != peer.public_key {
+        return Err(ParlanceError::Handshake(format!(
+            "{} presented an unexpected identity key",
+            peer.nickname
+        )));
+    }
+
+    Ok(channel)
+}
+
+fn touch(last_activity: &Mutex<Instant>) {
+    *last_activity.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+}
+
+/// Periodically close connections that have been idle too long
+async fn reap_idle(inner: Weak<Inner>) {
+    let Some(idle_timeout) = inner.upgrade().map(|inner| inner.config.idle_timeout) else {
+        return;
+    };
+    let mut interval = tokio::time::interval((idle_timeout / 4).max(Duration::from_millis(10)));
+
+    loop {
+        interval.tick().await;
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+
+        let idle: Vec<Connection> = {
+            let mut slots = inner.lock_slots();
+            slots
+                .values_mut()
+                .filter_map(|slot| {
+                    let is_idle = slot.connection.as_ref().is_some_and(|conn| {
+                        conn.last_activity
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .elapsed()
+                            > idle_timeout
+                    });
+                    if is_idle {
+                        slot.connection.take()
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        };
+
+        for conn in idle {
+            tracing::debug!(connection = conn.id, "Closing idle connection");
+            conn.close().await;
+        }
+    }
+}
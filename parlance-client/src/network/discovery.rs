@@ -3,11 +3,28 @@
 //! This module implements automatic peer discovery on the local network
 //! using UDP multicast. Peers broadcast their presence every 5 seconds
 //! and listen for announcements from others.
+//!
+//! Every announcement and goodbye is signed by the sender's identity key
+//! and carries a timestamp and random nonce. Unsigned, stale or replayed
+//! packets are dropped and counted, so other hosts on the LAN cannot inject
+//! fake peers or redirect a nickname to their own address. The signature
+//! covers the payload bytes as sent (see [`crate::network::signed`]), so
+//! announcements from newer versions with extra fields still verify.
+//!
+//! Announcements also carry the sender's protocol [`Hello`]; peers whose
+//! protocol versions do not overlap with ours are not added.
 
 use crate::core::error::{ParlanceError, Result};
-use crate::core::peer::{Peer, PeerRegistry};
+use crate::core::identity::{Identity, PublicKey};
+use crate::core::peer::{DiscoverySource, Peer, PeerRegistry};
+use crate::network::protocol::Hello;
+use crate::network::signed::SignedPayload;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::net::UdpSocket;
 use tokio::time;
@@ -20,20 +37,248 @@ pub const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
 /// This is part of the Parlance protocol - all peers must use the same port
 pub const MULTICAST_PORT: u16 = 6789;
 
+/// Maximum age of an announcement before it is rejected as stale
+///
+/// Also bounds how far in the future a timestamp may be, to tolerate
+/// small clock differences between peers.
+pub const MAX_ANNOUNCEMENT_AGE: Duration = Duration::from_secs(30);
+
+/// Domain separation prefix for signing discovery messages
+pub const SIGNATURE_CONTEXT: &[u8] = b"parlance-discovery:";
+
 /// Discovery message types
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum DiscoveryMessage {
     /// Announce presence to other peers
-    Announce { nickname: String, tcp_port: u16 },
+    Announce {
+        nickname: String,
+        tcp_port: u16,
+        public_key: PublicKey,
+        timestamp: i64,
+        nonce: u64,
+        #[serde(flatten)]
+        hello: Hello,
+    },
     /// Goodbye message when shutting down
-    Goodbye { nickname: String },
+    Goodbye {
+        nickname: String,
+        public_key: PublicKey,
+        timestamp: i64,
+        nonce: u64,
+    },
+}
+
+impl DiscoveryMessage {
+    /// Create an announcement advertising `hello`, stamped with the current
+    /// time and a fresh nonce
+    pub fn announce_with(
+        nickname: String,
+        tcp_port: u16,
+        public_key: PublicKey,
+        hello: Hello,
+    ) -> Self {
+        Self::Announce {
+            nickname,
+            tcp_port,
+            public_key,
+            timestamp: Utc::now().timestamp(),
+            nonce: rand::random(),
+            hello,
+        }
+    }
+
+    /// Create a goodbye stamped with the current time and a fresh nonce
+    pub fn goodbye(nickname: String, public_key: PublicKey) -> Self {
+        Self::Goodbye {
+            nickname,
+            public_key,
+            timestamp: Utc::now().timestamp(),
+            nonce: rand::random(),
+        }
+    }
+
+    /// Get the identity key of the sender
+    pub fn public_key(&self) -> &PublicKey {
+        match self {
+            Self::Announce { public_key, .. } | Self::Goodbye { public_key, .. } => public_key,
+        }
+    }
+
+    /// Get the Unix timestamp the message was created at
+    pub fn timestamp(&self) -> i64 {
+        match self {
+            Self::Announce { timestamp, .. } | Self::Goodbye { timestamp, .. } => *timestamp,
+        }
+    }
+
+    /// Get the message nonce
+    pub fn nonce(&self) -> u64 {
+        match self {
+            Self::Announce { nonce, .. } | Self::Goodbye { nonce, .. } => *nonce,
+        }
+    }
+
+    /// Sign the message with our identity
+    pub fn sign(&self, identity: &Identity) -> Result<SignedPayload> {
+        SignedPayload::sign(self, SIGNATURE_CONTEXT, identity)
+    }
+}
+
+/// Reason a discovery packet was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// Packet could not be parsed
+    Malformed,
+    /// Packet carried no signature
+    Unsigned,
+    /// Signature does not match the sender's key
+    BadSignature,
+    /// Timestamp is too old or too far in the future
+    Stale,
+    /// Nonce was already seen from this sender
+    Replayed,
+    /// Sender speaks a protocol version we cannot talk to
+    Incompatible,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::Malformed => write!(f, "malformed"),
+            Rejection::Unsigned => write!(f, "unsigned"),
+            Rejection::BadSignature => write!(f, "bad signature"),
+            Rejection::Stale => write!(f, "stale"),
+            Rejection::Replayed => write!(f, "replayed"),
+            Rejection::Incompatible => write!(f, "incompatible protocol"),
+        }
+    }
+}
+
+/// Counters of rejected discovery packets, for diagnostics
+#[derive(Debug, Default)]
+pub struct DiscoveryStats {
+    malformed: AtomicU64,
+    unsigned: AtomicU64,
+    bad_signature: AtomicU64,
+    stale: AtomicU64,
+    replayed: AtomicU64,
+    incompatible: AtomicU64,
+}
+
+/// Point-in-time copy of [`DiscoveryStats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RejectionCounts {
+    pub malformed: u64,
+    pub unsigned: u64,
+    pub bad_signature: u64,
+    pub stale: u64,
+    pub replayed: u64,
+    pub incompatible: u64,
+}
+
+impl RejectionCounts {
+    /// Total number of rejected packets
+    pub fn total(&self) -> u64 {
+        self.malformed
+            + self.unsigned
+            + self.bad_signature
+            + self.stale
+            + self.replayed
+            + self.incompatible
+    }
+}
+
+impl DiscoveryStats {
+    /// Record a rejected packet
+    pub fn record(&self, rejection: Rejection) {
+        let counter = match rejection {
+            Rejection::Malformed => &self.malformed,
+            Rejection::Unsigned => &self.unsigned,
+            Rejection::BadSignature => &self.bad_signature,
+            Rejection::Stale => &self.stale,
+            Rejection::Replayed => &self.replayed,
+            Rejection::Incompatible => &self.incompatible,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the current rejection counts
+    pub fn snapshot(&self) -> RejectionCounts {
+        RejectionCounts {
+            malformed: self.malformed.load(Ordering::Relaxed),
+            unsigned: self.unsigned.load(Ordering::Relaxed),
+            bad_signature: self.bad_signature.load(Ordering::Relaxed),
+            stale: self.stale.load(Ordering::Relaxed),
+            replayed: self.replayed.load(Ordering::Relaxed),
+            incompatible: self.incompatible.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Checks signatures, freshness and nonces of incoming discovery packets
+pub struct AnnouncementVerifier {
+    max_age: Duration,
+    /// Nonces seen within the freshness window, with their timestamps
+    seen: Mutex<HashMap<(PublicKey, u64), i64>>,
+    stats: Arc<DiscoveryStats>,
+}
+
+impl AnnouncementVerifier {
+    /// Create a verifier accepting messages up to `max_age` old
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            seen: Mutex::new(HashMap::new()),
+            stats: Arc::new(DiscoveryStats::default()),
+        }
+    }
+
+    /// Get the shared rejection counters
+    pub fn stats(&self) -> Arc<DiscoveryStats> {
+        self.stats.clone()
+    }
+
+    /// Parse and verify a raw packet, counting it if rejected
+    pub fn verify(&self, data: &[u8]) -> std::result::Result<DiscoveryMessage, Rejection> {
+        self.check(data).inspect_err(|&rejection| {
+            self.stats.record(rejection);
+        })
+    }
+
+    fn check(&self, data: &[u8]) -> std::result::Result<DiscoveryMessage, Rejection> {
+        let now = Utc::now().timestamp();
+        let message: DiscoveryMessage =
+            SignedPayload::open(data, SIGNATURE_CONTEXT, self.max_age, now)?;
+
+        if let DiscoveryMessage::Announce { hello, .. } = &message {
+            Hello::current()
+                .check(hello)
+                .map_err(|_| Rejection::Incompatible)?;
+        }
+
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        seen.retain(|_, timestamp| now.abs_diff(*timestamp) <= self.max_age.as_secs());
+        if seen
+            .insert(
+                (*message.public_key(), message.nonce()),
+                message.timestamp(),
+            )
+            .is_some()
+        {
+            return Err(Rejection::Replayed);
+        }
+
+        Ok(message)
+    }
 }
 
 /// Discovery service configuration
 pub struct DiscoveryConfig {
     /// Our nickname
     pub nickname: String,
+    /// Our identity keypair
+    pub identity: Identity,
     /// Our TCP port for messaging
     pub tcp_port: u16,
     /// Peer registry to update
@@ -42,6 +287,8 @@ pub struct DiscoveryConfig {
     pub announce_interval: Duration,
     /// Peer timeout duration
     pub peer_timeout: Duration,
+    /// Protocol versions and features to announce
+    pub hello: Hello,
 }
 
 /// Discovery service handle
@@ -49,52 +296,12 @@ pub struct DiscoveryService {
     socket: UdpSocket,
     config: DiscoveryConfig,
     multicast_addr: SocketAddr,
+    verifier: AnnouncementVerifier,
 }
-
 impl DiscoveryService {
     /// Create a new discovery service
     pub async fn new(config: DiscoveryConfig) -> Result<Self> {
-        let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), MULTICAST_PORT);
-
-        let socket = socket2::Socket::new(
-            socket2::Domain::IPV4,
-            socket2::Type::DGRAM,
-            Some(socket2::Protocol::UDP),
-        )?;
-
-        socket.set_reuse_address(true)?;
-
-        // On Unix systems, we set SO_REUSEPORT to allow multiple binds
-        #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
-        {
-            use std::os::unix::io::AsRawFd;
-            let fd = socket.as_raw_fd();
-            unsafe {
-                let optval: libc::c_int = 1;
-                libc::setsockopt(
-                    fd,
-                    libc::SOL_SOCKET,
-                    libc::SO_REUSEPORT,
-                    &optval as *const _ as *const libc::c_void,
-                    std::mem::size_of_val(&optval) as libc::socklen_t,
-                );
-            }
-        }
-
-        socket.bind(&bind_addr.into())?;
-        socket.set_nonblocking(true)?;
-
-        let socket: std::net::UdpSocket = socket.into();
-        let socket = UdpSocket::from_std(socket)?;
-
-        socket
-            .join_multicast_v4(MULTICAST_ADDR, Ipv4Addr::UNSPECIFIED)
-            .map_err(|e| ParlanceError::MulticastJoinError {
-                group: MULTICAST_ADDR.to_string(),
-                source: e,
-            })?;
-
-        socket.set_multicast_loop_v4(true)?;
+        let socket = bind_multicast(MULTICAST_ADDR, MULTICAST_PORT)?;
 
         let multicast_addr = SocketAddr::new(IpAddr::V4(MULTICAST_ADDR), MULTICAST_PORT);
 
@@ -107,164 +314,192 @@ impl DiscoveryService {
             socket,
             config,
             multicast_addr,
+            verifier: AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE),
         })
     }
 
-    /// Send an announcement to the multicast group
-    #[allow(dead_code)]
-    async fn announce(&self) -> Result<()> {
-        let msg = DiscoveryMessage::Announce {
-            nickname: self.config.nickname.clone(),
-            tcp_port: self.config.tcp_port,
-        };
+    /// Get the counters of rejected discovery packets
+    pub fn stats(&self) -> Arc<DiscoveryStats> {
+        self.verifier.stats()
+    }
 
-        let data = serde_json::to_vec(&msg)?;
+    /// Sign and send a discovery message to the multicast group
+    async fn send(&self, msg: DiscoveryMessage) -> Result<()> {
+        let data = serde_json::to_vec(&msg.sign(&self.config.identity)?)?;
         self.socket.send_to(&data, self.multicast_addr).await?;
+        Ok(())
+    }
+
+    /// Send an announcement to the multicast group
+    async fn announce(&self) -> Result<()> {
+        self.send(DiscoveryMessage::announce_with(
+            self.config.nickname.clone(),
+            self.config.tcp_port,
+            self.config.identity.public_key(),
+            self.config.hello.clone(),
+        ))
+        .await?;
 
         tracing::debug!("Sent announcement");
         Ok(())
     }
 
     /// Send a goodbye message to the multicast group
-    #[allow(dead_code)]
     pub async fn send_goodbye(&self) -> Result<()> {
-        let msg = DiscoveryMessage::Goodbye {
-            nickname: self.config.nickname.clone(),
-        };
-
-        let data = serde_json::to_vec(&msg)?;
-        self.socket.send_to(&data, self.multicast_addr).await?;
+        self.send(DiscoveryMessage::goodbye(
+            self.config.nickname.clone(),
+            self.config.identity.public_key(),
+        ))
+        .await?;
 
         tracing::info!("Sent goodbye message");
         Ok(())
     }
 
-    /// Handle a received discovery message
-    #[allow(dead_code)]
-    async fn handle_message(&self, data: &[u8], from: SocketAddr) -> Result<()> {
-        let msg: DiscoveryMessage = serde_json::from_slice(data)?;
+    /// Handle a received discovery packet
+    async fn handle_message(&self, data: &[u8], from: SocketAddr) {
+        let msg = match self.verifier.verify(data) {
+            Ok(msg) => msg,
+            Err(rejection) => {
+                tracing::debug!(
+                    from = %from,
+                    reason = %rejection,
+                    rejected = self.verifier.stats().snapshot().total(),
+                    "Rejected discovery packet"
+                );
+                return;
+            }
+        };
+
+        // Don't add ourselves as a peer
+        if *msg.public_key() == self.config.identity.public_key() {
+            return;
+        }
 
         match msg {
-            DiscoveryMessage::Announce { nickname, tcp_port } => {
-                // Don't add ourselves as a peer
-                if nickname == self.config.nickname {
-                    return Ok(());
-                }
-
+            DiscoveryMessage::Announce {
+                nickname,
+                tcp_port,
+                public_key,
+                hello,
+                ..
+            } => {
                 // Create peer address using the sender's IP and their announced TCP port
                 let peer_addr = SocketAddr::new(from.ip(), tcp_port);
-                let peer = Peer::new(nickname, peer_addr);
+                let peer = Peer::new(nickname, peer_addr, public_key)
+                    .via(DiscoverySource::Multicast)
+                    .with_features(hello.features);
 
                 self.config.registry.upsert(peer).await;
             }
-            DiscoveryMessage::Goodbye { nickname } => {
+            DiscoveryMessage::Goodbye {
+                nickname,
+                public_key,
+                ..
+            } => {
                 tracing::info!(nickname = %nickname, "Received goodbye from peer");
+                self.config.registry.remove(&public_key.peer_id()).await;
             }
         }
-
-        Ok(())
     }
 
     /// Run the discovery service
     ///
-    /// This function runs two concurrent tasks:
+    /// This function runs three concurrent loops:
     /// 1. Periodically announce our presence
     /// 2. Listen for announcements from other peers
-    pub async fn run(self) -> Result<()> {
-        let socket = std::sync::Arc::new(self.socket);
-        let config = std::sync::Arc::new(self.config);
-
-        let announce_socket = socket.clone();
-        let announce_config = config.clone();
-        let multicast_addr = self.multicast_addr;
-
-        let announce_task = tokio::spawn(async move {
-            let mut interval = time::interval(announce_config.announce_interval);
+    /// 3. Remove peers that stopped announcing
+    pub async fn run(&self) -> Result<()> {
+        let announce_loop = async {
+            let mut interval = time::interval(self.config.announce_interval);
             loop {
                 interval.tick().await;
-
-                let msg = DiscoveryMessage::Announce {
-                    nickname: announce_config.nickname.clone(),
-                    tcp_port: announce_config.tcp_port,
-                };
-
-                match serde_json::to_vec(&msg) {
-                    Ok(data) => {
-                        if let Err(e) = announce_socket.send_to(&data, multicast_addr).await {
-                            tracing::error!(error = ?e, "Failed to send announcement");
-                        } else {
-                            tracing::debug!("Sent announcement");
-                        }
-                    }
-                    Err(e) => {
-                        tracing::error!(error = ?e, "Failed to serialize announcement");
-                    }
+                if let Err(e) = self.announce().await {
+                    tracing::error!(error = ?e, "Failed to send announcement");
                 }
             }
-        });
-
-        let listen_socket = socket.clone();
-        let listen_config = config.clone();
+        };
 
-        let listen_task = tokio::spawn(async move {
+        let listen_loop = async {
             let mut buf = vec![0u8; 65536];
             loop {
-                match listen_socket.recv_from(&mut buf).await {
-                    Ok((len, from)) => {
-                        let data = &buf[..len];
-
-                        match serde_json::from_slice::<DiscoveryMessage>(data) {
-                            Ok(msg) => {
-                                match msg {
-                                    DiscoveryMessage::Announce { nickname, tcp_port } => {
-                                        // Don't add ourselves
-                                        if nickname == listen_config.nickname {
-                                            continue;
-                                        }
-
-                                        let peer_addr = SocketAddr::new(from.ip(), tcp_port);
-                                        let peer = Peer::new(nickname, peer_addr);
-                                        listen_config.registry.upsert(peer).await;
-                                    }
-                                    DiscoveryMessage::Goodbye { nickname } => {
-                                        tracing::info!(nickname = %nickname, "Received goodbye");
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                tracing::warn!(error = ?e, "Failed to parse discovery message");
-                            }
-                        }
-                    }
+                match self.socket.recv_from(&mut buf).await {
+                    Ok((len, from)) => self.handle_message(&buf[..len], from).await,
                     Err(e) => {
                         tracing::error!(error = ?e, "Failed to receive on discovery socket");
                     }
                 }
             }
-        });
+        };
 
-        let cleanup_registry = config.registry.clone();
-        let cleanup_timeout = config.peer_timeout;
-        let cleanup_task = tokio::spawn(async move {
+        let cleanup_loop = async {
             let mut interval = time::interval(Duration::from_secs(5));
             loop {
                 interval.tick().await;
-                cleanup_registry.remove_timed_out(cleanup_timeout).await;
+                self.config
+                    .registry
+                    .remove_timed_out(self.config.peer_timeout)
+                    .await;
             }
-        });
+        };
 
         tokio::select! {
-            _ = announce_task => {
-                tracing::error!("Announce task terminated unexpectedly");
-            }
-            _ = listen_task => {
-                tracing::error!("Listen task terminated unexpectedly");
-            }
-            _ = cleanup_task => {
-                tracing::error!("Cleanup task terminated unexpectedly");
-            }
+            _ = announce_loop => {}
+            _ = listen_loop => {}
+            _ = cleanup_loop => {}
         }
 
         Ok(())
     }
 }
+
+/// Bind a UDP socket to `port` and join the multicast `group`
+///
+/// The port is shared (SO_REUSEADDR and, on Unix, SO_REUSEPORT) so several
+/// instances on one machine can listen to the same group, and multicast
+/// loopback is enabled so they hear each other.
+pub(crate) fn bind_multicast(group: Ipv4Addr, port: u16) -> Result<UdpSocket> {
+    let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+
+    let socket = socket2::Socket::new(
+        socket2::Domain::IPV4,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+
+    socket.set_reuse_address(true)?;
+
+    // On Unix systems, we set SO_REUSEPORT to allow multiple binds
+    #[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+    {
+        use std::os::unix::io::AsRawFd;
+        let fd = socket.as_raw_fd();
+        unsafe {
+            let optval: libc::c_int = 1;
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_REUSEPORT,
+                &optval as *const _ as *const libc::c_void,
+                std::mem::size_of_val(&optval) as libc::socklen_t,
+            );
+        }
+    }
+
+    socket.bind(&bind_addr.into())?;
+    socket.set_nonblocking(true)?;
+
+    let socket: std::net::UdpSocket = socket.into();
+    let socket = UdpSocket::from_std(socket)?;
+
+    socket
+        .join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)
+        .map_err(|e| ParlanceError::MulticastJoinError {
+            group: group.to_string(),
+            source: e,
+        })?;
+
+    socket.set_multicast_loop_v4(true)?;
+
+    Ok(socket)
+}
@@ -2,75 +2,428 @@
 //!
 //! This module handles direct peer-to-peer messaging over TCP.
 //! Each peer listens on a TCP port and can send/receive messages.
+//! Every connection is wrapped in a [`SecureChannel`], so messages are
+//! end-to-end encrypted and authenticated against the peer's identity key.
+//! Connections are pooled by the [`ConnectionManager`] and reused for
+//! messages in both directions.
+//!
+//! Every message carries a unique ID. The receiver answers each message
+//! with an `ack` frame and ignores IDs it has already seen, so the sender
+//! can resend until the message is acknowledged or it runs out of attempts.
+//!
+//! Messages to trusted peers that are offline are kept in the [`Outbox`]
+//! and sent as soon as the peer is discovered again. When a [`History`] is
+//! configured, every sent and received message and its delivery state is
+//! recorded there.
+//!
+//! Group messages are fanned out as one copy per member over the same
+//! connections, with per-member acks and retries. Membership changes are
+//! sent to the members as `group` frames and resent to each member when it
+//! comes online, so members that missed a change catch up.
+//!
+//! Frames travel in a versioned wire envelope (see
+//! [`crate::network::wire`]) and are length-prefixed on the encrypted
+//! channel, so a single connection carries messages, acks, group updates and
+//! file data alike.
+//!
+//! With the QUIC transport configured, the service also listens for QUIC on
+//! the UDP port matching its TCP port, advertises the `quic` feature, and
+//! reaches peers that advertise it over QUIC instead of TCP.
+//!
+//! Files are offered with an `offer` frame and streamed in `chunk` frames
+//! once the receiver accepts (see [`crate::network::transfer`]). Accepted
+//! transfers that were cut off resume when the sender is seen again.
 
+use crate::core::config::Transport;
 use crate::core::error::{ParlanceError, Result};
-use crate::core::peer::PeerRegistry;
+use crate::core::group::{Group, GroupId, Groups, Member, SyncOutcome};
+use crate::core::history::{DeliveryState, Direction, History, HistoryEntry};
+use crate::core::identity::{Identity, PublicKey};
+use crate::core::peer::{Peer, PeerEvent, PeerRegistry};
+use crate::core::sync::lock;
+use crate::core::validation::GroupNameValidator;
+use crate::network::connection::{ConnectionManager, IncomingFrame, PoolConfig};
+use crate::network::outbox::{Outbox, OutboxEntry};
+use crate::network::protocol::{Hello, FEATURE_FILE_TRANSFER, FEATURE_GROUPS, FEATURE_QUIC};
+use crate::network::quic::QuicEndpoint;
+use crate::network::secure::SecureChannel;
+use crate::network::transfer::{
+    crosses_step, ChunkOutcome, FileChunk, FileOffer, Outgoing, TransferId, Transfers, CHUNK_SIZE,
+};
+use crate::network::wire::{Envelope, FrameType};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::SeekFrom;
 use std::net::SocketAddr;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use uuid::Uuid;
+
+/// Default time to wait for an acknowledgement before resending
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default number of send attempts before a message is reported as failed
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// How long received message IDs are remembered to suppress duplicates
+pub const DEDUP_WINDOW: Duration = Duration::from_secs(600);
+
+/// How often expired outbox entries are dropped
+const OUTBOX_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Unique identifier of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MessageId(Uuid);
+
+impl MessageId {
+    /// Create a new random message ID
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    /// Check whether the full ID starts with `prefix`
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        !prefix.is_empty() && self.0.to_string().starts_with(&prefix.to_lowercase())
+    }
+
+    /// The ID as carried in a wire envelope
+    pub fn to_bytes(self) -> [u8; 16] {
+        self.0.into_bytes()
+    }
+
+    /// Read an ID carried in a wire envelope
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(Uuid::from_bytes(bytes))
+    }
+}
+
+impl Default for MessageId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self.0.to_string()[..8])
+    }
+}
 
 /// A text message sent between peers
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TextMessage {
+    /// Unique message ID
+    pub id: MessageId,
     /// Sender's nickname
     pub from: String,
+    /// Sender's identity public key
+    pub public_key: PublicKey,
     /// Message content
     pub content: String,
     /// Unix timestamp (seconds since epoch)
     pub timestamp: i64,
+    /// Group the message was sent to, if it is a group message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<GroupId>,
 }
 
 impl TextMessage {
-    /// Create a new text message
-    pub fn new(from: String, content: String) -> Self {
+    /// Create a new text message with a fresh ID
+    pub fn new(from: String, public_key: PublicKey, content: String) -> Self {
         Self {
+            id: MessageId::new(),
             from,
+            public_key,
             content,
             timestamp: Utc::now().timestamp(),
+            group: None,
         }
     }
 
     /// Format the message for display
-    pub fn format(&self) -> String {
+    ///
+    /// Senders whose safety number was verified get a check mark badge, and
+    /// messages that came through the bootstrap server's relay are marked.
+    pub fn format(&self, verified: bool, relayed: bool) -> String {
+        self.format_with_prefix("", verified, relayed)
+    }
+
+    /// Format a group message for display, tagged with the group name
+    pub fn format_in_group(&self, group: &str, verified: bool, relayed: bool) -> String {
+        self.format_with_prefix(&format!("[{}] ", group), verified, relayed)
+    }
+
+    fn format_with_prefix(&self, prefix: &str, verified: bool, relayed: bool) -> String {
         let datetime = chrono::DateTime::from_timestamp(self.timestamp, 0)
             .map(|dt| dt.format("%H:%M:%S").to_string())
             .unwrap_or_else(|| "??:??:??".to_string());
 
-        format!("[{}] {}: {}", datetime, self.from, self.content)
+        let badge = if verified { " ✔" } else { "" };
+        let via = if relayed { " (relayed)" } else { "" };
+
+        format!(
+            "[{}] {}{}{}{}: {}",
+            datetime, prefix, self.from, badge, via, self.content
+        )
+    }
+}
+
+/// Frames exchanged over a peer connection
+///
+/// Each frame travels in a wire [`Envelope`]. Messages, group updates and
+/// offers carry a JSON payload; acks and the file transfer frames use a
+/// compact binary one.
+#[derive(Debug, Clone)]
+pub enum PeerFrame {
+    /// A text message
+    Message(TextMessage),
+    /// Acknowledges receipt of the message with the given ID
+    Ack { id: MessageId },
+    /// A group's current membership
+    Group(GroupUpdate),
+    /// Offers a file to the receiver
+    Offer(FileOffer),
+    /// Accepts a file offer, asking for the data from `offset` on
+    Accept { id: TransferId, offset: u64 },
+    /// A piece of an accepted file
+    Chunk(FileChunk),
+    /// The receiver got the whole file; `verified` is false if its hash
+    /// did not match
+    Done { id: TransferId, verified: bool },
+}
+
+impl PeerFrame {
+    /// Encode the frame in a wire envelope
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let envelope = match self {
+            PeerFrame::Message(msg) => Envelope::new(
+                FrameType::Message,
+                msg.id.to_bytes(),
+                serde_json::to_vec(msg)?,
+            ),
+            PeerFrame::Ack { id } => Envelope::new(FrameType::Ack, id.to_bytes(), Vec::new()),
+            PeerFrame::Group(update) => Envelope::new(
+                FrameType::Group,
+                update.id.to_bytes(),
+                serde_json::to_vec(update)?,
+            ),
+            PeerFrame::Offer(offer) => Envelope::new(
+                FrameType::Offer,
+                offer.id.to_bytes(),
+                serde_json::to_vec(offer)?,
+            ),
+            PeerFrame::Accept { id, offset } => Envelope::new(
+                FrameType::Accept,
+                id.to_bytes(),
+                offset.to_be_bytes().to_vec(),
+            ),
+            PeerFrame::Chunk(chunk) => Envelope::new(
+                FrameType::Chunk,
+                chunk.id.to_bytes(),
+                [&chunk.offset.to_be_bytes()[..], &chunk.data].concat(),
+            ),
+            PeerFrame::Done { id, verified } => {
+                Envelope::new(FrameType::Done, id.to_bytes(), vec![u8::from(*verified)])
+            }
+        };
+        Ok(envelope.encode())
+    }
+
+    /// Decode a frame from a wire envelope
+    ///
+    /// Frame types this build does not know fail with
+    /// [`ParlanceError::UnknownFrameType`].
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let envelope = Envelope::decode(data)?;
+        let payload = envelope.payload.as_slice();
+
+        let frame = match envelope.frame_type {
+            FrameType::Message => PeerFrame::Message(serde_json::from_slice(payload)?),
+            FrameType::Ack => PeerFrame::Ack {
+                id: MessageId::from_bytes(envelope.id),
+            },
+            FrameType::Group => PeerFrame::Group(serde_json::from_slice(payload)?),
+            FrameType::Offer => PeerFrame::Offer(serde_json::from_slice(payload)?),
+            FrameType::Accept => PeerFrame::Accept {
+                id: TransferId::from_bytes(envelope.id),
+                offset: read_offset(payload)?,
+            },
+            FrameType::Chunk => PeerFrame::Chunk(FileChunk {
+                id: TransferId::from_bytes(envelope.id),
+                offset: read_offset(payload)?,
+                data: payload[8..].to_vec(),
+            }),
+            FrameType::Done => PeerFrame::Done {
+                id: TransferId::from_bytes(envelope.id),
+                verified: payload.first().is_some_and(|v| *v != 0),
+            },
+        };
+        Ok(frame)
     }
 }
 
+/// Read the big-endian offset leading a transfer frame's payload
+fn read_offset(payload: &[u8]) -> Result<u64> {
+    payload
+        .get(..8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_be_bytes)
+        .ok_or_else(|| ParlanceError::InvalidMessage("Missing transfer offset".to_string()))
+}
+
+/// Membership of a group, sent to its members after every change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupUpdate {
+    /// Frame ID, acknowledged like a message ID
+    pub id: MessageId,
+    /// Sender's nickname
+    pub from: String,
+    /// The group as the sender now sees it
+    pub group: Group,
+    /// Member being invited by this update, who does not know the group yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invited: Option<PublicKey>,
+}
+
+/// A change to a group reported to the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupChange {
+    /// We were added to the group by a member
+    Invited { by: String },
+    /// A member was added
+    MemberJoined(String),
+    /// A member left
+    MemberLeft(String),
+}
+
 /// Events that occur in the messaging system
 #[derive(Debug, Clone)]
 pub enum MessageEvent {
-    /// A message was received from a peer
-    Received(TextMessage),
-    /// A message was successfully sent to a peer
-    #[allow(dead_code)]
-    Sent { to: String, content: String },
-    /// An error occurred while sending a message
-    #[allow(dead_code)]
-    SendError { to: String, error: String },
+    /// A message was received from a peer; `relayed` is set if it came
+    /// through the bootstrap server's relay
+    Received { msg: TextMessage, relayed: bool },
+    /// A message was received in a group
+    GroupReceived {
+        group: String,
+        msg: TextMessage,
+        relayed: bool,
+    },
+    /// A group's membership changed
+    GroupChanged { group: String, change: GroupChange },
+    /// The recipient is offline and the message was stored in the outbox,
+    /// or went back there after delivering it from the outbox failed
+    Queued { to: String, id: MessageId },
+    /// A message was written to the peer's connection for the first time
+    Sent { to: String, id: MessageId },
+    /// The peer acknowledged a message
+    Delivered { to: String, id: MessageId },
+    /// A message could not be delivered
+    Failed {
+        to: String,
+        id: MessageId,
+        error: String,
+    },
+    /// A peer offered us a file
+    FileOffered(FileOffer),
+    /// A file transfer made progress
+    TransferProgress {
+        peer: String,
+        id: TransferId,
+        name: String,
+        /// Whether we are sending the file
+        outgoing: bool,
+        transferred: u64,
+        size: u64,
+    },
+    /// A file transfer was cut off and waits to be resumed
+    TransferInterrupted {
+        peer: String,
+        id: TransferId,
+        name: String,
+        error: String,
+    },
+    /// A file transfer finished; `path` is where a received file was saved
+    TransferComplete {
+        peer: String,
+        id: TransferId,
+        name: String,
+        path: Option<PathBuf>,
+    },
+    /// A file transfer failed for good
+    TransferFailed {
+        peer: String,
+        id: TransferId,
+        name: String,
+        error: String,
+    },
+}
+
+/// Acknowledgement and retry settings
+#[derive(Debug, Clone, Copy)]
+pub struct DeliveryConfig {
+    /// Time to wait for an acknowledgement before resending
+    pub ack_timeout: Duration,
+    /// Number of send attempts before giving up
+    pub max_attempts: u32,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            ack_timeout: DEFAULT_ACK_TIMEOUT,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
 }
 
 /// Messaging service configuration
 pub struct MessagingConfig {
     /// Our nickname
     pub nickname: String,
+    /// Our identity keypair
+    pub identity: Identity,
     /// Port to listen on for incoming connections
     pub tcp_port: u16,
     /// Peer registry for looking up peers
     pub registry: PeerRegistry,
+    /// Connection pool settings
+    pub pool: PoolConfig,
+    /// Acknowledgement and retry settings
+    pub delivery: DeliveryConfig,
+    /// Store for messages to offline peers; without one, sending to an
+    /// offline peer fails
+    pub outbox: Option<Outbox>,
+    /// Conversation history to record messages in
+    pub history: Option<History>,
+    /// Groups we are in; without a store, group frames are ignored
+    pub groups: Option<Groups>,
+    /// File transfers; without them, file offers are ignored
+    pub transfers: Option<Transfers>,
+    /// Transport to reach peers over
+    pub transport: Transport,
 }
 
+/// Sent frames waiting for their acknowledgement, by recipient and ID
+type PendingAcks = Arc<std::sync::Mutex<HashMap<(PublicKey, MessageId), oneshot::Sender<()>>>>;
+
 /// Messaging service
 pub struct MessagingService {
     config: MessagingConfig,
     listener: TcpListener,
-    event_tx: mpsc::UnboundedSender<MessageEvent>,
+    /// QUIC endpoint on the UDP port matching the TCP port, if listening
+    quic: Option<Arc<QuicEndpoint>>,
+    events: EventReporter,
+    connections: ConnectionManager,
+    incoming_rx: Mutex<mpsc::UnboundedReceiver<IncomingFrame>>,
+    pending: PendingAcks,
+    /// Recently received message IDs per sender
+    seen: std::sync::Mutex<HashMap<(PublicKey, MessageId), Instant>>,
+    peer_rx: Mutex<mpsc::UnboundedReceiver<PeerEvent>>,
 }
 
 impl MessagingService {
@@ -92,10 +445,45 @@ impl MessagingService {
         let local_addr = listener.local_addr()?;
         tracing::info!(addr = %local_addr, "Messaging service listening");
 
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        let connections = ConnectionManager::new(config.identity.clone(), config.pool, incoming_tx);
+
+        // QUIC is optional: without a UDP port, peers are reached over TCP
+        let quic = match config.transport {
+            Transport::Tcp => None,
+            Transport::Quic => {
+                let quic_addr = SocketAddr::from(([0, 0, 0, 0], local_addr.port()));
+                match QuicEndpoint::bind(quic_addr) {
+                    Ok(endpoint) => {
+                        let addr = endpoint.local_addr().unwrap_or(quic_addr);
+                        tracing::info!(addr = %addr, "QUIC endpoint listening");
+                        let endpoint = Arc::new(endpoint);
+                        connections.set_quic(endpoint.clone());
+                        Some(endpoint)
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Failed to start QUIC, using TCP only");
+                        None
+                    }
+                }
+            }
+        };
+        let peer_rx = config.registry.subscribe();
+        let events = EventReporter {
+            event_tx,
+            history: config.history.clone(),
+        };
+
         Ok(Self {
             config,
             listener,
-            event_tx,
+            quic,
+            events,
+            connections,
+            incoming_rx: Mutex::new(incoming_rx),
+            pending: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            seen: std::sync::Mutex::new(HashMap::new()),
+            peer_rx: Mutex::new(peer_rx),
         })
     }
 
@@ -104,100 +492,1198 @@ impl MessagingService {
         Ok(self.listener.local_addr()?)
     }
 
+    /// Get the hello to advertise to peers
+    ///
+    /// Includes the `quic` feature while the QUIC endpoint is listening.
+    pub fn hello(&self) -> Hello {
+        match self.quic {
+            Some(_) => Hello::current().with_feature(FEATURE_QUIC),
+            None => Hello::current(),
+        }
+    }
+
+    /// Get the connection pool
+    pub fn connections(&self) -> &ConnectionManager {
+        &self.connections
+    }
+
     /// Send a message to a peer by nickname
-    pub async fn send_message(&self, to_nickname: &str, content: String) -> Result<()> {
+    ///
+    /// Returns once the message is queued for delivery. The outcome is
+    /// reported later as [`MessageEvent::Delivered`] or
+    /// [`MessageEvent::Failed`]. If the peer is offline but has a trusted
+    /// key, the message goes to the outbox and [`MessageEvent::Queued`] is
+    /// reported instead.
+    pub async fn send_message(&self, to_nickname: &str, content: String) -> Result<MessageId> {
+        let msg = TextMessage::new(
+            self.config.nickname.clone(),
+            self.config.identity.public_key(),
+            content,
+        );
+        let id = msg.id;
+
+        let peers = self.config.registry.get_all().await;
+        let Some(peer) = peers.into_iter().find(|p| p.nickname == to_nickname) else {
+            return self.queue(to_nickname, msg).await;
+        };
+
+        self.events
+            .record_sent(&peer.nickname, peer.public_key, &msg)
+            .await;
+        self.deliver(peer, msg)?;
+        Ok(id)
+    }
+
+    /// Store a message for an offline peer in the outbox
+    async fn queue(&self, to_nickname: &str, msg: TextMessage) -> Result<MessageId> {
+        let not_found = || ParlanceError::PeerNotFound(to_nickname.to_string());
+        let outbox = self.config.outbox.as_ref().ok_or_else(not_found)?;
+        let recipient = self
+            .config
+            .registry
+            .pinned_key(to_nickname)
+            .await
+            .ok_or_else(not_found)?;
+
+        let id = msg.id;
+        self.events.record_sent(to_nickname, recipient, &msg).await;
+        outbox
+            .push(OutboxEntry::new(to_nickname.to_string(), recipient, msg))
+            .await?;
+        tracing::info!(to = %to_nickname, id = %id, "Peer offline, message queued");
+
+        self.events
+            .report(MessageEvent::Queued {
+                to: to_nickname.to_string(),
+                id,
+            })
+            .await;
+
+        // The peer may have come online while the message was being stored
+        if let Some(peer) = self.config.registry.get(&recipient.peer_id()).await {
+            self.flush_outbox(&peer).await;
+        }
+        Ok(id)
+    }
+
+    /// Send the outbox entries waiting for a peer that just came online
+    async fn flush_outbox(&self, peer: &Peer) {
+        let Some(outbox) = &self.config.outbox else {
+            return;
+        };
+
+        let entries = outbox.claim_for(&peer.public_key).await;
+        if !entries.is_empty() {
+            tracing::info!(to = %peer.nickname, count = entries.len(), "Flushing outbox");
+        }
+        for entry in entries {
+            let id = entry.id();
+            let frame = PeerFrame::Message(entry.message);
+            let result = self.spawn_delivery(peer.clone(), id, &frame, true, Some(outbox.clone()));
+            if let Err(e) = result {
+                tracing::error!(to = %peer.nickname, error = %e, "Failed to send queued message");
+                outbox.release(&peer.public_key, id).await;
+            }
+        }
+    }
+
+    /// Drop expired outbox entries, reporting them as failed
+    async fn expire_outbox(&self) {
+        let Some(outbox) = &self.config.outbox else {
+            return;
+        };
+
+        match outbox.expire().await {
+            Ok(expired) => {
+                for entry in expired {
+                    tracing::warn!(to = %entry.to, id = %entry.id(), "Queued message expired");
+                    self.events
+                        .report(MessageEvent::Failed {
+                            id: entry.id(),
+                            to: entry.to,
+                            error: "expired in outbox".to_string(),
+                        })
+                        .await;
+                }
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to expire outbox");
+            }
+        }
+    }
+
+    /// Get the group store, failing if groups are not enabled
+    fn groups(&self) -> Result<&Groups> {
+        self.config
+            .groups
+            .as_ref()
+            .ok_or_else(|| ParlanceError::Group("Groups are not enabled".to_string()))
+    }
+
+    /// Our own membership entry
+    fn own_member(&self) -> Member {
+        Member {
+            nickname: self.config.nickname.clone(),
+            public_key: self.config.identity.public_key(),
+        }
+    }
+
+    /// Create a group with ourselves as the only member
+    pub async fn create_group(&self, name: &str) -> Result<Group> {
+        GroupNameValidator::validate(name)
+            .map_err(|e| ParlanceError::Group(format!("Invalid group name: {}", e)))?;
+        self.groups()?.create(name, self.own_member()).await
+    }
+
+    /// Add an online peer to a group and tell all members
+    pub async fn invite_to_group(&self, group: &str, nickname: &str) -> Result<Group> {
+        let groups = self.groups()?;
+        let mut group = groups.find(group).await?;
+
         let peers = self.config.registry.get_all().await;
         let peer = peers
+            .into_iter()
+            .find(|p| p.nickname == nickname)
+            .ok_or_else(|| ParlanceError::PeerNotFound(nickname.to_string()))?;
+        self.require_feature(&peer, FEATURE_GROUPS, "group chats")
+            .await?;
+
+        group.add_member(Member {
+            nickname: peer.nickname.clone(),
+            public_key: peer.public_key,
+        })?;
+        groups.update(group.clone()).await?;
+
+        tracing::info!(group = %group.name, member = %peer.nickname, "Invited to group");
+        self.broadcast_group(&group, &group, Some(peer.public_key))
+            .await;
+        Ok(group)
+    }
+
+    /// Leave a group, telling the remaining members
+    pub async fn leave_group(&self, group: &str) -> Result<Group> {
+        let groups = self.groups()?;
+        let group = groups.find(group).await?;
+
+        let mut remaining = group.clone();
+        remaining.remove_member(&self.config.identity.public_key())?;
+        self.broadcast_group(&group, &remaining, None).await;
+
+        groups.remove(&group.id).await?;
+        tracing::info!(group = %group.name, "Left group");
+        Ok(group)
+    }
+
+    /// Send a message to every other member of a group
+    ///
+    /// Each member gets its own copy with the same message ID, delivered
+    /// like a direct message: online members right away, offline ones
+    /// through the outbox.
+    pub async fn send_group_message(&self, group: &str, content: String) -> Result<MessageId> {
+        let group = self.groups()?.find(group).await?;
+
+        let mut msg = TextMessage::new(
+            self.config.nickname.clone(),
+            self.config.identity.public_key(),
+            content,
+        );
+        msg.group = Some(group.id);
+        let id = msg.id;
+
+        let own_key = self.config.identity.public_key();
+        for member in group.members.iter().filter(|m| m.public_key != own_key) {
+            if let Err(e) = self.send_to_member(member, msg.clone()).await {
+                tracing::warn!(to = %member.nickname, error = %e, "Failed to send group message");
+                self.events
+                    .report(MessageEvent::Failed {
+                        to: member.nickname.clone(),
+                        id,
+                        error: e.to_string(),
+                    })
+                    .await;
+            }
+        }
+
+        Ok(id)
+    }
+
+    /// Deliver a group message copy to one member, queueing it if offline
+    async fn send_to_member(&self, member: &Member, msg: TextMessage) -> Result<()> {
+        if let Some(peer) = self.config.registry.get(&member.public_key.peer_id()).await {
+            return self.deliver(peer, msg);
+        }
+
+        let outbox = self
+            .config
+            .outbox
+            .as_ref()
+            .ok_or_else(|| ParlanceError::PeerNotFound(member.nickname.clone()))?;
+
+        let id = msg.id;
+        outbox
+            .push(OutboxEntry::new(
+                member.nickname.clone(),
+                member.public_key,
+                msg,
+            ))
+            .await?;
+        self.events
+            .report(MessageEvent::Queued {
+                to: member.nickname.clone(),
+                id,
+            })
+            .await;
+        Ok(())
+    }
+
+    /// Send a group's membership to the online members of `recipients`
+    ///
+    /// Offline members catch up when they come online (see `sync_groups`).
+    async fn broadcast_group(&self, recipients: &Group, group: &Group, invited: Option<PublicKey>) {
+        let own_key = self.config.identity.public_key();
+        for member in recipients
+            .members
             .iter()
+            .filter(|m| m.public_key != own_key)
+        {
+            if let Some(peer) = self.config.registry.get(&member.public_key.peer_id()).await {
+                self.send_group_update(peer, group, invited);
+            }
+        }
+    }
+
+    /// Send our copy of every group shared with a peer that came online
+    async fn sync_groups(&self, peer: &Peer) {
+        let Some(groups) = &self.config.groups else {
+            return;
+        };
+
+        for group in groups.list().await {
+            if group.is_member(&peer.public_key) {
+                self.send_group_update(peer.clone(), &group, None);
+            }
+        }
+    }
+
+    /// Start delivering a group update to a member
+    fn send_group_update(&self, peer: Peer, group: &Group, invited: Option<PublicKey>) {
+        let update = GroupUpdate {
+            id: MessageId::new(),
+            from: self.config.nickname.clone(),
+            group: group.clone(),
+            invited,
+        };
+        let id = update.id;
+
+        if let Err(e) = self.deliver_frame(peer, id, &PeerFrame::Group(update), false) {
+            tracing::error!(group = %group.name, error = %e, "Failed to send group update");
+        }
+    }
+
+    /// Fail unless a peer agreed to `feature` when connecting
+    async fn require_feature(&self, peer: &Peer, feature: &str, description: &str) -> Result<()> {
+        if self.connections.protocol(peer).await?.supports(feature) {
+            Ok(())
+        } else {
+            Err(ParlanceError::IncompatibleProtocol(format!(
+                "{} does not support {}",
+                peer.nickname, description
+            )))
+        }
+    }
+
+    /// Get the transfer list, failing if file transfers are not enabled
+    fn transfers(&self) -> Result<&Transfers> {
+        self.config
+            .transfers
+            .as_ref()
+            .ok_or_else(|| ParlanceError::Transfer("File transfers are not enabled".to_string()))
+    }
+
+    /// Offer a file to an online peer
+    ///
+    /// The file is sent once the peer accepts; progress and the outcome are
+    /// reported as transfer events.
+    pub async fn send_file(&self, to_nickname: &str, path: &Path) -> Result<FileOffer> {
+        let transfers = self.transfers()?;
+        let peers = self.config.registry.get_all().await;
+        let peer = peers
+            .into_iter()
             .find(|p| p.nickname == to_nickname)
             .ok_or_else(|| ParlanceError::PeerNotFound(to_nickname.to_string()))?;
+        self.require_feature(&peer, FEATURE_FILE_TRANSFER, "file transfer")
+            .await?;
+
+        let offer = transfers
+            .offer(path, &self.config.nickname, &peer.nickname, peer.public_key)
+            .await?;
+        let data = PeerFrame::Offer(offer.clone()).encode()?;
+        self.connections.send(&peer, &data).await?;
+
+        tracing::info!(to = %peer.nickname, id = %offer.id, name = %offer.name, "File offered");
+        Ok(offer)
+    }
+
+    /// Accept a file offered to us, or resume an interrupted one
+    pub async fn accept_file(&self, id: &str) -> Result<FileOffer> {
+        let (transfer, offset) = self.transfers()?.accept(id).await?;
+        let offer = transfer.offer;
+
+        let peer = self
+            .config
+            .registry
+            .get(&transfer.peer_key.peer_id())
+            .await
+            .ok_or_else(|| ParlanceError::PeerNotFound(offer.from.clone()))?;
+        self.send_accept(&peer, offer.id, offset).await?;
+
+        tracing::info!(from = %offer.from, id = %offer.id, offset, "File accepted");
+        Ok(offer)
+    }
+
+    /// Ask a peer for the rest of every accepted file it was sending us
+    async fn resume_transfers(&self, peer: &Peer) {
+        let Some(transfers) = &self.config.transfers else {
+            return;
+        };
 
-        let stream = TcpStream::connect(peer.addr).await.map_err(|e| {
-            tracing::error!(
-                peer = %to_nickname,
-                addr = %peer.addr,
-                error = ?e,
-                "Failed to connect to peer"
+        for (id, offset) in transfers.resumable(&peer.public_key) {
+            tracing::info!(from = %peer.nickname, id = %id, offset, "Resuming file transfer");
+            if let Err(e) = self.send_accept(peer, id, offset).await {
+                tracing::warn!(from = %peer.nickname, id = %id, error = %e, "Failed to resume transfer");
+            }
+        }
+    }
+
+    async fn send_accept(&self, peer: &Peer, id: TransferId, offset: u64) -> Result<()> {
+        let data = PeerFrame::Accept { id, offset }.encode()?;
+        self.connections.send(peer, &data).await
+    }
+
+    /// Start delivering a message to an online peer in the background
+    fn deliver(&self, peer: Peer, msg: TextMessage) -> Result<()> {
+        let id = msg.id;
+        self.deliver_frame(peer, id, &PeerFrame::Message(msg), true)
+    }
+
+    /// Start delivering a frame until the peer acknowledges `id`
+    ///
+    /// Delivery events are only published if `report` is set.
+    fn deliver_frame(
+        &self,
+        peer: Peer,
+        id: MessageId,
+        frame: &PeerFrame,
+        report: bool,
+    ) -> Result<()> {
+        self.spawn_delivery(peer, id, frame, report, None)
+    }
+
+    /// Start delivering a frame, settling its outbox entry when done
+    ///
+    /// With an `outbox`, the entry for `id` is removed once the peer
+    /// acknowledges it and made pending again if delivery fails.
+    fn spawn_delivery(
+        &self,
+        peer: Peer,
+        id: MessageId,
+        frame: &PeerFrame,
+        report: bool,
+        outbox: Option<Outbox>,
+    ) -> Result<()> {
+        let data = frame.encode()?;
+
+        let (ack_tx, ack_rx) = oneshot::channel();
+        lock(&self.pending).insert((peer.public_key, id), ack_tx);
+
+        let delivery = Delivery {
+            id,
+            peer,
+            data,
+            report,
+            config: self.config.delivery,
+            connections: self.connections.clone(),
+            registry: self.config.registry.clone(),
+            events: self.events.clone(),
+            pending: self.pending.clone(),
+            outbox,
+        };
+        tokio::spawn(delivery.run(ack_rx));
+
+        Ok(())
+    }
+
+    /// Handle a frame received on a pooled connection
+    async fn handle_frame(&self, frame: IncomingFrame) {
+        match PeerFrame::decode(&frame.data) {
+            Ok(PeerFrame::Message(msg)) => self.handle_message(msg, &frame).await,
+            Ok(PeerFrame::Ack { id }) => self.handle_ack(id, &frame),
+            Ok(PeerFrame::Group(update)) => self.handle_group_update(update, &frame).await,
+            Ok(PeerFrame::Offer(offer)) => self.handle_offer(offer, &frame).await,
+            Ok(PeerFrame::Accept { id, offset }) => self.handle_accept(id, offset, &frame),
+            Ok(PeerFrame::Chunk(chunk)) => self.handle_chunk(chunk, &frame).await,
+            Ok(PeerFrame::Done { id, verified }) => self.handle_done(id, verified, &frame).await,
+            Err(ParlanceError::UnknownFrameType(code)) => {
+                tracing::debug!(peer = %frame.addr, code, "Skipping unknown frame type");
+            }
+            Err(e) => {
+                tracing::warn!(error = ?e, "Invalid message format");
+            }
+        }
+    }
+
+    /// Acknowledge and deliver a received message
+    async fn handle_message(&self, msg: TextMessage, frame: &IncomingFrame) {
+        if msg.public_key != frame.from {
+            tracing::warn!(
+                peer = %frame.addr,
+                from = %msg.from,
+                "Message identity does not match channel identity"
             );
-            e
-        })?;
+            return;
+        }
 
-        let msg = TextMessage::new(self.config.nickname.clone(), content.clone());
-        let data = serde_json::to_string(&msg)?;
+        if !self
+            .config
+            .registry
+            .is_trusted(&msg.from, &frame.from)
+            .await
+        {
+            tracing::warn!(
+                peer = %frame.addr,
+                from = %msg.from,
+                "Dropping message from untrusted key"
+            );
+            return;
+        }
 
-        let mut stream = stream;
-        stream.write_all(data.as_bytes()).await?;
-        stream.write_all(b"\n").await?;
-        stream.flush().await?;
+        // Acknowledge duplicates too, in case our earlier ack was lost
+        self.send_ack(&msg.from, msg.id, frame).await;
 
-        tracing::info!(to = %to_nickname, "Message sent");
+        if !self.first_sighting(frame.from, msg.id) {
+            tracing::debug!(from = %msg.from, id = %msg.id, "Ignoring duplicate message");
+            return;
+        }
 
-        let _ = self.event_tx.send(MessageEvent::Sent {
-            to: to_nickname.to_string(),
-            content,
-        });
+        if let Some(group_id) = msg.group {
+            self.handle_group_message(group_id, msg, frame.relayed)
+                .await;
+            return;
+        }
+
+        tracing::info!(
+            from = %msg.from,
+            id = %msg.id,
+            relayed = frame.relayed,
+            "Message received"
+        );
+
+        self.events
+            .report(MessageEvent::Received {
+                msg,
+                relayed: frame.relayed,
+            })
+            .await;
+    }
+
+    /// Deliver a received group message if the sender is a member
+    async fn handle_group_message(&self, group_id: GroupId, msg: TextMessage, relayed: bool) {
+        let group = match &self.config.groups {
+            Some(groups) => groups.get(&group_id).await,
+            None => None,
+        };
+        let Some(group) = group.filter(|g| g.is_member(&msg.public_key)) else {
+            tracing::warn!(
+                from = %msg.from,
+                group = %group_id,
+                "Dropping message for a group the sender is not in"
+            );
+            return;
+        };
+
+        tracing::info!(
+            from = %msg.from,
+            group = %group.name,
+            id = %msg.id,
+            "Group message received"
+        );
+
+        self.events
+            .report(MessageEvent::GroupReceived {
+                group: group.name,
+                msg,
+                relayed,
+            })
+            .await;
+    }
+
+    /// Apply a membership update sent by a group member
+    async fn handle_group_update(&self, update: GroupUpdate, frame: &IncomingFrame) {
+        let Some(groups) = &self.config.groups else {
+            return;
+        };
+
+        if !self
+            .config
+            .registry
+            .is_trusted(&update.from, &frame.from)
+            .await
+        {
+            tracing::warn!(
+                peer = %frame.addr,
+                from = %update.from,
+                "Dropping group update from untrusted key"
+            );
+            return;
+        }
+
+        self.send_ack(&update.from, update.id, frame).await;
+
+        if !self.first_sighting(frame.from, update.id) {
+            return;
+        }
+
+        if let Err(e) = GroupNameValidator::validate(&update.group.name) {
+            tracing::warn!(from = %update.from, error = %e, "Dropping group with invalid name");
+            return;
+        }
+
+        let own_key = self.config.identity.public_key();
+        let invited = update.invited == Some(own_key);
+        let group = update.group.clone();
+        let outcome = match groups
+            .apply(update.group, &frame.from, &own_key, invited)
+            .await
+        {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to store group update");
+                return;
+            }
+        };
+
+        let changes = match outcome {
+            SyncOutcome::Joined => vec![GroupChange::Invited {
+                by: update.from.clone(),
+            }],
+            SyncOutcome::Updated { previous } => membership_changes(&previous, &group),
+            SyncOutcome::Ignored => {
+                tracing::debug!(from = %update.from, group = %group.id, "Ignoring group update");
+                Vec::new()
+            }
+        };
+
+        for change in changes {
+            tracing::info!(group = %group.name, change = ?change, "Group changed");
+            self.events
+                .report(MessageEvent::GroupChanged {
+                    group: group.name.clone(),
+                    change,
+                })
+                .await;
+        }
+    }
+
+    /// Remember a file offered by a trusted peer and tell the user
+    async fn handle_offer(&self, offer: FileOffer, frame: &IncomingFrame) {
+        let Some(transfers) = &self.config.transfers else {
+            return;
+        };
+
+        if !self
+            .config
+            .registry
+            .is_trusted(&offer.from, &frame.from)
+            .await
+        {
+            tracing::warn!(
+                peer = %frame.addr,
+                from = %offer.from,
+                "Dropping file offer from untrusted key"
+            );
+            return;
+        }
+
+        match transfers.receive_offer(offer, frame.from) {
+            Ok(offer) => {
+                tracing::info!(from = %offer.from, id = %offer.id, name = %offer.name, size = offer.size, "File offered to us");
+                self.events.report(MessageEvent::FileOffered(offer)).await;
+            }
+            Err(e) => {
+                tracing::warn!(peer = %frame.addr, error = %e, "Rejected file offer");
+            }
+        }
+    }
+
+    /// Start streaming a file the peer accepted
+    fn handle_accept(&self, id: TransferId, offset: u64, frame: &IncomingFrame) {
+        let Some(transfers) = &self.config.transfers else {
+            return;
+        };
+        let Some((transfer, stream)) = transfers.start_stream(id, &frame.from) else {
+            tracing::debug!(id = %id, peer = %frame.addr, "Ignoring accept for unknown transfer");
+            return;
+        };
+
+        tracing::info!(to = %transfer.to, id = %id, offset, "Streaming file");
+        let file_stream = FileStream {
+            transfer,
+            stream,
+            offset,
+            connections: self.connections.clone(),
+            transfers: transfers.clone(),
+            events: self.events.clone(),
+        };
+        tokio::spawn(file_stream.run());
+    }
+
+    /// Store a chunk of a file we accepted
+    async fn handle_chunk(&self, chunk: FileChunk, frame: &IncomingFrame) {
+        let Some(transfers) = &self.config.transfers else {
+            return;
+        };
+        let Some(transfer) = transfers.incoming(chunk.id) else {
+            tracing::debug!(id = %chunk.id, peer = %frame.addr, "Ignoring chunk for unknown transfer");
+            return;
+        };
+        let offer = transfer.offer;
+
+        match transfers.write_chunk(&chunk, &frame.from).await {
+            Ok(ChunkOutcome::Progress { received, report }) => {
+                if report {
+                    self.events
+                        .report(MessageEvent::TransferProgress {
+                            peer: offer.from,
+                            id: offer.id,
+                            name: offer.name,
+                            outgoing: false,
+                            transferred: received,
+                            size: offer.size,
+                        })
+                        .await;
+                }
+            }
+            Ok(ChunkOutcome::OutOfOrder { expected }) => {
+                // Chunks behind us are left over from an earlier stream
+                if chunk.offset > expected {
+                    tracing::debug!(id = %offer.id, expected, "Chunk out of order, resuming");
+                    self.send_transfer_frame(
+                        &frame.from,
+                        &PeerFrame::Accept {
+                            id: offer.id,
+                            offset: expected,
+                        },
+                    )
+                    .await;
+                }
+            }
+            Ok(ChunkOutcome::Complete { path }) => {
+                tracing::info!(from = %offer.from, id = %offer.id, path = %path.display(), "File received");
+                self.send_transfer_frame(
+                    &frame.from,
+                    &PeerFrame::Done {
+                        id: offer.id,
+                        verified: true,
+                    },
+                )
+                .await;
+                self.events
+                    .report(MessageEvent::TransferComplete {
+                        peer: offer.from,
+                        id: offer.id,
+                        name: offer.name,
+                        path: Some(path),
+                    })
+                    .await;
+            }
+            Err(e) => {
+                tracing::warn!(from = %offer.from, id = %offer.id, error = %e, "File transfer failed");
+                self.send_transfer_frame(
+                    &frame.from,
+                    &PeerFrame::Done {
+                        id: offer.id,
+                        verified: false,
+                    },
+                )
+                .await;
+                self.events
+                    .report(MessageEvent::TransferFailed {
+                        peer: offer.from,
+                        id: offer.id,
+                        name: offer.name,
+                        error: e.to_string(),
+                    })
+                    .await;
+            }
+        }
+    }
+
+    /// Finish an outgoing transfer the receiver completed
+    async fn handle_done(&self, id: TransferId, verified: bool, frame: &IncomingFrame) {
+        let Some(transfers) = &self.config.transfers else {
+            return;
+        };
+        let Some(transfer) = transfers.finish_outgoing(id, &frame.from) else {
+            return;
+        };
+        let offer = transfer.offer;
+
+        let event = if verified {
+            tracing::info!(to = %transfer.to, id = %id, "File delivered");
+            MessageEvent::TransferComplete {
+                peer: transfer.to,
+                id,
+                name: offer.name,
+                path: None,
+            }
+        } else {
+            MessageEvent::TransferFailed {
+                peer: transfer.to,
+                id,
+                name: offer.name,
+                error: "the received file did not match its SHA-256".to_string(),
+            }
+        };
+        self.events.report(event).await;
+    }
+
+    /// Send a transfer control frame on the connection to a peer
+    async fn send_transfer_frame(&self, to: &PublicKey, frame: &PeerFrame) {
+        let result = match frame.encode() {
+            Ok(data) => self.connections.send_existing(to, &data).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "Failed to send transfer frame");
+        }
+    }
+
+    /// Acknowledge a frame on the connection it arrived on
+    async fn send_ack(&self, from: &str, id: MessageId, frame: &IncomingFrame) {
+        match (PeerFrame::Ack { id }).encode() {
+            Ok(ack) => {
+                if let Err(e) = self.connections.send_existing(&frame.from, &ack).await {
+                    tracing::warn!(from = %from, error = %e, "Failed to send ack");
+                }
+            }
+            Err(e) => {
+                tracing::error!(error = ?e, "Failed to serialize ack");
+            }
+        }
+    }
+
+    /// Resolve the pending delivery an ack refers to
+    fn handle_ack(&self, id: MessageId, frame: &IncomingFrame) {
+        match lock(&self.pending).remove(&(frame.from, id)) {
+            Some(ack_tx) => {
+                let _ = ack_tx.send(());
+            }
+            None => {
+                tracing::debug!(id = %id, peer = %frame.addr, "Ignoring unexpected ack");
+            }
+        }
+    }
+
+    /// Record a received message ID, returning false if it was seen before
+    fn first_sighting(&self, from: PublicKey, id: MessageId) -> bool {
+        let mut seen = lock(&self.seen);
+        seen.retain(|_, received| received.elapsed() <= DEDUP_WINDOW);
+        seen.insert((from, id), Instant::now()).is_none()
+    }
+
+    /// Run the messaging service
+    ///
+    /// This accepts incoming TCP connections into the connection pool,
+    /// handles the frames received on every pooled connection, and flushes
+    /// the outbox when peers come online.
+    pub async fn run(&self) -> Result<()> {
+        let accept_loop = async {
+            loop {
+                match self.listener.accept().await {
+                    Ok((stream, peer_addr)) => {
+                        tracing::debug!(peer = %peer_addr, "New connection");
+                        let connections = self.connections.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = connections.accept(stream, peer_addr).await {
+                                tracing::warn!(peer = %peer_addr, error = %e, "Rejected connection");
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        tracing::error!(error = ?e, "Failed to accept connection");
+                    }
+                }
+            }
+        };
+
+        let frame_loop = async {
+            let mut incoming_rx = self.incoming_rx.lock().await;
+            while let Some(frame) = incoming_rx.recv().await {
+                self.handle_frame(frame).await;
+            }
+        };
+
+        let peer_loop = async {
+            let mut peer_rx = self.peer_rx.lock().await;
+            while let Some(event) = peer_rx.recv().await {
+                if let PeerEvent::PeerJoined(peer) = event {
+                    self.flush_outbox(&peer).await;
+                    self.sync_groups(&peer).await;
+                    self.resume_transfers(&peer).await;
+                }
+            }
+        };
+
+        let expiry_loop = async {
+            let mut interval = tokio::time::interval(OUTBOX_EXPIRY_INTERVAL);
+            loop {
+                interval.tick().await;
+                self.expire_outbox().await;
+            }
+        };
+
+        tokio::select! {
+            _ = accept_loop => {}
+            _ = frame_loop => {}
+            _ = peer_loop => {}
+            _ = expiry_loop => {}
+        }
 
         Ok(())
     }
+}
 
-    /// Handle an incoming TCP connection
-    async fn handle_connection(
-        stream: TcpStream,
-        peer_addr: SocketAddr,
-        event_tx: mpsc::UnboundedSender<MessageEvent>,
-    ) {
-        tracing::debug!(peer = %peer_addr, "New connection");
-
-        let reader = BufReader::new(stream);
-        let mut lines = reader.lines();
-
-        while let Ok(Some(line)) = lines.next_line().await {
-            match serde_json::from_str::<TextMessage>(&line) {
-                Ok(msg) => {
-                    tracing::info!(
-                        from = %msg.from,
-                        content = %msg.content,
-                        "Message received"
-                    );
-
-                    if event_tx.send(MessageEvent::Received(msg)).is_err() {
-                        tracing::error!("Event channel closed");
-                        break;
+impl Drop for MessagingService {
+    fn drop(&mut self) {
+        // Stops the QUIC accept loop, which would otherwise hold the port
+        if let Some(quic) = &self.quic {
+            quic.close();
+        }
+    }
+}
+
+/// Background task that sends a message until it is acknowledged
+struct Delivery {
+    id: MessageId,
+    peer: Peer,
+    data: Vec<u8>,
+    /// Whether to publish delivery events
+    report: bool,
+    config: DeliveryConfig,
+    connections: ConnectionManager,
+    registry: PeerRegistry,
+    events: EventReporter,
+    pending: PendingAcks,
+    /// Outbox the message was queued in, if it is being flushed from there
+    outbox: Option<Outbox>,
+}
+
+impl Delivery {
+    async fn run(self, mut ack_rx: oneshot::Receiver<()>) {
+        let to = self.peer.nickname.clone();
+        let mut last_error = "no acknowledgement".to_string();
+
+        for attempt in 1..=self.config.max_attempts {
+            // The peer may have moved since the message was queued
+            let peer = self
+                .registry
+                .get(&self.peer.id)
+                .await
+                .unwrap_or_else(|| self.peer.clone());
+
+            match self.connections.send(&peer, &self.data).await {
+                Ok(()) => {
+                    if attempt == 1 && self.report {
+                        tracing::info!(to = %to, id = %self.id, "Message sent");
+                        self.events
+                            .report(MessageEvent::Sent {
+                                to: to.clone(),
+                                id: self.id,
+                            })
+                            .await;
+                    }
+
+                    if let Ok(Ok(())) =
+                        tokio::time::timeout(self.config.ack_timeout, &mut ack_rx).await
+                    {
+                        tracing::debug!(to = %to, id = %self.id, "Message delivered");
+                        if let Some(outbox) = &self.outbox {
+                            if let Err(e) = outbox.delivered(&self.peer.public_key, self.id).await {
+                                tracing::error!(error = %e, "Failed to update outbox");
+                            }
+                        }
+                        if self.report {
+                            self.events
+                                .report(MessageEvent::Delivered { to, id: self.id })
+                                .await;
+                        }
+                        return;
                     }
+                    last_error = "no acknowledgement".to_string();
+                    tracing::debug!(to = %to, id = %self.id, attempt, "No ack, resending");
+                }
+                Err(e @ ParlanceError::Handshake(_)) => {
+                    // A peer proving the wrong identity will not fix itself
+                    tracing::warn!(peer = %to, error = %e, "Secure handshake failed");
+                    last_error = e.to_string();
+                    break;
                 }
                 Err(e) => {
-                    tracing::warn!(error = ?e, line = %line, "Invalid message format");
+                    tracing::debug!(to = %to, id = %self.id, attempt, error = %e, "Send attempt failed");
+                    last_error = e.to_string();
+                    if attempt < self.config.max_attempts {
+                        tokio::time::sleep(self.config.ack_timeout).await;
+                    }
                 }
             }
         }
 
-        tracing::debug!(peer = %peer_addr, "Connection closed");
+        lock(&self.pending).remove(&(self.peer.public_key, self.id));
+
+        // A queued message goes back to wait for the peer's next appearance
+        if let Some(outbox) = &self.outbox {
+            outbox.release(&self.peer.public_key, self.id).await;
+            tracing::info!(to = %to, id = %self.id, error = %last_error, "Delivery failed, message queued again");
+            if self.report {
+                self.events
+                    .report(MessageEvent::Queued { to, id: self.id })
+                    .await;
+            }
+            return;
+        }
+
+        tracing::warn!(to = %to, id = %self.id, error = %last_error, "Message delivery failed");
+        if self.report {
+            self.events
+                .report(MessageEvent::Failed {
+                    to,
+                    id: self.id,
+                    error: last_error,
+                })
+                .await;
+        }
     }
+}
 
-    /// Run the messaging service
+/// Background task that streams an accepted file to the receiver
+struct FileStream {
+    transfer: Outgoing,
+    /// Stream number; the task stops once a newer stream is started
+    stream: u64,
+    offset: u64,
+    connections: ConnectionManager,
+    transfers: Transfers,
+    events: EventReporter,
+}
+
+impl FileStream {
+    async fn run(self) {
+        let offer = &self.transfer.offer;
+        if let Err(e) = self.stream_chunks().await {
+            // A file that changed since the offer cannot be resumed
+            let event = if matches!(e, ParlanceError::Transfer(_)) {
+                tracing::warn!(to = %self.transfer.to, id = %offer.id, error = %e, "File transfer failed");
+                self.transfers
+                    .finish_outgoing(offer.id, &self.transfer.peer_key);
+                MessageEvent::TransferFailed {
+                    peer: self.transfer.to.clone(),
+                    id: offer.id,
+                    name: offer.name.clone(),
+                    error: e.to_string(),
+                }
+            } else {
+                tracing::info!(to = %self.transfer.to, id = %offer.id, error = %e, "File transfer interrupted");
+                MessageEvent::TransferInterrupted {
+                    peer: self.transfer.to.clone(),
+                    id: offer.id,
+                    name: offer.name.clone(),
+                    error: e.to_string(),
+                }
+            };
+            self.events.report(event).await;
+        }
+    }
+
+    /// Send the file from the accepted offset to the end
     ///
-    /// This accepts incoming TCP connections and handles them concurrently.
-    pub async fn run(&self) -> Result<()> {
+    /// At least one chunk is sent, so empty files complete too.
+    async fn stream_chunks(&self) -> Result<()> {
+        let offer = &self.transfer.offer;
+        let read_err = |e: std::io::Error| {
+            ParlanceError::Transfer(format!(
+                "Failed to read {}: {}",
+                self.transfer.path.display(),
+                e
+            ))
+        };
+
+        let mut file = tokio::fs::File::open(&self.transfer.path)
+            .await
+            .map_err(read_err)?;
+        file.seek(SeekFrom::Start(self.offset))
+            .await
+            .map_err(read_err)?;
+
+        let mut offset = self.offset;
+        let mut buf = Vec::with_capacity(CHUNK_SIZE);
         loop {
-            match self.listener.accept().await {
-                Ok((stream, peer_addr)) => {
-                    let event_tx = self.event_tx.clone();
-                    tokio::spawn(async move {
-                        Self::handle_connection(stream, peer_addr, event_tx).await;
-                    });
+            if !self.transfers.is_current_stream(offer.id, self.stream) {
+                return Ok(());
+            }
+
+            buf.clear();
+            (&mut file)
+                .take(CHUNK_SIZE as u64)
+                .read_to_end(&mut buf)
+                .await
+                .map_err(read_err)?;
+            let end = offset + buf.len() as u64;
+            if end > offer.size || (buf.is_empty() && offset < offer.size) {
+                return Err(ParlanceError::Transfer(format!(
+                    "{} changed since it was offered",
+                    offer.name
+                )));
+            }
+
+            let data = PeerFrame::Chunk(FileChunk::new(offer.id, offset, &buf)).encode()?;
+            self.connections
+                .send_bulk(&self.transfer.peer_key, &data)
+                .await?;
+
+            if crosses_step(offset, end, offer.size) {
+                self.events
+                    .report(MessageEvent::TransferProgress {
+                        peer: self.transfer.to.clone(),
+                        id: offer.id,
+                        name: offer.name.clone(),
+                        outgoing: true,
+                        transferred: end,
+                        size: offer.size,
+                    })
+                    .await;
+            }
+
+            offset = end;
+            if offset >= offer.size {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Publishes message events and mirrors them into the history
+#[derive(Clone)]
+struct EventReporter {
+    event_tx: mpsc::UnboundedSender<MessageEvent>,
+    history: Option<History>,
+}
+
+impl EventReporter {
+    /// Record a message we are about to send
+    async fn record_sent(&self, to: &str, recipient: PublicKey, msg: &TextMessage) {
+        let Some(history) = &self.history else {
+            return;
+        };
+
+        let entry = HistoryEntry {
+            id: msg.id,
+            peer: to.to_string(),
+            peer_key: recipient,
+            direction: Direction::Sent,
+            content: msg.content.clone(),
+            timestamp: msg.timestamp,
+            state: DeliveryState::Pending,
+        };
+        if let Err(e) = history.record(entry).await {
+            tracing::error!(error = %e, "Failed to record sent message");
+        }
+    }
+
+    /// Update the history for an event and publish it
+    async fn report(&self, event: MessageEvent) {
+        if let Some(history) = &self.history {
+            let result = match &event {
+                MessageEvent::Received { msg, .. } => {
+                    history
+                        .record(HistoryEntry {
+                            id: msg.id,
+                            peer: msg.from.clone(),
+                            peer_key: msg.public_key,
+                            direction: Direction::Received,
+                            content: msg.content.clone(),
+                            timestamp: msg.timestamp,
+                            state: DeliveryState::Delivered,
+                        })
+                        .await
                 }
-                Err(e) => {
-                    tracing::error!(error = ?e, "Failed to accept connection");
+                MessageEvent::Queued { id, .. } => {
+                    history.set_state(*id, DeliveryState::Queued).await
                 }
+                MessageEvent::Sent { id, .. } => history.set_state(*id, DeliveryState::Sent).await,
+                MessageEvent::Delivered { id, .. } => {
+                    history.set_state(*id, DeliveryState::Delivered).await
+                }
+                MessageEvent::Failed { id, .. } => {
+                    history.set_state(*id, DeliveryState::Failed).await
+                }
+                MessageEvent::GroupReceived { .. }
+                | MessageEvent::GroupChanged { .. }
+                | MessageEvent::FileOffered(_)
+                | MessageEvent::TransferProgress { .. }
+                | MessageEvent::TransferInterrupted { .. }
+                | MessageEvent::TransferComplete { .. }
+                | MessageEvent::TransferFailed { .. } => Ok(()),
+            };
+            if let Err(e) = result {
+                tracing::error!(error = %e, "Failed to update history");
             }
         }
+
+        if self.event_tx.send(event).is_err() {
+            tracing::error!("Event channel closed");
+        }
     }
 }
 
+/// Describe how a group's members changed between two copies
+fn membership_changes(previous: &Group, current: &Group) -> Vec<GroupChange> {
+    let joined = current
+        .members
+        .iter()
+        .filter(|m| !previous.is_member(&m.public_key))
+        .map(|m| GroupChange::MemberJoined(m.nickname.clone()));
+    let left = previous
+        .members
+        .iter()
+        .filter(|m| !current.is_member(&m.public_key))
+        .map(|m| GroupChange::MemberLeft(m.nickname.clone()));
+
+    joined.chain(left).collect()
+}
+
+/// Run the handshake with a peer and check it presented the expected identity
+async fn open_channel(
+    stream: TcpStream,
+    identity: &Identity,
+    peer: &Peer,
+) -> Result<SecureChannel<TcpStream>> {
+    let channel = SecureChannel::initiate(stream, identity).await?;
+
+    if channel.remote_public_key() != peer.public_key {
+        return Err(ParlanceError::Handshake(format!(
+            "{} presented an unexpected identity key",
+            peer.nickname
+        )));
+    }
+
+    Ok(channel)
+}
+
 /// Helper to send a message to a peer
 #[allow(dead_code)]
 pub async fn send_to_peer(
     nickname: &str,
+    identity: &Identity,
     to_nickname: &str,
     content: String,
     registry: &PeerRegistry,
@@ -209,14 +1695,11 @@ pub async fn send_to_peer(
         .ok_or_else(|| ParlanceError::PeerNotFound(to_nickname.to_string()))?;
 
     let stream = TcpStream::connect(peer.addr).await?;
+    let mut channel = open_channel(stream, identity, peer).await?;
 
-    let msg = TextMessage::new(nickname.to_string(), content);
-    let data = serde_json::to_string(&msg)?;
-
-    let mut stream = stream;
-    stream.write_all(data.as_bytes()).await?;
-    stream.write_all(b"\n").await?;
-    stream.flush().await?;
+    let msg = TextMessage::new(nickname.to_string(), identity.public_key(), content);
+    let data = PeerFrame::Message(msg).encode()?;
+    channel.send(&data).await?;
 
     Ok(())
 }
@@ -1,5 +1,19 @@
 //! Network protocols for peer discovery and messaging.
 
 pub mod bootstrap;
+pub mod channels;
+pub mod connection;
 pub mod discovery;
 pub mod messaging;
+pub mod nat;
+pub mod outbox;
+pub mod portmap;
+pub mod protocol;
+pub mod punch;
+pub mod quic;
+pub mod relay;
+pub mod secure;
+pub mod signed;
+pub mod transfer;
+pub mod udp_stream;
+pub mod wire;
@@ -0,0 +1,191 @@
+//! Reflexive address discovery and NAT classification.
+//!
+//! The bootstrap server answers probe datagrams on two UDP ports with the
+//! address each probe arrived from, which is the address our NAT mapped the
+//! probing socket to. Like classic STUN, [`probe`] compares the answers to
+//! classify the NAT:
+//!
+//! 1. A probe to the primary port learns the mapped address. If it equals
+//!    the socket's local address, there is no NAT.
+//! 2. A probe asking for the answer to come from the alternate port tells
+//!    whether the NAT lets in datagrams from a port we never sent to.
+//! 3. A probe to the alternate port tells whether the NAT keeps the same
+//!    mapping for a new destination.
+//!
+//! The second test must run before anything is sent to the alternate port,
+//! which would otherwise open the NAT for its answer. The server has a
+//! single address, so a NAT that filters by address but not by port reads
+//! as full cone.
+
+use super::punch::{recv_from, send_to, Datagram};
+use crate::core::error::{ParlanceError, Result};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+use uuid::Uuid;
+
+/// How long to wait for the answer to each probe
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Delay before resending an unanswered probe
+const PROBE_RETRY: Duration = Duration::from_millis(250);
+
+/// Largest probe answer accepted
+const MAX_RESPONSE_SIZE: usize = 512;
+
+/// How a NAT maps and filters our UDP traffic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NatType {
+    /// No NAT: the mapped address is our own
+    Open,
+    /// One mapping for all destinations, open to datagrams from anyone
+    FullCone,
+    /// One mapping for all destinations, open only to addresses we sent to
+    Restricted,
+    /// A different mapping for every destination; cannot be hole punched
+    Symmetric,
+}
+
+impl std::fmt::Display for NatType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NatType::Open => write!(f, "open (no NAT)"),
+            NatType::FullCone => write!(f, "full cone"),
+            NatType::Restricted => write!(f, "restricted"),
+            NatType::Symmetric => write!(f, "symmetric"),
+        }
+    }
+}
+
+/// Result of probing the bootstrap server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NatProbe {
+    /// Address of the probing socket on the local network
+    pub local_addr: SocketAddr,
+    /// Address the server saw the probes arrive from
+    pub mapped_addr: SocketAddr,
+    pub nat_type: NatType,
+}
+
+impl NatProbe {
+    /// Whether peers can connect to our listeners without our help
+    ///
+    /// Only when there is no NAT. Even a full cone NAT only maps the probing
+    /// socket; nothing maps the TCP listener's port, so peers have to punch
+    /// or relay unless the router forwards it.
+    pub fn directly_reachable(&self) -> bool {
+        self.nat_type == NatType::Open
+    }
+}
+
+/// Datagram sent to a probe port
+#[derive(Debug, Serialize)]
+struct ProbeRequest {
+    id: String,
+    /// Ask for the answer to come from the other probe port
+    change_port: bool,
+}
+
+/// The server's answer to a probe
+#[derive(Debug, Deserialize)]
+struct ProbeResponse {
+    id: String,
+    mapped_addr: String,
+}
+
+/// Probe the server's two ports and classify the NAT in front of `socket`
+///
+/// `local_addr` is the socket's address on the local network, as opposed
+/// to the unspecified address it may be bound to.
+pub async fn probe(
+    socket: &dyn Datagram,
+    local_addr: SocketAddr,
+    primary: SocketAddr,
+    alternate: SocketAddr,
+    timeout: Duration,
+) -> Result<NatProbe> {
+    let mapped_addr = request(socket, primary, false, timeout)
+        .await?
+        .ok_or_else(|| ParlanceError::Traversal("no answer from the probe server".to_string()))?;
+
+    if mapped_addr == local_addr {
+        return Ok(NatProbe {
+            local_addr,
+            mapped_addr,
+            nat_type: NatType::Open,
+        });
+    }
+
+    let unsolicited = request(socket, primary, true, timeout).await?.is_some();
+
+    let alternate_mapped = request(socket, alternate, false, timeout)
+        .await?
+        .ok_or_else(|| {
+            ParlanceError::Traversal("no answer from the alternate probe port".to_string())
+        })?;
+
+    let nat_type = if alternate_mapped != mapped_addr {
+        NatType::Symmetric
+    } else if unsolicited {
+        NatType::FullCone
+    } else {
+        NatType::Restricted
+    };
+    tracing::debug!(mapped = %mapped_addr, nat = %nat_type, "NAT probed");
+
+    Ok(NatProbe {
+        local_addr,
+        mapped_addr,
+        nat_type,
+    })
+}
+
+/// Send one probe until it is answered, returning the mapped address
+///
+/// Returns `None` if no answer arrived within `timeout`.
+async fn request(
+    socket: &dyn Datagram,
+    server: SocketAddr,
+    change_port: bool,
+    timeout: Duration,
+) -> Result<Option<SocketAddr>> {
+    let id = Uuid::new_v4().to_string();
+    let packet = serde_json::to_vec(&ProbeRequest {
+        id: id.clone(),
+        change_port,
+    })?;
+    let deadline = Instant::now() + timeout;
+    let mut interval = tokio::time::interval(PROBE_RETRY);
+    let mut buf = [0u8; MAX_RESPONSE_SIZE];
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                send_to(socket, &packet, server).await?;
+            }
+
+            result = recv_from(socket, &mut buf) => {
+                let Ok((len, _)) = result else {
+                    continue;
+                };
+                let Ok(response) = serde_json::from_slice::<ProbeResponse>(&buf[..len]) else {
+                    continue;
+                };
+                if response.id != id {
+                    continue;
+                }
+                let mapped = response.mapped_addr.parse().map_err(|_| {
+                    ParlanceError::InvalidMessage(format!(
+                        "invalid mapped address: {}",
+                        response.mapped_addr
+                    ))
+                })?;
+                return Ok(Some(mapped));
+            }
+
+            _ = sleep_until(deadline) => return Ok(None),
+        }
+    }
+}
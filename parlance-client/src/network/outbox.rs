@@ -0,0 +1,228 @@
+//! Persistent outbox for messages to offline peers.
+//!
+//! Messages sent to a trusted peer that is not currently online are stored
+//! here and handed back to the messaging service when the peer reappears.
+//! A message stays in the outbox, marked in flight, until the peer
+//! acknowledges it; if delivery fails it becomes pending again. Entries
+//! older than the configured expiry are dropped instead of being delivered
+//! late. With a [`Vault`] the file is sealed on disk.
+
+use crate::core::error::{ParlanceError, Result};
+use crate::core::identity::PublicKey;
+use crate::core::storage;
+use crate::core::vault::{self, Vault};
+use crate::network::messaging::{MessageId, TextMessage};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// A message waiting for its recipient to come online
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// Recipient nickname
+    pub to: String,
+    /// Recipient identity key the message must be delivered to
+    pub recipient: PublicKey,
+    /// The queued message
+    pub message: TextMessage,
+    /// Unix timestamp when the message was queued
+    pub queued_at: i64,
+    /// Whether a delivery attempt is under way; not persisted, so messages
+    /// in flight when the client stopped are sent again
+    #[serde(skip)]
+    in_flight: bool,
+}
+
+impl OutboxEntry {
+    /// Create an entry queued now
+    pub fn new(to: String, recipient: PublicKey, message: TextMessage) -> Self {
+        Self {
+            to,
+            recipient,
+            message,
+            queued_at: Utc::now().timestamp(),
+            in_flight: false,
+        }
+    }
+
+    /// Get the ID of the queued message
+    pub fn id(&self) -> MessageId {
+        self.message.id
+    }
+
+    fn is_expired(&self, expiry: Duration, now: i64) -> bool {
+        now.saturating_sub(self.queued_at) > expiry.as_secs() as i64
+    }
+
+    fn matches(&self, recipient: &PublicKey, id: MessageId) -> bool {
+        self.recipient == *recipient && self.id() == id
+    }
+}
+
+/// On-disk representation of the outbox
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct OutboxFile {
+    #[serde(default)]
+    messages: Vec<OutboxEntry>,
+}
+
+/// Persistent, thread-safe outbox
+#[derive(Clone)]
+pub struct Outbox {
+    path: PathBuf,
+    expiry: Duration,
+    vault: Option<Vault>,
+    entries: Arc<RwLock<Vec<OutboxEntry>>>,
+}
+
+impl Outbox {
+    /// Load the outbox from `path`, starting empty if the file does not exist
+    ///
+    /// With a vault, a plaintext outbox is re-written sealed.
+    pub fn load<P: AsRef<Path>>(path: P, expiry: Duration, vault: Option<Vault>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut migrate = false;
+
+        let file = if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|e| {
+                ParlanceError::Storage(format!("Failed to read {}: {}", path.display(), e))
+            })?;
+            migrate = vault.is_some() && !vault::is_sealed(&contents);
+            serde_json::from_str(&vault::open_text(vault.as_ref(), &contents, &path)?)?
+        } else {
+            OutboxFile::default()
+        };
+
+        let outbox = Self {
+            path,
+            expiry,
+            vault,
+            entries: Arc::new(RwLock::new(file.messages.clone())),
+        };
+        if migrate {
+            storage::atomic_write(&outbox.path, &outbox.encode(&file.messages)?)?;
+        }
+        Ok(outbox)
+    }
+
+    /// Queue a message
+    pub async fn push(&self, entry: OutboxEntry) -> Result<()> {
+        let mut entries = self.entries.write().await;
+        entries.push(entry);
+        self.save(&entries).await
+    }
+
+    /// Get all pending messages, oldest first
+    pub async fn list(&self) -> Vec<OutboxEntry> {
+        let entries = self.entries.read().await;
+        let now = Utc::now().timestamp();
+        entries
+            .iter()
+            .filter(|e| !e.is_expired(self.expiry, now))
+            .cloned()
+            .collect()
+    }
+
+    /// Mark the pending messages for a recipient in flight and return them,
+    /// oldest first
+    ///
+    /// The messages stay in the outbox until [`Outbox::delivered`] removes
+    /// them or [`Outbox::release`] makes them pending again, and are not
+    /// returned twice in the meantime.
+    pub async fn claim_for(&self, recipient: &PublicKey) -> Vec<OutboxEntry> {
+        let mut entries = self.entries.write().await;
+        let now = Utc::now().timestamp();
+
+        // Expired entries stay behind so that `expire` can report them
+        entries
+            .iter_mut()
+            .filter(|e| {
+                e.recipient == *recipient && !e.in_flight && !e.is_expired(self.expiry, now)
+            })
+            .map(|e| {
+                e.in_flight = true;
+                e.clone()
+            })
+            .collect()
+    }
+
+    /// Remove a message its recipient acknowledged
+    pub async fn delivered(&self, recipient: &PublicKey, id: MessageId) -> Result<()> {
+        let mut entries = self.entries.write().await;
+        let before = entries.len();
+        entries.retain(|e| !e.matches(recipient, id));
+
+        if entries.len() != before {
+            self.save(&entries).await?;
+        }
+        Ok(())
+    }
+
+    /// Make an in-flight message pending again after delivery failed
+    pub async fn release(&self, recipient: &PublicKey, id: MessageId) {
+        let mut entries = self.entries.write().await;
+        if let Some(entry) = entries.iter_mut().find(|e| e.matches(recipient, id)) {
+            entry.in_flight = false;
+        }
+    }
+
+    /// Cancel the pending message whose ID starts with `id_prefix`
+    ///
+    /// Nothing is removed unless exactly one message matches. Messages in
+    /// flight cannot be cancelled.
+    pub async fn cancel(&self, id_prefix: &str) -> Result<Option<OutboxEntry>> {
+        let mut entries = self.entries.write().await;
+
+        let matches: Vec<usize> = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| !e.in_flight && e.id().starts_with(id_prefix))
+            .map(|(i, _)| i)
+            .collect();
+
+        let [index] = matches.as_slice() else {
+            return Ok(None);
+        };
+
+        let entry = entries.remove(*index);
+        self.save(&entries).await?;
+        Ok(Some(entry))
+    }
+
+    /// Drop messages older than the expiry, returning them
+    ///
+    /// Messages in flight are left to finish their delivery attempt.
+    pub async fn expire(&self) -> Result<Vec<OutboxEntry>> {
+        let mut entries = self.entries.write().await;
+        let now = Utc::now().timestamp();
+
+        let (expired, kept): (Vec<_>, Vec<_>) = entries
+            .drain(..)
+            .partition(|e| !e.in_flight && e.is_expired(self.expiry, now));
+        *entries = kept;
+
+        if !expired.is_empty() {
+            self.save(&entries).await?;
+        }
+        Ok(expired)
+    }
+
+    /// Write the outbox to disk
+    async fn save(&self, entries: &[OutboxEntry]) -> Result<()> {
+        let contents = self.encode(entries)?;
+        storage::atomic_write_async(self.path.clone(), contents).await
+    }
+
+    /// Serialize the outbox file, sealed if there is a vault
+    fn encode(&self, entries: &[OutboxEntry]) -> Result<Vec<u8>> {
+        let file = OutboxFile {
+            messages: entries.to_vec(),
+        };
+        let json = serde_json::to_string_pretty(&file)?;
+        Ok(vault::seal_text(self.vault.as_ref(), &json)?.into_bytes())
+    }
+}
@@ -0,0 +1,648 @@
+//! Automatic port forwarding on home routers.
+//!
+//! Peers behind a home router can only dial us if the router forwards our
+//! messaging port. A [`PortMapper`] asks the router to, first over NAT-PMP
+//! (RFC 6886), which routers speaking its successor PCP still answer, then
+//! over UPnP-IGD. Mappings are leases: the mapper renews them at half their
+//! lifetime and removes them when told to stop.
+//!
+//! NAT-PMP is a pair of small UDP datagrams sent to the default gateway.
+//! UPnP finds the router with an SSDP multicast search, reads its device
+//! description over HTTP and calls its `WANIPConnection` service with SOAP
+//! requests. Both are spoken by hand, with just enough HTTP and XML
+//! handling for the handful of messages involved.
+
+use crate::core::error::{ParlanceError, Result};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{oneshot, watch};
+use tokio::time::{sleep, timeout, Instant};
+
+/// Port NAT-PMP gateways listen on
+pub const NAT_PMP_PORT: u16 = 5351;
+
+/// Multicast address UPnP devices answer searches on
+pub const SSDP_ADDR: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900);
+
+/// Lease requested for a mapping
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(2 * 60 * 60);
+
+/// How long each protocol gets to answer
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Delay before trying again after no gateway mapped the port
+const RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// First NAT-PMP retransmission delay, doubled after every attempt
+const NAT_PMP_RETRY: Duration = Duration::from_millis(250);
+
+/// Largest HTTP response read from a router
+const MAX_HTTP_RESPONSE: usize = 64 * 1024;
+
+/// Description routers show next to our mappings
+const MAPPING_DESCRIPTION: &str = "parlance";
+
+/// WAN services able to forward ports, in order of preference
+const WAN_SERVICES: [&str; 3] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:2",
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+/// UPnP error code for routers that only accept permanent mappings
+const ONLY_PERMANENT_LEASES: &str = "725";
+
+/// Where to look for a router that forwards ports
+#[derive(Debug, Clone)]
+pub struct PortMapConfig {
+    /// NAT-PMP server, normally the default gateway on [`NAT_PMP_PORT`]
+    pub nat_pmp: Option<SocketAddr>,
+    /// Where UPnP search requests are sent
+    pub ssdp: SocketAddr,
+    /// Lease to request for the mapping
+    pub lifetime: Duration,
+    /// How long each protocol gets to answer
+    pub timeout: Duration,
+}
+
+impl Default for PortMapConfig {
+    fn default() -> Self {
+        Self {
+            nat_pmp: default_gateway().map(|ip| SocketAddr::new(ip.into(), NAT_PMP_PORT)),
+            ssdp: SSDP_ADDR,
+            lifetime: DEFAULT_LIFETIME,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+/// Protocol a mapping was made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingProtocol {
+    NatPmp,
+    Upnp,
+}
+
+impl fmt::Display for MappingProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MappingProtocol::NatPmp => write!(f, "NAT-PMP"),
+            MappingProtocol::Upnp => write!(f, "UPnP"),
+        }
+    }
+}
+
+/// A port the router forwards to our messaging port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortMapping {
+    pub protocol: MappingProtocol,
+    /// Address peers can dial us at
+    pub external_addr: SocketAddr,
+    /// How long the router keeps the mapping; zero if it never expires
+    pub lifetime: Duration,
+}
+
+/// A router we can ask to forward ports
+#[derive(Debug, Clone)]
+enum Gateway {
+    NatPmp(SocketAddr),
+    Upnp(IgdService),
+}
+
+/// The port forwarding service of a UPnP Internet Gateway Device
+#[derive(Debug, Clone)]
+struct IgdService {
+    control_url: String,
+    service_type: String,
+    /// Our address on the router's network, which it forwards to
+    local_ip: IpAddr,
+}
+
+/// Keeps the router forwarding a TCP port to us
+pub struct PortMapper {
+    config: PortMapConfig,
+    internal_port: u16,
+    gateway: Option<Gateway>,
+    mapping: watch::Sender<Option<PortMapping>>,
+}
+
+impl PortMapper {
+    /// Create a mapper for the TCP port `internal_port`
+    pub fn new(config: PortMapConfig, internal_port: u16) -> Self {
+        Self {
+            config,
+            internal_port,
+            gateway: None,
+            mapping: watch::channel(None).0,
+        }
+    }
+
+    /// Subscribe to the current mapping
+    pub fn subscribe(&self) -> watch::Receiver<Option<PortMapping>> {
+        self.mapping.subscribe()
+    }
+
+    /// Create or renew the mapping
+    ///
+    /// The first call looks for a router, trying NAT-PMP before UPnP. Later
+    /// calls renew the mapping with the router found, and look again if it
+    /// stopped answering.
+    pub async fn map(&mut self) -> Result<PortMapping> {
+        let gateway = match self.gateway.take() {
+            Some(gateway) => gateway,
+            None => self.discover().await?,
+        };
+
+        let mapping = match &gateway {
+            Gateway::NatPmp(server) => nat_pmp_map(*server, self.internal_port, &self.config).await,
+            Gateway::Upnp(service) => upnp_map(service, self.internal_port, &self.config).await,
+        };
+
+        match mapping {
+            Ok(mapping) => {
+                self.gateway = Some(gateway);
+                self.mapping.send_replace(Some(mapping));
+                Ok(mapping)
+            }
+            Err(e) => {
+                self.mapping.send_replace(None);
+                Err(e)
+            }
+        }
+    }
+
+    /// Remove the mapping, if any
+    pub async fn unmap(&mut self) -> Result<()> {
+        let Some(mapping) = self.mapping.send_replace(None) else {
+            return Ok(());
+        };
+        match &self.gateway {
+            Some(Gateway::NatPmp(server)) => {
+                nat_pmp_request(
+                    *server,
+                    &map_request(self.internal_port, 0, 0),
+                    &self.config,
+                )
+                .await?;
+            }
+            Some(Gateway::Upnp(service)) => {
+                let args = format!(
+                    "<NewRemoteHost></NewRemoteHost>\
+                     <NewExternalPort>{}</NewExternalPort>\
+                     <NewProtocol>TCP</NewProtocol>",
+                    mapping.external_addr.port()
+                );
+                soap_call(service, "DeletePortMapping", &args, &self.config).await?;
+            }
+            None => {}
+        }
+        tracing::info!(external_addr = %mapping.external_addr, "Removed port mapping");
+        Ok(())
+    }
+
+    /// Keep the port mapped until `shutdown` fires, then remove the mapping
+    pub async fn run(mut self, mut shutdown: oneshot::Receiver<()>) {
+        loop {
+            let delay = match self.map().await {
+                Ok(mapping) => {
+                    tracing::info!(
+                        protocol = %mapping.protocol,
+                        external_addr = %mapping.external_addr,
+                        lifetime_secs = mapping.lifetime.as_secs(),
+                        "Port mapped"
+                    );
+                    renew_after(&mapping, &self.config)
+                }
+                Err(e) => {
+                    tracing::info!(error = %e, "Could not map port");
+                    RETRY_DELAY
+                }
+            };
+
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = &mut shutdown => break,
+            }
+        }
+
+        if let Err(e) = self.unmap().await {
+            tracing::warn!(error = %e, "Failed to remove port mapping");
+        }
+    }
+
+    /// Find a router that answers NAT-PMP or UPnP
+    async fn discover(&self) -> Result<Gateway> {
+        if let Some(server) = self.config.nat_pmp {
+            match nat_pmp_request(server, &[0, 0], &self.config).await {
+                Ok(_) => return Ok(Gateway::NatPmp(server)),
+                Err(e) => tracing::debug!(error = %e, "No NAT-PMP gateway"),
+            }
+        }
+
+        let service = upnp_discover(&self.config).await?;
+        Ok(Gateway::Upnp(service))
+    }
+}
+
+/// How long to wait before renewing `mapping`
+fn renew_after(mapping: &PortMapping, config: &PortMapConfig) -> Duration {
+    let lifetime = if mapping.lifetime.is_zero() {
+        // Permanent mappings are still refreshed in case the router restarts
+        config.lifetime
+    } else {
+        mapping.lifetime
+    };
+    (lifetime / 2).max(Duration::from_secs(1))
+}
+
+/// A NAT-PMP request to map `internal_port` for `lifetime_secs`
+fn map_request(internal_port: u16, external_port: u16, lifetime_secs: u32) -> [u8; 12] {
+    let mut request = [0u8; 12];
+    // Version 0, opcode 2 (map TCP), two reserved bytes
+    request[1] = 2;
+    request[4..6].copy_from_slice(&internal_port.to_be_bytes());
+    request[6..8].copy_from_slice(&external_port.to_be_bytes());
+    request[8..12].copy_from_slice(&lifetime_secs.to_be_bytes());
+    request
+}
+
+/// Map a port with a NAT-PMP gateway
+async fn nat_pmp_map(
+    server: SocketAddr,
+    internal_port: u16,
+    config: &PortMapConfig,
+) -> Result<PortMapping> {
+    let response = nat_pmp_request(server, &[0, 0], config).await?;
+    let external_ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+    let lifetime_secs = u32::try_from(config.lifetime.as_secs()).unwrap_or(u32::MAX);
+    let request = map_request(internal_port, internal_port, lifetime_secs);
+    let response = nat_pmp_request(server, &request, config).await?;
+    if response.len() < 16 {
+        return Err(ParlanceError::InvalidMessage(
+            "short NAT-PMP mapping response".to_string(),
+        ));
+    }
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    let lifetime = u32::from_be_bytes([response[12], response[13], response[14], response[15]]);
+
+    Ok(PortMapping {
+        protocol: MappingProtocol::NatPmp,
+        external_addr: SocketAddr::new(external_ip.into(), external_port),
+        lifetime: Duration::from_secs(lifetime.into()),
+    })
+}
+
+/// Send a NAT-PMP request until it is answered, returning the answer
+///
+/// Fails if the gateway reports an error or does not answer in time.
+async fn nat_pmp_request(
+    server: SocketAddr,
+    request: &[u8],
+    config: &PortMapConfig,
+) -> Result<Vec<u8>> {
+    let bind_addr: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(server).await?;
+
+    let deadline = Instant::now() + config.timeout;
+    let mut retry = NAT_PMP_RETRY;
+    let mut buf = [0u8; 16];
+    loop {
+        socket.send(request).await?;
+        let wait = retry.min(deadline.saturating_duration_since(Instant::now()));
+        match timeout(wait, socket.recv(&mut buf)).await {
+            Ok(Ok(len)) if len >= 12 && buf[1] == (request[1] | 0x80) => {
+                let result = u16::from_be_bytes([buf[2], buf[3]]);
+                if result != 0 {
+                    return Err(ParlanceError::Traversal(format!(
+                        "NAT-PMP gateway refused with result code {}",
+                        result
+                    )));
+                }
+                return Ok(buf[..len].to_vec());
+            }
+            // Unrelated datagrams and ICMP errors are retried like losses
+            Ok(_) | Err(_) => {}
+        }
+        if Instant::now() >= deadline {
+            return Err(ParlanceError::Traversal(
+                "no answer from the NAT-PMP gateway".to_string(),
+            ));
+        }
+        retry *= 2;
+    }
+}
+
+/// Find a UPnP Internet Gateway Device and its port forwarding service
+async fn upnp_discover(config: &PortMapConfig) -> Result<IgdService> {
+    let location = ssdp_search(config).await?;
+    let (status, description, local_addr) =
+        http_request(&location, "GET", &[], "", config.timeout).await?;
+    if status != 200 {
+        return Err(ParlanceError::Traversal(format!(
+            "UPnP device description returned HTTP {}",
+            status
+        )));
+    }
+
+    let (service_type, control_url) = WAN_SERVICES
+        .iter()
+        .find_map(|service| {
+            let control = control_url(&description, service)?;
+            Some((service.to_string(), control))
+        })
+        .ok_or_else(|| {
+            ParlanceError::Traversal("UPnP gateway has no WAN connection service".to_string())
+        })?;
+
+    Ok(IgdService {
+        control_url: resolve_url(&location, &control_url),
+        service_type,
+        local_ip: local_addr.ip(),
+    })
+}
+
+/// Multicast an SSDP search and return the first gateway's description URL
+async fn ssdp_search(config: &PortMapConfig) -> Result<String> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: {}\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: 1\r\n\
+         ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n",
+        config.ssdp
+    );
+    socket.send_to(request.as_bytes(), config.ssdp).await?;
+
+    let deadline = Instant::now() + config.timeout;
+    let mut buf = [0u8; 2048];
+    loop {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        let Ok(received) = timeout(wait, socket.recv_from(&mut buf)).await else {
+            return Err(ParlanceError::Traversal(
+                "no UPnP gateway answered".to_string(),
+            ));
+        };
+        let (len, _) = received?;
+        let response = String::from_utf8_lossy(&buf[..len]);
+        if let Some(location) = header(&response, "location") {
+            return Ok(location.to_string());
+        }
+    }
+}
+
+/// Map a port with a UPnP gateway
+async fn upnp_map(
+    service: &IgdService,
+    internal_port: u16,
+    config: &PortMapConfig,
+) -> Result<PortMapping> {
+    let add = |lease: u64| {
+        format!(
+            "<NewRemoteHost></NewRemoteHost>\
+             <NewExternalPort>{port}</NewExternalPort>\
+             <NewProtocol>TCP</NewProtocol>\
+             <NewInternalPort>{port}</NewInternalPort>\
+             <NewInternalClient>{client}</NewInternalClient>\
+             <NewEnabled>1</NewEnabled>\
+             <NewPortMappingDescription>{description}</NewPortMappingDescription>\
+             <NewLeaseDuration>{lease}</NewLeaseDuration>",
+            port = internal_port,
+            client = service.local_ip,
+            description = MAPPING_DESCRIPTION,
+            lease = lease,
+        )
+    };
+
+    let mut lifetime = config.lifetime;
+    if let Err(e) = soap_call(service, "AddPortMapping", &add(lifetime.as_secs()), config).await {
+        if !matches!(&e, ParlanceError::Traversal(msg) if msg.ends_with(ONLY_PERMANENT_LEASES)) {
+            return Err(e);
+        }
+        lifetime = Duration::ZERO;
+        soap_call(service, "AddPortMapping", &add(0), config).await?;
+    }
+
+    let response = soap_call(service, "GetExternalIPAddress", "", config).await?;
+    let external_ip = xml_text(&response, "NewExternalIPAddress")
+        .and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+        .ok_or_else(|| {
+            ParlanceError::InvalidMessage("UPnP gateway sent no external address".to_string())
+        })?;
+
+    Ok(PortMapping {
+        protocol: MappingProtocol::Upnp,
+        external_addr: SocketAddr::new(external_ip, internal_port),
+        lifetime,
+    })
+}
+
+/// Invoke a SOAP action on the gateway's WAN service and return the reply
+///
+/// Faults are reported as [`ParlanceError::Traversal`] ending in the UPnP
+/// error code.
+async fn soap_call(
+    service: &IgdService,
+    action: &str,
+    args: &str,
+    config: &PortMapConfig,
+) -> Result<String> {
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:{action} xmlns:u=\"{service}\">{args}</u:{action}></s:Body>\
+         </s:Envelope>",
+        action = action,
+        service = service.service_type,
+        args = args,
+    );
+    let soap_action = format!("\"{}#{}\"", service.service_type, action);
+    let headers = [
+        ("Content-Type", "text/xml; charset=\"utf-8\""),
+        ("SOAPAction", soap_action.as_str()),
+    ];
+
+    let (status, response, _) = http_request(
+        &service.control_url,
+        "POST",
+        &headers,
+        &body,
+        config.timeout,
+    )
+    .await?;
+    if status != 200 {
+        let code = xml_text(&response, "errorCode").unwrap_or("unknown");
+        return Err(ParlanceError::Traversal(format!(
+            "UPnP {} failed with error {}",
+            action,
+            code.trim()
+        )));
+    }
+    Ok(response)
+}
+
+/// Make an HTTP request, returning the status, the body and our address
+async fn http_request(
+    url: &str,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+    limit: Duration,
+) -> Result<(u16, String, SocketAddr)> {
+    let (host, path) = split_url(url)?;
+
+    let exchange = async {
+        let mut stream = TcpStream::connect(host).await?;
+        let local_addr = stream.local_addr()?;
+
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            host,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        (&mut stream)
+            .take(MAX_HTTP_RESPONSE as u64)
+            .read_to_end(&mut response)
+            .await?;
+        Ok::<_, ParlanceError>((response, local_addr))
+    };
+    let (response, local_addr) = timeout(limit, exchange).await.map_err(|_| {
+        ParlanceError::Traversal(format!("UPnP gateway at {} did not answer", host))
+    })??;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").ok_or_else(|| {
+        ParlanceError::InvalidMessage("truncated HTTP response from gateway".to_string())
+    })?;
+    let status = head
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| {
+            ParlanceError::InvalidMessage("invalid HTTP status line from gateway".to_string())
+        })?;
+
+    let chunked = header(head, "transfer-encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+    let body = if chunked {
+        dechunk(body)
+    } else {
+        body.to_string()
+    };
+    Ok((status, body, local_addr))
+}
+
+/// Split an `http://` URL into its host and path
+fn split_url(url: &str) -> Result<(&str, &str)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| ParlanceError::InvalidMessage(format!("unsupported URL: {}", url)))?;
+    Ok(match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    })
+}
+
+/// Resolve a control URL from a device description against its location
+fn resolve_url(location: &str, url: &str) -> String {
+    if url.starts_with("http://") {
+        return url.to_string();
+    }
+    let host = split_url(location)
+        .map(|(host, _)| host)
+        .unwrap_or_default();
+    if url.starts_with('/') {
+        format!("http://{}{}", host, url)
+    } else {
+        format!("http://{}/{}", host, url)
+    }
+}
+
+/// Value of an HTTP header, matched case-insensitively
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+/// Decode a body sent with chunked transfer encoding
+fn dechunk(mut body: &str) -> String {
+    let mut decoded = String::new();
+    while let Some((size, rest)) = body.split_once("\r\n") {
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let Ok(size) = usize::from_str_radix(size, 16) else {
+            break;
+        };
+        if size == 0 || rest.len() < size {
+            break;
+        }
+        decoded.push_str(&rest[..size]);
+        body = rest[size..].trim_start_matches("\r\n");
+    }
+    decoded
+}
+
+/// Text of the first element named `tag`, ignoring namespace prefixes
+fn xml_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    loop {
+        let open = rest.find('<')?;
+        rest = &rest[open + 1..];
+        let end = rest.find('>')?;
+        let name = rest[..end].split_whitespace().next()?;
+        let local = name.rsplit(':').next()?;
+        rest = &rest[end + 1..];
+        if local == tag {
+            let close = rest.find("</")?;
+            return Some(&rest[..close]);
+        }
+    }
+}
+
+/// The control URL of the service of type `service_type`, if described
+fn control_url(description: &str, service_type: &str) -> Option<String> {
+    description.split("<service>").skip(1).find_map(|service| {
+        let service = service.split("</service>").next()?;
+        (xml_text(service, "serviceType")?.trim() == service_type)
+            .then(|| xml_text(service, "controlURL"))
+            .flatten()
+            .map(|url| url.trim().to_string())
+    })
+}
+
+/// The IPv4 default gateway, where the platform lets us find it
+fn default_gateway() -> Option<Ipv4Addr> {
+    // Linux lists routes with little-endian hex addresses
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.get(1) != Some(&"00000000") {
+            return None;
+        }
+        let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+        (gateway != 0).then(|| Ipv4Addr::from(gateway.to_le_bytes()))
+    })
+}
@@ -0,0 +1,132 @@
+//! Protocol versions and feature negotiation.
+//!
+//! Every peer advertises a [`Hello`]: the protocol version it speaks, the
+//! oldest version it still understands and the optional features it
+//! supports. The hello travels in multicast announcements, in the bootstrap
+//! registration and in the handshake of every TCP connection, where both
+//! sides [`negotiate`](Hello::negotiate) the version and features they share.
+//! Peers whose version ranges do not overlap fail with
+//! [`ParlanceError::IncompatibleProtocol`] instead of exchanging frames the
+//! other side cannot read.
+
+use crate::core::error::{ParlanceError, Result};
+use serde::{Deserialize, Serialize};
+
+/// Protocol version spoken by this build
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Oldest protocol version this build can still talk to
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Group chats with synced membership
+pub const FEATURE_GROUPS: &str = "groups";
+
+/// Chunked file transfer
+pub const FEATURE_FILE_TRANSFER: &str = "file-transfer";
+
+/// Connections over QUIC on the UDP port matching the TCP port
+///
+/// Not part of [`FEATURES`]: it is only advertised while a QUIC endpoint
+/// is listening.
+pub const FEATURE_QUIC: &str = "quic";
+
+/// Features supported by this build
+pub const FEATURES: &[&str] = &[FEATURE_GROUPS, FEATURE_FILE_TRANSFER];
+
+/// Protocol versions and features advertised by a peer
+///
+/// Peers from before versioning send no hello fields at all, which reads
+/// as version 0 and is rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    /// Protocol version the peer speaks
+    #[serde(default)]
+    pub protocol_version: u16,
+    /// Oldest protocol version the peer understands
+    #[serde(default)]
+    pub min_protocol_version: u16,
+    /// Optional features the peer supports
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+/// What two peers agreed on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    /// Highest protocol version both sides speak
+    pub version: u16,
+    /// Features both sides support
+    pub features: Vec<String>,
+}
+
+impl Hello {
+    /// The hello advertised by this build
+    pub fn current() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            features: FEATURES.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// Add an optional feature to the hello
+    pub fn with_feature(mut self, feature: &str) -> Self {
+        if !self.supports(feature) {
+            self.features.push(feature.to_string());
+        }
+        self
+    }
+
+    /// Check whether the peer advertises `feature`
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Check that a peer's hello is compatible with ours
+    pub fn check(&self, remote: &Hello) -> Result<()> {
+        self.negotiate(remote).map(|_| ())
+    }
+
+    /// Agree on a version and features with a peer
+    pub fn negotiate(&self, remote: &Hello) -> Result<Negotiated> {
+        let version = self.protocol_version.min(remote.protocol_version);
+        let oldest = self
+            .min_protocol_version
+            .max(remote.min_protocol_version)
+            .max(1);
+
+        if version < oldest {
+            return Err(ParlanceError::IncompatibleProtocol(format!(
+                "peer speaks protocol {}, we speak {}",
+                describe_range(remote),
+                describe_range(self)
+            )));
+        }
+
+        let features = self
+            .features
+            .iter()
+            .filter(|f| remote.supports(f))
+            .cloned()
+            .collect();
+        Ok(Negotiated { version, features })
+    }
+}
+
+impl Negotiated {
+    /// Check whether both sides support `feature`
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// Describe the versions a peer understands, e.g. `1-3` or `0 (unversioned)`
+fn describe_range(hello: &Hello) -> String {
+    if hello.protocol_version == 0 {
+        "0 (unversioned)".to_string()
+    } else if hello.min_protocol_version >= hello.protocol_version {
+        hello.protocol_version.to_string()
+    } else {
+        format!("{}-{}", hello.min_protocol_version, hello.protocol_version)
+    }
+}
@@ -0,0 +1,170 @@
+//! UDP hole punching.
+//!
+//! Two peers behind NATs cannot accept each other's connections, but most
+//! NATs let packets in on a mapping once the host behind it has sent to the
+//! packet's source. When both peers send to each other's candidate addresses
+//! at the same moment, each side's outgoing packets open the mapping the
+//! other side's packets need. The bootstrap server supplies the candidates
+//! and the start time; [`punch`] does the sending and returns the first
+//! address that answered, over which [`UdpStream`](super::udp_stream::UdpStream)
+//! then carries the encrypted channel.
+
+use crate::core::error::{ParlanceError, Result};
+use std::future::poll_fn;
+use std::io;
+use std::net::SocketAddr;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::io::ReadBuf;
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, sleep_until, Instant};
+use uuid::Uuid;
+
+/// How long to keep punching before giving up
+pub const PUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay between rounds of punch packets
+const PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Punch packet: kind byte followed by the session ID
+pub(crate) const KIND_PUNCH: u8 = 1;
+
+/// Answer to a punch packet, confirming the path works both ways
+pub(crate) const KIND_PUNCH_ACK: u8 = 2;
+
+/// Length of punch and punch ack packets
+pub(crate) const PUNCH_PACKET_LEN: usize = 17;
+
+/// A socket that sends and receives datagrams
+///
+/// Implemented for [`UdpSocket`]; tests substitute sockets that filter
+/// traffic the way a NAT would.
+pub trait Datagram: Send + Sync {
+    /// Attempt to send a datagram to `target`
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>>;
+
+    /// Attempt to receive a datagram, returning its source address
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>>;
+}
+
+impl Datagram for UdpSocket {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        UdpSocket::poll_send_to(self, cx, buf, target)
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>> {
+        UdpSocket::poll_recv_from(self, cx, buf)
+    }
+}
+
+/// Send a datagram to `target`
+pub async fn send_to(socket: &dyn Datagram, buf: &[u8], target: SocketAddr) -> io::Result<usize> {
+    poll_fn(|cx| socket.poll_send_to(cx, buf, target)).await
+}
+
+/// Receive a datagram, returning its length and source address
+pub async fn recv_from(socket: &dyn Datagram, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    poll_fn(|cx| {
+        let mut read = ReadBuf::new(&mut *buf);
+        socket
+            .poll_recv_from(cx, &mut read)
+            .map_ok(|addr| (read.filled().len(), addr))
+    })
+    .await
+}
+
+/// Build a punch or punch ack packet for a session
+pub(crate) fn punch_packet(kind: u8, session: &Uuid) -> [u8; PUNCH_PACKET_LEN] {
+    let mut packet = [0u8; PUNCH_PACKET_LEN];
+    packet[0] = kind;
+    packet[1..].copy_from_slice(session.as_bytes());
+    packet
+}
+
+/// Punch towards a peer's candidates and return the address that answered
+///
+/// Waits until `start_at`, then sends punch packets for `session` to every
+/// candidate until one of them sends a punch or a punch ack back. Punches
+/// from the peer are acknowledged, so both sides learn that the path works.
+/// Candidates that cannot be sent to, such as private addresses on another
+/// network, are skipped.
+pub async fn punch(
+    socket: &dyn Datagram,
+    session: Uuid,
+    candidates: &[SocketAddr],
+    start_at: SystemTime,
+    timeout: Duration,
+) -> Result<SocketAddr> {
+    if let Ok(delay) = start_at.duration_since(SystemTime::now()) {
+        sleep(delay).await;
+    }
+
+    let punch = punch_packet(KIND_PUNCH, &session);
+    let ack = punch_packet(KIND_PUNCH_ACK, &session);
+    let deadline = Instant::now() + timeout;
+    let mut interval = tokio::time::interval(PUNCH_INTERVAL);
+    let mut buf = [0u8; PUNCH_PACKET_LEN + 1];
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                for candidate in candidates {
+                    if let Err(e) = send_to(socket, &punch, *candidate).await {
+                        tracing::trace!(candidate = %candidate, error = %e, "Punch not sent");
+                    }
+                }
+            }
+
+            result = recv_from(socket, &mut buf) => {
+                let (len, from) = match result {
+                    Ok(received) => received,
+                    Err(e) => {
+                        // ICMP errors from closed candidates surface here
+                        tracing::trace!(error = %e, "Punch receive failed");
+                        continue;
+                    }
+                };
+                if len != PUNCH_PACKET_LEN || buf[1..len] != session.as_bytes()[..] {
+                    continue;
+                }
+                match buf[0] {
+                    KIND_PUNCH => {
+                        send_to(socket, &ack, from).await?;
+                        tracing::debug!(addr = %from, "Hole punched");
+                        return Ok(from);
+                    }
+                    KIND_PUNCH_ACK => {
+                        tracing::debug!(addr = %from, "Hole punched");
+                        return Ok(from);
+                    }
+                    _ => {}
+                }
+            }
+
+            _ = sleep_until(deadline) => {
+                return Err(ParlanceError::Traversal(format!(
+                    "no answer from {} candidate address(es)",
+                    candidates.len()
+                )));
+            }
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! QUIC transport for peer connections.
+//!
+//! A [`QuicEndpoint`] listens on the UDP port with the same number as our
+//! TCP messaging port, so peers that advertise the `quic` feature can be
+//! dialed at the address they announce. The first bidirectional stream of a
+//! peer connection carries the same Noise handshake and frames as a TCP
+//! connection. The dialer then opens a second stream with a Noise channel
+//! of its own for bulk data such as file chunks, so a transfer does not
+//! hold up messages queued behind it.
+//!
+//! QUIC requires TLS, but peers have no certificates anyone could vouch
+//! for: each endpoint presents a throwaway self-signed certificate, and
+//! clients accept any. Peers are authenticated by the Noise handshake run
+//! on top, exactly as over TCP.
+
+use crate::core::error::{ParlanceError, Result};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Application protocol negotiated in the TLS handshake
+const ALPN: &[u8] = b"parlance";
+
+/// Server name sent when connecting; certificates are not checked against it
+const SERVER_NAME: &str = "parlance";
+
+/// Interval between keepalives, which also hold NAT mappings open
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A QUIC endpoint that both accepts and opens peer connections
+pub struct QuicEndpoint {
+    endpoint: quinn::Endpoint,
+}
+
+impl QuicEndpoint {
+    /// Bind an endpoint on `addr`
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let server_config = server_config(provider.clone())?;
+        let mut endpoint =
+            quinn::Endpoint::server(server_config, addr).map_err(|e| ParlanceError::BindError {
+                address: addr.to_string(),
+                source: e,
+            })?;
+        endpoint.set_default_client_config(client_config(provider)?);
+        Ok(Self { endpoint })
+    }
+
+    /// Get the local UDP address
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Open a connection to `addr` and the stream to run the channel over
+    pub async fn connect(&self, addr: SocketAddr) -> Result<QuicStream> {
+        let failed = |e: &dyn std::fmt::Display| {
+            ParlanceError::ConnectionUnavailable(format!(
+                "QUIC connection to {} failed: {}",
+                addr, e
+            ))
+        };
+        let connection = self
+            .endpoint
+            .connect(addr, SERVER_NAME)
+            .map_err(|e| failed(&e))?
+            .await
+            .map_err(|e| failed(&e))?;
+        let (send, recv) = connection.open_bi().await.map_err(|e| failed(&e))?;
+        Ok(QuicStream {
+            connection,
+            send,
+            recv,
+        })
+    }
+
+    /// Wait for the next peer to connect
+    ///
+    /// Returns `None` once the endpoint is closed.
+    pub async fn accept(&self) -> Option<QuicIncoming> {
+        self.endpoint.accept().await.map(QuicIncoming)
+    }
+
+    /// Close every connection and stop accepting new ones
+    pub fn close(&self) {
+        self.endpoint.close(0u32.into(), b"");
+    }
+}
+
+/// A connection attempt from a peer
+pub struct QuicIncoming(quinn::Incoming);
+
+impl QuicIncoming {
+    /// Address the peer connects from
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.0.remote_address()
+    }
+
+    /// Complete the handshake and wait for the peer to open its stream
+    pub async fn stream(self) -> Result<QuicStream> {
+        let addr = self.peer_addr();
+        let failed = |e: quinn::ConnectionError| {
+            ParlanceError::ConnectionUnavailable(format!(
+                "QUIC connection from {} failed: {}",
+                addr, e
+            ))
+        };
+        let connection = self.0.await.map_err(failed)?;
+        let (send, recv) = connection.accept_bi().await.map_err(failed)?;
+        Ok(QuicStream {
+            connection,
+            send,
+            recv,
+        })
+    }
+}
+
+/// A bidirectional stream over its own QUIC connection
+pub struct QuicStream {
+    connection: quinn::Connection,
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+}
+
+impl QuicStream {
+    /// Address of the remote peer
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.connection.remote_address()
+    }
+
+    /// Handle to the connection the stream belongs to
+    pub fn connection(&self) -> QuicConnection {
+        QuicConnection(self.connection.clone())
+    }
+}
+
+/// A QUIC connection, for streams beyond the first
+#[derive(Clone)]
+pub struct QuicConnection(quinn::Connection);
+
+impl QuicConnection {
+    /// Open another stream to the peer
+    ///
+    /// The peer only learns of the stream once something is written to it.
+    pub async fn open_stream(&self) -> Result<QuicStream> {
+        let (send, recv) = self.0.open_bi().await.map_err(|e| self.failed(e))?;
+        Ok(QuicStream {
+            connection: self.0.clone(),
+            send,
+            recv,
+        })
+    }
+
+    /// Wait for the peer to open another stream
+    pub async fn accept_stream(&self) -> Result<QuicStream> {
+        let (send, recv) = self.0.accept_bi().await.map_err(|e| self.failed(e))?;
+        Ok(QuicStream {
+            connection: self.0.clone(),
+            send,
+            recv,
+        })
+    }
+
+    fn failed(&self, e: quinn::ConnectionError) -> ParlanceError {
+        ParlanceError::ConnectionUnavailable(format!(
+            "QUIC stream with {} failed: {}",
+            self.0.remote_address(),
+            e
+        ))
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.send)
+            .poll_write(cx, buf)
+            .map_err(io::Error::from)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+/// Transport settings shared by both sides of a connection
+fn transport_config() -> Arc<quinn::TransportConfig> {
+    let mut transport = quinn::TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEPALIVE_INTERVAL));
+    Arc::new(transport)
+}
+
+/// Accept connections with a fresh self-signed certificate
+fn server_config(provider: Arc<CryptoProvider>) -> Result<quinn::ServerConfig> {
+    let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+        .map_err(|e| tls_error(&e))?;
+    let cert = certified.cert.der().clone();
+    let key = PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|e| tls_error(&e))?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key.into())
+        .map_err(|e| tls_error(&e))?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicServerConfig::try_from(crypto).map_err(|e| tls_error(&e))?;
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+/// Connect without checking certificates; Noise authenticates the peer
+fn client_config(provider: Arc<CryptoProvider>) -> Result<quinn::ClientConfig> {
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|e| tls_error(&e))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicClientConfig::try_from(crypto).map_err(|e| tls_error(&e))?;
+    let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+fn tls_error(e: &dyn std::fmt::Display) -> ParlanceError {
+    ParlanceError::ConfigError(format!("QUIC TLS setup failed: {}", e))
+}
+
+/// Accepts any server certificate that signs the handshake correctly
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
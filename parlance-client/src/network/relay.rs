@@ -0,0 +1,108 @@
+//! Byte streams relayed through the bootstrap server.
+//!
+//! When a peer can be reached neither over TCP nor over a punched UDP path,
+//! both sides can still exchange bytes through the bootstrap server's relay.
+//! A [`RelayStream`] cuts written bytes into chunks that the bootstrap
+//! client forwards to the peer, and reassembles the chunks the peer sends
+//! back. The encrypted channel runs on top, so the server only ever sees
+//! ciphertext. The relay is reliable and ordered because the connection to
+//! the server is; an empty chunk marks the end of a direction.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::sync::mpsc;
+
+/// Largest chunk sent in one relay message
+pub const MAX_RELAY_CHUNK: usize = 16 * 1024;
+
+/// Bytes buffered between the stream and its relay task
+const PIPE_CAPACITY: usize = 4 * MAX_RELAY_CHUNK;
+
+/// An ordered byte stream to one peer through the bootstrap server
+pub struct RelayStream {
+    pipe: DuplexStream,
+    relay_addr: SocketAddr,
+}
+
+impl RelayStream {
+    /// Open a stream over a relay circuit
+    ///
+    /// Chunks written to the stream are sent on `outbound`, and chunks
+    /// received on `inbound` are read from it. The stream reaches its end
+    /// when the peer closes the circuit or `inbound` is closed.
+    pub fn open(
+        relay_addr: SocketAddr,
+        outbound: mpsc::UnboundedSender<Vec<u8>>,
+        inbound: mpsc::UnboundedReceiver<Vec<u8>>,
+    ) -> Self {
+        let (pipe, task_end) = tokio::io::duplex(PIPE_CAPACITY);
+        tokio::spawn(drive(task_end, outbound, inbound));
+        Self { pipe, relay_addr }
+    }
+
+    /// Address of the server relaying the stream
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay_addr
+    }
+}
+
+impl AsyncRead for RelayStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for RelayStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.pipe).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_shutdown(cx)
+    }
+}
+
+/// Move bytes between the stream's pipe and the relay channels
+async fn drive(
+    pipe: DuplexStream,
+    outbound: mpsc::UnboundedSender<Vec<u8>>,
+    mut inbound: mpsc::UnboundedReceiver<Vec<u8>>,
+) {
+    let (mut reader, mut writer) = tokio::io::split(pipe);
+
+    let send = async move {
+        let mut buf = vec![0u8; MAX_RELAY_CHUNK];
+        loop {
+            let len = reader.read(&mut buf).await.unwrap_or(0);
+            if outbound.send(buf[..len].to_vec()).is_err() || len == 0 {
+                break;
+            }
+        }
+    };
+
+    let receive = async move {
+        while let Some(chunk) = inbound.recv().await {
+            if chunk.is_empty() || writer.write_all(&chunk).await.is_err() {
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    };
+
+    tokio::join!(send, receive);
+}
@@ -0,0 +1,410 @@
+//! Encrypted, authenticated peer channels.
+//!
+//! Every TCP connection between peers starts with a Noise XX handshake.
+//! Each side generates a fresh X25519 static key for the connection and
+//! proves that it belongs to their long-term Ed25519 identity by signing it
+//! in the handshake payload. Once the handshake completes, every frame is
+//! encrypted and authenticated with the resulting transport keys.
+//!
+//! The same payload carries each side's [`Hello`], so the handshake also
+//! negotiates the protocol version and features; it fails with
+//! [`ParlanceError::IncompatibleProtocol`] when the versions do not overlap.
+//!
+//! On the wire each Noise message is prefixed with its length as a 2-byte
+//! big-endian integer. Frames are prefixed with their length as a 4-byte
+//! big-endian integer and split across as many Noise messages as needed, so
+//! a frame can be larger than one Noise message but never larger than the
+//! channel's maximum frame size.
+//!
+//! A channel can be split into a [`SecureReader`] and [`SecureWriter`] so
+//! that one task can receive while another sends on the same connection.
+
+use crate::core::error::{ParlanceError, Result};
+use crate::core::identity::{Identity, PublicKey, Signature};
+use crate::network::protocol::{Hello, Negotiated};
+use serde::{Deserialize, Serialize};
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+
+/// Noise protocol used for every peer connection
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Domain separation prefix for signing the Noise static key
+const STATIC_KEY_CONTEXT: &[u8] = b"parlance-noise-static-key:";
+
+/// Maximum size of a single Noise message
+pub const MAX_NOISE_MESSAGE_LEN: usize = 65535;
+
+/// Size of the authentication tag appended to every encrypted frame
+const TAG_LEN: usize = 16;
+
+/// Maximum plaintext that fits into a single encrypted frame
+pub const MAX_PLAINTEXT_LEN: usize = MAX_NOISE_MESSAGE_LEN - TAG_LEN;
+
+/// Length of the prefix announcing a frame's size
+const FRAME_HEADER_LEN: usize = 4;
+
+/// Default limit for the size of a single frame
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// How long a peer gets to complete the handshake
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshake payload binding the Noise static key to an identity
+#[derive(Debug, Serialize, Deserialize)]
+struct IdentityProof {
+    public_key: PublicKey,
+    signature: Signature,
+    #[serde(flatten)]
+    hello: Hello,
+}
+
+/// An established encrypted channel to a peer
+pub struct SecureChannel<S> {
+    reader: SecureReader<ReadHalf<S>>,
+    writer: SecureWriter<WriteHalf<S>>,
+    remote_key: PublicKey,
+    protocol: Negotiated,
+}
+
+/// Receiving half of a [`SecureChannel`]
+pub struct SecureReader<R> {
+    stream: R,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+    buf: Vec<u8>,
+    max_frame_size: usize,
+}
+
+/// Sending half of a [`SecureChannel`]
+pub struct SecureWriter<W> {
+    stream: W,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+    buf: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SecureChannel<S> {
+    /// Perform the handshake as the connecting side
+    pub async fn initiate(stream: S, identity: &Identity) -> Result<Self> {
+        Self::initiate_with(stream, identity, &Hello::current()).await
+    }
+
+    /// Perform the handshake as the accepting side
+    pub async fn accept(stream: S, identity: &Identity) -> Result<Self> {
+        Self::accept_with(stream, identity, &Hello::current()).await
+    }
+
+    /// Perform the handshake as the connecting side, advertising `hello`
+    pub async fn initiate_with(stream: S, identity: &Identity, hello: &Hello) -> Result<Self> {
+        Self::handshake_with_timeout(stream, identity, hello, true).await
+    }
+
+    /// Perform the handshake as the accepting side, advertising `hello`
+    pub async fn accept_with(stream: S, identity: &Identity, hello: &Hello) -> Result<Self> {
+        Self::handshake_with_timeout(stream, identity, hello, false).await
+    }
+
+    async fn handshake_with_timeout(
+        stream: S,
+        identity: &Identity,
+        hello: &Hello,
+        initiator: bool,
+    ) -> Result<Self> {
+        tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            Self::handshake(stream, identity, hello, initiator),
+        )
+        .await
+        .map_err(|_| ParlanceError::Handshake("timed out".to_string()))?
+    }
+
+    async fn handshake(
+        mut stream: S,
+        identity: &Identity,
+        hello: &Hello,
+        initiator: bool,
+    ) -> Result<Self> {
+        let params = NOISE_PARAMS.parse().map_err(noise_error)?;
+        let builder = Builder::new(params);
+        let keypair = builder.generate_keypair().map_err(noise_error)?;
+        let builder = builder.local_private_key(&keypair.private);
+
+        let mut state = if initiator {
+            builder.build_initiator()
+        } else {
+            builder.build_responder()
+        }
+        .map_err(noise_error)?;
+
+        let proof = serde_json::to_vec(&IdentityProof {
+            public_key: identity.public_key(),
+            signature: identity.sign(&static_key_message(&keypair.public)),
+            hello: hello.clone(),
+        })?;
+
+        // XX pattern: -> e, <- e ee s es, -> s se
+        // The initiator checks the responder's proof before revealing its
+        // own. When only the versions do not overlap it still sends its
+        // proof, so the responder fails with the same error instead of
+        // waiting for the handshake to time out.
+        let (remote_key, protocol) = if initiator {
+            write_handshake(&mut stream, &mut state, &[]).await?;
+            let remote_proof = read_handshake(&mut stream, &mut state).await?;
+            let remote = verify_remote(&state, &remote_proof, hello);
+            if matches!(remote, Ok(_) | Err(ParlanceError::IncompatibleProtocol(_))) {
+                write_handshake(&mut stream, &mut state, &proof).await?;
+            }
+            remote?
+        } else {
+            read_handshake(&mut stream, &mut state).await?;
+            write_handshake(&mut stream, &mut state, &proof).await?;
+            let remote_proof = read_handshake(&mut stream, &mut state).await?;
+            verify_remote(&state, &remote_proof, hello)?
+        };
+
+        let transport = Arc::new(state.into_stateless_transport_mode().map_err(noise_error)?);
+        let (read_half, write_half) = tokio::io::split(stream);
+
+        Ok(Self {
+            reader: SecureReader {
+                stream: read_half,
+                transport: transport.clone(),
+                nonce: 0,
+                buf: vec![0u8; MAX_NOISE_MESSAGE_LEN],
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            },
+            writer: SecureWriter {
+                stream: write_half,
+                transport,
+                nonce: 0,
+                buf: vec![0u8; MAX_NOISE_MESSAGE_LEN],
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            },
+            remote_key,
+            protocol,
+        })
+    }
+
+    /// Get the authenticated identity key of the remote peer
+    pub fn remote_public_key(&self) -> PublicKey {
+        self.remote_key
+    }
+
+    /// Get the protocol version and features agreed with the remote peer
+    pub fn protocol(&self) -> &Negotiated {
+        &self.protocol
+    }
+
+    /// Limit the size of frames sent and accepted on this channel
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.reader.max_frame_size = max_frame_size;
+        self.writer.max_frame_size = max_frame_size;
+    }
+
+    /// Encrypt and send a frame
+    pub async fn send(&mut self, frame: &[u8]) -> Result<()> {
+        self.writer.send(frame).await
+    }
+
+    /// Receive and decrypt the next frame
+    ///
+    /// Returns `Ok(None)` once the peer closes the connection.
+    #[allow(dead_code)]
+    pub async fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        self.reader.recv().await
+    }
+
+    /// Split the channel into halves that can be used from separate tasks
+    pub fn into_split(self) -> (SecureReader<ReadHalf<S>>, SecureWriter<WriteHalf<S>>) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<R: AsyncRead + Unpin> SecureReader<R> {
+    /// Receive and decrypt the next frame
+    ///
+    /// Returns `Ok(None)` once the peer closes the connection. A frame
+    /// announced as larger than the maximum frame size is rejected before
+    /// any of it is buffered.
+    pub async fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(first) = self.recv_message().await? else {
+            return Ok(None);
+        };
+
+        let header: [u8; FRAME_HEADER_LEN] = first
+            .get(..FRAME_HEADER_LEN)
+            .and_then(|h| h.try_into().ok())
+            .ok_or_else(|| ParlanceError::InvalidMessage("Truncated frame header".to_string()))?;
+        let len = u32::from_be_bytes(header) as usize;
+        if len > self.max_frame_size {
+            return Err(ParlanceError::FrameTooLarge {
+                size: len,
+                max: self.max_frame_size,
+            });
+        }
+
+        let mut frame = Vec::with_capacity(len.min(MAX_PLAINTEXT_LEN));
+        frame.extend_from_slice(&first[FRAME_HEADER_LEN..]);
+        while frame.len() < len {
+            let part = self.recv_message().await?.ok_or_else(|| {
+                ParlanceError::InvalidMessage("Connection closed inside a frame".to_string())
+            })?;
+            frame.extend_from_slice(&part);
+        }
+
+        if frame.len() != len {
+            return Err(ParlanceError::InvalidMessage(
+                "Frame longer than its length prefix".to_string(),
+            ));
+        }
+        Ok(Some(frame))
+    }
+
+    /// Receive and decrypt the next Noise message
+    async fn recv_message(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(frame) = read_frame(&mut self.stream).await? else {
+            return Ok(None);
+        };
+
+        let len = self
+            .transport
+            .read_message(self.nonce, &frame, &mut self.buf)
+            .map_err(|e| {
+                ParlanceError::InvalidMessage(format!("Failed to decrypt frame: {}", e))
+            })?;
+        self.nonce += 1;
+        Ok(Some(self.buf[..len].to_vec()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> SecureWriter<W> {
+    /// Encrypt and send a frame, split across Noise messages as needed
+    pub async fn send(&mut self, frame: &[u8]) -> Result<()> {
+        if frame.len() > self.max_frame_size {
+            return Err(ParlanceError::FrameTooLarge {
+                size: frame.len(),
+                max: self.max_frame_size,
+            });
+        }
+        let len = u32::try_from(frame.len()).map_err(|_| ParlanceError::FrameTooLarge {
+            size: frame.len(),
+            max: u32::MAX as usize,
+        })?;
+
+        let data = [&len.to_be_bytes()[..], frame].concat();
+        for plaintext in data.chunks(MAX_PLAINTEXT_LEN) {
+            self.send_message(plaintext).await?;
+        }
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Encrypt and send a single Noise message
+    async fn send_message(&mut self, plaintext: &[u8]) -> Result<()> {
+        let len = self
+            .transport
+            .write_message(self.nonce, plaintext, &mut self.buf)
+            .map_err(|e| {
+                ParlanceError::InvalidMessage(format!("Failed to encrypt frame: {}", e))
+            })?;
+        self.nonce += 1;
+        write_frame(&mut self.stream, &self.buf[..len]).await
+    }
+
+    /// Shut down the write side of the connection
+    pub async fn close(&mut self) -> Result<()> {
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Build the message signed to bind a Noise static key to an identity
+fn static_key_message(static_key: &[u8]) -> Vec<u8> {
+    [STATIC_KEY_CONTEXT, static_key].concat()
+}
+
+/// Verify the remote proof and negotiate the protocol with the peer
+fn verify_remote(
+    state: &HandshakeState,
+    payload: &[u8],
+    hello: &Hello,
+) -> Result<(PublicKey, Negotiated)> {
+    let remote_static = state
+        .get_remote_static()
+        .ok_or_else(|| ParlanceError::Handshake("peer sent no static key".to_string()))?;
+    let (remote_key, remote_hello) = verify_proof(payload, remote_static)?;
+    let protocol = hello.negotiate(&remote_hello)?;
+    Ok((remote_key, protocol))
+}
+
+/// Check that the remote proof signs the static key seen in the handshake
+fn verify_proof(payload: &[u8], remote_static: &[u8]) -> Result<(PublicKey, Hello)> {
+    let proof: IdentityProof = serde_json::from_slice(payload)
+        .map_err(|e| ParlanceError::Handshake(format!("invalid identity proof: {}", e)))?;
+
+    proof
+        .public_key
+        .verify(&static_key_message(remote_static), &proof.signature)
+        .map_err(|_| ParlanceError::Handshake("identity proof signature mismatch".to_string()))?;
+
+    Ok((proof.public_key, proof.hello))
+}
+
+async fn write_handshake<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    state: &mut HandshakeState,
+    payload: &[u8],
+) -> Result<()> {
+    let mut buf = vec![0u8; MAX_NOISE_MESSAGE_LEN];
+    let len = state
+        .write_message(payload, &mut buf)
+        .map_err(noise_error)?;
+    write_frame(stream, &buf[..len]).await
+}
+
+async fn read_handshake<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    state: &mut HandshakeState,
+) -> Result<Vec<u8>> {
+    let message = read_frame(stream)
+        .await?
+        .ok_or_else(|| ParlanceError::Handshake("connection closed".to_string()))?;
+
+    let mut payload = vec![0u8; MAX_NOISE_MESSAGE_LEN];
+    let len = state
+        .read_message(&message, &mut payload)
+        .map_err(noise_error)?;
+    payload.truncate(len);
+    Ok(payload)
+}
+
+/// Write a length-prefixed frame
+async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, data: &[u8]) -> Result<()> {
+    let len = u16::try_from(data.len())
+        .map_err(|_| ParlanceError::InvalidMessage("Frame too large".to_string()))?;
+    stream.write_u16(len).await?;
+    stream.write_all(data).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read a length-prefixed frame, returning `None` on a clean end of stream
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<Vec<u8>>> {
+    let len = match stream.read_u16().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut data = vec![0u8; len];
+    stream.read_exact(&mut data).await?;
+    Ok(Some(data))
+}
+
+fn noise_error(e: snow::Error) -> ParlanceError {
+    ParlanceError::Handshake(e.to_string())
+}
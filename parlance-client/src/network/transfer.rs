@@ -0,0 +1,503 @@
+//! Chunked, resumable file transfer between peers.
+//!
+//! A transfer starts with an `offer` frame carrying the file's name, size
+//! and SHA-256. Nothing is sent until the receiver accepts with an `accept`
+//! frame naming the offset to start from; the sender then streams `chunk`
+//! frames over the usual peer connection. The receiver appends chunks to a
+//! `.part` file in its downloads directory and, after the last one, checks
+//! the hash, moves the file into place and answers with a `done` frame.
+//!
+//! If the connection drops, the receiver keeps the partial file and asks
+//! for the rest by sending `accept` again with the number of bytes it
+//! already has. The partial file is named after the sender's key and the
+//! file's hash, not the transfer ID, so after either side restarts a new
+//! offer of the same file resumes from it too.
+
+use crate::core::error::{ParlanceError, Result};
+use crate::core::identity::PublicKey;
+use crate::core::sync::lock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// Size of the file data carried by one chunk frame
+pub const CHUNK_SIZE: usize = 32 * 1024;
+
+/// Progress is reported every time a transfer crosses this many percent
+pub const PROGRESS_STEP: u64 = 10;
+
+/// Extension of partially received files
+const PART_EXTENSION: &str = "part";
+
+/// Unique identifier of a file transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TransferId(Uuid);
+
+impl TransferId {
+    /// Create a new random transfer ID
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+
+    /// Check whether the full ID starts with `prefix`
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        !prefix.is_empty() && self.0.to_string().starts_with(&prefix.to_lowercase())
+    }
+
+    /// The ID as carried in a wire envelope
+    pub fn to_bytes(self) -> [u8; 16] {
+        self.0.into_bytes()
+    }
+
+    /// Read an ID carried in a wire envelope
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(Uuid::from_bytes(bytes))
+    }
+}
+
+impl Default for TransferId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for TransferId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self.0.to_string()[..8])
+    }
+}
+
+/// A file offered to a peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileOffer {
+    /// Transfer ID
+    pub id: TransferId,
+    /// Sender's nickname
+    pub from: String,
+    /// File name, without any directories
+    pub name: String,
+    /// File size in bytes
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the file
+    pub sha256: String,
+}
+
+/// A piece of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChunk {
+    /// Transfer ID
+    pub id: TransferId,
+    /// Position of the data in the file
+    pub offset: u64,
+    /// File data
+    pub data: Vec<u8>,
+}
+
+impl FileChunk {
+    /// Create a chunk holding `bytes` found at `offset`
+    pub fn new(id: TransferId, offset: u64, bytes: &[u8]) -> Self {
+        Self {
+            id,
+            offset,
+            data: bytes.to_vec(),
+        }
+    }
+}
+
+/// Result of storing a received chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkOutcome {
+    /// The chunk was appended; `report` is set when progress should be shown
+    Progress { received: u64, report: bool },
+    /// The last chunk arrived and the file matched its hash
+    Complete { path: PathBuf },
+    /// The chunk does not continue the file; the sender should resume at
+    /// `expected`
+    OutOfOrder { expected: u64 },
+}
+
+/// A file we offered to a peer
+#[derive(Debug, Clone)]
+pub struct Outgoing {
+    /// The offer sent to the peer
+    pub offer: FileOffer,
+    /// Nickname of the recipient
+    pub to: String,
+    /// Identity key of the recipient
+    pub peer_key: PublicKey,
+    /// File being sent
+    pub path: PathBuf,
+    /// Bumped on every accept, so a stream started earlier stops
+    stream: u64,
+}
+
+/// A file offered to us
+#[derive(Debug, Clone)]
+pub struct Incoming {
+    /// The offer received from the peer
+    pub offer: FileOffer,
+    /// Identity key of the sender
+    pub peer_key: PublicKey,
+    /// Whether we accepted the offer
+    pub accepted: bool,
+    /// Bytes received so far
+    pub received: u64,
+}
+
+/// In-progress incoming and outgoing transfers
+#[derive(Clone)]
+pub struct Transfers {
+    download_dir: PathBuf,
+    outgoing: Arc<Mutex<HashMap<TransferId, Outgoing>>>,
+    incoming: Arc<Mutex<HashMap<TransferId, Incoming>>>,
+}
+
+impl Transfers {
+    /// Create an empty transfer list saving received files in `download_dir`
+    pub fn new<P: AsRef<Path>>(download_dir: P) -> Self {
+        Self {
+            download_dir: download_dir.as_ref().to_path_buf(),
+            outgoing: Arc::new(Mutex::new(HashMap::new())),
+            incoming: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Prepare an offer of the file at `path`, hashing its contents
+    pub async fn offer(
+        &self,
+        path: &Path,
+        from: &str,
+        to: &str,
+        peer_key: PublicKey,
+    ) -> Result<FileOffer> {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(sanitize_file_name)
+            .ok_or_else(|| {
+                ParlanceError::Transfer(format!("{} is not a file name", path.display()))
+            })?;
+
+        let (size, sha256) = hash_file(path.to_path_buf()).await?;
+        let offer = FileOffer {
+            id: TransferId::new(),
+            from: from.to_string(),
+            name,
+            size,
+            sha256,
+        };
+
+        lock(&self.outgoing).insert(
+            offer.id,
+            Outgoing {
+                offer: offer.clone(),
+                to: to.to_string(),
+                peer_key,
+                path: path.to_path_buf(),
+                stream: 0,
+            },
+        );
+        Ok(offer)
+    }
+
+    /// Remember an offer received from the peer with key `peer_key`
+    ///
+    /// Offers with a name that is unsafe to save, or a reused ID, are rejected.
+    pub fn receive_offer(&self, mut offer: FileOffer, peer_key: PublicKey) -> Result<FileOffer> {
+        offer.name = sanitize_file_name(&offer.name)
+            .ok_or_else(|| ParlanceError::Transfer(format!("Unsafe file name {:?}", offer.name)))?;
+
+        let mut incoming = lock(&self.incoming);
+        if incoming.contains_key(&offer.id) {
+            return Err(ParlanceError::Transfer(format!(
+                "Transfer {} was already offered",
+                offer.id
+            )));
+        }
+
+        incoming.insert(
+            offer.id,
+            Incoming {
+                offer: offer.clone(),
+                peer_key,
+                accepted: false,
+                received: 0,
+            },
+        );
+        Ok(offer)
+    }
+
+    /// Accept an offer by ID prefix
+    ///
+    /// Accepting again resumes the transfer. Returns the transfer and the
+    /// offset the sender should continue from, which is the size of any
+    /// partial file already received.
+    pub async fn accept(&self, id: &str) -> Result<(Incoming, u64)> {
+        let transfer = {
+            let incoming = lock(&self.incoming);
+            let matches: Vec<&Incoming> = incoming
+                .values()
+                .filter(|t| t.offer.id.starts_with(id))
+                .collect();
+            match matches.as_slice() {
+                [transfer] => (*transfer).clone(),
+                [] => {
+                    return Err(ParlanceError::Transfer(format!(
+                        "No file offer matches {}",
+                        id
+                    )))
+                }
+                _ => {
+                    return Err(ParlanceError::Transfer(format!(
+                        "{} matches several file offers",
+                        id
+                    )))
+                }
+            }
+        };
+
+        let part_path = self.part_path(&transfer.offer, &transfer.peer_key);
+        let offset = match fs::metadata(&part_path).await {
+            Ok(metadata) => metadata.len().min(transfer.offer.size),
+            Err(_) => 0,
+        };
+
+        let mut incoming = lock(&self.incoming);
+
+        // An earlier offer of the same file writes to the same partial file
+        incoming.retain(|id, t| {
+            *id == transfer.offer.id
+                || !(t.accepted
+                    && t.peer_key == transfer.peer_key
+                    && t.offer.sha256 == transfer.offer.sha256)
+        });
+
+        let Some(entry) = incoming.get_mut(&transfer.offer.id) else {
+            return Err(ParlanceError::Transfer(format!(
+                "Transfer {} is no longer pending",
+                transfer.offer.id
+            )));
+        };
+        entry.accepted = true;
+        entry.received = offset;
+        Ok((entry.clone(), offset))
+    }
+
+    /// Get the accepted, unfinished transfers from a peer with their offsets
+    pub fn resumable(&self, peer_key: &PublicKey) -> Vec<(TransferId, u64)> {
+        lock(&self.incoming)
+            .values()
+            .filter(|t| t.accepted && t.peer_key == *peer_key)
+            .map(|t| (t.offer.id, t.received))
+            .collect()
+    }
+
+    /// Start a new stream of an outgoing transfer the peer accepted
+    ///
+    /// Returns the transfer and a stream number that is current until the
+    /// next accept.
+    pub fn start_stream(&self, id: TransferId, peer_key: &PublicKey) -> Option<(Outgoing, u64)> {
+        let mut outgoing = lock(&self.outgoing);
+        let transfer = outgoing.get_mut(&id).filter(|t| t.peer_key == *peer_key)?;
+        transfer.stream += 1;
+        Some((transfer.clone(), transfer.stream))
+    }
+
+    /// Check whether a stream is still the current one of its transfer
+    pub fn is_current_stream(&self, id: TransferId, stream: u64) -> bool {
+        lock(&self.outgoing)
+            .get(&id)
+            .is_some_and(|t| t.stream == stream)
+    }
+
+    /// Forget an outgoing transfer the peer finished
+    pub fn finish_outgoing(&self, id: TransferId, peer_key: &PublicKey) -> Option<Outgoing> {
+        let mut outgoing = lock(&self.outgoing);
+        if outgoing.get(&id)?.peer_key != *peer_key {
+            return None;
+        }
+        outgoing.remove(&id)
+    }
+
+    /// Get an incoming transfer
+    pub fn incoming(&self, id: TransferId) -> Option<Incoming> {
+        lock(&self.incoming).get(&id).cloned()
+    }
+
+    /// Append a chunk sent by the peer with key `peer_key`
+    ///
+    /// Chunks of transfers we did not accept, or from another peer, are
+    /// rejected. After the last chunk the file is checked against its hash;
+    /// on a mismatch the partial file is deleted and an error returned.
+    pub async fn write_chunk(
+        &self,
+        chunk: &FileChunk,
+        peer_key: &PublicKey,
+    ) -> Result<ChunkOutcome> {
+        let transfer = lock(&self.incoming)
+            .get(&chunk.id)
+            .filter(|t| t.accepted && t.peer_key == *peer_key)
+            .cloned()
+            .ok_or_else(|| {
+                ParlanceError::Transfer(format!("Unexpected chunk for transfer {}", chunk.id))
+            })?;
+
+        if chunk.offset != transfer.received {
+            return Ok(ChunkOutcome::OutOfOrder {
+                expected: transfer.received,
+            });
+        }
+
+        let data = &chunk.data;
+        let size = transfer.offer.size;
+        let received = transfer.received + data.len() as u64;
+        if received > size {
+            self.abandon(&transfer).await;
+            return Err(ParlanceError::Transfer(format!(
+                "{} is larger than the offered {} bytes",
+                transfer.offer.name, size
+            )));
+        }
+
+        let part_path = self.part_path(&transfer.offer, &transfer.peer_key);
+        let write_err = |e: std::io::Error| {
+            ParlanceError::Storage(format!("Failed to write {}: {}", part_path.display(), e))
+        };
+        fs::create_dir_all(&self.download_dir)
+            .await
+            .map_err(write_err)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(transfer.received == 0)
+            .append(transfer.received > 0)
+            .open(&part_path)
+            .await
+            .map_err(write_err)?;
+        file.write_all(data).await.map_err(write_err)?;
+        file.flush().await.map_err(write_err)?;
+
+        if let Some(entry) = lock(&self.incoming).get_mut(&chunk.id) {
+            entry.received = received;
+        }
+
+        if received < size {
+            return Ok(ChunkOutcome::Progress {
+                received,
+                report: crosses_step(transfer.received, received, size),
+            });
+        }
+
+        let (_, sha256) = hash_file(part_path.clone()).await?;
+        if sha256 != transfer.offer.sha256 {
+            self.abandon(&transfer).await;
+            return Err(ParlanceError::Transfer(format!(
+                "{} does not match its SHA-256",
+                transfer.offer.name
+            )));
+        }
+
+        let path = unique_path(&self.download_dir, &transfer.offer.name).await;
+        fs::rename(&part_path, &path).await.map_err(write_err)?;
+        lock(&self.incoming).remove(&chunk.id);
+        Ok(ChunkOutcome::Complete { path })
+    }
+
+    /// Forget an incoming transfer and delete its partial file
+    async fn abandon(&self, transfer: &Incoming) {
+        lock(&self.incoming).remove(&transfer.offer.id);
+        let _ = fs::remove_file(self.part_path(&transfer.offer, &transfer.peer_key)).await;
+    }
+
+    /// Partial file an incoming transfer is written to
+    ///
+    /// Keyed by sender and content so that it outlives the transfer ID.
+    fn part_path(&self, offer: &FileOffer, peer_key: &PublicKey) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(peer_key.as_bytes());
+        hasher.update(offer.sha256.as_bytes());
+        let key = hex::encode(&hasher.finalize()[..8]);
+        self.download_dir
+            .join(format!("{}.{}.{}", offer.name, key, PART_EXTENSION))
+    }
+}
+
+/// Check whether progress from `before` to `after` crosses a reporting step
+pub fn crosses_step(before: u64, after: u64, size: u64) -> bool {
+    if size == 0 {
+        return false;
+    }
+    let step = |bytes: u64| bytes.saturating_mul(100) / size / PROGRESS_STEP;
+    step(after) > step(before)
+}
+
+/// Reduce an offered file name to a plain name that is safe to save
+///
+/// Any directories are dropped; names that are empty, hidden or refer to a
+/// directory are rejected.
+pub fn sanitize_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+    if name.is_empty() || name.starts_with('.') || name.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+/// Compute the size and hex-encoded SHA-256 of a file
+async fn hash_file(path: PathBuf) -> Result<(u64, String)> {
+    let read_err = |path: &Path, e: std::io::Error| {
+        ParlanceError::Transfer(format!("Failed to read {}: {}", path.display(), e))
+    };
+
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path).map_err(|e| read_err(&path, e))?;
+        if !file.metadata().map_err(|e| read_err(&path, e))?.is_file() {
+            return Err(ParlanceError::Transfer(format!(
+                "{} is not a regular file",
+                path.display()
+            )));
+        }
+
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        let mut size = 0u64;
+        loop {
+            let n = file.read(&mut buf).map_err(|e| read_err(&path, e))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            size += n as u64;
+        }
+        Ok((size, hex::encode(hasher.finalize())))
+    })
+    .await
+    .map_err(|e| ParlanceError::Transfer(format!("Hashing failed: {}", e)))?
+}
+
+/// Find a path in `dir` for `name` that does not exist yet
+async fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let candidate = dir.join(name);
+    if fs::metadata(&candidate).await.is_err() {
+        return candidate;
+    }
+
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    let mut n = 1;
+    loop {
+        let candidate = dir.join(format!("{} ({}){}", stem, n, extension));
+        if fs::metadata(&candidate).await.is_err() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
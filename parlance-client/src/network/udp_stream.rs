@@ -0,0 +1,380 @@
+//! Reliable byte stream over a punched UDP path.
+//!
+//! The encrypted channel needs an ordered, reliable byte stream like TCP.
+//! [`UdpStream`] provides one over a single UDP path: written bytes are cut
+//! into numbered segments and the receiver acknowledges the next sequence
+//! number it expects, holding segments that arrive early until the gap
+//! before them is filled. Repeated acknowledgements for the same number make
+//! the sender resend the missing segment at once; when an acknowledgement is
+//! overdue it resends everything unacknowledged. Keepalives hold the NAT
+//! mapping open while the connection is idle, and a peer that stays silent
+//! for [`DEAD_TIMEOUT`] is considered gone.
+//!
+//! Each packet starts with a kind byte and a big-endian `u32` sequence
+//! number. A background task owns the socket and exchanges bytes with the
+//! stream through an in-memory pipe.
+
+use super::punch::{
+    punch_packet, recv_from, send_to, Datagram, KIND_PUNCH, KIND_PUNCH_ACK, PUNCH_PACKET_LEN,
+};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+/// Close the stream after hearing nothing from the peer for this long
+pub const DEAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest payload carried in one segment, small enough to avoid IP fragmentation
+const MAX_SEGMENT: usize = 1200;
+
+/// Segments that may be in flight without an acknowledgement
+const WINDOW: usize = 64;
+
+/// Received bytes buffered for a reader that is not keeping up
+const MAX_INBOX: usize = WINDOW * MAX_SEGMENT;
+
+/// First retransmission timeout, doubled while nothing is acknowledged
+const INITIAL_RTO: Duration = Duration::from_millis(200);
+
+/// Upper bound for the retransmission timeout
+const MAX_RTO: Duration = Duration::from_secs(2);
+
+/// Send a keepalive after this long without sending anything
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the timers are checked
+const TICK: Duration = Duration::from_millis(50);
+
+/// Repeated acknowledgements that trigger an immediate retransmission
+const DUPLICATE_ACKS: u32 = 3;
+
+const KIND_DATA: u8 = 3;
+const KIND_ACK: u8 = 4;
+const KIND_FIN: u8 = 5;
+const KIND_KEEPALIVE: u8 = 6;
+
+const HEADER_LEN: usize = 5;
+
+/// An ordered, reliable byte stream to one peer over UDP
+pub struct UdpStream {
+    pipe: DuplexStream,
+    peer_addr: SocketAddr,
+}
+
+impl UdpStream {
+    /// Open a stream to `peer_addr` over a socket punched for `session`
+    ///
+    /// Both peers must open their stream for the same session. Late punch
+    /// packets from the peer are still acknowledged, so a peer that missed
+    /// our acknowledgement during punching finishes too.
+    pub fn open(socket: Arc<dyn Datagram>, peer_addr: SocketAddr, session: Uuid) -> Self {
+        let (pipe, task_end) = tokio::io::duplex(MAX_INBOX);
+        tokio::spawn(drive(socket, peer_addr, session, task_end));
+        Self { pipe, peer_addr }
+    }
+
+    /// Address of the peer
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+}
+
+impl AsyncRead for UdpStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UdpStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.pipe).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_shutdown(cx)
+    }
+}
+
+/// A sent segment waiting for its acknowledgement
+struct Segment {
+    seq: u32,
+    packet: Vec<u8>,
+}
+
+fn packet(kind: u8, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + payload.len());
+    packet.push(kind);
+    packet.extend_from_slice(&seq.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Whether sequence number `a` comes before `b`, allowing for wraparound
+fn seq_before(a: u32, b: u32) -> bool {
+    (b.wrapping_sub(a) as i32) > 0
+}
+
+/// Sender and receiver state of one stream
+struct Driver {
+    socket: Arc<dyn Datagram>,
+    peer: SocketAddr,
+    session: Uuid,
+    /// Sent segments waiting for an acknowledgement, oldest first
+    unacked: VecDeque<Segment>,
+    next_seq: u32,
+    /// Repeated acknowledgements of the oldest unacknowledged segment
+    duplicate_acks: u32,
+    /// Sequence number of the next segment to deliver
+    expected: u32,
+    /// Segments that arrived ahead of `expected`, by sequence number
+    early: HashMap<u32, (u8, Vec<u8>)>,
+    /// Received bytes not yet taken by the reader
+    inbox: VecDeque<Vec<u8>>,
+    inbox_len: usize,
+    /// Our writer shut down, so a FIN is queued
+    sent_fin: bool,
+    received_fin: bool,
+    /// The stream was dropped, so received bytes have nowhere to go
+    reader_gone: bool,
+    rto: Duration,
+    last_progress: Instant,
+    last_heard: Instant,
+    last_sent: Instant,
+}
+
+impl Driver {
+    fn new(socket: Arc<dyn Datagram>, peer: SocketAddr, session: Uuid) -> Self {
+        let now = Instant::now();
+        Self {
+            socket,
+            peer,
+            session,
+            unacked: VecDeque::new(),
+            next_seq: 0,
+            duplicate_acks: 0,
+            expected: 0,
+            early: HashMap::new(),
+            inbox: VecDeque::new(),
+            inbox_len: 0,
+            sent_fin: false,
+            received_fin: false,
+            reader_gone: false,
+            rto: INITIAL_RTO,
+            last_progress: now,
+            last_heard: now,
+            last_sent: now,
+        }
+    }
+
+    /// Both directions are closed and everything was delivered
+    fn is_done(&self) -> bool {
+        let received_all = (self.received_fin && self.inbox.is_empty()) || self.reader_gone;
+        self.sent_fin && self.unacked.is_empty() && received_all
+    }
+
+    async fn send(&mut self, packet: &[u8]) {
+        // Lost datagrams are recovered by retransmission
+        let _ = send_to(&*self.socket, packet, self.peer).await;
+        self.last_sent = Instant::now();
+    }
+
+    /// Send bytes from the writer, or a FIN once it shut down
+    async fn queue(&mut self, data: Option<&[u8]>) {
+        let packet = match data {
+            Some(data) => packet(KIND_DATA, self.next_seq, data),
+            None => {
+                self.sent_fin = true;
+                packet(KIND_FIN, self.next_seq, &[])
+            }
+        };
+        if self.unacked.is_empty() {
+            self.last_progress = Instant::now();
+        }
+        self.send(&packet).await;
+        self.unacked.push_back(Segment {
+            seq: self.next_seq,
+            packet,
+        });
+        self.next_seq = self.next_seq.wrapping_add(1);
+    }
+
+    /// Note that the reader took `n` bytes from the inbox
+    fn delivered(&mut self, n: usize) {
+        self.inbox_len -= n;
+        if let Some(front) = self.inbox.front_mut() {
+            front.drain(..n);
+            if front.is_empty() {
+                self.inbox.pop_front();
+            }
+        }
+    }
+
+    fn reader_gone(&mut self) {
+        self.reader_gone = true;
+        self.inbox.clear();
+        self.inbox_len = 0;
+    }
+
+    /// Handle a datagram from the peer
+    async fn receive(&mut self, datagram: &[u8]) {
+        self.last_heard = Instant::now();
+        let kind = datagram[0];
+
+        if kind == KIND_PUNCH {
+            if datagram.len() == PUNCH_PACKET_LEN && datagram[1..] == self.session.as_bytes()[..] {
+                self.send(&punch_packet(KIND_PUNCH_ACK, &self.session))
+                    .await;
+            }
+            return;
+        }
+        if datagram.len() < HEADER_LEN {
+            return;
+        }
+        let seq = u32::from_be_bytes([datagram[1], datagram[2], datagram[3], datagram[4]]);
+        let payload = &datagram[HEADER_LEN..];
+
+        match kind {
+            KIND_DATA | KIND_FIN => {
+                let offset = seq.wrapping_sub(self.expected);
+                if offset == 0 {
+                    self.accept(kind, payload.to_vec());
+                } else if (offset as usize) < WINDOW {
+                    self.early.insert(seq, (kind, payload.to_vec()));
+                }
+                self.send(&packet(KIND_ACK, self.expected, &[])).await;
+            }
+            KIND_ACK => {
+                let before = self.unacked.len();
+                while self
+                    .unacked
+                    .front()
+                    .is_some_and(|segment| seq_before(segment.seq, seq))
+                {
+                    self.unacked.pop_front();
+                }
+                if self.unacked.len() < before {
+                    self.last_progress = Instant::now();
+                    self.rto = INITIAL_RTO;
+                    self.duplicate_acks = 0;
+                } else if let Some(front) = self.unacked.front() {
+                    self.duplicate_acks += 1;
+                    if self.duplicate_acks == DUPLICATE_ACKS {
+                        let packet = front.packet.clone();
+                        self.send(&packet).await;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Take the segment at `expected` and any held segments it makes contiguous
+    ///
+    /// Without room in the inbox the segment is dropped and arrives again
+    /// with a later retransmission.
+    fn accept(&mut self, kind: u8, payload: Vec<u8>) {
+        let mut next = Some((kind, payload));
+        while let Some((kind, payload)) = next {
+            if self.received_fin || self.inbox_len + payload.len() > MAX_INBOX {
+                return;
+            }
+            self.expected = self.expected.wrapping_add(1);
+            if kind == KIND_FIN {
+                self.received_fin = true;
+            } else if !self.reader_gone && !payload.is_empty() {
+                self.inbox_len += payload.len();
+                self.inbox.push_back(payload);
+            }
+            next = self.early.remove(&self.expected);
+        }
+    }
+
+    /// Retransmit overdue segments and keep the path alive
+    ///
+    /// Returns false once the peer has been silent too long.
+    async fn tick(&mut self) -> bool {
+        if self.last_heard.elapsed() > DEAD_TIMEOUT {
+            return false;
+        }
+        if !self.unacked.is_empty() && self.last_progress.elapsed() >= self.rto {
+            let packets: Vec<Vec<u8>> = self.unacked.iter().map(|s| s.packet.clone()).collect();
+            for packet in packets {
+                self.send(&packet).await;
+            }
+            self.last_progress = Instant::now();
+            self.rto = (self.rto * 2).min(MAX_RTO);
+        } else if self.last_sent.elapsed() >= KEEPALIVE_INTERVAL {
+            self.send(&packet(KIND_KEEPALIVE, 0, &[])).await;
+        }
+        true
+    }
+}
+
+/// Move bytes between the pipe and the socket until both sides are done
+async fn drive(socket: Arc<dyn Datagram>, peer: SocketAddr, session: Uuid, pipe: DuplexStream) {
+    let (mut pipe_reader, mut pipe_writer) = tokio::io::split(pipe);
+    let mut read_buf = vec![0u8; MAX_SEGMENT];
+    let mut recv_buf = vec![0u8; HEADER_LEN + MAX_SEGMENT + 1];
+    let mut tick = tokio::time::interval(TICK);
+    let mut pipe_shut = false;
+    let mut driver = Driver::new(socket.clone(), peer, session);
+
+    while !driver.is_done() {
+        if driver.received_fin && driver.inbox.is_empty() && !pipe_shut {
+            let _ = pipe_writer.shutdown().await;
+            pipe_shut = true;
+        }
+
+        let can_send = !driver.sent_fin && driver.unacked.len() < WINDOW;
+        let pending = driver.inbox.front().map_or(&[][..], Vec::as_slice);
+
+        tokio::select! {
+            result = pipe_reader.read(&mut read_buf), if can_send => match result {
+                Ok(n) if n > 0 => driver.queue(Some(&read_buf[..n])).await,
+                _ => driver.queue(None).await,
+            },
+
+            result = pipe_writer.write(pending), if !pending.is_empty() => match result {
+                Ok(n) => driver.delivered(n),
+                Err(_) => driver.reader_gone(),
+            },
+
+            result = recv_from(&*socket, &mut recv_buf) => {
+                if let Ok((len, from)) = result {
+                    if from == peer && len > 0 {
+                        driver.receive(&recv_buf[..len]).await;
+                    }
+                }
+            }
+
+            _ = tick.tick() => {
+                if !driver.tick().await {
+                    tracing::debug!(peer = %peer, "UDP stream timed out");
+                    break;
+                }
+            }
+        }
+    }
+
+    // Let a reader waiting on the stream see the end
+    let _ = pipe_writer.shutdown().await;
+}
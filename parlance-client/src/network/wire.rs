@@ -0,0 +1,138 @@
+//! Typed envelope for frames exchanged over peer connections.
+//!
+//! Every frame sent over a [`SecureChannel`](crate::network::secure::SecureChannel)
+//! is an envelope laid out as:
+//!
+//! ```text
+//! +---------+------+----------+---------+
+//! | version | type |    id    | payload |
+//! |  1 byte | 1 b  | 16 bytes |  rest   |
+//! +---------+------+----------+---------+
+//! ```
+//!
+//! The version lets the layout change later, the type says how to read the
+//! payload and the ID names the message or transfer the frame belongs to,
+//! so acks and file chunks can be routed without decoding their payload.
+//! Receivers skip frame types they do not know, which lets newer peers add
+//! frames without breaking older ones.
+
+use crate::core::error::{ParlanceError, Result};
+
+/// Version of the envelope layout written by this build
+pub const WIRE_VERSION: u8 = 1;
+
+/// Length of the envelope header
+pub const HEADER_LEN: usize = 2 + ID_LEN;
+
+/// Length of the ID carried by every envelope
+const ID_LEN: usize = 16;
+
+/// Kind of frame carried by an envelope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameType {
+    /// A text message
+    Message,
+    /// Acknowledges a message or group update
+    Ack,
+    /// A group's current membership
+    Group,
+    /// Offers a file
+    Offer,
+    /// Accepts a file offer
+    Accept,
+    /// A piece of a file
+    Chunk,
+    /// Reports a finished file transfer
+    Done,
+}
+
+impl FrameType {
+    /// Code identifying this frame type on the wire
+    pub fn code(self) -> u8 {
+        match self {
+            FrameType::Message => 1,
+            FrameType::Ack => 2,
+            FrameType::Group => 3,
+            FrameType::Offer => 4,
+            FrameType::Accept => 5,
+            FrameType::Chunk => 6,
+            FrameType::Done => 7,
+        }
+    }
+
+    /// Look up a frame type by its wire code
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(FrameType::Message),
+            2 => Some(FrameType::Ack),
+            3 => Some(FrameType::Group),
+            4 => Some(FrameType::Offer),
+            5 => Some(FrameType::Accept),
+            6 => Some(FrameType::Chunk),
+            7 => Some(FrameType::Done),
+            _ => None,
+        }
+    }
+}
+
+/// A frame with its type, ID and still encoded payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    /// How to read the payload
+    pub frame_type: FrameType,
+    /// ID of the message or transfer the frame belongs to
+    pub id: [u8; ID_LEN],
+    /// Type specific payload
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// Create an envelope
+    pub fn new(frame_type: FrameType, id: [u8; ID_LEN], payload: Vec<u8>) -> Self {
+        Self {
+            frame_type,
+            id,
+            payload,
+        }
+    }
+
+    /// Encode the envelope for sending
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        data.push(WIRE_VERSION);
+        data.push(self.frame_type.code());
+        data.extend_from_slice(&self.id);
+        data.extend_from_slice(&self.payload);
+        data
+    }
+
+    /// Decode a received envelope
+    ///
+    /// Fails with [`ParlanceError::UnknownFrameType`] for frame types this
+    /// build does not know, so callers can skip them.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_LEN {
+            return Err(ParlanceError::InvalidMessage(format!(
+                "Truncated frame: {} bytes",
+                data.len()
+            )));
+        }
+        if data[0] != WIRE_VERSION {
+            return Err(ParlanceError::InvalidMessage(format!(
+                "Unsupported wire version {}",
+                data[0]
+            )));
+        }
+        let frame_type =
+            FrameType::from_code(data[1]).ok_or(ParlanceError::UnknownFrameType(data[1]))?;
+
+        let mut id = [0u8; ID_LEN];
+        id.copy_from_slice(&data[2..HEADER_LEN]);
+
+        Ok(Self {
+            frame_type,
+            id,
+            payload: data[HEADER_LEN..].to_vec(),
+        })
+    }
+}
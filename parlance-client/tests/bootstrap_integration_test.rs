@@ -11,6 +11,9 @@ use tokio::time::sleep;
 // Import from bootstrap-server (we'd need to expose these as a lib)
 // For now, we'll just test the client side with a mock/real server
 
+mod common;
+
+use common::test_public_key;
 use parlance::core::peer::{Peer, PeerRegistry};
 use parlance::network::bootstrap::BootstrapClient;
 
@@ -23,10 +26,10 @@ async fn test_bootstrap_client_creation() {
     let _client = BootstrapClient::new(
         "ws://localhost:8080".to_string(),
         "test_peer".to_string(),
+        test_public_key(),
         local_addr,
         registry,
     );
-
 }
 
 /// Test that peer registry updates work correctly.
@@ -34,8 +37,16 @@ async fn test_bootstrap_client_creation() {
 async fn test_peer_registry_integration() {
     let registry = Arc::new(PeerRegistry::new());
 
-    let peer1 = Peer::new("alice".to_string(), "192.168.1.100:5000".parse().unwrap());
-    let peer2 = Peer::new("bob".to_string(), "192.168.1.101:5000".parse().unwrap());
+    let peer1 = Peer::new(
+        "alice".to_string(),
+        "192.168.1.100:5000".parse().unwrap(),
+        test_public_key(),
+    );
+    let peer2 = Peer::new(
+        "bob".to_string(),
+        "192.168.1.101:5000".parse().unwrap(),
+        test_public_key(),
+    );
 
     registry.upsert(peer1.clone()).await;
     registry.upsert(peer2.clone()).await;
@@ -53,7 +64,11 @@ async fn test_peer_registry_integration() {
 async fn test_peer_timeout() {
     let registry = Arc::new(PeerRegistry::new());
 
-    let peer = Peer::new("charlie".to_string(), "192.168.1.102:5000".parse().unwrap());
+    let peer = Peer::new(
+        "charlie".to_string(),
+        "192.168.1.102:5000".parse().unwrap(),
+        test_public_key(),
+    );
     registry.upsert(peer).await;
 
     assert_eq!(registry.count().await, 1);
@@ -70,12 +85,17 @@ async fn test_peer_timeout() {
 async fn test_dual_mode_peer_registry() {
     let registry = Arc::new(PeerRegistry::new());
 
-    let local_peer = Peer::new("local1".to_string(), "192.168.1.10:5000".parse().unwrap());
+    let local_peer = Peer::new(
+        "local1".to_string(),
+        "192.168.1.10:5000".parse().unwrap(),
+        test_public_key(),
+    );
     registry.upsert(local_peer).await;
 
     let internet_peer = Peer::new(
         "internet1".to_string(),
         "203.0.113.10:5000".parse().unwrap(),
+        test_public_key(),
     );
     registry.upsert(internet_peer).await;
 
@@ -0,0 +1,221 @@
+//! Integration tests for LAN channels.
+
+use parlance::core::identity::Identity;
+use parlance::network::channels::{
+    ChannelPacket, ChannelRouter, MAX_CHANNEL_MESSAGE_LEN, MAX_CHANNEL_PACKET_SIZE,
+};
+use parlance::network::discovery::Rejection;
+use parlance::network::signed::SignedPayload;
+use std::time::Duration;
+
+const MEMBER_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn router(nickname: &str) -> ChannelRouter {
+    ChannelRouter::new(nickname.to_string(), Identity::generate())
+}
+
+/// Deliver a packet to `to`, applying presence like the service does
+fn deliver(to: &ChannelRouter, data: &[u8]) -> Option<ChannelPacket> {
+    let packet = to.receive(data).unwrap()?;
+    if let ChannelPacket::Presence {
+        from,
+        public_key,
+        channels,
+        ..
+    } = &packet
+    {
+        to.update_membership(from, public_key, channels);
+    }
+    Some(packet)
+}
+
+#[test]
+fn test_post_reaches_other_router() {
+    let alice = router("alice");
+    let bob = router("bob");
+    alice.join("#general").unwrap();
+    bob.join("#general").unwrap();
+
+    let (sent, data) = alice.post("#general", "hello lan".to_string()).unwrap();
+
+    match deliver(&bob, &data) {
+        Some(ChannelPacket::Post(msg)) => {
+            assert_eq!(msg, sent);
+            assert_eq!(msg.channel, "#general");
+            assert_eq!(msg.from, "alice");
+        }
+        other => panic!("Expected a post, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_channel_names_are_case_insensitive() {
+    let alice = router("alice");
+    alice.join("#General").unwrap();
+
+    assert!(alice.is_joined("#general"));
+    assert!(!alice.join("#GENERAL").unwrap());
+    assert_eq!(alice.joined(), vec!["#general".to_string()]);
+}
+
+#[test]
+fn test_post_requires_join() {
+    let alice = router("alice");
+    assert!(alice.post("#general", "hello".to_string()).is_err());
+
+    alice.join("#general").unwrap();
+    alice.leave("#general").unwrap();
+    assert!(alice.post("#general", "hello".to_string()).is_err());
+}
+
+#[test]
+fn test_invalid_channel_names_are_rejected() {
+    let alice = router("alice");
+    assert!(alice.join("general").is_err());
+    assert!(alice.join("#no spaces").is_err());
+}
+
+#[test]
+fn test_duplicate_packets_are_dropped() {
+    let alice = router("alice");
+    let bob = router("bob");
+    alice.join("#general").unwrap();
+
+    let (_, data) = alice.post("#general", "once".to_string()).unwrap();
+
+    assert!(bob.receive(&data).unwrap().is_some());
+    assert_eq!(bob.receive(&data), Err(Rejection::Replayed));
+}
+
+#[test]
+fn test_own_packets_are_ignored() {
+    let alice = router("alice");
+    alice.join("#general").unwrap();
+
+    let (_, data) = alice.post("#general", "echo".to_string()).unwrap();
+    assert_eq!(alice.receive(&data), Ok(None));
+}
+
+#[test]
+fn test_message_size_limit() {
+    let alice = router("alice");
+    alice.join("#general").unwrap();
+
+    let max = "a".repeat(MAX_CHANNEL_MESSAGE_LEN);
+    let (_, data) = alice.post("#general", max).unwrap();
+    assert!(data.len() <= MAX_CHANNEL_PACKET_SIZE);
+
+    let too_long = "a".repeat(MAX_CHANNEL_MESSAGE_LEN + 1);
+    assert!(alice.post("#general", too_long).is_err());
+}
+
+#[test]
+fn test_oversized_and_malformed_packets_are_rejected() {
+    let bob = router("bob");
+
+    assert_eq!(
+        bob.receive(&vec![b' '; MAX_CHANNEL_PACKET_SIZE + 1]),
+        Err(Rejection::Malformed)
+    );
+    assert_eq!(bob.receive(b"not json"), Err(Rejection::Malformed));
+}
+
+#[test]
+fn test_tampered_post_is_rejected() {
+    let alice = router("alice");
+    let bob = router("bob");
+    alice.join("#general").unwrap();
+
+    let (_, data) = alice.post("#general", "pay alice".to_string()).unwrap();
+    let tampered = String::from_utf8(data)
+        .unwrap()
+        .replace("pay alice", "pay mallory");
+
+    assert_eq!(
+        bob.receive(tampered.as_bytes()),
+        Err(Rejection::BadSignature)
+    );
+}
+
+#[test]
+fn test_unsigned_post_is_rejected() {
+    let alice = router("alice");
+    let bob = router("bob");
+    alice.join("#general").unwrap();
+
+    let (msg, _) = alice.post("#general", "hello".to_string()).unwrap();
+    let unsigned = serde_json::to_vec(&SignedPayload {
+        payload: serde_json::to_string(&ChannelPacket::Post(msg)).unwrap(),
+        signature: None,
+    })
+    .unwrap();
+
+    assert_eq!(bob.receive(&unsigned), Err(Rejection::Unsigned));
+}
+
+#[test]
+fn test_posts_with_extreme_timestamps_are_stale() {
+    let alice = router("alice");
+    let bob = router("bob");
+    alice.join("#general").unwrap();
+
+    let (_, data) = alice.post("#general", "hello".to_string()).unwrap();
+    let mut signed: SignedPayload = serde_json::from_slice(&data).unwrap();
+    let original = signed.payload.clone();
+    for extreme in [i64::MIN, i64::MAX] {
+        let mut payload: serde_json::Value = serde_json::from_str(&original).unwrap();
+        payload["timestamp"] = extreme.into();
+        signed.payload = payload.to_string();
+
+        let data = serde_json::to_vec(&signed).unwrap();
+        assert_eq!(bob.receive(&data), Err(Rejection::Stale));
+    }
+}
+
+#[test]
+fn test_presence_tracks_members() {
+    let alice = router("alice");
+    let bob = router("bob");
+    let carol = router("carol");
+    alice.join("#general").unwrap();
+    bob.join("#general").unwrap();
+    bob.join("#random").unwrap();
+    carol.join("#random").unwrap();
+
+    deliver(&alice, &bob.presence().unwrap());
+    deliver(&alice, &carol.presence().unwrap());
+
+    assert_eq!(
+        alice.members("#general", MEMBER_TIMEOUT).unwrap(),
+        vec!["alice".to_string(), "bob".to_string()]
+    );
+    assert_eq!(
+        alice.members("#random", MEMBER_TIMEOUT).unwrap(),
+        vec!["bob".to_string(), "carol".to_string()]
+    );
+
+    // Leaving is picked up from the next presence packet
+    bob.leave("#general").unwrap();
+    deliver(&alice, &bob.presence().unwrap());
+    assert_eq!(
+        alice.members("#general", MEMBER_TIMEOUT).unwrap(),
+        vec!["alice".to_string()]
+    );
+}
+
+#[test]
+fn test_silent_members_expire() {
+    let alice = router("alice");
+    let bob = router("bob");
+    bob.join("#general").unwrap();
+
+    deliver(&alice, &bob.presence().unwrap());
+    assert_eq!(
+        alice.members("#general", MEMBER_TIMEOUT).unwrap(),
+        vec!["bob".to_string()]
+    );
+    assert!(alice
+        .members("#general", Duration::ZERO)
+        .unwrap()
+        .is_empty());
+}
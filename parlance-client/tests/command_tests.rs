@@ -1,6 +1,7 @@
 //! Integration tests for command parsing.
 
-use parlance::app::command::{Command, CommandParseError};
+use chrono::NaiveDate;
+use parlance::app::command::{Command, CommandParseError, DEFAULT_HISTORY_LIMIT};
 
 #[test]
 fn test_parse_send() {
@@ -32,6 +33,74 @@ fn test_parse_peers() {
     assert_eq!(cmd, Command::Peers);
 }
 
+#[test]
+fn test_parse_netinfo() {
+    assert_eq!(Command::parse("/netinfo").unwrap(), Command::NetInfo);
+}
+
+#[test]
+fn test_parse_keys() {
+    assert_eq!(Command::parse("/keys").unwrap(), Command::Keys);
+}
+
+#[test]
+fn test_parse_trust_and_untrust() {
+    assert_eq!(
+        Command::parse("/trust alice").unwrap(),
+        Command::Trust {
+            nickname: "alice".to_string()
+        }
+    );
+    assert_eq!(
+        Command::parse("/untrust bob").unwrap(),
+        Command::Untrust {
+            nickname: "bob".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_parse_trust_missing_nickname() {
+    assert!(matches!(
+        Command::parse("/trust"),
+        Err(CommandParseError::MissingArguments { .. })
+    ));
+    assert!(matches!(
+        Command::parse("/untrust "),
+        Err(CommandParseError::MissingArguments { .. })
+    ));
+}
+
+#[test]
+fn test_parse_verify() {
+    assert_eq!(
+        Command::parse("/verify alice").unwrap(),
+        Command::Verify {
+            nickname: "alice".to_string(),
+            confirm: false
+        }
+    );
+    assert_eq!(
+        Command::parse("/verify alice confirm").unwrap(),
+        Command::Verify {
+            nickname: "alice".to_string(),
+            confirm: true
+        }
+    );
+}
+
+#[test]
+fn test_parse_verify_invalid_arguments() {
+    assert!(matches!(
+        Command::parse("/verify"),
+        Err(CommandParseError::MissingArguments { .. })
+    ));
+    assert!(matches!(
+        Command::parse("/verify alice yes"),
+        Err(CommandParseError::MissingArguments { .. })
+    ));
+}
+
 #[test]
 fn test_parse_quit_variants() {
     assert_eq!(Command::parse("/quit").unwrap(), Command::Quit);
@@ -75,6 +144,210 @@ fn test_parse_send_missing_all_args() {
     ));
 }
 
+#[test]
+fn test_parse_history() {
+    assert_eq!(
+        Command::parse("/history bob").unwrap(),
+        Command::History {
+            nickname: "bob".to_string(),
+            limit: DEFAULT_HISTORY_LIMIT
+        }
+    );
+    assert_eq!(
+        Command::parse("/history bob 5").unwrap(),
+        Command::History {
+            nickname: "bob".to_string(),
+            limit: 5
+        }
+    );
+}
+
+#[test]
+fn test_parse_history_invalid_arguments() {
+    for input in ["/history", "/history bob zero", "/history bob 0"] {
+        assert!(matches!(
+            Command::parse(input),
+            Err(CommandParseError::MissingArguments { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_parse_search() {
+    assert_eq!(
+        Command::parse("/search deploy failed").unwrap(),
+        Command::Search {
+            query: "deploy failed".to_string(),
+            from: None,
+            since: None,
+            until: None,
+        }
+    );
+    assert_eq!(
+        Command::parse("/search --from bob deploy --since 2024-01-01 --until 2024-02-01").unwrap(),
+        Command::Search {
+            query: "deploy".to_string(),
+            from: Some("bob".to_string()),
+            since: NaiveDate::from_ymd_opt(2024, 1, 1),
+            until: NaiveDate::from_ymd_opt(2024, 2, 1),
+        }
+    );
+}
+
+#[test]
+fn test_parse_search_invalid_arguments() {
+    for input in [
+        "/search",
+        "/search --from bob",
+        "/search deploy --from",
+        "/search deploy --since yesterday",
+    ] {
+        assert!(matches!(
+            Command::parse(input),
+            Err(CommandParseError::MissingArguments { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_parse_outbox() {
+    assert_eq!(Command::parse("/outbox").unwrap(), Command::Outbox);
+    assert_eq!(
+        Command::parse("/outbox cancel 1a2b3c4d").unwrap(),
+        Command::OutboxCancel {
+            id: "1a2b3c4d".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_parse_outbox_invalid_arguments() {
+    for input in ["/outbox cancel", "/outbox clear", "/outbox cancel a b"] {
+        assert!(matches!(
+            Command::parse(input),
+            Err(CommandParseError::MissingArguments { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_parse_group() {
+    assert_eq!(
+        Command::parse("/group create friends").unwrap(),
+        Command::GroupCreate {
+            name: "friends".to_string()
+        }
+    );
+    assert_eq!(
+        Command::parse("/group invite friends bob").unwrap(),
+        Command::GroupInvite {
+            group: "friends".to_string(),
+            nickname: "bob".to_string()
+        }
+    );
+    assert_eq!(
+        Command::parse("/group leave friends").unwrap(),
+        Command::GroupLeave {
+            group: "friends".to_string()
+        }
+    );
+    assert_eq!(Command::parse("/group list").unwrap(), Command::GroupList);
+    assert_eq!(Command::parse("/group").unwrap(), Command::GroupList);
+}
+
+#[test]
+fn test_parse_group_invalid_arguments() {
+    for input in [
+        "/group create",
+        "/group invite friends",
+        "/group leave",
+        "/group rename friends",
+    ] {
+        assert!(matches!(
+            Command::parse(input),
+            Err(CommandParseError::MissingArguments { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_parse_gsend() {
+    assert_eq!(
+        Command::parse("/gsend friends hello everyone").unwrap(),
+        Command::GroupSend {
+            group: "friends".to_string(),
+            content: "hello everyone".to_string()
+        }
+    );
+
+    for input in ["/gsend", "/gsend friends", "/gsend friends  "] {
+        assert!(matches!(
+            Command::parse(input),
+            Err(CommandParseError::MissingArguments { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_parse_file_commands() {
+    assert_eq!(
+        Command::parse("/sendfile bob ~/My Photos/beach.jpg").unwrap(),
+        Command::SendFile {
+            to: "bob".to_string(),
+            path: "~/My Photos/beach.jpg".to_string()
+        }
+    );
+    assert_eq!(
+        Command::parse("/accept 1a2b3c4d").unwrap(),
+        Command::Accept {
+            id: "1a2b3c4d".to_string()
+        }
+    );
+
+    for input in ["/sendfile", "/sendfile bob", "/sendfile bob  ", "/accept"] {
+        assert!(matches!(
+            Command::parse(input),
+            Err(CommandParseError::MissingArguments { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_parse_channel_commands() {
+    assert_eq!(
+        Command::parse("/join #general").unwrap(),
+        Command::Join {
+            channel: "#general".to_string()
+        }
+    );
+    assert_eq!(
+        Command::parse("/leave #general").unwrap(),
+        Command::Leave {
+            channel: "#general".to_string()
+        }
+    );
+    assert_eq!(
+        Command::parse("/who #general").unwrap(),
+        Command::Who {
+            channel: "#general".to_string()
+        }
+    );
+    assert_eq!(
+        Command::parse("/send #general hi all").unwrap(),
+        Command::ChannelSend {
+            channel: "#general".to_string(),
+            content: "hi all".to_string()
+        }
+    );
+
+    for input in ["/join", "/join general", "/leave", "/who #a #b"] {
+        assert!(matches!(
+            Command::parse(input),
+            Err(CommandParseError::MissingArguments { .. })
+        ));
+    }
+}
+
 #[test]
 fn test_parse_with_extra_whitespace() {
     let cmd = Command::parse("  /peers  ").unwrap();
@@ -87,6 +360,15 @@ fn test_help_text_not_empty() {
     assert!(!help.is_empty());
     assert!(help.contains("/send"));
     assert!(help.contains("/peers"));
+    assert!(help.contains("/keys"));
+    assert!(help.contains("/trust"));
+    assert!(help.contains("/verify"));
+    assert!(help.contains("/outbox"));
+    assert!(help.contains("/history"));
+    assert!(help.contains("/search"));
+    assert!(help.contains("/group"));
+    assert!(help.contains("/gsend"));
+    assert!(help.contains("/netinfo"));
     assert!(help.contains("/quit"));
     assert!(help.contains("/help"));
 }
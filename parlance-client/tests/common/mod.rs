@@ -2,6 +2,7 @@
 
 #![allow(dead_code)]
 
+use parlance::core::identity::{Identity, PublicKey};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 /// Create a test socket address with a given port
@@ -9,6 +10,11 @@ pub fn test_addr(port: u16) -> SocketAddr {
     SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port)
 }
 
+/// Generate a fresh random public key for a test peer
+pub fn test_public_key() -> PublicKey {
+    Identity::generate().public_key()
+}
+
 /// Create a test socket address with a random IP and given port
 pub fn test_addr_with_ip(ip: [u8; 4], port: u16) -> SocketAddr {
     SocketAddr::new(IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])), port)
@@ -0,0 +1,276 @@
+//! Integration tests for pooled peer connections.
+
+mod common;
+
+use common::test_addr;
+use parlance::core::config::Transport;
+use parlance::core::identity::Identity;
+use parlance::core::peer::{Peer, PeerRegistry};
+use parlance::network::connection::{backoff_delay, ConnectionState, PoolConfig};
+use parlance::network::messaging::{
+    DeliveryConfig, MessageEvent, MessagingConfig, MessagingService,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+struct Node {
+    identity: Identity,
+    registry: PeerRegistry,
+    service: Arc<MessagingService>,
+    port: u16,
+    events: mpsc::UnboundedReceiver<MessageEvent>,
+}
+
+async fn start_node(nickname: &str, pool: PoolConfig) -> Node {
+    let identity = Identity::generate();
+    let registry = PeerRegistry::new();
+    let (event_tx, events) = mpsc::unbounded_channel();
+    let config = MessagingConfig {
+        nickname: nickname.to_string(),
+        identity: identity.clone(),
+        tcp_port: 0,
+        registry: registry.clone(),
+        pool,
+        // A single attempt, so dial failures surface right away
+        delivery: DeliveryConfig {
+            ack_timeout: Duration::from_secs(1),
+            max_attempts: 1,
+        },
+        outbox: None,
+        history: None,
+        groups: None,
+        transfers: None,
+        transport: Transport::Tcp,
+    };
+
+    let service = Arc::new(MessagingService::new(config, event_tx).await.unwrap());
+    let port = service.local_addr().unwrap().port();
+
+    let runner = service.clone();
+    tokio::spawn(async move { runner.run().await });
+
+    Node {
+        identity,
+        registry,
+        service,
+        port,
+        events,
+    }
+}
+
+async fn expect_message(events: &mut mpsc::UnboundedReceiver<MessageEvent>) -> String {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("timed out waiting for message")
+            .unwrap();
+        if let MessageEvent::Received { msg, .. } = event {
+            return msg.content;
+        }
+    }
+}
+
+async fn expect_failure(events: &mut mpsc::UnboundedReceiver<MessageEvent>) -> String {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("timed out waiting for failure")
+            .unwrap();
+        if let MessageEvent::Failed { error, .. } = event {
+            return error;
+        }
+    }
+}
+
+/// Get a local port with nothing listening on it
+async fn closed_port() -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+#[tokio::test]
+async fn test_connection_reused_in_both_directions() {
+    let alice = start_node("alice", PoolConfig::default()).await;
+    let mut bob = start_node("bob", PoolConfig::default()).await;
+
+    alice
+        .registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(bob.port),
+            bob.identity.public_key(),
+        ))
+        .await;
+    // Bob only knows an address where alice is not listening, so his reply
+    // can only arrive over the connection alice opened
+    bob.registry
+        .upsert(Peer::new(
+            "alice".to_string(),
+            test_addr(closed_port().await),
+            alice.identity.public_key(),
+        ))
+        .await;
+
+    let mut alice = alice;
+    alice
+        .service
+        .send_message("bob", "one".to_string())
+        .await
+        .unwrap();
+    alice
+        .service
+        .send_message("bob", "two".to_string())
+        .await
+        .unwrap();
+
+    assert_eq!(expect_message(&mut bob.events).await, "one");
+    assert_eq!(expect_message(&mut bob.events).await, "two");
+
+    assert_eq!(
+        alice
+            .service
+            .connections()
+            .state(&bob.identity.public_key()),
+        ConnectionState::Connected
+    );
+    assert_eq!(
+        bob.service
+            .connections()
+            .state(&alice.identity.public_key()),
+        ConnectionState::Connected
+    );
+
+    bob.service
+        .send_message("alice", "reply".to_string())
+        .await
+        .unwrap();
+    assert_eq!(expect_message(&mut alice.events).await, "reply");
+}
+
+#[tokio::test]
+async fn test_failed_dial_backs_off() {
+    let alice = start_node("alice", PoolConfig::default()).await;
+    let bob = Identity::generate();
+
+    alice
+        .registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(closed_port().await),
+            bob.public_key(),
+        ))
+        .await;
+
+    let mut alice = alice;
+    alice
+        .service
+        .send_message("bob", "hi".to_string())
+        .await
+        .unwrap();
+    expect_failure(&mut alice.events).await;
+    assert!(matches!(
+        alice.service.connections().state(&bob.public_key()),
+        ConnectionState::Backoff { failures: 1, .. }
+    ));
+
+    alice
+        .service
+        .send_message("bob", "hi".to_string())
+        .await
+        .unwrap();
+    let error = expect_failure(&mut alice.events).await;
+    assert!(error.starts_with("Connection unavailable"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_reconnects_after_backoff() {
+    let pool = PoolConfig {
+        initial_backoff: Duration::from_millis(50),
+        ..PoolConfig::default()
+    };
+    let mut alice = start_node("alice", pool).await;
+    let mut bob = start_node("bob", PoolConfig::default()).await;
+    let bob_key = bob.identity.public_key();
+
+    alice
+        .registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(closed_port().await),
+            bob_key,
+        ))
+        .await;
+    alice
+        .service
+        .send_message("bob", "lost".to_string())
+        .await
+        .unwrap();
+    expect_failure(&mut alice.events).await;
+
+    // Bob shows up at a reachable address
+    alice
+        .registry
+        .upsert(Peer::new("bob".to_string(), test_addr(bob.port), bob_key))
+        .await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    alice
+        .service
+        .send_message("bob", "hello".to_string())
+        .await
+        .unwrap();
+    assert_eq!(expect_message(&mut bob.events).await, "hello");
+}
+
+#[tokio::test]
+async fn test_idle_connections_are_closed() {
+    let pool = PoolConfig {
+        idle_timeout: Duration::from_millis(100),
+        ..PoolConfig::default()
+    };
+    let alice = start_node("alice", pool).await;
+    let mut bob = start_node("bob", PoolConfig::default()).await;
+    let bob_key = bob.identity.public_key();
+
+    alice
+        .registry
+        .upsert(Peer::new("bob".to_string(), test_addr(bob.port), bob_key))
+        .await;
+
+    alice
+        .service
+        .send_message("bob", "first".to_string())
+        .await
+        .unwrap();
+    assert_eq!(expect_message(&mut bob.events).await, "first");
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(
+        alice.service.connections().state(&bob_key),
+        ConnectionState::Disconnected
+    );
+
+    // The next message opens a fresh connection
+    alice
+        .service
+        .send_message("bob", "second".to_string())
+        .await
+        .unwrap();
+    assert_eq!(expect_message(&mut bob.events).await, "second");
+}
+
+#[test]
+fn test_backoff_delay_doubles_up_to_max() {
+    let config = PoolConfig {
+        initial_backoff: Duration::from_secs(1),
+        max_backoff: Duration::from_secs(10),
+        ..PoolConfig::default()
+    };
+
+    assert_eq!(backoff_delay(&config, 1), Duration::from_secs(1));
+    assert_eq!(backoff_delay(&config, 2), Duration::from_secs(2));
+    assert_eq!(backoff_delay(&config, 4), Duration::from_secs(8));
+    assert_eq!(backoff_delay(&config, 5), Duration::from_secs(10));
+    assert_eq!(backoff_delay(&config, 100), Duration::from_secs(10));
+}
@@ -1,31 +1,63 @@
 //! Integration tests for discovery protocol.
 
-use parlance::network::discovery::DiscoveryMessage;
+mod common;
+
+use common::test_public_key;
+use parlance::core::identity::Identity;
+use parlance::network::discovery::{
+    AnnouncementVerifier, DiscoveryMessage, Rejection, MAX_ANNOUNCEMENT_AGE, SIGNATURE_CONTEXT,
+};
+use parlance::network::protocol::{Hello, PROTOCOL_VERSION};
+use parlance::network::signed::SignedPayload;
+
+fn signed_bytes(msg: DiscoveryMessage, identity: &Identity) -> Vec<u8> {
+    serde_json::to_vec(&msg.sign(identity).unwrap()).unwrap()
+}
 
 #[test]
 fn test_announce_message_serialization() {
-    let msg = DiscoveryMessage::Announce {
-        nickname: "Alice".to_string(),
-        tcp_port: 8080,
-    };
+    let msg = DiscoveryMessage::announce_with(
+        "Alice".to_string(),
+        8080,
+        test_public_key(),
+        Hello::current(),
+    );
 
     let json = serde_json::to_string(&msg).expect("Failed to serialize");
 
     assert!(json.contains("announce"));
     assert!(json.contains("Alice"));
     assert!(json.contains("8080"));
+    assert!(json.contains("timestamp"));
+    assert!(json.contains("nonce"));
 }
 
 #[test]
 fn test_announce_message_deserialization() {
-    let json = r#"{"type":"announce","nickname":"Bob","tcp_port":9090}"#;
+    let json = r#"{"type":"announce","nickname":"Bob","tcp_port":9090,"public_key":"d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a","timestamp":1700000000,"nonce":42,"protocol_version":2,"min_protocol_version":1,"features":["groups"]}"#;
 
     let msg: DiscoveryMessage = serde_json::from_str(json).expect("Failed to deserialize");
 
     match msg {
-        DiscoveryMessage::Announce { nickname, tcp_port } => {
+        DiscoveryMessage::Announce {
+            nickname,
+            tcp_port,
+            public_key,
+            timestamp,
+            nonce,
+            hello,
+        } => {
             assert_eq!(nickname, "Bob");
             assert_eq!(tcp_port, 9090);
+            assert_eq!(
+                public_key.to_hex(),
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+            );
+            assert_eq!(timestamp, 1700000000);
+            assert_eq!(nonce, 42);
+            assert_eq!(hello.protocol_version, 2);
+            assert_eq!(hello.min_protocol_version, 1);
+            assert!(hello.supports("groups"));
         }
         _ => panic!("Wrong message type"),
     }
@@ -33,9 +65,7 @@ fn test_announce_message_deserialization() {
 
 #[test]
 fn test_goodbye_message_serialization() {
-    let msg = DiscoveryMessage::Goodbye {
-        nickname: "Charlie".to_string(),
-    };
+    let msg = DiscoveryMessage::goodbye("Charlie".to_string(), test_public_key());
 
     let json = serde_json::to_string(&msg).expect("Failed to serialize");
 
@@ -45,12 +75,12 @@ fn test_goodbye_message_serialization() {
 
 #[test]
 fn test_goodbye_message_deserialization() {
-    let json = r#"{"type":"goodbye","nickname":"Dave"}"#;
+    let json = r#"{"type":"goodbye","nickname":"Dave","public_key":"d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a","timestamp":1700000000,"nonce":7}"#;
 
     let msg: DiscoveryMessage = serde_json::from_str(json).expect("Failed to deserialize");
 
     match msg {
-        DiscoveryMessage::Goodbye { nickname } => {
+        DiscoveryMessage::Goodbye { nickname, .. } => {
             assert_eq!(nickname, "Dave");
         }
         _ => panic!("Wrong message type"),
@@ -59,30 +89,28 @@ fn test_goodbye_message_deserialization() {
 
 #[test]
 fn test_discovery_message_roundtrip() {
-    let original = DiscoveryMessage::Announce {
-        nickname: "TestUser".to_string(),
-        tcp_port: 12345,
-    };
+    let original = DiscoveryMessage::announce_with(
+        "TestUser".to_string(),
+        12345,
+        test_public_key(),
+        Hello::current(),
+    );
 
     let json = serde_json::to_string(&original).expect("Failed to serialize");
     let deserialized: DiscoveryMessage =
         serde_json::from_str(&json).expect("Failed to deserialize");
 
-    match deserialized {
-        DiscoveryMessage::Announce { nickname, tcp_port } => {
-            assert_eq!(nickname, "TestUser");
-            assert_eq!(tcp_port, 12345);
-        }
-        _ => panic!("Wrong message type after roundtrip"),
-    }
+    assert_eq!(deserialized, original);
 }
 
 #[test]
 fn test_discovery_message_with_special_nickname() {
-    let msg = DiscoveryMessage::Announce {
-        nickname: "User-123_Test".to_string(),
-        tcp_port: 5000,
-    };
+    let msg = DiscoveryMessage::announce_with(
+        "User-123_Test".to_string(),
+        5000,
+        test_public_key(),
+        Hello::current(),
+    );
 
     let json = serde_json::to_string(&msg).expect("Failed to serialize");
     let deserialized: DiscoveryMessage =
@@ -95,3 +123,286 @@ fn test_discovery_message_with_special_nickname() {
         _ => panic!("Wrong message type"),
     }
 }
+
+#[test]
+fn test_announce_rejects_invalid_public_key() {
+    let json = r#"{"type":"announce","nickname":"Eve","tcp_port":9090,"public_key":"not-a-key","timestamp":1700000000,"nonce":1}"#;
+
+    let result = serde_json::from_str::<DiscoveryMessage>(json);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_fresh_nonces() {
+    let key = test_public_key();
+    let a = DiscoveryMessage::announce_with("alice".to_string(), 1, key, Hello::current());
+    let b = DiscoveryMessage::announce_with("alice".to_string(), 1, key, Hello::current());
+
+    assert_ne!(a.nonce(), b.nonce());
+}
+
+#[test]
+fn test_verifier_accepts_signed_messages() {
+    let identity = Identity::generate();
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+
+    let announce = DiscoveryMessage::announce_with(
+        "alice".to_string(),
+        4000,
+        identity.public_key(),
+        Hello::current(),
+    );
+    let goodbye = DiscoveryMessage::goodbye("alice".to_string(), identity.public_key());
+
+    assert_eq!(
+        verifier.verify(&signed_bytes(announce.clone(), &identity)),
+        Ok(announce)
+    );
+    assert_eq!(
+        verifier.verify(&signed_bytes(goodbye.clone(), &identity)),
+        Ok(goodbye)
+    );
+    assert_eq!(verifier.stats().snapshot().total(), 0);
+}
+
+#[test]
+fn test_verifier_rejects_unsigned() {
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+    let msg = DiscoveryMessage::announce_with(
+        "alice".to_string(),
+        4000,
+        test_public_key(),
+        Hello::current(),
+    );
+
+    let unsigned = SignedPayload {
+        payload: serde_json::to_string(&msg).unwrap(),
+        signature: None,
+    };
+    let data = serde_json::to_vec(&unsigned).unwrap();
+    assert_eq!(verifier.verify(&data), Err(Rejection::Unsigned));
+    assert_eq!(verifier.stats().snapshot().unsigned, 1);
+}
+
+#[test]
+fn test_verifier_rejects_malformed() {
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+
+    assert_eq!(verifier.verify(b"not json"), Err(Rejection::Malformed));
+    assert_eq!(verifier.stats().snapshot().malformed, 1);
+}
+
+#[test]
+fn test_verifier_rejects_tampered_message() {
+    let identity = Identity::generate();
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+
+    let mut signed = DiscoveryMessage::announce_with(
+        "alice".to_string(),
+        4000,
+        identity.public_key(),
+        Hello::current(),
+    )
+    .sign(&identity)
+    .unwrap();
+    let mut payload: serde_json::Value = serde_json::from_str(&signed.payload).unwrap();
+    payload["tcp_port"] = 6666.into();
+    signed.payload = payload.to_string();
+
+    let data = serde_json::to_vec(&signed).unwrap();
+    assert_eq!(verifier.verify(&data), Err(Rejection::BadSignature));
+}
+
+#[test]
+fn test_verifier_rejects_key_substitution() {
+    let identity = Identity::generate();
+    let attacker = Identity::generate();
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+
+    // Signed by the attacker but claiming the victim's key
+    let msg = DiscoveryMessage::announce_with(
+        "alice".to_string(),
+        4000,
+        identity.public_key(),
+        Hello::current(),
+    );
+    let data = signed_bytes(msg, &attacker);
+
+    assert_eq!(verifier.verify(&data), Err(Rejection::BadSignature));
+    assert_eq!(verifier.stats().snapshot().bad_signature, 1);
+}
+
+#[test]
+fn test_verifier_rejects_stale_and_future_messages() {
+    let identity = Identity::generate();
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+    let max_age = MAX_ANNOUNCEMENT_AGE.as_secs() as i64;
+
+    for offset in [-(max_age + 5), max_age + 5] {
+        let mut msg = DiscoveryMessage::announce_with(
+            "alice".to_string(),
+            4000,
+            identity.public_key(),
+            Hello::current(),
+        );
+        if let DiscoveryMessage::Announce { timestamp, .. } = &mut msg {
+            *timestamp += offset;
+        }
+
+        assert_eq!(
+            verifier.verify(&signed_bytes(msg, &identity)),
+            Err(Rejection::Stale)
+        );
+    }
+    assert_eq!(verifier.stats().snapshot().stale, 2);
+}
+
+#[test]
+fn test_verifier_rejects_extreme_timestamps() {
+    let identity = Identity::generate();
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+
+    for extreme in [i64::MIN, i64::MAX] {
+        let mut msg = DiscoveryMessage::announce_with(
+            "alice".to_string(),
+            4000,
+            identity.public_key(),
+            Hello::current(),
+        );
+        if let DiscoveryMessage::Announce { timestamp, .. } = &mut msg {
+            *timestamp = extreme;
+        }
+
+        assert_eq!(
+            verifier.verify(&signed_bytes(msg, &identity)),
+            Err(Rejection::Stale)
+        );
+    }
+}
+
+#[test]
+fn test_verifier_rejects_replay() {
+    let identity = Identity::generate();
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+
+    let data = signed_bytes(
+        DiscoveryMessage::announce_with(
+            "alice".to_string(),
+            4000,
+            identity.public_key(),
+            Hello::current(),
+        ),
+        &identity,
+    );
+
+    assert!(verifier.verify(&data).is_ok());
+    assert_eq!(verifier.verify(&data), Err(Rejection::Replayed));
+    assert_eq!(verifier.stats().snapshot().replayed, 1);
+}
+
+#[test]
+fn test_signed_message_wire_format() {
+    let identity = Identity::generate();
+    let signed = DiscoveryMessage::announce_with(
+        "alice".to_string(),
+        4000,
+        identity.public_key(),
+        Hello::current(),
+    )
+    .sign(&identity)
+    .unwrap();
+
+    let value: serde_json::Value = serde_json::to_value(&signed).unwrap();
+    assert!(value["payload"].is_string());
+    assert!(value["signature"].is_string());
+
+    let payload: serde_json::Value =
+        serde_json::from_str(value["payload"].as_str().unwrap()).unwrap();
+    assert_eq!(payload["type"], "announce");
+    assert_eq!(payload["tcp_port"], 4000);
+
+    let parsed: SignedPayload = serde_json::from_value(value).unwrap();
+    assert_eq!(parsed, signed);
+}
+
+#[test]
+fn test_verifier_accepts_unknown_signed_fields() {
+    let identity = Identity::generate();
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+
+    // A newer version adds a field; the signature still covers it
+    let msg = DiscoveryMessage::announce_with(
+        "alice".to_string(),
+        4000,
+        identity.public_key(),
+        Hello::current(),
+    );
+    let mut payload = serde_json::to_value(&msg).unwrap();
+    payload["avatar"] = "🦀".into();
+    let signed = SignedPayload::sign_raw(payload.to_string(), SIGNATURE_CONTEXT, &identity);
+
+    let data = serde_json::to_vec(&signed).unwrap();
+    assert_eq!(verifier.verify(&data), Ok(msg));
+}
+
+#[test]
+fn test_verifier_checks_freshness_before_signature() {
+    let identity = Identity::generate();
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+
+    let mut msg = DiscoveryMessage::announce_with(
+        "alice".to_string(),
+        4000,
+        identity.public_key(),
+        Hello::current(),
+    );
+    if let DiscoveryMessage::Announce { timestamp, .. } = &mut msg {
+        *timestamp -= 3600;
+    }
+    let mut signed = msg.sign(&identity).unwrap();
+    let mut payload: serde_json::Value = serde_json::from_str(&signed.payload).unwrap();
+    payload["tcp_port"] = 6666.into();
+    signed.payload = payload.to_string();
+
+    let data = serde_json::to_vec(&signed).unwrap();
+    assert_eq!(verifier.verify(&data), Err(Rejection::Stale));
+}
+
+#[test]
+fn test_verifier_rejects_incompatible_protocol() {
+    let identity = Identity::generate();
+    let verifier = AnnouncementVerifier::new(MAX_ANNOUNCEMENT_AGE);
+
+    let mut msg = DiscoveryMessage::announce_with(
+        "alice".to_string(),
+        4000,
+        identity.public_key(),
+        Hello::current(),
+    );
+    if let DiscoveryMessage::Announce { hello, .. } = &mut msg {
+        hello.protocol_version = PROTOCOL_VERSION + 1;
+        hello.min_protocol_version = PROTOCOL_VERSION + 1;
+    }
+    assert_eq!(
+        verifier.verify(&signed_bytes(msg, &identity)),
+        Err(Rejection::Incompatible)
+    );
+
+    // Announcements from before versioning carry no hello at all
+    let mut value = serde_json::to_value(DiscoveryMessage::announce_with(
+        "alice".to_string(),
+        4000,
+        identity.public_key(),
+        Hello::current(),
+    ))
+    .unwrap();
+    let fields = value.as_object_mut().unwrap();
+    fields.remove("protocol_version");
+    fields.remove("min_protocol_version");
+    fields.remove("features");
+    let signed = SignedPayload::sign_raw(value.to_string(), SIGNATURE_CONTEXT, &identity);
+    let data = serde_json::to_vec(&signed).unwrap();
+    assert_eq!(verifier.verify(&data), Err(Rejection::Incompatible));
+
+    assert_eq!(verifier.stats().snapshot().incompatible, 2);
+}
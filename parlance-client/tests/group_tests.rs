@@ -0,0 +1,280 @@
+//! Integration tests for the group store.
+
+use parlance::core::group::{
+    Group, GroupId, Groups, Member, SyncOutcome, MAX_GROUP_MEMBERS, MAX_VERSION_STEP,
+};
+use parlance::core::identity::Identity;
+
+fn member(nickname: &str) -> Member {
+    Member {
+        nickname: nickname.to_string(),
+        public_key: Identity::generate().public_key(),
+    }
+}
+
+fn load_groups(dir: &tempfile::TempDir) -> Groups {
+    Groups::load(dir.path().join("groups.json")).unwrap()
+}
+
+#[tokio::test]
+async fn test_groups_survive_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let alice = member("alice");
+
+    let created = {
+        let groups = load_groups(&dir);
+        groups.create("friends", alice.clone()).await.unwrap()
+    };
+
+    let groups = load_groups(&dir);
+    assert_eq!(groups.list().await, vec![created.clone()]);
+    assert_eq!(groups.get(&created.id).await.unwrap().members, vec![alice]);
+}
+
+#[tokio::test]
+async fn test_create_rejects_duplicate_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let groups = load_groups(&dir);
+
+    groups.create("friends", member("alice")).await.unwrap();
+    assert!(groups.create("friends", member("alice")).await.is_err());
+}
+
+#[tokio::test]
+async fn test_find_by_name_or_id_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let groups = load_groups(&dir);
+    let group = groups.create("friends", member("alice")).await.unwrap();
+
+    assert_eq!(groups.find("friends").await.unwrap().id, group.id);
+    assert_eq!(
+        groups.find(&group.id.to_string()).await.unwrap().id,
+        group.id
+    );
+    assert!(groups.find("family").await.is_err());
+}
+
+#[tokio::test]
+async fn test_find_rejects_ambiguous_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let groups = load_groups(&dir);
+    let alice = member("alice");
+    let bob = member("bob");
+
+    // Two different groups that happen to share a name
+    let first = Group::new("friends".to_string(), alice.clone());
+    let mut second = Group::new("friends".to_string(), bob.clone());
+    second.add_member(alice.clone()).unwrap();
+    groups.update(first.clone()).await.unwrap();
+    groups.update(second.clone()).await.unwrap();
+
+    assert!(groups.find("friends").await.is_err());
+    assert_eq!(
+        groups.find(&second.id.to_string()).await.unwrap().id,
+        second.id
+    );
+}
+
+#[test]
+fn test_membership_changes_bump_version() {
+    let alice = member("alice");
+    let bob = member("bob");
+    let mut group = Group::new("friends".to_string(), alice);
+    assert_eq!(group.version, 1);
+
+    group.add_member(bob.clone()).unwrap();
+    assert_eq!(group.version, 2);
+    assert!(group.add_member(bob.clone()).is_err());
+    assert_eq!(group.version, 2);
+
+    assert_eq!(group.remove_member(&bob.public_key).unwrap(), Some(bob));
+    assert_eq!(group.version, 3);
+}
+
+#[test]
+fn test_version_overflow_is_an_error() {
+    let alice = member("alice");
+    let bob = member("bob");
+    let mut group = Group::new("friends".to_string(), alice.clone());
+    group.version = u64::MAX;
+
+    assert!(group.add_member(bob.clone()).is_err());
+    assert!(!group.is_member(&bob.public_key));
+    assert!(group.remove_member(&alice.public_key).is_err());
+    assert!(group.is_member(&alice.public_key));
+    assert_eq!(group.version, u64::MAX);
+}
+
+#[test]
+fn test_member_limit() {
+    let mut group = Group::new("crowd".to_string(), member("alice"));
+    for i in 1..MAX_GROUP_MEMBERS {
+        group.add_member(member(&format!("peer{}", i))).unwrap();
+    }
+
+    assert!(group.add_member(member("one-too-many")).is_err());
+}
+
+#[test]
+fn test_concurrent_changes_converge() {
+    let alice = member("alice");
+    let mut base = Group::new("friends".to_string(), alice);
+    base.add_member(member("bob")).unwrap();
+
+    let mut a = base.clone();
+    a.add_member(member("carol")).unwrap();
+    let mut b = base.clone();
+    b.add_member(member("dave")).unwrap();
+
+    // Same version: exactly one of them wins, whichever way we compare
+    assert_ne!(a.supersedes(&b), b.supersedes(&a));
+    assert!(a.supersedes(&base));
+    assert!(!base.supersedes(&a));
+}
+
+#[tokio::test]
+async fn test_apply_accepts_invitation() {
+    let dir = tempfile::tempdir().unwrap();
+    let groups = load_groups(&dir);
+    let alice = member("alice");
+    let bob = member("bob");
+
+    let mut group = Group::new("friends".to_string(), alice.clone());
+    group.add_member(bob.clone()).unwrap();
+
+    let outcome = groups
+        .apply(group.clone(), &alice.public_key, &bob.public_key, true)
+        .await
+        .unwrap();
+    assert_eq!(outcome, SyncOutcome::Joined);
+    assert_eq!(groups.get(&group.id).await, Some(group));
+}
+
+#[tokio::test]
+async fn test_apply_ignores_unknown_group_without_invitation() {
+    let dir = tempfile::tempdir().unwrap();
+    let groups = load_groups(&dir);
+    let alice = member("alice");
+    let bob = member("bob");
+
+    let mut group = Group::new("friends".to_string(), alice.clone());
+    group.add_member(bob.clone()).unwrap();
+
+    let outcome = groups
+        .apply(group, &alice.public_key, &bob.public_key, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome, SyncOutcome::Ignored);
+    assert!(groups.list().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_apply_updates_from_members_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let groups = load_groups(&dir);
+    let alice = member("alice");
+    let bob = member("bob");
+    let mallory = member("mallory");
+
+    let mut group = Group::new("friends".to_string(), alice.clone());
+    group.add_member(bob.clone()).unwrap();
+    groups.update(group.clone()).await.unwrap();
+
+    let mut newer = group.clone();
+    newer.add_member(mallory.clone()).unwrap();
+
+    let outcome = groups
+        .apply(newer.clone(), &mallory.public_key, &bob.public_key, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome, SyncOutcome::Ignored);
+
+    let outcome = groups
+        .apply(newer.clone(), &alice.public_key, &bob.public_key, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome, SyncOutcome::Updated { previous: group });
+    assert_eq!(groups.get(&newer.id).await, Some(newer));
+}
+
+#[tokio::test]
+async fn test_apply_ignores_stale_update() {
+    let dir = tempfile::tempdir().unwrap();
+    let groups = load_groups(&dir);
+    let alice = member("alice");
+    let bob = member("bob");
+
+    let mut stale = Group::new("friends".to_string(), alice.clone());
+    stale.add_member(bob.clone()).unwrap();
+    let mut current = stale.clone();
+    current.add_member(member("carol")).unwrap();
+    groups.update(current.clone()).await.unwrap();
+
+    let outcome = groups
+        .apply(stale, &alice.public_key, &bob.public_key, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome, SyncOutcome::Ignored);
+    assert_eq!(groups.get(&current.id).await, Some(current));
+}
+
+#[tokio::test]
+async fn test_apply_ignores_version_jump() {
+    let dir = tempfile::tempdir().unwrap();
+    let groups = load_groups(&dir);
+    let alice = member("alice");
+    let bob = member("bob");
+
+    let mut group = Group::new("friends".to_string(), alice.clone());
+    group.add_member(bob.clone()).unwrap();
+    groups.update(group.clone()).await.unwrap();
+
+    let mut inflated = group.clone();
+    inflated.version = u64::MAX;
+    let outcome = groups
+        .apply(inflated, &alice.public_key, &bob.public_key, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome, SyncOutcome::Ignored);
+
+    let mut ahead = group.clone();
+    ahead.version += MAX_VERSION_STEP;
+    let outcome = groups
+        .apply(ahead.clone(), &alice.public_key, &bob.public_key, false)
+        .await
+        .unwrap();
+    assert!(matches!(outcome, SyncOutcome::Updated { .. }));
+    assert_eq!(groups.get(&group.id).await, Some(ahead));
+}
+
+#[tokio::test]
+async fn test_apply_ignores_oversized_member_list() {
+    let dir = tempfile::tempdir().unwrap();
+    let groups = load_groups(&dir);
+    let alice = member("alice");
+    let bob = member("bob");
+
+    let mut group = Group::new("friends".to_string(), alice.clone());
+    group.add_member(bob.clone()).unwrap();
+    groups.update(group.clone()).await.unwrap();
+
+    let mut crowded = group.clone();
+    crowded.version += 1;
+    crowded
+        .members
+        .extend((0..MAX_GROUP_MEMBERS).map(|i| member(&format!("peer{}", i))));
+    let outcome = groups
+        .apply(crowded.clone(), &alice.public_key, &bob.public_key, false)
+        .await
+        .unwrap();
+    assert_eq!(outcome, SyncOutcome::Ignored);
+    assert_eq!(groups.get(&group.id).await, Some(group));
+
+    // Invitations are held to the same limit
+    crowded.id = GroupId::new();
+    let outcome = groups
+        .apply(crowded, &alice.public_key, &bob.public_key, true)
+        .await
+        .unwrap();
+    assert_eq!(outcome, SyncOutcome::Ignored);
+}
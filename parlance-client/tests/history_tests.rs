@@ -0,0 +1,138 @@
+//! Integration tests for the conversation history store.
+
+mod common;
+
+use chrono::Utc;
+use common::test_public_key;
+use parlance::core::history::{DeliveryState, Direction, History, HistoryEntry};
+use parlance::network::messaging::MessageId;
+use std::time::Duration;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn entry(peer: &str, direction: Direction, content: &str, age: Duration) -> HistoryEntry {
+    HistoryEntry {
+        id: MessageId::new(),
+        peer: peer.to_string(),
+        peer_key: test_public_key(),
+        direction,
+        content: content.to_string(),
+        timestamp: Utc::now().timestamp() - age.as_secs() as i64,
+        state: DeliveryState::Pending,
+    }
+}
+
+#[tokio::test]
+async fn test_history_survives_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.jsonl");
+    let sent = entry("bob", Direction::Sent, "hi bob", Duration::ZERO);
+    let received = entry("bob", Direction::Received, "hi alice", Duration::ZERO);
+
+    {
+        let history = History::load(&path, None, None).unwrap();
+        history.record(sent.clone()).await.unwrap();
+        history.record(received.clone()).await.unwrap();
+    }
+
+    let history = History::load(&path, None, None).unwrap();
+    assert_eq!(history.conversation("bob", 10).await, vec![sent, received]);
+}
+
+#[tokio::test]
+async fn test_delivery_state_is_persisted() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.jsonl");
+    let sent = entry("bob", Direction::Sent, "hi", Duration::ZERO);
+
+    {
+        let history = History::load(&path, None, None).unwrap();
+        history.record(sent.clone()).await.unwrap();
+        history
+            .set_state(sent.id, DeliveryState::Sent)
+            .await
+            .unwrap();
+        history
+            .set_state(sent.id, DeliveryState::Delivered)
+            .await
+            .unwrap();
+    }
+
+    let history = History::load(&path, None, None).unwrap();
+    let conversation = history.conversation("bob", 10).await;
+    assert_eq!(conversation[0].state, DeliveryState::Delivered);
+
+    // Loading folded the state records into the message
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(log.lines().count(), 1);
+}
+
+#[tokio::test]
+async fn test_conversation_returns_latest_messages_for_peer() {
+    let dir = tempfile::tempdir().unwrap();
+    let history = History::load(dir.path().join("history.jsonl"), None, None).unwrap();
+
+    for i in 0..5 {
+        history
+            .record(entry(
+                "bob",
+                Direction::Sent,
+                &i.to_string(),
+                Duration::ZERO,
+            ))
+            .await
+            .unwrap();
+        history
+            .record(entry("carol", Direction::Sent, "other", Duration::ZERO))
+            .await
+            .unwrap();
+    }
+
+    let contents: Vec<_> = history
+        .conversation("bob", 3)
+        .await
+        .into_iter()
+        .map(|e| e.content)
+        .collect();
+    assert_eq!(contents, ["2", "3", "4"]);
+    assert!(history.conversation("dave", 3).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_retention_drops_old_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.jsonl");
+    let old = entry("bob", Direction::Received, "old", DAY * 10);
+    let recent = entry("bob", Direction::Received, "recent", DAY);
+
+    {
+        let history = History::load(&path, None, None).unwrap();
+        history.record(old.clone()).await.unwrap();
+        history.record(recent.clone()).await.unwrap();
+    }
+
+    let history = History::load(&path, Some(DAY * 7), None).unwrap();
+    assert_eq!(history.conversation("bob", 10).await, vec![recent.clone()]);
+
+    // The old message is gone from disk as well
+    let history = History::load(&path, None, None).unwrap();
+    assert_eq!(history.conversation("bob", 10).await, vec![recent]);
+}
+
+#[tokio::test]
+async fn test_corrupt_records_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.jsonl");
+    let kept = entry("bob", Direction::Sent, "kept", Duration::ZERO);
+
+    {
+        let history = History::load(&path, None, None).unwrap();
+        history.record(kept.clone()).await.unwrap();
+    }
+    let mut log = std::fs::read_to_string(&path).unwrap();
+    log.push_str("{\"record\":\"message\",\"id\":");
+    std::fs::write(&path, log).unwrap();
+
+    let history = History::load(&path, None, None).unwrap();
+    assert_eq!(history.conversation("bob", 10).await, vec![kept]);
+}
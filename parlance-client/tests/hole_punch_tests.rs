@@ -0,0 +1,224 @@
+//! Tests for UDP hole punching and the streams run over punched paths.
+//!
+//! Each peer sits behind a userspace stand-in for a port-restricted cone
+//! NAT: its socket only delivers datagrams from addresses it has already
+//! sent to, and its private candidate is an address the other peer cannot
+//! reach.
+
+use parlance::core::error::ParlanceError;
+use parlance::core::identity::Identity;
+use parlance::network::connection::{ConnectionManager, PoolConfig};
+use parlance::network::punch::{punch, recv_from, send_to, Datagram};
+use parlance::network::secure::SecureChannel;
+use parlance::network::udp_stream::UdpStream;
+use std::collections::HashSet;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadBuf};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// A socket behind a simulated port-restricted cone NAT
+struct NatSocket {
+    socket: UdpSocket,
+    /// Addresses the inside host has sent to
+    contacted: Mutex<HashSet<SocketAddr>>,
+    /// Drop every nth datagram that passes the filter, 0 to drop none
+    lose_every: usize,
+    passed: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl NatSocket {
+    async fn bind(lose_every: usize) -> Self {
+        Self {
+            socket: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            contacted: Mutex::new(HashSet::new()),
+            lose_every,
+            passed: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// The address the NAT maps the socket to
+    fn public_addr(&self) -> SocketAddr {
+        self.socket.local_addr().unwrap()
+    }
+
+    /// An address on the private network behind the NAT
+    fn private_addr(&self) -> SocketAddr {
+        SocketAddr::new([192, 0, 2, 1].into(), self.public_addr().port())
+    }
+}
+
+impl Datagram for NatSocket {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        self.contacted.lock().unwrap().insert(target);
+        self.socket.poll_send_to(cx, buf, target)
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>> {
+        loop {
+            let from = ready!(self.socket.poll_recv_from(cx, buf))?;
+            if self.contacted.lock().unwrap().contains(&from) {
+                let passed = self.passed.fetch_add(1, Ordering::Relaxed) + 1;
+                if self.lose_every == 0 || !passed.is_multiple_of(self.lose_every) {
+                    return Poll::Ready(Ok(from));
+                }
+            }
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            buf.clear();
+        }
+    }
+}
+
+/// Punch between two NATed sockets as the bootstrap server would arrange it
+async fn punch_pair(lose_every: usize) -> (UdpStream, UdpStream) {
+    let alice = Arc::new(NatSocket::bind(lose_every).await);
+    let bob = Arc::new(NatSocket::bind(lose_every).await);
+    let session = Uuid::new_v4();
+    let start_at = SystemTime::now() + Duration::from_millis(100);
+
+    let alice_candidates = [bob.public_addr(), bob.private_addr()];
+    let bob_candidates = [alice.public_addr(), alice.private_addr()];
+    let timeout = Duration::from_secs(5);
+    let (alice_remote, bob_remote) = tokio::join!(
+        punch(&*alice, session, &alice_candidates, start_at, timeout),
+        punch(&*bob, session, &bob_candidates, start_at, timeout),
+    );
+
+    let (alice_remote, bob_remote) = (alice_remote.unwrap(), bob_remote.unwrap());
+    assert_eq!(alice_remote, bob.public_addr());
+    assert_eq!(bob_remote, alice.public_addr());
+
+    (
+        UdpStream::open(alice, alice_remote, session),
+        UdpStream::open(bob, bob_remote, session),
+    )
+}
+
+#[tokio::test]
+async fn test_nat_drops_unsolicited_datagrams() {
+    let inside = NatSocket::bind(0).await;
+    let outside = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let outside_addr = outside.local_addr().unwrap();
+    let mut buf = [0u8; 16];
+
+    outside
+        .send_to(b"early", inside.public_addr())
+        .await
+        .unwrap();
+    let early =
+        tokio::time::timeout(Duration::from_millis(200), recv_from(&inside, &mut buf)).await;
+    assert!(early.is_err(), "unsolicited datagram was delivered");
+    assert_eq!(inside.dropped.load(Ordering::Relaxed), 1);
+
+    send_to(&inside, b"hello", outside_addr).await.unwrap();
+    outside
+        .send_to(b"reply", inside.public_addr())
+        .await
+        .unwrap();
+    let (len, from) = recv_from(&inside, &mut buf).await.unwrap();
+    assert_eq!(&buf[..len], b"reply");
+    assert_eq!(from, outside_addr);
+}
+
+#[tokio::test]
+async fn test_punch_through_two_nats() {
+    let (mut alice, mut bob) = punch_pair(0).await;
+
+    alice.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    bob.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    bob.write_all(b"pong").await.unwrap();
+    alice.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+}
+
+#[tokio::test]
+async fn test_punch_times_out_without_peer() {
+    let alice = NatSocket::bind(0).await;
+    let silent = NatSocket::bind(0).await;
+
+    let result = punch(
+        &alice,
+        Uuid::new_v4(),
+        &[silent.public_addr()],
+        SystemTime::now(),
+        Duration::from_millis(300),
+    )
+    .await;
+
+    assert!(matches!(result, Err(ParlanceError::Traversal(_))));
+}
+
+#[tokio::test]
+async fn test_udp_stream_recovers_lost_datagrams() {
+    let (mut alice, mut bob) = punch_pair(7).await;
+
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let expected = data.clone();
+    let writer = tokio::spawn(async move {
+        alice.write_all(&data).await.unwrap();
+        alice.shutdown().await.unwrap();
+        alice
+    });
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(30), bob.read_to_end(&mut received))
+        .await
+        .expect("transfer stalled")
+        .unwrap();
+    assert_eq!(received, expected);
+    writer.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_messaging_over_punched_path() {
+    let (alice_stream, bob_stream) = punch_pair(0).await;
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+
+    // Bob accepts the punched path into his connection pool
+    let (incoming_tx, mut incoming) = mpsc::unbounded_channel();
+    let pool = ConnectionManager::new(bob.clone(), PoolConfig::default(), incoming_tx);
+    let bob_addr = bob_stream.peer_addr();
+    let accept = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.accept_stream(bob_stream, bob_addr).await }
+    });
+
+    let mut channel = SecureChannel::initiate(alice_stream, &alice).await.unwrap();
+    assert_eq!(channel.remote_public_key(), bob.public_key());
+    assert_eq!(accept.await.unwrap().unwrap(), alice.public_key());
+
+    let large = vec![7u8; 100_000];
+    channel.send(b"hello over udp").await.unwrap();
+    channel.send(&large).await.unwrap();
+
+    let frame = incoming.recv().await.unwrap();
+    assert_eq!(frame.from, alice.public_key());
+    assert_eq!(frame.data, b"hello over udp");
+    assert_eq!(incoming.recv().await.unwrap().data, large);
+
+    pool.send_existing(&alice.public_key(), b"reply")
+        .await
+        .unwrap();
+    assert_eq!(channel.recv().await.unwrap().unwrap(), b"reply");
+}
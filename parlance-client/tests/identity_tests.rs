@@ -0,0 +1,154 @@
+//! Integration tests for peer identity.
+
+use parlance::core::identity::{safety_number, Identity, PublicKey};
+use parlance::core::peer::PeerId;
+
+#[test]
+fn test_generated_identities_differ() {
+    let a = Identity::generate();
+    let b = Identity::generate();
+
+    assert_ne!(a.public_key(), b.public_key());
+    assert_ne!(a.peer_id(), b.peer_id());
+}
+
+#[test]
+fn test_peer_id_is_stable_for_key() {
+    let identity = Identity::generate();
+    let key = identity.public_key();
+
+    assert_eq!(identity.peer_id(), PeerId::from_public_key(&key));
+    assert_eq!(key.peer_id(), PeerId::from_public_key(&key));
+}
+
+#[test]
+fn test_load_or_generate_persists_identity() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir
+        .path()
+        .join("profiles")
+        .join("alice")
+        .join("identity.key");
+
+    let first = Identity::load_or_generate(&path, None).unwrap();
+    assert!(path.exists());
+
+    let second = Identity::load_or_generate(&path, None).unwrap();
+    assert_eq!(first.public_key(), second.public_key());
+    assert_eq!(first.peer_id(), second.peer_id());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_key_file_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.key");
+    Identity::generate().save(&path, None).unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_save_replaces_existing_key_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.key");
+    std::fs::write(&path, "old key that is much longer than the new one").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let identity = Identity::generate();
+    identity.save(&path, None).unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(!path.with_extension("tmp").exists());
+    assert_eq!(
+        Identity::load(&path, None).unwrap().public_key(),
+        identity.public_key()
+    );
+}
+
+#[test]
+fn test_load_rejects_corrupt_key_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.key");
+    std::fs::write(&path, "not hex").unwrap();
+
+    assert!(Identity::load(&path, None).is_err());
+}
+
+#[test]
+fn test_public_key_hex_roundtrip() {
+    let key = Identity::generate().public_key();
+    let parsed: PublicKey = key.to_hex().parse().unwrap();
+
+    assert_eq!(parsed, key);
+}
+
+#[test]
+fn test_public_key_serde_roundtrip() {
+    let key = Identity::generate().public_key();
+    let json = serde_json::to_string(&key).unwrap();
+
+    assert_eq!(json, format!("\"{}\"", key.to_hex()));
+
+    let parsed: PublicKey = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, key);
+}
+
+#[test]
+fn test_public_key_rejects_wrong_length() {
+    assert!("abcd".parse::<PublicKey>().is_err());
+    assert!("zz".repeat(32).parse::<PublicKey>().is_err());
+}
+
+#[test]
+fn test_sign_and_verify() {
+    let identity = Identity::generate();
+    let signature = identity.sign(b"hello");
+
+    assert!(identity.public_key().verify(b"hello", &signature).is_ok());
+    assert!(identity
+        .public_key()
+        .verify(b"goodbye", &signature)
+        .is_err());
+    assert!(Identity::generate()
+        .public_key()
+        .verify(b"hello", &signature)
+        .is_err());
+}
+
+#[test]
+fn test_safety_number_is_symmetric() {
+    let a = Identity::generate().public_key();
+    let b = Identity::generate().public_key();
+
+    assert_eq!(safety_number(&a, &b), safety_number(&b, &a));
+}
+
+#[test]
+fn test_safety_number_format() {
+    let a = Identity::generate().public_key();
+    let b = Identity::generate().public_key();
+    let number = safety_number(&a, &b);
+
+    let groups: Vec<&str> = number.split(' ').collect();
+    assert_eq!(groups.len(), 12);
+    assert!(groups
+        .iter()
+        .all(|g| g.len() == 5 && g.chars().all(|c| c.is_ascii_digit())));
+}
+
+#[test]
+fn test_safety_number_differs_per_pair() {
+    let a = Identity::generate().public_key();
+    let b = Identity::generate().public_key();
+    let c = Identity::generate().public_key();
+
+    assert_ne!(safety_number(&a, &b), safety_number(&a, &c));
+}
@@ -0,0 +1,241 @@
+//! Integration tests for trust-on-first-use key pinning.
+
+mod common;
+
+use common::{test_addr, test_public_key};
+use parlance::core::known_peers::{KeyChangeWarning, KnownPeers, TrustDecision, TrustStatus};
+use parlance::core::peer::{Peer, PeerRegistry};
+use tokio::sync::mpsc;
+
+fn load_store(dir: &tempfile::TempDir) -> (KnownPeers, mpsc::UnboundedReceiver<KeyChangeWarning>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let store = KnownPeers::load(dir.path().join("known_peers.json"), tx).unwrap();
+    (store, rx)
+}
+
+#[tokio::test]
+async fn test_first_key_is_pinned() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+    let key = test_public_key();
+
+    assert_eq!(store.check("alice", &key).await, TrustDecision::Accept);
+    assert_eq!(store.check("alice", &key).await, TrustDecision::Accept);
+
+    let entry = store.get("alice").await.unwrap();
+    assert_eq!(entry.public_key, key);
+    assert_eq!(entry.status, TrustStatus::Trusted);
+}
+
+#[tokio::test]
+async fn test_pins_survive_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = test_public_key();
+
+    {
+        let (store, _rx) = load_store(&dir);
+        store.check("alice", &key).await;
+    }
+
+    let (store, _rx) = load_store(&dir);
+    let entry = store.get("alice").await.unwrap();
+    assert_eq!(entry.public_key, key);
+}
+
+#[tokio::test]
+async fn test_changed_key_is_rejected_and_warned_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, mut rx) = load_store(&dir);
+    let original = test_public_key();
+    let impostor = test_public_key();
+
+    store.check("alice", &original).await;
+
+    assert_eq!(store.check("alice", &impostor).await, TrustDecision::Reject);
+    assert_eq!(store.check("alice", &impostor).await, TrustDecision::Reject);
+
+    let warning = rx.try_recv().unwrap();
+    assert_eq!(warning.nickname, "alice");
+    assert_eq!(warning.pinned, original);
+    assert_eq!(warning.presented, impostor);
+    assert!(rx.try_recv().is_err());
+
+    // The original key keeps working
+    assert_eq!(store.check("alice", &original).await, TrustDecision::Accept);
+}
+
+#[tokio::test]
+async fn test_nickname_collision_names_the_known_peer() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, mut rx) = load_store(&dir);
+    let alice = test_public_key();
+    let bob = test_public_key();
+
+    store.check("alice", &alice).await;
+    store.check("bob", &bob).await;
+
+    // Bob, already pinned under his own nickname, announces as alice
+    assert_eq!(store.check("alice", &bob).await, TrustDecision::Reject);
+    let warning = rx.try_recv().unwrap();
+    assert_eq!(warning.presented, bob);
+    assert_eq!(warning.known_as.as_deref(), Some("bob"));
+
+    // An unknown key is a plain key change
+    store.check("bob", &test_public_key()).await;
+    assert_eq!(rx.try_recv().unwrap().known_as, None);
+}
+
+#[tokio::test]
+async fn test_entries_carry_peer_id() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+    let key = test_public_key();
+
+    store.check("alice", &key).await;
+    assert_eq!(store.get("alice").await.unwrap().peer_id(), key.peer_id());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_store_written_atomically_and_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+    store.check("alice", &test_public_key()).await;
+
+    let path = dir.path().join("known_peers.json");
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(!dir.path().join("known_peers.tmp").exists());
+}
+
+#[tokio::test]
+async fn test_trust_approves_pending_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+    let original = test_public_key();
+    let rotated = test_public_key();
+
+    store.check("alice", &original).await;
+    store.check("alice", &rotated).await;
+
+    let replaced = store.trust("alice").await.unwrap();
+    assert_eq!(replaced, Some(original));
+
+    assert_eq!(store.check("alice", &rotated).await, TrustDecision::Accept);
+    assert_eq!(store.check("alice", &original).await, TrustDecision::Reject);
+}
+
+#[tokio::test]
+async fn test_untrust_and_retrust() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+    let key = test_public_key();
+
+    store.check("alice", &key).await;
+    store.untrust("alice").await.unwrap();
+    assert_eq!(store.check("alice", &key).await, TrustDecision::Reject);
+
+    assert_eq!(store.trust("alice").await.unwrap(), None);
+    assert_eq!(store.check("alice", &key).await, TrustDecision::Accept);
+}
+
+#[tokio::test]
+async fn test_trust_unknown_nickname_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+
+    assert!(store.trust("nobody").await.is_err());
+    assert!(store.untrust("nobody").await.is_err());
+}
+
+#[tokio::test]
+async fn test_mark_verified() {
+    let dir = tempfile::tempdir().unwrap();
+    let key = test_public_key();
+
+    {
+        let (store, _rx) = load_store(&dir);
+        store.check("alice", &key).await;
+        assert!(!store.is_verified("alice", &key).await);
+
+        store.mark_verified("alice").await.unwrap();
+        assert!(store.is_verified("alice", &key).await);
+        assert!(!store.is_verified("alice", &test_public_key()).await);
+    }
+
+    let (store, _rx) = load_store(&dir);
+    assert!(store.is_verified("alice", &key).await);
+}
+
+#[tokio::test]
+async fn test_verification_reset_by_key_change_and_untrust() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+    let original = test_public_key();
+    let rotated = test_public_key();
+
+    store.check("alice", &original).await;
+    store.mark_verified("alice").await.unwrap();
+
+    // A pending key change blocks verification until approved
+    store.check("alice", &rotated).await;
+    assert!(store.mark_verified("alice").await.is_err());
+
+    store.trust("alice").await.unwrap();
+    assert!(!store.is_verified("alice", &rotated).await);
+
+    store.mark_verified("alice").await.unwrap();
+    store.untrust("alice").await.unwrap();
+    assert!(!store.is_verified("alice", &rotated).await);
+    assert!(store.mark_verified("alice").await.is_err());
+}
+
+#[tokio::test]
+async fn test_verify_unknown_nickname_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+
+    assert!(store.mark_verified("nobody").await.is_err());
+}
+
+#[tokio::test]
+async fn test_registry_ignores_impostor() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+    let registry = PeerRegistry::with_known_peers(store);
+
+    let original = test_public_key();
+    registry
+        .upsert(Peer::new("alice".to_string(), test_addr(8080), original))
+        .await;
+    registry
+        .upsert(Peer::new(
+            "alice".to_string(),
+            test_addr(6666),
+            test_public_key(),
+        ))
+        .await;
+
+    let peers = registry.get_all().await;
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].public_key, original);
+    assert_eq!(peers[0].addr, test_addr(8080));
+}
+
+#[tokio::test]
+async fn test_registry_pinned_key_requires_trust() {
+    let dir = tempfile::tempdir().unwrap();
+    let (store, _rx) = load_store(&dir);
+    let registry = PeerRegistry::with_known_peers(store.clone());
+    let key = test_public_key();
+
+    assert_eq!(registry.pinned_key("alice").await, None);
+
+    store.check("alice", &key).await;
+    assert_eq!(registry.pinned_key("alice").await, Some(key));
+
+    store.untrust("alice").await.unwrap();
+    assert_eq!(registry.pinned_key("alice").await, None);
+}
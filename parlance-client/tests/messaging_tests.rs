@@ -1,10 +1,78 @@
 //! Integration tests for messaging functionality.
 
-use parlance::network::messaging::TextMessage;
+mod common;
+
+use common::{test_addr, test_public_key};
+use parlance::core::config::Transport;
+use parlance::core::error::ParlanceError;
+use parlance::core::group::Groups;
+use parlance::core::history::{DeliveryState, Direction, History};
+use parlance::core::identity::Identity;
+use parlance::core::known_peers::KnownPeers;
+use parlance::core::peer::{Peer, PeerRegistry};
+use parlance::network::connection::PoolConfig;
+use parlance::network::messaging::{
+    DeliveryConfig, GroupChange, MessageEvent, MessageId, MessagingConfig, MessagingService,
+    PeerFrame, TextMessage,
+};
+use parlance::network::outbox::Outbox;
+use parlance::network::secure::SecureChannel;
+use parlance::network::transfer::Transfers;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Messaging config with default settings and no outbox or history
+fn messaging_config(nickname: &str, identity: Identity, registry: PeerRegistry) -> MessagingConfig {
+    MessagingConfig {
+        nickname: nickname.to_string(),
+        identity,
+        tcp_port: 0,
+        registry,
+        pool: PoolConfig::default(),
+        delivery: DeliveryConfig::default(),
+        outbox: None,
+        history: None,
+        groups: None,
+        transfers: None,
+        transport: Transport::Tcp,
+    }
+}
+
+/// Start a messaging service on an ephemeral port
+async fn start_service(
+    nickname: &str,
+    identity: Identity,
+    registry: PeerRegistry,
+) -> (
+    Arc<MessagingService>,
+    u16,
+    mpsc::UnboundedReceiver<MessageEvent>,
+) {
+    start_service_with(messaging_config(nickname, identity, registry)).await
+}
+
+/// Start a messaging service with a custom config
+async fn start_service_with(
+    config: MessagingConfig,
+) -> (
+    Arc<MessagingService>,
+    u16,
+    mpsc::UnboundedReceiver<MessageEvent>,
+) {
+    let (event_tx, event_rx) = mpsc::unbounded_channel();
+    let service = Arc::new(MessagingService::new(config, event_tx).await.unwrap());
+    let port = service.local_addr().unwrap().port();
+
+    let runner = service.clone();
+    tokio::spawn(async move { runner.run().await });
+
+    (service, port, event_rx)
+}
 
 #[test]
 fn test_text_message_creation() {
-    let msg = TextMessage::new("Alice".to_string(), "Hello!".to_string());
+    let msg = TextMessage::new("Alice".to_string(), test_public_key(), "Hello!".to_string());
 
     assert_eq!(msg.from, "Alice");
     assert_eq!(msg.content, "Hello!");
@@ -13,32 +81,89 @@ fn test_text_message_creation() {
 
 #[test]
 fn test_text_message_format() {
-    let msg = TextMessage::new("Alice".to_string(), "Hello World!".to_string());
-    let formatted = msg.format();
+    let msg = TextMessage::new(
+        "Alice".to_string(),
+        test_public_key(),
+        "Hello World!".to_string(),
+    );
+    let formatted = msg.format(false, false);
 
     assert!(formatted.contains("Alice"));
     assert!(formatted.contains("Hello World!"));
     assert!(formatted.contains("["));
     assert!(formatted.contains("]"));
+    assert!(!formatted.contains("✔"));
+}
+
+#[test]
+fn test_text_message_format_verified_badge() {
+    let msg = TextMessage::new("Alice".to_string(), test_public_key(), "Hello".to_string());
+
+    assert!(msg.format(true, false).contains("Alice ✔: Hello"));
+}
+
+#[test]
+fn test_text_message_format_relayed() {
+    let msg = TextMessage::new("Alice".to_string(), test_public_key(), "Hello".to_string());
+
+    assert!(!msg.format(false, false).contains("relayed"));
+    assert!(msg.format(false, true).contains("Alice (relayed): Hello"));
+    assert!(msg
+        .format_in_group("friends", true, true)
+        .contains("[friends] Alice ✔ (relayed): Hello"));
 }
 
 #[test]
 fn test_text_message_serialization() {
-    let msg = TextMessage::new("Alice".to_string(), "Test message".to_string());
+    let msg = TextMessage::new(
+        "Alice".to_string(),
+        test_public_key(),
+        "Test message".to_string(),
+    );
 
     let json = serde_json::to_string(&msg).expect("Failed to serialize");
 
     let deserialized: TextMessage = serde_json::from_str(&json).expect("Failed to deserialize");
 
+    assert_eq!(deserialized.id, msg.id);
     assert_eq!(deserialized.from, msg.from);
+    assert_eq!(deserialized.public_key, msg.public_key);
     assert_eq!(deserialized.content, msg.content);
     assert_eq!(deserialized.timestamp, msg.timestamp);
 }
 
+#[test]
+fn test_message_ids_are_unique() {
+    let a = TextMessage::new("Alice".to_string(), test_public_key(), "hi".to_string());
+    let b = TextMessage::new("Alice".to_string(), test_public_key(), "hi".to_string());
+
+    assert_ne!(a.id, b.id);
+}
+
+#[test]
+fn test_peer_frame_serialization() {
+    let msg = TextMessage::new("Alice".to_string(), test_public_key(), "hi".to_string());
+    let data = PeerFrame::Message(msg.clone()).encode().unwrap();
+    match PeerFrame::decode(&data).unwrap() {
+        PeerFrame::Message(parsed) => {
+            assert_eq!(parsed.id, msg.id);
+            assert_eq!(parsed.content, "hi");
+        }
+        other => panic!("Unexpected frame: {:?}", other),
+    }
+
+    let id = MessageId::new();
+    let data = PeerFrame::Ack { id }.encode().unwrap();
+    match PeerFrame::decode(&data).unwrap() {
+        PeerFrame::Ack { id: parsed } => assert_eq!(parsed, id),
+        other => panic!("Unexpected frame: {:?}", other),
+    }
+}
+
 #[test]
 fn test_text_message_with_special_characters() {
     let content = "Hello! 🦀 Special chars: @#$%^&*()";
-    let msg = TextMessage::new("Bob".to_string(), content.to_string());
+    let msg = TextMessage::new("Bob".to_string(), test_public_key(), content.to_string());
 
     assert_eq!(msg.content, content);
 
@@ -50,7 +175,7 @@ fn test_text_message_with_special_characters() {
 
 #[test]
 fn test_text_message_empty_content() {
-    let msg = TextMessage::new("Alice".to_string(), "".to_string());
+    let msg = TextMessage::new("Alice".to_string(), test_public_key(), "".to_string());
 
     assert_eq!(msg.from, "Alice");
     assert_eq!(msg.content, "");
@@ -59,8 +184,686 @@ fn test_text_message_empty_content() {
 #[test]
 fn test_text_message_long_content() {
     let long_content = "a".repeat(10000);
-    let msg = TextMessage::new("Alice".to_string(), long_content.clone());
+    let msg = TextMessage::new("Alice".to_string(), test_public_key(), long_content.clone());
 
     assert_eq!(msg.content.len(), 10000);
     assert_eq!(msg.content, long_content);
 }
+
+#[tokio::test]
+async fn test_encrypted_message_delivery() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+
+    let (_bob_service, bob_port, mut bob_events) =
+        start_service("bob", bob.clone(), PeerRegistry::new()).await;
+
+    let alice_registry = PeerRegistry::new();
+    alice_registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(bob_port),
+            bob.public_key(),
+        ))
+        .await;
+    let (alice_service, _, mut alice_events) =
+        start_service("alice", alice.clone(), alice_registry).await;
+
+    let id = alice_service
+        .send_message("bob", "hello bob".to_string())
+        .await
+        .unwrap();
+
+    let event = tokio::time::timeout(Duration::from_secs(5), bob_events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    match event {
+        MessageEvent::Received { msg, relayed } => {
+            assert!(!relayed);
+            assert_eq!(msg.from, "alice");
+            assert_eq!(msg.content, "hello bob");
+            assert_eq!(msg.public_key, alice.public_key());
+            assert_eq!(msg.id, id);
+        }
+        other => panic!("Unexpected event: {:?}", other),
+    }
+
+    let delivered = next_event(&mut alice_events, |e| {
+        matches!(e, MessageEvent::Delivered { .. })
+    })
+    .await;
+    assert!(matches!(delivered, MessageEvent::Delivered { id: d, .. } if d == id));
+}
+
+#[tokio::test]
+async fn test_send_fails_on_identity_mismatch() {
+    let bob = Identity::generate();
+    let (_bob_service, bob_port, _bob_events) =
+        start_service("bob", bob, PeerRegistry::new()).await;
+
+    // Registry claims bob has a different key than the one he proves
+    let alice_registry = PeerRegistry::new();
+    alice_registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(bob_port),
+            test_public_key(),
+        ))
+        .await;
+    let (alice_service, _, mut alice_events) =
+        start_service("alice", Identity::generate(), alice_registry).await;
+
+    let id = alice_service
+        .send_message("bob", "hello".to_string())
+        .await
+        .unwrap();
+
+    let event = next_event(&mut alice_events, |e| {
+        matches!(e, MessageEvent::Failed { .. })
+    })
+    .await;
+    match event {
+        MessageEvent::Failed {
+            to,
+            id: failed,
+            error,
+        } => {
+            assert_eq!(to, "bob");
+            assert_eq!(failed, id);
+            assert!(error.contains("handshake"), "{}", error);
+        }
+        other => panic!("Unexpected event: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_send_to_unknown_peer_fails() {
+    let (service, _, _events) =
+        start_service("alice", Identity::generate(), PeerRegistry::new()).await;
+
+    let result = service.send_message("nobody", "hello".to_string()).await;
+    assert!(matches!(result, Err(ParlanceError::PeerNotFound(_))));
+}
+
+#[tokio::test]
+async fn test_message_fails_after_max_attempts() {
+    // Nothing listens on the peer's port, so every attempt fails
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let registry = PeerRegistry::new();
+    registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(port),
+            Identity::generate().public_key(),
+        ))
+        .await;
+    let delivery = DeliveryConfig {
+        ack_timeout: Duration::from_millis(50),
+        max_attempts: 2,
+    };
+    let (service, _, mut events) = start_service_with(MessagingConfig {
+        delivery,
+        ..messaging_config("alice", Identity::generate(), registry)
+    })
+    .await;
+
+    let id = service
+        .send_message("bob", "hello".to_string())
+        .await
+        .unwrap();
+
+    let event = next_event(&mut events, |e| matches!(e, MessageEvent::Failed { .. })).await;
+    assert!(matches!(event, MessageEvent::Failed { id: failed, .. } if failed == id));
+}
+
+#[tokio::test]
+async fn test_duplicate_messages_are_dropped() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (_bob_service, bob_port, mut bob_events) =
+        start_service("bob", bob.clone(), PeerRegistry::new()).await;
+
+    // Send the same frame twice over one channel, as a retry would
+    let stream = tokio::net::TcpStream::connect(test_addr(bob_port))
+        .await
+        .unwrap();
+    let mut channel = SecureChannel::initiate(stream, &alice).await.unwrap();
+    let msg = TextMessage::new("alice".to_string(), alice.public_key(), "once".to_string());
+    let frame = PeerFrame::Message(msg.clone()).encode().unwrap();
+    channel.send(&frame).await.unwrap();
+    channel.send(&frame).await.unwrap();
+
+    // Both copies are acknowledged
+    for _ in 0..2 {
+        let reply = tokio::time::timeout(Duration::from_secs(5), channel.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        match PeerFrame::decode(&reply).unwrap() {
+            PeerFrame::Ack { id } => assert_eq!(id, msg.id),
+            other => panic!("Unexpected frame: {:?}", other),
+        }
+    }
+
+    // But only delivered once
+    let event = bob_events.recv().await.unwrap();
+    assert!(matches!(event, MessageEvent::Received { msg: m, .. } if m.content == "once"));
+    assert!(bob_events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_offline_message_is_sent_when_peer_reappears() {
+    let dir = tempfile::tempdir().unwrap();
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+
+    // Alice has talked to bob before, so his key is pinned
+    let (warning_tx, _warning_rx) = mpsc::unbounded_channel();
+    let known_peers = KnownPeers::load(dir.path().join("known_peers.json"), warning_tx).unwrap();
+    known_peers.check("bob", &bob.public_key()).await;
+    let alice_registry = PeerRegistry::with_known_peers(known_peers);
+
+    let outbox = Outbox::load(
+        dir.path().join("outbox.json"),
+        Duration::from_secs(60),
+        None,
+    )
+    .unwrap();
+    let (alice_service, _, mut alice_events) = start_service_with(MessagingConfig {
+        outbox: Some(outbox.clone()),
+        ..messaging_config("alice", alice, alice_registry.clone())
+    })
+    .await;
+
+    let id = alice_service
+        .send_message("bob", "while you were out".to_string())
+        .await
+        .unwrap();
+    let event = next_event(&mut alice_events, |e| {
+        matches!(e, MessageEvent::Queued { .. })
+    })
+    .await;
+    assert!(matches!(event, MessageEvent::Queued { id: q, .. } if q == id));
+    assert_eq!(outbox.list().await.len(), 1);
+
+    // Bob comes online and is discovered
+    let (_bob_service, bob_port, mut bob_events) =
+        start_service("bob", bob.clone(), PeerRegistry::new()).await;
+    alice_registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(bob_port),
+            bob.public_key(),
+        ))
+        .await;
+
+    let event = next_event(&mut bob_events, |e| {
+        matches!(e, MessageEvent::Received { .. })
+    })
+    .await;
+    match event {
+        MessageEvent::Received { msg, .. } => {
+            assert_eq!(msg.id, id);
+            assert_eq!(msg.content, "while you were out");
+        }
+        other => panic!("Unexpected event: {:?}", other),
+    }
+    next_event(
+        &mut alice_events,
+        |e| matches!(e, MessageEvent::Delivered { id: d, .. } if *d == id),
+    )
+    .await;
+    assert!(outbox.list().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_queued_message_survives_peer_leaving_during_flush() {
+    let dir = tempfile::tempdir().unwrap();
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+
+    let (warning_tx, _warning_rx) = mpsc::unbounded_channel();
+    let known_peers = KnownPeers::load(dir.path().join("known_peers.json"), warning_tx).unwrap();
+    known_peers.check("bob", &bob.public_key()).await;
+    let alice_registry = PeerRegistry::with_known_peers(known_peers);
+
+    let outbox = Outbox::load(
+        dir.path().join("outbox.json"),
+        Duration::from_secs(60),
+        None,
+    )
+    .unwrap();
+    let (alice_service, _, mut alice_events) = start_service_with(MessagingConfig {
+        outbox: Some(outbox.clone()),
+        delivery: DeliveryConfig {
+            ack_timeout: Duration::from_millis(500),
+            max_attempts: 2,
+        },
+        pool: PoolConfig {
+            initial_backoff: Duration::from_millis(10),
+            ..PoolConfig::default()
+        },
+        ..messaging_config("alice", alice, alice_registry.clone())
+    })
+    .await;
+
+    let id = alice_service
+        .send_message("bob", "are you there?".to_string())
+        .await
+        .unwrap();
+    next_event(&mut alice_events, |e| {
+        matches!(e, MessageEvent::Queued { .. })
+    })
+    .await;
+
+    // Bob shows up, but is gone again before the flush reaches him
+    let gone = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let gone_port = gone.local_addr().unwrap().port();
+    drop(gone);
+    alice_registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(gone_port),
+            bob.public_key(),
+        ))
+        .await;
+
+    let event = next_event(&mut alice_events, |e| {
+        matches!(
+            e,
+            MessageEvent::Queued { .. } | MessageEvent::Delivered { .. }
+        )
+    })
+    .await;
+    assert!(matches!(event, MessageEvent::Queued { id: q, .. } if q == id));
+    let queued = outbox.list().await;
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].id(), id);
+    assert_eq!(
+        Outbox::load(
+            dir.path().join("outbox.json"),
+            Duration::from_secs(60),
+            None
+        )
+        .unwrap()
+        .list()
+        .await
+        .len(),
+        1
+    );
+
+    // The message goes out when bob really comes back
+    alice_registry.remove(&bob.public_key().peer_id()).await;
+    let (_bob_service, bob_port, mut bob_events) =
+        start_service("bob", bob.clone(), PeerRegistry::new()).await;
+    alice_registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(bob_port),
+            bob.public_key(),
+        ))
+        .await;
+
+    let event = next_event(&mut bob_events, |e| {
+        matches!(e, MessageEvent::Received { .. })
+    })
+    .await;
+    assert!(matches!(event, MessageEvent::Received { msg, .. } if msg.id == id));
+    next_event(
+        &mut alice_events,
+        |e| matches!(e, MessageEvent::Delivered { id: d, .. } if *d == id),
+    )
+    .await;
+    assert!(outbox.list().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_send_to_unknown_nickname_is_not_queued() {
+    let dir = tempfile::tempdir().unwrap();
+    let (warning_tx, _warning_rx) = mpsc::unbounded_channel();
+    let known_peers = KnownPeers::load(dir.path().join("known_peers.json"), warning_tx).unwrap();
+    let outbox = Outbox::load(
+        dir.path().join("outbox.json"),
+        Duration::from_secs(60),
+        None,
+    )
+    .unwrap();
+
+    let (service, _, _events) = start_service_with(MessagingConfig {
+        outbox: Some(outbox.clone()),
+        ..messaging_config(
+            "alice",
+            Identity::generate(),
+            PeerRegistry::with_known_peers(known_peers),
+        )
+    })
+    .await;
+
+    let result = service.send_message("nobody", "hello".to_string()).await;
+    assert!(matches!(result, Err(ParlanceError::PeerNotFound(_))));
+    assert!(outbox.list().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_messages_are_recorded_in_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let alice_history = History::load(dir.path().join("alice.jsonl"), None, None).unwrap();
+    let bob_history = History::load(dir.path().join("bob.jsonl"), None, None).unwrap();
+
+    let (_bob_service, bob_port, mut bob_events) = start_service_with(MessagingConfig {
+        history: Some(bob_history.clone()),
+        ..messaging_config("bob", bob.clone(), PeerRegistry::new())
+    })
+    .await;
+
+    let alice_registry = PeerRegistry::new();
+    alice_registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(bob_port),
+            bob.public_key(),
+        ))
+        .await;
+    let (alice_service, _, mut alice_events) = start_service_with(MessagingConfig {
+        history: Some(alice_history.clone()),
+        ..messaging_config("alice", alice.clone(), alice_registry)
+    })
+    .await;
+
+    let id = alice_service
+        .send_message("bob", "remember me".to_string())
+        .await
+        .unwrap();
+    next_event(&mut bob_events, |e| {
+        matches!(e, MessageEvent::Received { .. })
+    })
+    .await;
+    next_event(&mut alice_events, |e| {
+        matches!(e, MessageEvent::Delivered { .. })
+    })
+    .await;
+
+    let sent = alice_history.conversation("bob", 10).await;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].id, id);
+    assert_eq!(sent[0].direction, Direction::Sent);
+    assert_eq!(sent[0].peer_key, bob.public_key());
+    assert_eq!(sent[0].state, DeliveryState::Delivered);
+
+    let received = bob_history.conversation("alice", 10).await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].id, id);
+    assert_eq!(received[0].direction, Direction::Received);
+    assert_eq!(received[0].content, "remember me");
+}
+
+/// A peer with its own group store, started on an ephemeral port
+struct GroupNode {
+    identity: Identity,
+    registry: PeerRegistry,
+    groups: Groups,
+    service: Arc<MessagingService>,
+    port: u16,
+    events: mpsc::UnboundedReceiver<MessageEvent>,
+}
+
+async fn start_group_node(dir: &tempfile::TempDir, nickname: &str) -> GroupNode {
+    let identity = Identity::generate();
+    let registry = PeerRegistry::new();
+    let groups = Groups::load(dir.path().join(format!("{}-groups.json", nickname))).unwrap();
+    let (service, port, events) = start_service_with(MessagingConfig {
+        groups: Some(groups.clone()),
+        ..messaging_config(nickname, identity.clone(), registry.clone())
+    })
+    .await;
+
+    GroupNode {
+        identity,
+        registry,
+        groups,
+        service,
+        port,
+        events,
+    }
+}
+
+/// Make every node discover every other node
+async fn introduce(nodes: &[(&str, &GroupNode)]) {
+    for (_, node) in nodes {
+        for (nickname, other) in nodes {
+            if other.port != node.port {
+                node.registry
+                    .upsert(Peer::new(
+                        nickname.to_string(),
+                        test_addr(other.port),
+                        other.identity.public_key(),
+                    ))
+                    .await;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_group_membership_and_fan_out() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut alice = start_group_node(&dir, "alice").await;
+    let mut bob = start_group_node(&dir, "bob").await;
+    let mut carol = start_group_node(&dir, "carol").await;
+    introduce(&[("alice", &alice), ("bob", &bob), ("carol", &carol)]).await;
+
+    alice.service.create_group("friends").await.unwrap();
+    alice
+        .service
+        .invite_to_group("friends", "bob")
+        .await
+        .unwrap();
+    let event = next_event(&mut bob.events, |e| {
+        matches!(e, MessageEvent::GroupChanged { .. })
+    })
+    .await;
+    assert!(matches!(
+        event,
+        MessageEvent::GroupChanged { group, change: GroupChange::Invited { by } }
+            if group == "friends" && by == "alice"
+    ));
+
+    alice
+        .service
+        .invite_to_group("friends", "carol")
+        .await
+        .unwrap();
+    next_event(&mut carol.events, |e| {
+        matches!(e, MessageEvent::GroupChanged { .. })
+    })
+    .await;
+    let event = next_event(&mut bob.events, |e| {
+        matches!(e, MessageEvent::GroupChanged { .. })
+    })
+    .await;
+    assert!(matches!(
+        event,
+        MessageEvent::GroupChanged { change: GroupChange::MemberJoined(n), .. } if n == "carol"
+    ));
+    assert_eq!(bob.groups.find("friends").await.unwrap().members.len(), 3);
+
+    // Bob's message reaches both other members
+    let id = bob
+        .service
+        .send_group_message("friends", "hi all".to_string())
+        .await
+        .unwrap();
+    for events in [&mut alice.events, &mut carol.events] {
+        let event = next_event(events, |e| matches!(e, MessageEvent::GroupReceived { .. })).await;
+        match event {
+            MessageEvent::GroupReceived { group, msg, .. } => {
+                assert_eq!(group, "friends");
+                assert_eq!(msg.id, id);
+                assert_eq!(msg.from, "bob");
+                assert_eq!(msg.content, "hi all");
+            }
+            other => panic!("Unexpected event: {:?}", other),
+        }
+    }
+
+    // Carol leaves and the others are told
+    carol.service.leave_group("friends").await.unwrap();
+    assert!(carol.groups.list().await.is_empty());
+    for events in [&mut alice.events, &mut bob.events] {
+        let event = next_event(events, |e| matches!(e, MessageEvent::GroupChanged { .. })).await;
+        assert!(matches!(
+            event,
+            MessageEvent::GroupChanged { change: GroupChange::MemberLeft(n), .. } if n == "carol"
+        ));
+    }
+    assert_eq!(alice.groups.find("friends").await.unwrap().members.len(), 2);
+}
+
+#[tokio::test]
+async fn test_group_message_from_non_member_is_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    let alice = start_group_node(&dir, "alice").await;
+    let mut bob = start_group_node(&dir, "bob").await;
+    let mut carol = start_group_node(&dir, "carol").await;
+    introduce(&[("alice", &alice), ("bob", &bob), ("carol", &carol)]).await;
+
+    // Carol keeps a group with bob that bob never joined
+    carol.service.create_group("secret").await.unwrap();
+    let mut group = carol.groups.find("secret").await.unwrap();
+    group
+        .add_member(parlance::core::group::Member {
+            nickname: "bob".to_string(),
+            public_key: bob.identity.public_key(),
+        })
+        .unwrap();
+    carol.groups.update(group).await.unwrap();
+
+    carol
+        .service
+        .send_group_message("secret", "psst".to_string())
+        .await
+        .unwrap();
+    // Bob acknowledges the copy even though he drops it
+    next_event(&mut carol.events, |e| {
+        matches!(e, MessageEvent::Delivered { .. })
+    })
+    .await;
+    alice
+        .service
+        .send_message("bob", "direct".to_string())
+        .await
+        .unwrap();
+
+    // The direct message arrives, the group message never surfaces
+    let event = next_event(&mut bob.events, |e| {
+        matches!(
+            e,
+            MessageEvent::Received { .. } | MessageEvent::GroupReceived { .. }
+        )
+    })
+    .await;
+    assert!(matches!(event, MessageEvent::Received { msg: m, .. } if m.content == "direct"));
+}
+
+#[tokio::test]
+async fn test_file_transfer_between_peers() {
+    let dir = tempfile::tempdir().unwrap();
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let alice_transfers = Transfers::new(dir.path().join("alice"));
+    let bob_transfers = Transfers::new(dir.path().join("bob"));
+
+    let bob_registry = PeerRegistry::new();
+    let (bob_service, bob_port, mut bob_events) = start_service_with(MessagingConfig {
+        transfers: Some(bob_transfers),
+        ..messaging_config("bob", bob.clone(), bob_registry.clone())
+    })
+    .await;
+    let alice_registry = PeerRegistry::new();
+    alice_registry
+        .upsert(Peer::new(
+            "bob".to_string(),
+            test_addr(bob_port),
+            bob.public_key(),
+        ))
+        .await;
+    let (alice_service, alice_port, mut alice_events) = start_service_with(MessagingConfig {
+        transfers: Some(alice_transfers),
+        ..messaging_config("alice", alice.clone(), alice_registry)
+    })
+    .await;
+    bob_registry
+        .upsert(Peer::new(
+            "alice".to_string(),
+            test_addr(alice_port),
+            alice.public_key(),
+        ))
+        .await;
+
+    let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+    let path = dir.path().join("holiday.jpg");
+    std::fs::write(&path, &contents).unwrap();
+
+    let offer = alice_service.send_file("bob", &path).await.unwrap();
+    let event = next_event(&mut bob_events, |e| {
+        matches!(e, MessageEvent::FileOffered(_))
+    })
+    .await;
+    assert!(matches!(event, MessageEvent::FileOffered(o) if o == offer));
+
+    bob_service
+        .accept_file(&offer.id.to_string())
+        .await
+        .unwrap();
+
+    let event = next_event(&mut bob_events, |e| {
+        matches!(
+            e,
+            MessageEvent::TransferComplete { .. } | MessageEvent::TransferFailed { .. }
+        )
+    })
+    .await;
+    let saved = match event {
+        MessageEvent::TransferComplete {
+            path: Some(path), ..
+        } => path,
+        other => panic!("Unexpected event: {:?}", other),
+    };
+    assert_eq!(std::fs::read(saved).unwrap(), contents);
+
+    let event = next_event(&mut alice_events, |e| {
+        matches!(
+            e,
+            MessageEvent::TransferComplete { .. } | MessageEvent::TransferFailed { .. }
+        )
+    })
+    .await;
+    assert!(matches!(
+        event,
+        MessageEvent::TransferComplete { id, path: None, .. } if id == offer.id
+    ));
+}
+
+/// Wait for the first event matching the predicate
+async fn next_event(
+    events: &mut mpsc::UnboundedReceiver<MessageEvent>,
+    matches: impl Fn(&MessageEvent) -> bool,
+) -> MessageEvent {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("timed out waiting for event")
+            .unwrap();
+        if matches(&event) {
+            return event;
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Tests for reflexive address probing and NAT classification.
+//!
+//! A stand-in for the bootstrap server's probe service answers on two
+//! loopback ports, and userspace wrappers around the probing socket play
+//! the part of the different kinds of NAT.
+
+use parlance::network::nat::{probe, NatProbe, NatType};
+use parlance::network::punch::Datagram;
+use std::collections::HashSet;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::ReadBuf;
+use tokio::net::UdpSocket;
+
+const TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Answer probes on `socket`, from `other` when asked to change ports
+async fn serve(socket: Arc<UdpSocket>, other: Arc<UdpSocket>) {
+    let mut buf = [0u8; 512];
+    loop {
+        let Ok((len, from)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let request: serde_json::Value = serde_json::from_slice(&buf[..len]).unwrap();
+        let response = serde_json::json!({
+            "id": request["id"],
+            "mapped_addr": from.to_string(),
+        });
+        let reply_from = if request["change_port"] == true {
+            &other
+        } else {
+            &socket
+        };
+        let _ = reply_from
+            .send_to(response.to_string().as_bytes(), from)
+            .await;
+    }
+}
+
+/// Start a probe server and return its primary and alternate ports
+async fn probe_server() -> (SocketAddr, SocketAddr) {
+    let primary = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let alternate = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    let addrs = (
+        primary.local_addr().unwrap(),
+        alternate.local_addr().unwrap(),
+    );
+    tokio::spawn(serve(primary.clone(), alternate.clone()));
+    tokio::spawn(serve(alternate, primary));
+    addrs
+}
+
+/// An address on the private network behind a simulated NAT
+fn private_addr(public: SocketAddr) -> SocketAddr {
+    SocketAddr::new([192, 0, 2, 1].into(), public.port())
+}
+
+/// A socket behind a simulated cone NAT
+///
+/// When `restricted`, only datagrams from addresses the inside host has
+/// sent to are let in.
+struct ConeNat {
+    socket: UdpSocket,
+    restricted: bool,
+    contacted: Mutex<HashSet<SocketAddr>>,
+}
+
+impl ConeNat {
+    async fn bind(restricted: bool) -> Self {
+        Self {
+            socket: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            restricted,
+            contacted: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn private_addr(&self) -> SocketAddr {
+        private_addr(self.socket.local_addr().unwrap())
+    }
+}
+
+impl Datagram for ConeNat {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        self.contacted.lock().unwrap().insert(target);
+        self.socket.poll_send_to(cx, buf, target)
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>> {
+        loop {
+            let from = ready!(self.socket.poll_recv_from(cx, buf))?;
+            if !self.restricted || self.contacted.lock().unwrap().contains(&from) {
+                return Poll::Ready(Ok(from));
+            }
+            buf.clear();
+        }
+    }
+}
+
+/// A socket behind a simulated symmetric NAT
+///
+/// Traffic to `first` leaves from one mapping and traffic to anywhere else
+/// from another.
+struct SymmetricNat {
+    first: SocketAddr,
+    to_first: UdpSocket,
+    to_others: UdpSocket,
+}
+
+impl SymmetricNat {
+    async fn bind(first: SocketAddr) -> Self {
+        Self {
+            first,
+            to_first: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            to_others: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        }
+    }
+}
+
+impl Datagram for SymmetricNat {
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        if target == self.first {
+            self.to_first.poll_send_to(cx, buf, target)
+        } else {
+            self.to_others.poll_send_to(cx, buf, target)
+        }
+    }
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<SocketAddr>> {
+        if let Poll::Ready(result) = self.to_first.poll_recv_from(cx, buf) {
+            return Poll::Ready(result);
+        }
+        self.to_others.poll_recv_from(cx, buf)
+    }
+}
+
+#[tokio::test]
+async fn test_probe_without_nat() {
+    let (primary, alternate) = probe_server().await;
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let local_addr = socket.local_addr().unwrap();
+
+    let result = probe(&socket, local_addr, primary, alternate, TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(result.nat_type, NatType::Open);
+    assert_eq!(result.mapped_addr, local_addr);
+    assert!(result.directly_reachable());
+}
+
+#[tokio::test]
+async fn test_probe_full_cone() {
+    let (primary, alternate) = probe_server().await;
+    let socket = ConeNat::bind(false).await;
+
+    let result = probe(&socket, socket.private_addr(), primary, alternate, TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(result.nat_type, NatType::FullCone);
+    assert_eq!(result.mapped_addr, socket.socket.local_addr().unwrap());
+    // The mapping only covers the probing socket, not the TCP listener
+    assert!(!result.directly_reachable());
+}
+
+#[tokio::test]
+async fn test_probe_restricted() {
+    let (primary, alternate) = probe_server().await;
+    let socket = ConeNat::bind(true).await;
+
+    let result = probe(&socket, socket.private_addr(), primary, alternate, TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(result.nat_type, NatType::Restricted);
+    assert_eq!(result.mapped_addr, socket.socket.local_addr().unwrap());
+    assert!(!result.directly_reachable());
+}
+
+#[tokio::test]
+async fn test_probe_symmetric() {
+    let (primary, alternate) = probe_server().await;
+    let socket = SymmetricNat::bind(primary).await;
+    let local_addr = private_addr(socket.to_first.local_addr().unwrap());
+
+    let result = probe(&socket, local_addr, primary, alternate, TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(result.nat_type, NatType::Symmetric);
+    assert_eq!(result.mapped_addr, socket.to_first.local_addr().unwrap());
+    assert!(!result.directly_reachable());
+}
+
+#[tokio::test]
+async fn test_probe_without_answer_fails() {
+    // Bound but never answering
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let silent_addr = silent.local_addr().unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let local_addr = socket.local_addr().unwrap();
+
+    let result = probe(&socket, local_addr, silent_addr, silent_addr, TIMEOUT).await;
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_full_cone_with_preserved_port_is_not_reachable() {
+    let nat = NatProbe {
+        local_addr: "192.168.1.10:5000".parse().unwrap(),
+        mapped_addr: "203.0.113.7:5000".parse().unwrap(),
+        nat_type: NatType::FullCone,
+    };
+    assert!(!nat.directly_reachable());
+}
+
+#[test]
+fn test_nat_type_display() {
+    assert_eq!(NatType::Open.to_string(), "open (no NAT)");
+    assert_eq!(NatType::FullCone.to_string(), "full cone");
+    assert_eq!(NatType::Restricted.to_string(), "restricted");
+    assert_eq!(NatType::Symmetric.to_string(), "symmetric");
+}
@@ -0,0 +1,140 @@
+//! Integration tests for the offline outbox.
+
+mod common;
+
+use common::test_public_key;
+use parlance::network::messaging::TextMessage;
+use parlance::network::outbox::{Outbox, OutboxEntry};
+use std::time::Duration;
+
+const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+fn load_outbox(dir: &tempfile::TempDir) -> Outbox {
+    Outbox::load(dir.path().join("outbox.json"), WEEK, None).unwrap()
+}
+
+fn entry(to: &str, content: &str) -> OutboxEntry {
+    let msg = TextMessage::new("alice".to_string(), test_public_key(), content.to_string());
+    OutboxEntry::new(to.to_string(), test_public_key(), msg)
+}
+
+#[tokio::test]
+async fn test_queued_messages_survive_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let queued = entry("bob", "hello");
+
+    {
+        let outbox = load_outbox(&dir);
+        outbox.push(queued.clone()).await.unwrap();
+    }
+
+    let outbox = load_outbox(&dir);
+    let messages = outbox.list().await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].id(), queued.id());
+    assert_eq!(messages[0].recipient, queued.recipient);
+    assert_eq!(messages[0].message.content, "hello");
+}
+
+#[tokio::test]
+async fn test_claim_for_only_returns_recipient_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    let outbox = load_outbox(&dir);
+
+    let first = entry("bob", "one");
+    let mut second = entry("bob", "two");
+    second.recipient = first.recipient;
+    let other = entry("carol", "three");
+
+    outbox.push(first.clone()).await.unwrap();
+    outbox.push(other.clone()).await.unwrap();
+    outbox.push(second.clone()).await.unwrap();
+
+    let claimed = outbox.claim_for(&first.recipient).await;
+    let contents: Vec<_> = claimed.iter().map(|e| e.message.content.as_str()).collect();
+    assert_eq!(contents, ["one", "two"]);
+    assert_eq!(outbox.claim_for(&other.recipient).await.len(), 1);
+}
+
+#[tokio::test]
+async fn test_claimed_messages_stay_until_delivered() {
+    let dir = tempfile::tempdir().unwrap();
+    let outbox = load_outbox(&dir);
+    let queued = entry("bob", "hello");
+    outbox.push(queued.clone()).await.unwrap();
+
+    // In flight: kept on disk, not handed out twice, not cancellable
+    assert_eq!(outbox.claim_for(&queued.recipient).await.len(), 1);
+    assert!(outbox.claim_for(&queued.recipient).await.is_empty());
+    assert!(outbox
+        .cancel(&queued.id().to_string())
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(outbox.list().await.len(), 1);
+    assert_eq!(load_outbox(&dir).list().await.len(), 1);
+
+    // A failed attempt makes it pending again
+    outbox.release(&queued.recipient, queued.id()).await;
+    assert_eq!(outbox.claim_for(&queued.recipient).await.len(), 1);
+
+    outbox
+        .delivered(&queued.recipient, queued.id())
+        .await
+        .unwrap();
+    assert!(outbox.list().await.is_empty());
+    assert!(load_outbox(&dir).list().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_cancel_by_id_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let outbox = load_outbox(&dir);
+    let queued = entry("bob", "hello");
+    outbox.push(queued.clone()).await.unwrap();
+
+    assert!(outbox.cancel("not-an-id").await.unwrap().is_none());
+
+    let cancelled = outbox
+        .cancel(&queued.id().to_string())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(cancelled.id(), queued.id());
+    assert!(outbox.list().await.is_empty());
+    assert!(load_outbox(&dir).list().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_cancel_requires_unambiguous_id() {
+    let dir = tempfile::tempdir().unwrap();
+    let outbox = load_outbox(&dir);
+    outbox.push(entry("bob", "one")).await.unwrap();
+    outbox.push(entry("bob", "two")).await.unwrap();
+
+    assert!(outbox.cancel("").await.unwrap().is_none());
+    assert_eq!(outbox.list().await.len(), 2);
+}
+
+#[tokio::test]
+async fn test_expired_messages_are_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    let outbox = load_outbox(&dir);
+
+    let mut stale = entry("bob", "old");
+    stale.queued_at -= WEEK.as_secs() as i64 + 1;
+    let fresh = entry("bob", "new");
+    outbox.push(stale.clone()).await.unwrap();
+    outbox.push(fresh.clone()).await.unwrap();
+
+    // Expired messages are never delivered
+    let listed = outbox.list().await;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].id(), fresh.id());
+    assert!(outbox.claim_for(&stale.recipient).await.is_empty());
+
+    let expired = outbox.expire().await.unwrap();
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].id(), stale.id());
+    assert!(outbox.expire().await.unwrap().is_empty());
+}
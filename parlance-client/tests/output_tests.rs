@@ -0,0 +1,202 @@
+//! Tests for the output sinks and the events the app renders.
+
+use parlance::app::output::{
+    Event, JsonOutput, MemoryOutput, OutboxRow, Output, TerminalOutput, Tone,
+};
+use parlance::app::{App, AppConfig};
+use parlance::core::config::{Config, DiscoveryMode};
+use parlance::core::identity::Identity;
+use parlance::network::messaging::TextMessage;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// A writer whose output the test can read back
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_memory_output_records_events() {
+    let output = MemoryOutput::new();
+    output.info("hello");
+    output.error("oops");
+
+    let events = output.take();
+    assert!(matches!(&events[0], Event::Info { message } if message == "hello"));
+    assert!(matches!(&events[1], Event::Error { message } if message == "oops"));
+    assert!(output.events().is_empty());
+}
+
+#[test]
+fn test_json_output_writes_one_object_per_line() {
+    let buffer = SharedBuffer::default();
+    let output = JsonOutput::new(Box::new(buffer.clone()));
+
+    output.emit(Event::PeerJoined {
+        nickname: "alice".to_string(),
+        addr: "127.0.0.1:5000".to_string(),
+    });
+    output.success("done");
+
+    let lines: Vec<serde_json::Value> = buffer
+        .contents()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            serde_json::json!({
+                "event": "peer_joined",
+                "nickname": "alice",
+                "addr": "127.0.0.1:5000",
+            }),
+            serde_json::json!({"event": "success", "message": "done"}),
+        ]
+    );
+}
+
+#[test]
+fn test_terminal_render() {
+    let line = TerminalOutput::render(&Event::FileOffer {
+        id: "1234abcd".to_string(),
+        from: "alice".to_string(),
+        name: "photo.jpg".to_string(),
+        size: 1536,
+    });
+    assert_eq!(line.tone, Tone::Notice);
+    assert_eq!(
+        line.text,
+        "* alice offers photo.jpg (1.5 KiB). Type /accept 1234abcd to receive it"
+    );
+
+    let line = TerminalOutput::render(&Event::Outbox {
+        messages: vec![OutboxRow {
+            id: "deadbeef".to_string(),
+            to: "bob".to_string(),
+            queued_at: "2024-01-01 12:00".to_string(),
+            content: "hi".to_string(),
+        }],
+    });
+    assert!(line
+        .text
+        .contains("  • deadbeef to bob (queued 2024-01-01 12:00): hi"));
+}
+
+#[test]
+fn test_relayed_messages_are_marked() {
+    let message = TextMessage::new(
+        "alice".to_string(),
+        Identity::generate().public_key(),
+        "hi".to_string(),
+    );
+    let event = Event::Message {
+        message,
+        group: None,
+        verified: false,
+        relayed: true,
+    };
+    assert!(TerminalOutput::render(&event)
+        .text
+        .ends_with("alice (relayed): hi"));
+
+    let buffer = SharedBuffer::default();
+    JsonOutput::new(Box::new(buffer.clone())).emit(event);
+    let line: serde_json::Value = serde_json::from_str(buffer.contents().trim()).unwrap();
+    assert_eq!(line["relayed"], true);
+}
+
+#[test]
+fn test_notices_are_marked() {
+    assert!(Event::PeerLeft {
+        nickname: "alice".to_string()
+    }
+    .is_notice());
+    assert!(!Event::Info {
+        message: "hello".to_string()
+    }
+    .is_notice());
+}
+
+#[test]
+fn test_terminal_output_sends_lines_to_screen() {
+    let output = TerminalOutput::new();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    assert!(output.attach_screen(tx));
+
+    output.info("one\ntwo");
+    assert_eq!(rx.try_recv().unwrap().text, "one");
+    assert_eq!(rx.try_recv().unwrap().text, "two");
+
+    output.detach_screen();
+    output.info("stdout");
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_app_renders_command_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::default();
+    config.storage.data_dir = Some(dir.path().to_path_buf());
+    config.network.mode = DiscoveryMode::Internet;
+    config.network.bootstrap_server = "ws://127.0.0.1:9".to_string();
+
+    let output = MemoryOutput::new();
+    let app = App::new(
+        AppConfig::new("alice".to_string(), Identity::generate()),
+        config,
+        Arc::new(output.clone()),
+    )
+    .unwrap();
+
+    let (line_tx, line_rx) = mpsc::unbounded_channel();
+    for line in ["/outbox", "/netinfo", "/frobnicate", "/quit"] {
+        line_tx.send(line.to_string()).unwrap();
+    }
+    tokio::time::timeout(Duration::from_secs(10), app.run_with_input(line_rx))
+        .await
+        .expect("app did not quit")
+        .unwrap();
+
+    let events = output.events();
+    assert!(matches!(
+        &events[0],
+        Event::Welcome { nickname, .. } if nickname == "alice"
+    ));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, Event::Outbox { messages } if messages.is_empty())));
+    assert!(events.iter().any(|e| matches!(
+        e,
+        Event::NetInfo {
+            public_addr: None,
+            mapped_addr: None,
+            ..
+        }
+    )));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, Event::Error { message } if message.starts_with("Unknown command"))));
+    assert!(matches!(
+        events.last(),
+        Some(Event::Info { message }) if message == "Goodbye!"
+    ));
+}
@@ -2,8 +2,8 @@
 
 mod common;
 
-use common::test_addr;
-use parlance::core::peer::{Peer, PeerRegistry};
+use common::{test_addr, test_addr_with_ip, test_public_key};
+use parlance::core::peer::{DiscoverySource, Peer, PeerEvent, PeerRegistry};
 use std::time::Duration;
 
 const TEST_TIMEOUT: Duration = Duration::from_secs(15);
@@ -12,7 +12,7 @@ const TEST_TIMEOUT: Duration = Duration::from_secs(15);
 async fn test_peer_registry_upsert() {
     let registry = PeerRegistry::new();
     let addr = test_addr(8080);
-    let peer = Peer::new("Alice".to_string(), addr);
+    let peer = Peer::new("Alice".to_string(), addr, test_public_key());
 
     registry.upsert(peer.clone()).await;
 
@@ -26,10 +26,10 @@ async fn test_peer_registry_multiple_peers() {
     let registry = PeerRegistry::new();
 
     let addr1 = test_addr(8080);
-    let peer1 = Peer::new("Alice".to_string(), addr1);
+    let peer1 = Peer::new("Alice".to_string(), addr1, test_public_key());
 
     let addr2 = test_addr(8081);
-    let peer2 = Peer::new("Bob".to_string(), addr2);
+    let peer2 = Peer::new("Bob".to_string(), addr2, test_public_key());
 
     registry.upsert(peer1).await;
     registry.upsert(peer2).await;
@@ -42,16 +42,30 @@ async fn test_peer_registry_multiple_peers() {
 async fn test_peer_registry_update_existing() {
     let registry = PeerRegistry::new();
     let addr = test_addr(8080);
+    let key = test_public_key();
 
-    let peer1 = Peer::new("Alice".to_string(), addr);
+    let peer1 = Peer::new("Alice".to_string(), addr, key);
     registry.upsert(peer1).await;
 
-    let peer2 = Peer::new("AliceUpdated".to_string(), addr);
+    let peer2 = Peer::new("AliceUpdated".to_string(), test_addr(9090), key);
     registry.upsert(peer2).await;
 
     let peers = registry.get_all().await;
     assert_eq!(peers.len(), 1);
     assert_eq!(peers[0].nickname, "AliceUpdated");
+    assert_eq!(peers[0].addr, test_addr(9090));
+}
+
+#[tokio::test]
+async fn test_peer_id_derived_from_key() {
+    let key = test_public_key();
+    let peer1 = Peer::new("Alice".to_string(), test_addr(8080), key);
+    let peer2 = Peer::new("Alice".to_string(), test_addr(9090), key);
+    let peer3 = Peer::new("Alice".to_string(), test_addr(8080), test_public_key());
+
+    assert_eq!(peer1.id, peer2.id);
+    assert_ne!(peer1.id, peer3.id);
+    assert_eq!(peer1.id, key.peer_id());
 }
 
 #[tokio::test]
@@ -59,7 +73,7 @@ async fn test_peer_timeout() {
     let registry = PeerRegistry::new();
     let addr = test_addr(8080);
 
-    let mut peer = Peer::new("Alice".to_string(), addr);
+    let mut peer = Peer::new("Alice".to_string(), addr, test_public_key());
 
     peer.last_seen = std::time::Instant::now() - TEST_TIMEOUT - Duration::from_secs(1);
 
@@ -78,7 +92,7 @@ async fn test_peer_timeout() {
 async fn test_peer_not_timed_out() {
     let registry = PeerRegistry::new();
     let addr = test_addr(8080);
-    let peer = Peer::new("Alice".to_string(), addr);
+    let peer = Peer::new("Alice".to_string(), addr, test_public_key());
 
     registry.upsert(peer).await;
 
@@ -93,7 +107,7 @@ async fn test_peer_not_timed_out() {
 async fn test_peer_get_by_id() {
     let registry = PeerRegistry::new();
     let addr = test_addr(8080);
-    let peer = Peer::new("Alice".to_string(), addr);
+    let peer = Peer::new("Alice".to_string(), addr, test_public_key());
     let peer_id = peer.id;
 
     registry.upsert(peer).await;
@@ -107,7 +121,7 @@ async fn test_peer_get_by_id() {
 async fn test_peer_remove() {
     let registry = PeerRegistry::new();
     let addr = test_addr(8080);
-    let peer = Peer::new("Alice".to_string(), addr);
+    let peer = Peer::new("Alice".to_string(), addr, test_public_key());
     let peer_id = peer.id;
 
     registry.upsert(peer).await;
@@ -116,3 +130,155 @@ async fn test_peer_remove() {
     registry.remove(&peer_id).await;
     assert_eq!(registry.count().await, 0);
 }
+
+#[tokio::test]
+async fn test_peer_events_joined_and_address_changed() {
+    let registry = PeerRegistry::new();
+    let mut events = registry.subscribe();
+    let key = test_public_key();
+
+    registry
+        .upsert(Peer::new("Alice".to_string(), test_addr(8080), key))
+        .await;
+    registry
+        .upsert(Peer::new("Alice".to_string(), test_addr(8080), key))
+        .await;
+    registry
+        .upsert(Peer::new("Alice".to_string(), test_addr(9090), key))
+        .await;
+
+    match events.try_recv().unwrap() {
+        PeerEvent::PeerJoined(peer) => assert_eq!(peer.nickname, "Alice"),
+        other => panic!("Unexpected event: {:?}", other),
+    }
+    match events.try_recv().unwrap() {
+        PeerEvent::PeerAddressChanged { peer, old_addr } => {
+            assert_eq!(old_addr, test_addr(8080));
+            assert_eq!(peer.addr, test_addr(9090));
+        }
+        other => panic!("Unexpected event: {:?}", other),
+    }
+    // A refresh from the same address is not an event
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_peer_events_left() {
+    let registry = PeerRegistry::new();
+    let peer = Peer::new("Alice".to_string(), test_addr(8080), test_public_key());
+    registry.upsert(peer.clone()).await;
+
+    let mut events = registry.subscribe();
+
+    assert!(registry.remove(&peer.id).await.is_some());
+    assert!(registry.remove(&peer.id).await.is_none());
+
+    match events.try_recv().unwrap() {
+        PeerEvent::PeerLeft(left) => assert_eq!(left.id, peer.id),
+        other => panic!("Unexpected event: {:?}", other),
+    }
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_peer_events_timed_out() {
+    let registry = PeerRegistry::new();
+    let mut events = registry.subscribe();
+    registry
+        .upsert(Peer::new(
+            "Alice".to_string(),
+            test_addr(8080),
+            test_public_key(),
+        ))
+        .await;
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    registry.remove_timed_out(Duration::from_millis(10)).await;
+
+    assert!(matches!(
+        events.try_recv().unwrap(),
+        PeerEvent::PeerJoined(_)
+    ));
+    match events.try_recv().unwrap() {
+        PeerEvent::PeerTimedOut(peer) => assert_eq!(peer.nickname, "Alice"),
+        other => panic!("Unexpected event: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_peer_events_reach_every_subscriber() {
+    let registry = PeerRegistry::new();
+    let mut first = registry.subscribe();
+    let mut second = registry.subscribe();
+    drop(registry.subscribe());
+
+    registry
+        .upsert(Peer::new(
+            "Alice".to_string(),
+            test_addr(8080),
+            test_public_key(),
+        ))
+        .await;
+
+    assert!(matches!(first.try_recv(), Ok(PeerEvent::PeerJoined(_))));
+    assert!(matches!(second.try_recv(), Ok(PeerEvent::PeerJoined(_))));
+}
+
+#[tokio::test]
+async fn test_peer_sources_merged_and_lan_preferred() {
+    let registry = PeerRegistry::new();
+    let key = test_public_key();
+    let lan = test_addr_with_ip([192, 168, 1, 10], 5000);
+    let public = test_addr_with_ip([203, 0, 113, 10], 5000);
+
+    registry
+        .upsert(Peer::new("Alice".to_string(), public, key).via(DiscoverySource::Bootstrap))
+        .await;
+    registry
+        .upsert(Peer::new("Alice".to_string(), lan, key).via(DiscoverySource::Multicast))
+        .await;
+    // A later bootstrap refresh does not override the LAN address
+    registry
+        .upsert(Peer::new("Alice".to_string(), public, key).via(DiscoverySource::Bootstrap))
+        .await;
+
+    let peers = registry.get_all().await;
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].addr, lan);
+    assert_eq!(
+        peers[0].sources(),
+        vec![DiscoverySource::Multicast, DiscoverySource::Bootstrap]
+    );
+}
+
+#[tokio::test]
+async fn test_peer_falls_back_when_source_expires() {
+    let registry = PeerRegistry::new();
+    let key = test_public_key();
+    let lan = test_addr_with_ip([192, 168, 1, 10], 5000);
+    let public = test_addr_with_ip([203, 0, 113, 10], 5000);
+
+    registry
+        .upsert(Peer::new("Alice".to_string(), lan, key).via(DiscoverySource::Multicast))
+        .await;
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    registry
+        .upsert(Peer::new("Alice".to_string(), public, key).via(DiscoverySource::Bootstrap))
+        .await;
+
+    let mut events = registry.subscribe();
+    let removed = registry.remove_timed_out(Duration::from_millis(40)).await;
+    assert!(removed.is_empty());
+
+    let peers = registry.get_all().await;
+    assert_eq!(peers[0].addr, public);
+    assert_eq!(peers[0].sources(), vec![DiscoverySource::Bootstrap]);
+
+    match events.try_recv().unwrap() {
+        PeerEvent::PeerAddressChanged { peer, old_addr } => {
+            assert_eq!(old_addr, lan);
+            assert_eq!(peer.addr, public);
+        }
+        other => panic!("Unexpected event: {:?}", other),
+    }
+}
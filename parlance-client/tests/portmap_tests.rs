@@ -0,0 +1,327 @@
+//! Tests for automatic port mapping.
+//!
+//! Fake routers on loopback stand in for a NAT-PMP gateway and a UPnP
+//! Internet Gateway Device, recording the mappings they are asked for.
+
+use parlance::network::portmap::{MappingProtocol, PortMapConfig, PortMapper};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::oneshot;
+
+const EXTERNAL_IP: Ipv4Addr = Ipv4Addr::new(203, 0, 113, 9);
+
+/// Requests a fake router received
+type Log = Arc<Mutex<Vec<String>>>;
+
+fn config(nat_pmp: Option<SocketAddr>, ssdp: SocketAddr) -> PortMapConfig {
+    PortMapConfig {
+        nat_pmp,
+        ssdp,
+        lifetime: Duration::from_secs(3600),
+        timeout: Duration::from_millis(500),
+    }
+}
+
+/// A bound socket that never answers
+async fn silent() -> UdpSocket {
+    UdpSocket::bind("127.0.0.1:0").await.unwrap()
+}
+
+/// Start a NAT-PMP gateway that grants every request as asked
+///
+/// Mapping requests are logged as `map <internal> <external> <lifetime>`.
+async fn nat_pmp_gateway() -> (SocketAddr, Log) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let log = Log::default();
+
+    tokio::spawn({
+        let log = log.clone();
+        async move {
+            let mut buf = [0u8; 64];
+            loop {
+                let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+                let mut response = vec![0, buf[1] | 0x80, 0, 0, 0, 0, 0, 1];
+                match (len, buf[1]) {
+                    (2, 0) => response.extend_from_slice(&EXTERNAL_IP.octets()),
+                    (12, 2) => {
+                        let internal = u16::from_be_bytes([buf[4], buf[5]]);
+                        let external = u16::from_be_bytes([buf[6], buf[7]]);
+                        let lifetime = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
+                        log.lock()
+                            .unwrap()
+                            .push(format!("map {} {} {}", internal, external, lifetime));
+                        response.extend_from_slice(&buf[4..12]);
+                    }
+                    _ => continue,
+                }
+                socket.send_to(&response, from).await.unwrap();
+            }
+        }
+    });
+
+    (addr, log)
+}
+
+const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+        <controlURL>/ctl/IPConn</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+
+/// Start a UPnP gateway: an SSDP responder and its HTTP server
+///
+/// SOAP calls are logged by action name, followed by the external port for
+/// mapping changes. With `permanent_only`, mappings that expire are
+/// refused with error 725.
+async fn upnp_gateway(permanent_only: bool) -> (SocketAddr, Log) {
+    let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http_addr = http.local_addr().unwrap();
+    let ssdp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let ssdp_addr = ssdp.local_addr().unwrap();
+    let log = Log::default();
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (len, from) = ssdp.recv_from(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..len]);
+            if !request.starts_with("M-SEARCH") || !request.contains("InternetGatewayDevice") {
+                continue;
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\n\
+                 ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                 Location: http://{}/rootDesc.xml\r\n\r\n",
+                http_addr
+            );
+            ssdp.send_to(response.as_bytes(), from).await.unwrap();
+        }
+    });
+
+    tokio::spawn({
+        let log = log.clone();
+        async move {
+            loop {
+                let (mut stream, _) = http.accept().await.unwrap();
+                let log = log.clone();
+                tokio::spawn(async move {
+                    let request = read_request(&mut stream).await;
+                    let (status, body) = answer(&request, &log, permanent_only);
+                    // Send the body chunked, like many routers do
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Type: text/xml\r\n\
+                         Transfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        }
+    });
+
+    (ssdp_addr, log)
+}
+
+/// Read an HTTP request with its body
+async fn read_request(stream: &mut tokio::net::TcpStream) -> String {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let len = stream.read(&mut buf).await.unwrap();
+        request.extend_from_slice(&buf[..len]);
+        let text = String::from_utf8_lossy(&request).to_string();
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .map_or(0, |length| length.parse().unwrap());
+            if body.len() >= length {
+                return text;
+            }
+        }
+        if len == 0 {
+            return text;
+        }
+    }
+}
+
+/// The fake gateway's answer to an HTTP request
+fn answer(request: &str, log: &Log, permanent_only: bool) -> (&'static str, String) {
+    if request.starts_with("GET /rootDesc.xml") {
+        return ("200 OK", DESCRIPTION.to_string());
+    }
+    assert!(request.starts_with("POST /ctl/IPConn"), "{}", request);
+
+    let tag = |name: &str| {
+        let start = request.find(&format!("<{}>", name))? + name.len() + 2;
+        let end = request[start..].find('<')? + start;
+        Some(request[start..end].to_string())
+    };
+    let action = [
+        "AddPortMapping",
+        "DeletePortMapping",
+        "GetExternalIPAddress",
+    ]
+    .into_iter()
+    .find(|action| request.contains(&format!("#{}\"", action)))
+    .unwrap();
+
+    if action == "AddPortMapping" && permanent_only && tag("NewLeaseDuration").unwrap() != "0" {
+        return (
+            "500 Internal Server Error",
+            "<s:Envelope><s:Body><s:Fault><detail><UPnPError>\
+             <errorCode>725</errorCode>\
+             <errorDescription>OnlyPermanentLeasesSupported</errorDescription>\
+             </UPnPError></detail></s:Fault></s:Body></s:Envelope>"
+                .to_string(),
+        );
+    }
+
+    let entry = match action {
+        "GetExternalIPAddress" => action.to_string(),
+        _ => format!("{} {}", action, tag("NewExternalPort").unwrap()),
+    };
+    if action == "AddPortMapping" {
+        assert_eq!(tag("NewInternalClient").unwrap(), "127.0.0.1");
+        assert_eq!(tag("NewProtocol").unwrap(), "TCP");
+    }
+    log.lock().unwrap().push(entry);
+
+    let body = format!(
+        "<s:Envelope><s:Body><u:{action}Response xmlns:u=\"urn:schemas-upnp-org:service:WANIPConnection:1\">\
+         <NewExternalIPAddress>{ip}</NewExternalIPAddress>\
+         </u:{action}Response></s:Body></s:Envelope>",
+        action = action,
+        ip = EXTERNAL_IP,
+    );
+    ("200 OK", body)
+}
+
+#[tokio::test]
+async fn test_nat_pmp_mapping() {
+    let (gateway, log) = nat_pmp_gateway().await;
+    let ssdp = silent().await;
+    let mut mapper = PortMapper::new(config(Some(gateway), ssdp.local_addr().unwrap()), 4500);
+    let subscription = mapper.subscribe();
+
+    let mapping = mapper.map().await.unwrap();
+    assert_eq!(mapping.protocol, MappingProtocol::NatPmp);
+    assert_eq!(
+        mapping.external_addr,
+        SocketAddr::new(IpAddr::V4(EXTERNAL_IP), 4500)
+    );
+    assert_eq!(mapping.lifetime, Duration::from_secs(3600));
+    assert_eq!(*subscription.borrow(), Some(mapping));
+
+    mapper.unmap().await.unwrap();
+    assert_eq!(*subscription.borrow(), None);
+    assert_eq!(*log.lock().unwrap(), ["map 4500 4500 3600", "map 4500 0 0"]);
+}
+
+#[tokio::test]
+async fn test_upnp_mapping_when_nat_pmp_is_missing() {
+    let (ssdp, log) = upnp_gateway(false).await;
+    let nat_pmp = silent().await;
+    let mut mapper = PortMapper::new(config(Some(nat_pmp.local_addr().unwrap()), ssdp), 4501);
+
+    let mapping = mapper.map().await.unwrap();
+    assert_eq!(mapping.protocol, MappingProtocol::Upnp);
+    assert_eq!(
+        mapping.external_addr,
+        SocketAddr::new(IpAddr::V4(EXTERNAL_IP), 4501)
+    );
+    assert_eq!(mapping.lifetime, Duration::from_secs(3600));
+
+    mapper.unmap().await.unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "AddPortMapping 4501",
+            "GetExternalIPAddress",
+            "DeletePortMapping 4501"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_upnp_falls_back_to_permanent_mapping() {
+    let (ssdp, log) = upnp_gateway(true).await;
+    let mut mapper = PortMapper::new(config(None, ssdp), 4502);
+
+    let mapping = mapper.map().await.unwrap();
+    assert_eq!(mapping.lifetime, Duration::ZERO);
+    assert_eq!(
+        *log.lock().unwrap(),
+        ["AddPortMapping 4502", "GetExternalIPAddress"]
+    );
+}
+
+#[tokio::test]
+async fn test_mapping_fails_without_gateway() {
+    let nat_pmp = silent().await;
+    let ssdp = silent().await;
+    let mut mapper = PortMapper::new(
+        config(
+            Some(nat_pmp.local_addr().unwrap()),
+            ssdp.local_addr().unwrap(),
+        ),
+        4503,
+    );
+
+    assert!(mapper.map().await.is_err());
+    assert_eq!(*mapper.subscribe().borrow(), None);
+    // Nothing was mapped, so there is nothing to remove
+    mapper.unmap().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_run_renews_and_removes_mapping() {
+    let (gateway, log) = nat_pmp_gateway().await;
+    let ssdp = silent().await;
+    let mut config = config(Some(gateway), ssdp.local_addr().unwrap());
+    // Renewed every second
+    config.lifetime = Duration::from_secs(2);
+    let mapper = PortMapper::new(config, 4504);
+    let mut subscription = mapper.subscribe();
+
+    let (stop_tx, stop_rx) = oneshot::channel();
+    let task = tokio::spawn(mapper.run(stop_rx));
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while log.lock().unwrap().len() < 2 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("mapping was not renewed");
+    assert!(subscription.borrow_and_update().is_some());
+
+    stop_tx.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .expect("mapper did not stop")
+        .unwrap();
+
+    assert!(subscription.borrow().is_none());
+    let log = log.lock().unwrap();
+    assert_eq!(log[0], "map 4504 4504 2");
+    assert_eq!(log[1], "map 4504 4504 2");
+    assert_eq!(log.last().unwrap(), "map 4504 0 0");
+}
@@ -0,0 +1,73 @@
+//! Integration tests for protocol version negotiation.
+
+use parlance::core::error::ParlanceError;
+use parlance::network::protocol::{
+    Hello, FEATURE_FILE_TRANSFER, FEATURE_GROUPS, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+
+fn hello(min: u16, version: u16, features: &[&str]) -> Hello {
+    Hello {
+        protocol_version: version,
+        min_protocol_version: min,
+        features: features.iter().map(|f| f.to_string()).collect(),
+    }
+}
+
+#[test]
+fn test_current_hello_advertises_features() {
+    let current = Hello::current();
+    assert_eq!(current.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(current.min_protocol_version, MIN_PROTOCOL_VERSION);
+    assert!(current.supports(FEATURE_GROUPS));
+    assert!(current.supports(FEATURE_FILE_TRANSFER));
+}
+
+#[test]
+fn test_negotiate_with_same_version() {
+    let negotiated = Hello::current().negotiate(&Hello::current()).unwrap();
+    assert_eq!(negotiated.version, PROTOCOL_VERSION);
+    assert!(negotiated.supports(FEATURE_GROUPS));
+}
+
+#[test]
+fn test_negotiate_picks_highest_common_version() {
+    let local = hello(1, 3, &[]);
+    let remote = hello(2, 5, &[]);
+    assert_eq!(local.negotiate(&remote).unwrap().version, 3);
+    assert_eq!(remote.negotiate(&local).unwrap().version, 3);
+}
+
+#[test]
+fn test_negotiate_keeps_only_shared_features() {
+    let local = hello(1, 1, &[FEATURE_GROUPS, FEATURE_FILE_TRANSFER]);
+    let remote = hello(1, 1, &[FEATURE_GROUPS, "typing"]);
+
+    let negotiated = local.negotiate(&remote).unwrap();
+    assert_eq!(negotiated.features, vec![FEATURE_GROUPS.to_string()]);
+    assert!(!negotiated.supports(FEATURE_FILE_TRANSFER));
+    assert!(!negotiated.supports("typing"));
+}
+
+#[test]
+fn test_negotiate_rejects_disjoint_versions() {
+    let local = hello(1, 2, &[]);
+    let remote = hello(3, 4, &[]);
+
+    assert!(matches!(
+        local.negotiate(&remote),
+        Err(ParlanceError::IncompatibleProtocol(_))
+    ));
+    assert!(matches!(
+        remote.negotiate(&local),
+        Err(ParlanceError::IncompatibleProtocol(_))
+    ));
+}
+
+#[test]
+fn test_unversioned_peer_is_incompatible() {
+    let legacy: Hello = serde_json::from_str("{}").unwrap();
+    assert_eq!(legacy.protocol_version, 0);
+
+    let err = Hello::current().negotiate(&legacy).unwrap_err();
+    assert!(err.to_string().contains("unversioned"));
+}
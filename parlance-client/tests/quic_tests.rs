@@ -0,0 +1,335 @@
+//! Integration tests for the QUIC transport.
+
+mod common;
+
+use common::test_addr;
+use parlance::core::config::{Config, Transport};
+use parlance::core::identity::Identity;
+use parlance::core::peer::{Peer, PeerRegistry};
+use parlance::network::connection::{ConnectionState, PoolConfig};
+use parlance::network::messaging::{
+    DeliveryConfig, MessageEvent, MessagingConfig, MessagingService,
+};
+use parlance::network::protocol::FEATURE_QUIC;
+use parlance::network::quic::QuicEndpoint;
+use parlance::network::transfer::Transfers;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+struct Node {
+    identity: Identity,
+    registry: PeerRegistry,
+    service: Arc<MessagingService>,
+    port: u16,
+    events: mpsc::UnboundedReceiver<MessageEvent>,
+}
+
+async fn start_node(nickname: &str, transport: Transport) -> Node {
+    start_node_with(nickname, transport, None).await
+}
+
+async fn start_node_with(
+    nickname: &str,
+    transport: Transport,
+    transfers: Option<Transfers>,
+) -> Node {
+    let identity = Identity::generate();
+    let registry = PeerRegistry::new();
+    let (event_tx, events) = mpsc::unbounded_channel();
+    let config = MessagingConfig {
+        nickname: nickname.to_string(),
+        identity: identity.clone(),
+        tcp_port: 0,
+        registry: registry.clone(),
+        pool: PoolConfig::default(),
+        delivery: DeliveryConfig::default(),
+        outbox: None,
+        history: None,
+        groups: None,
+        transfers,
+        transport,
+    };
+
+    let service = Arc::new(MessagingService::new(config, event_tx).await.unwrap());
+    let port = service.local_addr().unwrap().port();
+
+    let runner = service.clone();
+    tokio::spawn(async move { runner.run().await });
+
+    Node {
+        identity,
+        registry,
+        service,
+        port,
+        events,
+    }
+}
+
+/// Tell `node` about `peer`, which advertises `features`
+async fn introduce(node: &Node, nickname: &str, peer: &Node, features: Vec<String>) {
+    node.registry
+        .upsert(
+            Peer::new(
+                nickname.to_string(),
+                test_addr(peer.port),
+                peer.identity.public_key(),
+            )
+            .with_features(features),
+        )
+        .await;
+}
+
+async fn expect_message(events: &mut mpsc::UnboundedReceiver<MessageEvent>) -> String {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(15), events.recv())
+            .await
+            .expect("timed out waiting for message")
+            .unwrap();
+        if let MessageEvent::Received { msg, .. } = event {
+            return msg.content;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_endpoint_round_trip() {
+    let server = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let client = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let accepting = tokio::spawn(async move {
+        let incoming = server.accept().await.unwrap();
+        let addr = incoming.peer_addr();
+        (incoming.stream().await.unwrap(), addr, server)
+    });
+
+    // The stream only reaches the server once the client writes to it
+    let mut outbound = client.connect(server_addr).await.unwrap();
+    outbound.write_all(b"ping").await.unwrap();
+    outbound.flush().await.unwrap();
+
+    let (mut inbound, addr, _server) = accepting.await.unwrap();
+    assert_eq!(addr, client.local_addr().unwrap());
+    assert_eq!(inbound.peer_addr(), addr);
+
+    let mut buf = [0u8; 4];
+    inbound.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    inbound.write_all(b"pong").await.unwrap();
+    inbound.flush().await.unwrap();
+    outbound.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+}
+
+#[tokio::test]
+async fn test_connection_opens_further_streams() {
+    let server = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let client = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    let accepting = tokio::spawn(async move {
+        let first = server.accept().await.unwrap().stream().await.unwrap();
+        let mut second = first.connection().accept_stream().await.unwrap();
+        let mut buf = [0u8; 4];
+        second.read_exact(&mut buf).await.unwrap();
+        second.write_all(b"pong").await.unwrap();
+        second.flush().await.unwrap();
+        (buf, first, server)
+    });
+
+    let mut first = client.connect(server_addr).await.unwrap();
+    first.write_all(b"main").await.unwrap();
+    first.flush().await.unwrap();
+    let mut second = first.connection().open_stream().await.unwrap();
+    second.write_all(b"ping").await.unwrap();
+    second.flush().await.unwrap();
+
+    let mut buf = [0u8; 4];
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+    let (received, _first, _server) = accepting.await.unwrap();
+    assert_eq!(&received, b"ping");
+}
+
+#[tokio::test]
+async fn test_accept_ends_when_closed() {
+    let endpoint = Arc::new(QuicEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap());
+    let accepting = endpoint.clone();
+    let task = tokio::spawn(async move { accepting.accept().await.is_none() });
+
+    endpoint.close();
+    let ended = tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .expect("accept did not return")
+        .unwrap();
+    assert!(ended);
+}
+
+#[tokio::test]
+async fn test_hello_advertises_quic_only_when_listening() {
+    let tcp = start_node("tcp", Transport::Tcp).await;
+    let quic = start_node("quic", Transport::Quic).await;
+
+    assert!(!tcp.service.hello().supports(FEATURE_QUIC));
+    assert!(quic.service.hello().supports(FEATURE_QUIC));
+}
+
+#[tokio::test]
+async fn test_messages_travel_over_quic_when_advertised() {
+    let alice = start_node("alice", Transport::Quic).await;
+    let mut bob = start_node("bob", Transport::Quic).await;
+    introduce(&alice, "bob", &bob, bob.service.hello().features).await;
+
+    alice
+        .service
+        .send_message("bob", "over quic".to_string())
+        .await
+        .unwrap();
+    assert_eq!(expect_message(&mut bob.events).await, "over quic");
+
+    let alice_pool = alice.service.connections();
+    let bob_pool = bob.service.connections();
+    assert!(alice_pool.uses_quic(&bob.identity.public_key()));
+    assert!(bob_pool.uses_quic(&alice.identity.public_key()));
+    assert_eq!(
+        alice_pool.state(&bob.identity.public_key()),
+        ConnectionState::Connected
+    );
+}
+
+#[tokio::test]
+async fn test_tcp_used_when_peer_does_not_advertise_quic() {
+    let alice = start_node("alice", Transport::Quic).await;
+    let mut bob = start_node("bob", Transport::Quic).await;
+    introduce(&alice, "bob", &bob, Vec::new()).await;
+
+    alice
+        .service
+        .send_message("bob", "over tcp".to_string())
+        .await
+        .unwrap();
+    assert_eq!(expect_message(&mut bob.events).await, "over tcp");
+
+    let alice_pool = alice.service.connections();
+    assert!(!alice_pool.uses_quic(&bob.identity.public_key()));
+    assert_eq!(
+        alice_pool.state(&bob.identity.public_key()),
+        ConnectionState::Connected
+    );
+}
+
+#[tokio::test]
+async fn test_falls_back_to_tcp_when_quic_fails() {
+    let alice = start_node("alice", Transport::Quic).await;
+    // Bob claims QUIC support but only listens on TCP
+    let mut bob = start_node("bob", Transport::Tcp).await;
+    introduce(&alice, "bob", &bob, vec![FEATURE_QUIC.to_string()]).await;
+
+    alice
+        .service
+        .send_message("bob", "fallback".to_string())
+        .await
+        .unwrap();
+    assert_eq!(expect_message(&mut bob.events).await, "fallback");
+    assert!(!alice
+        .service
+        .connections()
+        .uses_quic(&bob.identity.public_key()));
+}
+
+#[tokio::test]
+async fn test_file_transfer_over_quic() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut alice = start_node_with(
+        "alice",
+        Transport::Quic,
+        Some(Transfers::new(dir.path().join("alice"))),
+    )
+    .await;
+    let mut bob = start_node_with(
+        "bob",
+        Transport::Quic,
+        Some(Transfers::new(dir.path().join("bob"))),
+    )
+    .await;
+    introduce(&alice, "bob", &bob, bob.service.hello().features).await;
+    introduce(&bob, "alice", &alice, alice.service.hello().features).await;
+
+    let contents: Vec<u8> = (0..500_000u32).map(|i| (i % 251) as u8).collect();
+    let path = dir.path().join("archive.tar");
+    std::fs::write(&path, &contents).unwrap();
+
+    let offer = alice.service.send_file("bob", &path).await.unwrap();
+    assert!(alice
+        .service
+        .connections()
+        .uses_quic(&bob.identity.public_key()));
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(15), bob.events.recv())
+            .await
+            .expect("timed out waiting for the offer")
+            .unwrap();
+        if matches!(event, MessageEvent::FileOffered(_)) {
+            break;
+        }
+    }
+    bob.service
+        .accept_file(&offer.id.to_string())
+        .await
+        .unwrap();
+
+    // Chat keeps flowing on the main stream while chunks are sent
+    alice
+        .service
+        .send_message("bob", "during the transfer".to_string())
+        .await
+        .unwrap();
+
+    let mut saved = None;
+    let mut message = None;
+    while saved.is_none() || message.is_none() {
+        let event = tokio::time::timeout(Duration::from_secs(15), bob.events.recv())
+            .await
+            .expect("timed out waiting for the transfer")
+            .unwrap();
+        match event {
+            MessageEvent::Received { msg, .. } => message = Some(msg.content),
+            MessageEvent::TransferComplete {
+                path: Some(path), ..
+            } => saved = Some(path),
+            MessageEvent::TransferFailed { .. } => panic!("Unexpected event: {:?}", event),
+            _ => {}
+        }
+    }
+    assert_eq!(std::fs::read(saved.unwrap()).unwrap(), contents);
+    assert_eq!(message.as_deref(), Some("during the transfer"));
+
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(15), alice.events.recv())
+            .await
+            .expect("timed out waiting for the sender")
+            .unwrap();
+        match event {
+            MessageEvent::TransferComplete { .. } => break,
+            MessageEvent::TransferFailed { .. } => panic!("Unexpected event: {:?}", event),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_transport_setting() {
+    assert_eq!(Config::default().network.transport, Transport::Tcp);
+    assert_eq!("QUIC".parse::<Transport>(), Ok(Transport::Quic));
+    assert!("sctp".parse::<Transport>().is_err());
+    assert_eq!(Transport::Quic.to_string(), "quic");
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("parlance.toml");
+    std::fs::write(&path, "[network]\ntransport = \"quic\"\n").unwrap();
+    let config = Config::from_file(&path).unwrap();
+    assert_eq!(config.network.transport, Transport::Quic);
+}
@@ -0,0 +1,122 @@
+//! Tests for streams relayed through the bootstrap server.
+//!
+//! The server only forwards chunks between two peers' circuits, so these
+//! tests stand in for it by wiring each stream's outgoing chunks straight
+//! to the other stream.
+
+use parlance::core::identity::Identity;
+use parlance::network::connection::{ConnectionManager, ConnectionState, PoolConfig};
+use parlance::network::relay::{RelayStream, MAX_RELAY_CHUNK};
+use parlance::network::secure::SecureChannel;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+fn relay_addr() -> SocketAddr {
+    "203.0.113.1:8080".parse().unwrap()
+}
+
+/// Two ends of a circuit, as the relay would connect them
+fn relay_pair() -> (RelayStream, RelayStream) {
+    let (alice_tx, bob_rx) = mpsc::unbounded_channel();
+    let (bob_tx, alice_rx) = mpsc::unbounded_channel();
+    (
+        RelayStream::open(relay_addr(), alice_tx, alice_rx),
+        RelayStream::open(relay_addr(), bob_tx, bob_rx),
+    )
+}
+
+#[tokio::test]
+async fn test_relay_stream_round_trip() {
+    let (mut alice, mut bob) = relay_pair();
+
+    alice.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    bob.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    bob.write_all(b"pong").await.unwrap();
+    alice.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+
+    alice.shutdown().await.unwrap();
+    let mut rest = Vec::new();
+    bob.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+}
+
+#[tokio::test]
+async fn test_relay_stream_splits_into_chunks() {
+    let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel();
+    let (_inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+    let mut stream = RelayStream::open(relay_addr(), outbound_tx, inbound_rx);
+
+    let data = vec![5u8; 3 * MAX_RELAY_CHUNK + 10];
+    stream.write_all(&data).await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    let mut received = Vec::new();
+    loop {
+        let chunk = outbound_rx.recv().await.unwrap();
+        if chunk.is_empty() {
+            break;
+        }
+        assert!(chunk.len() <= MAX_RELAY_CHUNK);
+        received.extend_from_slice(&chunk);
+    }
+    assert_eq!(received, data);
+}
+
+#[tokio::test]
+async fn test_relay_stream_ends_when_circuit_closes() {
+    let (outbound_tx, _outbound_rx) = mpsc::unbounded_channel();
+    let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+    let mut stream = RelayStream::open(relay_addr(), outbound_tx, inbound_rx);
+
+    inbound_tx.send(b"partial".to_vec()).unwrap();
+    // The bootstrap client drops the circuit when the server rejects it
+    drop(inbound_tx);
+
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+        .await
+        .expect("stream did not end")
+        .unwrap();
+    assert_eq!(received, b"partial");
+}
+
+#[tokio::test]
+async fn test_messaging_over_relay() {
+    let (alice_stream, bob_stream) = relay_pair();
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+
+    let (incoming_tx, mut incoming) = mpsc::unbounded_channel();
+    let pool = ConnectionManager::new(bob.clone(), PoolConfig::default(), incoming_tx);
+    let accept = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.accept_relayed(bob_stream).await }
+    });
+
+    let mut channel = SecureChannel::initiate(alice_stream, &alice).await.unwrap();
+    assert_eq!(accept.await.unwrap().unwrap(), alice.public_key());
+    assert_eq!(pool.state(&alice.public_key()), ConnectionState::Relayed);
+    assert_eq!(pool.state(&alice.public_key()).to_string(), "relayed");
+
+    let large = vec![9u8; 100_000];
+    channel.send(b"hello via relay").await.unwrap();
+    channel.send(&large).await.unwrap();
+
+    let frame = incoming.recv().await.unwrap();
+    assert_eq!(frame.from, alice.public_key());
+    assert_eq!(frame.addr, relay_addr());
+    assert_eq!(frame.data, b"hello via relay");
+    assert!(frame.relayed);
+    assert_eq!(incoming.recv().await.unwrap().data, large);
+
+    pool.send_existing(&alice.public_key(), b"reply")
+        .await
+        .unwrap();
+    assert_eq!(channel.recv().await.unwrap().unwrap(), b"reply");
+}
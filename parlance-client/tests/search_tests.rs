@@ -0,0 +1,183 @@
+//! Integration tests for history search.
+
+mod common;
+
+use chrono::Utc;
+use common::test_public_key;
+use parlance::core::history::{DeliveryState, Direction, History, HistoryEntry};
+use parlance::core::search::{tokenize, SearchIndex, SearchQuery};
+use parlance::network::messaging::MessageId;
+
+fn entry(peer: &str, direction: Direction, content: &str, timestamp: i64) -> HistoryEntry {
+    HistoryEntry {
+        id: MessageId::new(),
+        peer: peer.to_string(),
+        peer_key: test_public_key(),
+        direction,
+        content: content.to_string(),
+        timestamp,
+        state: DeliveryState::Delivered,
+    }
+}
+
+fn contents(hits: &[parlance::core::search::SearchHit]) -> Vec<&str> {
+    hits.iter().map(|h| h.entry.content.as_str()).collect()
+}
+
+async fn history_with(entries: Vec<HistoryEntry>) -> (tempfile::TempDir, History) {
+    let dir = tempfile::tempdir().unwrap();
+    let history = History::load(dir.path().join("history.jsonl"), None, None).unwrap();
+    for entry in entries {
+        history.record(entry).await.unwrap();
+    }
+    (dir, history)
+}
+
+#[test]
+fn test_tokenize_lowercases_and_splits_punctuation() {
+    assert_eq!(
+        tokenize("Deploy the API, then ping Bob!"),
+        ["deploy", "the", "api", "then", "ping", "bob"]
+    );
+    assert!(tokenize("  ...  ").is_empty());
+}
+
+#[tokio::test]
+async fn test_search_requires_all_words() {
+    let now = Utc::now().timestamp();
+    let (_dir, history) = history_with(vec![
+        entry("bob", Direction::Received, "the deploy failed", now),
+        entry("bob", Direction::Sent, "deploy again?", now),
+        entry("bob", Direction::Received, "it failed twice", now),
+    ])
+    .await;
+
+    let hits = history.search(&SearchQuery::new("DEPLOY failed")).await;
+    assert_eq!(contents(&hits), ["the deploy failed"]);
+
+    let hits = history.search(&SearchQuery::new("deploy")).await;
+    assert_eq!(contents(&hits), ["deploy again?", "the deploy failed"]);
+
+    assert!(history
+        .search(&SearchQuery::new("nothing"))
+        .await
+        .is_empty());
+    assert!(history.search(&SearchQuery::new("")).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_search_filters() {
+    let day = 24 * 60 * 60;
+    let now = Utc::now().timestamp();
+    let (_dir, history) = history_with(vec![
+        entry("bob", Direction::Received, "lunch?", now - 3 * day),
+        entry("carol", Direction::Received, "lunch today", now - day),
+        entry("bob", Direction::Sent, "lunch sounds good", now),
+    ])
+    .await;
+
+    let from_bob = SearchQuery {
+        peer: Some("bob".to_string()),
+        direction: Some(Direction::Received),
+        ..SearchQuery::new("lunch")
+    };
+    assert_eq!(contents(&history.search(&from_bob).await), ["lunch?"]);
+
+    let recent = SearchQuery {
+        since: Some(now - 2 * day),
+        ..SearchQuery::new("lunch")
+    };
+    assert_eq!(
+        contents(&history.search(&recent).await),
+        ["lunch sounds good", "lunch today"]
+    );
+
+    let older = SearchQuery {
+        until: Some(now - day),
+        ..SearchQuery::new("lunch")
+    };
+    assert_eq!(contents(&history.search(&older).await), ["lunch?"]);
+
+    let limited = SearchQuery {
+        limit: 1,
+        ..SearchQuery::new("lunch")
+    };
+    assert_eq!(
+        contents(&history.search(&limited).await),
+        ["lunch sounds good"]
+    );
+}
+
+#[tokio::test]
+async fn test_search_hits_include_conversation_context() {
+    let now = Utc::now().timestamp();
+    let (_dir, history) = history_with(vec![
+        entry("bob", Direction::Received, "are you around", now),
+        entry("carol", Direction::Received, "unrelated", now),
+        entry("bob", Direction::Sent, "the password is in the vault", now),
+        entry("bob", Direction::Received, "thanks", now),
+        entry("bob", Direction::Received, "much later", now),
+    ])
+    .await;
+
+    let hits = history.search(&SearchQuery::new("vault")).await;
+    assert_eq!(hits.len(), 1);
+
+    let before: Vec<_> = hits[0].before.iter().map(|e| e.content.as_str()).collect();
+    let after: Vec<_> = hits[0].after.iter().map(|e| e.content.as_str()).collect();
+    assert_eq!(before, ["are you around"]);
+    assert_eq!(after, ["thanks"]);
+}
+
+#[tokio::test]
+async fn test_search_survives_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.jsonl");
+    {
+        let history = History::load(&path, None, None).unwrap();
+        history
+            .record(entry(
+                "bob",
+                Direction::Received,
+                "remember the milk",
+                Utc::now().timestamp(),
+            ))
+            .await
+            .unwrap();
+    }
+
+    let history = History::load(&path, None, None).unwrap();
+    let hits = history.search(&SearchQuery::new("milk")).await;
+    assert_eq!(contents(&hits), ["remember the milk"]);
+}
+
+#[test]
+fn test_index_scales_to_large_histories() {
+    let template = entry("bob", Direction::Received, "", Utc::now().timestamp());
+    let entries: Vec<_> = (0..200_000)
+        .map(|i| {
+            let content = if i % 50_000 == 0 {
+                format!("needle {} in the haystack", i)
+            } else {
+                format!("message number {} about nothing", i)
+            };
+            HistoryEntry {
+                id: MessageId::new(),
+                content,
+                ..template.clone()
+            }
+        })
+        .collect();
+    let index = SearchIndex::build(&entries);
+
+    let hits = index.search(&entries, &SearchQuery::new("needle haystack"));
+    assert_eq!(
+        contents(&hits),
+        [
+            "needle 150000 in the haystack",
+            "needle 100000 in the haystack",
+            "needle 50000 in the haystack",
+            "needle 0 in the haystack",
+        ]
+    );
+}
@@ -0,0 +1,207 @@
+//! Integration tests for the encrypted peer channel.
+
+use parlance::core::error::ParlanceError;
+use parlance::core::identity::Identity;
+use parlance::network::protocol::{Hello, FEATURE_GROUPS, PROTOCOL_VERSION};
+use parlance::network::secure::{SecureChannel, DEFAULT_MAX_FRAME_SIZE, MAX_PLAINTEXT_LEN};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test]
+async fn test_handshake_authenticates_both_sides() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (a, b) = tokio::io::duplex(MAX_PLAINTEXT_LEN);
+
+    let bob_identity = bob.clone();
+    let responder = tokio::spawn(async move { SecureChannel::accept(b, &bob_identity).await });
+    let initiator = SecureChannel::initiate(a, &alice).await.unwrap();
+    let responder = responder.await.unwrap().unwrap();
+
+    assert_eq!(initiator.remote_public_key(), bob.public_key());
+    assert_eq!(responder.remote_public_key(), alice.public_key());
+}
+
+#[tokio::test]
+async fn test_frames_roundtrip_in_both_directions() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (a, b) = tokio::io::duplex(MAX_PLAINTEXT_LEN);
+
+    let responder = tokio::spawn(async move {
+        let mut channel = SecureChannel::accept(b, &bob).await.unwrap();
+        let frame = channel.recv().await.unwrap().unwrap();
+        channel.send(&frame).await.unwrap();
+    });
+
+    let mut channel = SecureChannel::initiate(a, &alice).await.unwrap();
+    channel.send(b"hello bob").await.unwrap();
+    let echoed = channel.recv().await.unwrap().unwrap();
+
+    assert_eq!(echoed, b"hello bob");
+    responder.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_recv_returns_none_on_close() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (a, b) = tokio::io::duplex(MAX_PLAINTEXT_LEN);
+
+    let responder = tokio::spawn(async move { SecureChannel::accept(b, &bob).await.unwrap() });
+    let channel = SecureChannel::initiate(a, &alice).await.unwrap();
+    let mut responder = responder.await.unwrap();
+
+    drop(channel);
+    assert!(responder.recv().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_rejects_oversized_frame() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (a, b) = tokio::io::duplex(MAX_PLAINTEXT_LEN);
+
+    let _responder = tokio::spawn(async move { SecureChannel::accept(b, &bob).await });
+    let mut channel = SecureChannel::initiate(a, &alice).await.unwrap();
+
+    let oversized = vec![0u8; DEFAULT_MAX_FRAME_SIZE + 1];
+    assert!(matches!(
+        channel.send(&oversized).await,
+        Err(ParlanceError::FrameTooLarge { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_frames_larger_than_a_noise_message_roundtrip() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (a, b) = tokio::io::duplex(MAX_PLAINTEXT_LEN);
+
+    let responder = tokio::spawn(async move {
+        let mut channel = SecureChannel::accept(b, &bob).await.unwrap();
+        channel.recv().await.unwrap().unwrap()
+    });
+
+    let mut channel = SecureChannel::initiate(a, &alice).await.unwrap();
+    let frame: Vec<u8> = (0..3 * MAX_PLAINTEXT_LEN).map(|i| i as u8).collect();
+    channel.send(&frame).await.unwrap();
+
+    assert_eq!(responder.await.unwrap(), frame);
+}
+
+#[tokio::test]
+async fn test_receiver_rejects_frame_above_its_limit() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (a, b) = tokio::io::duplex(MAX_PLAINTEXT_LEN);
+
+    let responder = tokio::spawn(async move {
+        let mut channel = SecureChannel::accept(b, &bob).await.unwrap();
+        channel.set_max_frame_size(1024);
+        channel.recv().await
+    });
+
+    let mut channel = SecureChannel::initiate(a, &alice).await.unwrap();
+    channel.send(&[0u8; 2048]).await.unwrap();
+
+    assert!(matches!(
+        responder.await.unwrap(),
+        Err(ParlanceError::FrameTooLarge {
+            size: 2048,
+            max: 1024
+        })
+    ));
+}
+
+#[tokio::test]
+async fn test_handshake_fails_against_plaintext_peer() {
+    let alice = Identity::generate();
+    let (a, mut b) = tokio::io::duplex(1024);
+
+    let peer = tokio::spawn(async move {
+        let mut buf = [0u8; 64];
+        let _ = b.read(&mut buf).await;
+        let _ = b.write_all(b"{\"from\":\"eve\"}\n").await;
+    });
+
+    let result = SecureChannel::initiate(a, &alice).await;
+    assert!(result.is_err());
+    peer.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_handshake_negotiates_shared_features() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (a, b) = tokio::io::duplex(MAX_PLAINTEXT_LEN);
+
+    let bob_hello = Hello {
+        features: vec![FEATURE_GROUPS.to_string()],
+        ..Hello::current()
+    };
+    let responder =
+        tokio::spawn(async move { SecureChannel::accept_with(b, &bob, &bob_hello).await });
+    let initiator = SecureChannel::initiate(a, &alice).await.unwrap();
+    let responder = responder.await.unwrap().unwrap();
+
+    for channel in [initiator.protocol(), responder.protocol()] {
+        assert_eq!(channel.version, PROTOCOL_VERSION);
+        assert_eq!(channel.features, vec![FEATURE_GROUPS.to_string()]);
+    }
+}
+
+#[tokio::test]
+async fn test_handshake_rejects_incompatible_version() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (a, b) = tokio::io::duplex(MAX_PLAINTEXT_LEN);
+
+    let future = Hello {
+        protocol_version: PROTOCOL_VERSION + 1,
+        min_protocol_version: PROTOCOL_VERSION + 1,
+        features: Vec::new(),
+    };
+    let responder = tokio::spawn(async move { SecureChannel::accept_with(b, &bob, &future).await });
+    let initiator = SecureChannel::initiate(a, &alice).await;
+
+    assert!(matches!(
+        initiator,
+        Err(ParlanceError::IncompatibleProtocol(_))
+    ));
+    assert!(matches!(
+        responder.await.unwrap(),
+        Err(ParlanceError::IncompatibleProtocol(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_responder_learns_of_incompatible_version_without_timeout() {
+    let alice = Identity::generate();
+    let bob = Identity::generate();
+    let (a, b) = tokio::io::duplex(MAX_PLAINTEXT_LEN);
+
+    let future = Hello {
+        protocol_version: PROTOCOL_VERSION + 1,
+        min_protocol_version: PROTOCOL_VERSION + 1,
+        features: Vec::new(),
+    };
+    let responder = tokio::spawn(async move { SecureChannel::accept(b, &bob).await });
+
+    // The initiator keeps its end open, so only the handshake can end it
+    let mut stream = a;
+    let initiator = SecureChannel::initiate_with(&mut stream, &alice, &future).await;
+    assert!(matches!(
+        initiator,
+        Err(ParlanceError::IncompatibleProtocol(_))
+    ));
+
+    let responder = tokio::time::timeout(Duration::from_secs(2), responder)
+        .await
+        .expect("responder waited for the handshake timeout");
+    assert!(matches!(
+        responder.unwrap(),
+        Err(ParlanceError::IncompatibleProtocol(_))
+    ));
+    drop(stream);
+}
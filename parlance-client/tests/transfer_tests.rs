@@ -0,0 +1,335 @@
+//! Integration tests for file transfers.
+
+use parlance::core::identity::Identity;
+use parlance::network::transfer::{
+    crosses_step, sanitize_file_name, ChunkOutcome, FileChunk, FileOffer, Transfers, CHUNK_SIZE,
+};
+
+/// Offer `contents` from a sender store and register it with a receiver
+async fn offer_file(
+    dir: &tempfile::TempDir,
+    name: &str,
+    contents: &[u8],
+) -> (Transfers, Transfers, FileOffer, Identity) {
+    let sender = Identity::generate();
+    let path = dir.path().join(name);
+    std::fs::write(&path, contents).unwrap();
+
+    let outgoing = Transfers::new(dir.path().join("sender"));
+    let offer = outgoing
+        .offer(&path, "alice", "bob", Identity::generate().public_key())
+        .await
+        .unwrap();
+
+    let incoming = Transfers::new(dir.path().join("downloads"));
+    incoming
+        .receive_offer(offer.clone(), sender.public_key())
+        .unwrap();
+    (outgoing, incoming, offer, sender)
+}
+
+fn chunks(offer: &FileOffer, contents: &[u8], from: usize) -> Vec<FileChunk> {
+    contents[from..]
+        .chunks(CHUNK_SIZE)
+        .enumerate()
+        .map(|(i, data)| FileChunk::new(offer.id, (from + i * CHUNK_SIZE) as u64, data))
+        .collect()
+}
+
+fn contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn test_offer_describes_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, _, offer, _) = offer_file(&dir, "notes.txt", b"hello").await;
+
+    assert_eq!(offer.name, "notes.txt");
+    assert_eq!(offer.size, 5);
+    assert_eq!(
+        offer.sha256,
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+    );
+}
+
+#[tokio::test]
+async fn test_offer_of_missing_file_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let transfers = Transfers::new(dir.path());
+
+    let result = transfers
+        .offer(
+            &dir.path().join("missing.txt"),
+            "alice",
+            "bob",
+            Identity::generate().public_key(),
+        )
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_chunks_are_assembled_and_verified() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = contents(CHUNK_SIZE * 2 + 100);
+    let (_, incoming, offer, sender) = offer_file(&dir, "photo.jpg", &data).await;
+
+    let (_, offset) = incoming.accept(&offer.id.to_string()).await.unwrap();
+    assert_eq!(offset, 0);
+
+    let mut outcome = None;
+    for chunk in chunks(&offer, &data, 0) {
+        outcome = Some(
+            incoming
+                .write_chunk(&chunk, &sender.public_key())
+                .await
+                .unwrap(),
+        );
+    }
+
+    let path = match outcome {
+        Some(ChunkOutcome::Complete { path }) => path,
+        other => panic!("Expected completion, got {:?}", other),
+    };
+    assert_eq!(path, dir.path().join("downloads").join("photo.jpg"));
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert!(incoming.incoming(offer.id).is_none());
+}
+
+#[tokio::test]
+async fn test_accepting_again_resumes_from_partial_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = contents(CHUNK_SIZE * 3);
+    let (_, incoming, offer, sender) = offer_file(&dir, "big.bin", &data).await;
+    let all = chunks(&offer, &data, 0);
+
+    incoming.accept(&offer.id.to_string()).await.unwrap();
+    let outcome = incoming
+        .write_chunk(&all[0], &sender.public_key())
+        .await
+        .unwrap();
+    assert!(
+        matches!(outcome, ChunkOutcome::Progress { received, .. } if received == CHUNK_SIZE as u64)
+    );
+
+    // The connection dropped; accepting again continues after the first chunk
+    let (_, offset) = incoming.accept(&offer.id.to_string()).await.unwrap();
+    assert_eq!(offset, CHUNK_SIZE as u64);
+    assert_eq!(
+        incoming.resumable(&sender.public_key()),
+        vec![(offer.id, offset)]
+    );
+
+    for chunk in chunks(&offer, &data, CHUNK_SIZE) {
+        incoming
+            .write_chunk(&chunk, &sender.public_key())
+            .await
+            .unwrap();
+    }
+    let saved = dir.path().join("downloads").join("big.bin");
+    assert_eq!(std::fs::read(saved).unwrap(), data);
+}
+
+#[tokio::test]
+async fn test_new_offer_resumes_after_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = contents(CHUNK_SIZE * 3);
+    let (outgoing, incoming, offer, sender) = offer_file(&dir, "big.bin", &data).await;
+
+    incoming.accept(&offer.id.to_string()).await.unwrap();
+    let first = &chunks(&offer, &data, 0)[0];
+    incoming
+        .write_chunk(first, &sender.public_key())
+        .await
+        .unwrap();
+    drop(incoming);
+
+    // After a restart the sender offers the same file under a new ID
+    let again = outgoing
+        .offer(
+            &dir.path().join("big.bin"),
+            "alice",
+            "bob",
+            Identity::generate().public_key(),
+        )
+        .await
+        .unwrap();
+    assert_ne!(again.id, offer.id);
+
+    let incoming = Transfers::new(dir.path().join("downloads"));
+    incoming
+        .receive_offer(again.clone(), sender.public_key())
+        .unwrap();
+    let (_, offset) = incoming.accept(&again.id.to_string()).await.unwrap();
+    assert_eq!(offset, CHUNK_SIZE as u64);
+
+    for chunk in chunks(&again, &data, CHUNK_SIZE) {
+        incoming
+            .write_chunk(&chunk, &sender.public_key())
+            .await
+            .unwrap();
+    }
+    let saved = dir.path().join("downloads").join("big.bin");
+    assert_eq!(std::fs::read(saved).unwrap(), data);
+}
+
+#[tokio::test]
+async fn test_partial_file_is_not_shared_between_senders() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = contents(CHUNK_SIZE * 2);
+    let (_, incoming, offer, sender) = offer_file(&dir, "big.bin", &data).await;
+
+    incoming.accept(&offer.id.to_string()).await.unwrap();
+    incoming
+        .write_chunk(&chunks(&offer, &data, 0)[0], &sender.public_key())
+        .await
+        .unwrap();
+
+    // Someone else offering the same file starts from scratch
+    let other = FileOffer {
+        id: Default::default(),
+        ..offer.clone()
+    };
+    incoming
+        .receive_offer(other.clone(), Identity::generate().public_key())
+        .unwrap();
+    let (_, offset) = incoming.accept(&other.id.to_string()).await.unwrap();
+    assert_eq!(offset, 0);
+}
+
+#[tokio::test]
+async fn test_out_of_order_chunk_asks_for_expected_offset() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = contents(CHUNK_SIZE * 2);
+    let (_, incoming, offer, sender) = offer_file(&dir, "big.bin", &data).await;
+    incoming.accept(&offer.id.to_string()).await.unwrap();
+
+    let second = &chunks(&offer, &data, 0)[1];
+    let outcome = incoming
+        .write_chunk(second, &sender.public_key())
+        .await
+        .unwrap();
+    assert_eq!(outcome, ChunkOutcome::OutOfOrder { expected: 0 });
+}
+
+#[tokio::test]
+async fn test_chunks_need_acceptance_from_the_sender() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = contents(100);
+    let (_, incoming, offer, sender) = offer_file(&dir, "a.txt", &data).await;
+    let chunk = FileChunk::new(offer.id, 0, &data);
+
+    // Not accepted yet
+    assert!(incoming
+        .write_chunk(&chunk, &sender.public_key())
+        .await
+        .is_err());
+
+    // Accepted, but sent by someone else
+    incoming.accept(&offer.id.to_string()).await.unwrap();
+    assert!(incoming
+        .write_chunk(&chunk, &Identity::generate().public_key())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_corrupted_file_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = contents(100);
+    let (_, incoming, offer, sender) = offer_file(&dir, "a.txt", &data).await;
+    incoming.accept(&offer.id.to_string()).await.unwrap();
+
+    let mut corrupted = data.clone();
+    corrupted[10] ^= 1;
+    let result = incoming
+        .write_chunk(
+            &FileChunk::new(offer.id, 0, &corrupted),
+            &sender.public_key(),
+        )
+        .await;
+
+    assert!(result.is_err());
+    assert!(incoming.incoming(offer.id).is_none());
+    assert!(!dir.path().join("downloads").join("a.txt").exists());
+}
+
+#[tokio::test]
+async fn test_empty_file_completes_with_one_chunk() {
+    let dir = tempfile::tempdir().unwrap();
+    let (_, incoming, offer, sender) = offer_file(&dir, "empty.txt", b"").await;
+    incoming.accept(&offer.id.to_string()).await.unwrap();
+
+    let outcome = incoming
+        .write_chunk(&FileChunk::new(offer.id, 0, b""), &sender.public_key())
+        .await
+        .unwrap();
+    assert!(matches!(outcome, ChunkOutcome::Complete { .. }));
+}
+
+#[tokio::test]
+async fn test_existing_files_are_not_overwritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let downloads = dir.path().join("downloads");
+    std::fs::create_dir_all(&downloads).unwrap();
+    std::fs::write(downloads.join("report.pdf"), b"old").unwrap();
+
+    let (_, incoming, offer, sender) = offer_file(&dir, "report.pdf", b"new").await;
+    incoming.accept(&offer.id.to_string()).await.unwrap();
+    let outcome = incoming
+        .write_chunk(&FileChunk::new(offer.id, 0, b"new"), &sender.public_key())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        outcome,
+        ChunkOutcome::Complete {
+            path: downloads.join("report (1).pdf")
+        }
+    );
+    assert_eq!(std::fs::read(downloads.join("report.pdf")).unwrap(), b"old");
+}
+
+#[tokio::test]
+async fn test_unsafe_offer_names_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let transfers = Transfers::new(dir.path());
+    let (_, _, mut offer, sender) = offer_file(&dir, "a.txt", b"x").await;
+
+    offer.name = "..".to_string();
+    assert!(transfers
+        .receive_offer(offer.clone(), sender.public_key())
+        .is_err());
+
+    offer.name = "../../.bashrc".to_string();
+    assert!(transfers.receive_offer(offer, sender.public_key()).is_err());
+}
+
+#[test]
+fn test_sanitize_file_name() {
+    assert_eq!(
+        sanitize_file_name("report.pdf").as_deref(),
+        Some("report.pdf")
+    );
+    assert_eq!(
+        sanitize_file_name("../secret/report.pdf").as_deref(),
+        Some("report.pdf")
+    );
+    assert_eq!(
+        sanitize_file_name("C:\\Users\\bob\\report.pdf").as_deref(),
+        Some("report.pdf")
+    );
+    assert_eq!(sanitize_file_name(""), None);
+    assert_eq!(sanitize_file_name("dir/"), None);
+    assert_eq!(sanitize_file_name(".hidden"), None);
+    assert_eq!(sanitize_file_name("bad\nname"), None);
+}
+
+#[test]
+fn test_progress_steps() {
+    assert!(crosses_step(0, 10, 100));
+    assert!(!crosses_step(10, 19, 100));
+    assert!(crosses_step(19, 100, 100));
+    assert!(!crosses_step(0, 0, 0));
+}
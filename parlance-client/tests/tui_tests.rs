@@ -0,0 +1,157 @@
+//! Tests for the pieces of the full-screen UI that do not need a terminal.
+
+use parlance::app::output::{ScreenLine, Tone};
+use parlance::app::tui::{wrap, InputLine, Scrollback};
+
+fn line(text: &str) -> ScreenLine {
+    ScreenLine {
+        tone: Tone::Normal,
+        text: text.to_string(),
+    }
+}
+
+fn texts(rows: Vec<(Tone, String)>) -> Vec<String> {
+    rows.into_iter().map(|(_, text)| text).collect()
+}
+
+#[test]
+fn test_wrap_short_line() {
+    assert_eq!(wrap("hello", 10), vec!["hello"]);
+    assert_eq!(wrap("", 10), vec![""]);
+}
+
+#[test]
+fn test_wrap_breaks_at_spaces() {
+    assert_eq!(
+        wrap("the quick brown fox", 10),
+        vec!["the quick", "brown fox"]
+    );
+}
+
+#[test]
+fn test_wrap_splits_long_words() {
+    assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+}
+
+#[test]
+fn test_wrap_keeps_indentation() {
+    assert_eq!(wrap("  one two", 6), vec!["  one", "two"]);
+}
+
+#[test]
+fn test_wrap_counts_characters_not_bytes() {
+    assert_eq!(wrap("ééé ééé", 3), vec!["ééé", "ééé"]);
+}
+
+#[test]
+fn test_input_line_editing() {
+    let mut input = InputLine::new();
+    for c in "helo".chars() {
+        input.insert(c);
+    }
+    input.left();
+    input.insert('l');
+    assert_eq!(input.text(), "hello");
+    assert_eq!(input.visible(10), ("hello".to_string(), 4));
+
+    input.home();
+    input.delete();
+    assert_eq!(input.text(), "ello");
+
+    input.end();
+    input.backspace();
+    assert_eq!(input.text(), "ell");
+
+    input.right();
+    assert_eq!(input.visible(10), ("ell".to_string(), 3));
+}
+
+#[test]
+fn test_input_line_take_clears() {
+    let mut input = InputLine::new();
+    input.set("/peers");
+    assert_eq!(input.take(), "/peers");
+    assert_eq!(input.visible(10), (String::new(), 0));
+}
+
+#[test]
+fn test_input_line_scrolls_to_cursor() {
+    let mut input = InputLine::new();
+    input.set("abcdefghij");
+
+    let (text, cursor) = input.visible(5);
+    assert_eq!(text, "ghij");
+    assert_eq!(cursor, 4);
+
+    input.home();
+    let (text, cursor) = input.visible(5);
+    assert_eq!(text, "abcde");
+    assert_eq!(cursor, 0);
+}
+
+#[test]
+fn test_scrollback_shows_newest_lines() {
+    let mut messages = Scrollback::new(100);
+    for i in 0..5 {
+        messages.push(line(&format!("line {}", i)));
+    }
+
+    assert_eq!(
+        texts(messages.visible(20, 3)),
+        vec!["line 2", "line 3", "line 4"]
+    );
+}
+
+#[test]
+fn test_scrollback_wraps_lines() {
+    let mut messages = Scrollback::new(100);
+    messages.push(line("first"));
+    messages.push(line("alice: hello there"));
+
+    assert_eq!(
+        texts(messages.visible(12, 3)),
+        vec!["first", "alice: hello", "there"]
+    );
+}
+
+#[test]
+fn test_scrollback_drops_oldest_lines() {
+    let mut messages = Scrollback::new(2);
+    messages.push(line("a"));
+    messages.push(line("b"));
+    messages.push(line("c"));
+
+    assert_eq!(texts(messages.visible(10, 10)), vec!["b", "c"]);
+}
+
+#[test]
+fn test_scrollback_keeps_view_while_scrolled() {
+    let mut messages = Scrollback::new(100);
+    for i in 0..5 {
+        messages.push(line(&format!("line {}", i)));
+    }
+
+    messages.scroll_up(2);
+    assert!(messages.is_scrolled());
+    assert_eq!(texts(messages.visible(20, 2)), vec!["line 1", "line 2"]);
+
+    messages.push(line("line 5"));
+    assert_eq!(texts(messages.visible(20, 2)), vec!["line 1", "line 2"]);
+
+    messages.scroll_to_bottom();
+    assert!(!messages.is_scrolled());
+    assert_eq!(texts(messages.visible(20, 2)), vec!["line 4", "line 5"]);
+}
+
+#[test]
+fn test_scrollback_scroll_is_clamped() {
+    let mut messages = Scrollback::new(100);
+    messages.push(line("a"));
+    messages.push(line("b"));
+
+    messages.scroll_up(10);
+    assert_eq!(texts(messages.visible(10, 5)), vec!["a"]);
+
+    messages.scroll_down(10);
+    assert_eq!(texts(messages.visible(10, 5)), vec!["a", "b"]);
+}
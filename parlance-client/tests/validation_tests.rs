@@ -1,6 +1,9 @@
 //! Tests for nickname validation.
 
-use parlance::core::validation::{NicknameValidationError, NicknameValidator};
+use parlance::core::validation::{
+    ChannelNameValidationError, ChannelNameValidator, GroupNameValidationError, GroupNameValidator,
+    NicknameValidationError, NicknameValidator,
+};
 
 #[test]
 fn test_valid_nicknames() {
@@ -81,3 +84,60 @@ fn test_max_length_boundary() {
         Err(NicknameValidationError::TooLong { .. })
     ));
 }
+
+#[test]
+fn test_group_names() {
+    assert!(GroupNameValidator::validate("friends").is_ok());
+    assert!(GroupNameValidator::validate("team-42").is_ok());
+
+    assert_eq!(
+        GroupNameValidator::validate(""),
+        Err(GroupNameValidationError::Empty)
+    );
+    assert_eq!(
+        GroupNameValidator::validate("two words"),
+        Err(GroupNameValidationError::ContainsWhitespace)
+    );
+    assert_eq!(
+        GroupNameValidator::validate("bad\x07name"),
+        Err(GroupNameValidationError::InvalidCharacters)
+    );
+    assert!(matches!(
+        GroupNameValidator::validate(&"a".repeat(GroupNameValidator::MAX_LENGTH + 1)),
+        Err(GroupNameValidationError::TooLong { .. })
+    ));
+}
+
+#[test]
+fn test_nickname_cannot_look_like_a_channel() {
+    assert_eq!(
+        NicknameValidator::validate("#alice"),
+        Err(NicknameValidationError::ReservedPrefix)
+    );
+}
+
+#[test]
+fn test_channel_names() {
+    assert!(ChannelNameValidator::validate("#general").is_ok());
+    assert!(ChannelNameValidator::validate("#rust_2024-fans").is_ok());
+
+    assert_eq!(
+        ChannelNameValidator::validate("general"),
+        Err(ChannelNameValidationError::MissingPrefix)
+    );
+    assert_eq!(
+        ChannelNameValidator::validate("#"),
+        Err(ChannelNameValidationError::Empty)
+    );
+    assert_eq!(
+        ChannelNameValidator::validate("#bad!name"),
+        Err(ChannelNameValidationError::InvalidCharacters)
+    );
+    assert!(matches!(
+        ChannelNameValidator::validate(&format!(
+            "#{}",
+            "a".repeat(ChannelNameValidator::MAX_LENGTH)
+        )),
+        Err(ChannelNameValidationError::TooLong { .. })
+    ));
+}
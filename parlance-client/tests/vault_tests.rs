@@ -0,0 +1,204 @@
+//! Integration tests for encrypted storage.
+
+mod common;
+
+use chrono::Utc;
+use common::test_public_key;
+use parlance::core::error::ParlanceError;
+use parlance::core::history::{DeliveryState, Direction, History, HistoryEntry};
+use parlance::core::identity::Identity;
+use parlance::core::vault::{KdfParams, Vault};
+use parlance::network::messaging::{MessageId, TextMessage};
+use parlance::network::outbox::{Outbox, OutboxEntry};
+use std::time::Duration;
+
+const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Cheap key derivation so tests stay fast
+const TEST_KDF: KdfParams = KdfParams {
+    memory_kib: 64,
+    iterations: 1,
+    parallelism: 1,
+};
+
+fn create_vault(dir: &tempfile::TempDir) -> Vault {
+    Vault::create(dir.path().join("vault.json"), "correct horse", TEST_KDF).unwrap()
+}
+
+fn entry(content: &str) -> HistoryEntry {
+    HistoryEntry {
+        id: MessageId::new(),
+        peer: "bob".to_string(),
+        peer_key: test_public_key(),
+        direction: Direction::Sent,
+        content: content.to_string(),
+        timestamp: Utc::now().timestamp(),
+        state: DeliveryState::Pending,
+    }
+}
+
+#[test]
+fn test_unlock_with_correct_passphrase() {
+    let dir = tempfile::tempdir().unwrap();
+    let vault = create_vault(&dir);
+    let sealed = vault.seal_str("secret").unwrap();
+
+    let unlocked = Vault::unlock(dir.path().join("vault.json"), "correct horse").unwrap();
+    assert_eq!(unlocked.open_str(&sealed).unwrap(), "secret");
+}
+
+#[test]
+fn test_unlock_rejects_wrong_passphrase() {
+    let dir = tempfile::tempdir().unwrap();
+    create_vault(&dir);
+
+    let result = Vault::unlock(dir.path().join("vault.json"), "battery staple");
+    assert!(matches!(result, Err(ParlanceError::IncorrectPassphrase)));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_vault_file_written_atomically_and_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("profile").join("vault.json");
+    Vault::create(&path, "correct horse", TEST_KDF).unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(!path.with_extension("tmp").exists());
+    assert!(Vault::unlock(&path, "correct horse").is_ok());
+}
+
+#[test]
+fn test_sealing_uses_fresh_nonces() {
+    let dir = tempfile::tempdir().unwrap();
+    let vault = create_vault(&dir);
+
+    let a = vault.seal_str("same").unwrap();
+    let b = vault.seal_str("same").unwrap();
+    assert_ne!(a, b);
+    assert!(!a.contains("same"));
+}
+
+#[test]
+fn test_tampered_data_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let vault = create_vault(&dir);
+
+    let mut sealed = vault.seal(b"secret").unwrap();
+    let last = sealed.len() - 1;
+    sealed[last] ^= 1;
+    assert!(vault.open(&sealed).is_err());
+}
+
+#[test]
+fn test_identity_key_is_sealed() {
+    let dir = tempfile::tempdir().unwrap();
+    let vault = create_vault(&dir);
+    let path = dir.path().join("identity.key");
+    let identity = Identity::generate();
+
+    identity.save(&path, Some(&vault)).unwrap();
+    assert!(Identity::is_sealed(&path).unwrap());
+    assert!(Identity::load(&path, None).is_err());
+
+    let loaded = Identity::load(&path, Some(&vault)).unwrap();
+    assert_eq!(loaded.public_key(), identity.public_key());
+}
+
+#[test]
+fn test_plaintext_identity_is_migrated() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("identity.key");
+    let identity = Identity::load_or_generate(&path, None).unwrap();
+    assert!(!Identity::is_sealed(&path).unwrap());
+
+    let vault = create_vault(&dir);
+    let migrated = Identity::load_or_generate(&path, Some(&vault)).unwrap();
+    assert_eq!(migrated.public_key(), identity.public_key());
+    assert!(Identity::is_sealed(&path).unwrap());
+}
+
+#[tokio::test]
+async fn test_outbox_is_sealed() {
+    let dir = tempfile::tempdir().unwrap();
+    let vault = create_vault(&dir);
+    let path = dir.path().join("outbox.json");
+    let msg = TextMessage::new("alice".to_string(), test_public_key(), "hello".to_string());
+
+    {
+        let outbox = Outbox::load(&path, WEEK, Some(vault.clone())).unwrap();
+        outbox
+            .push(OutboxEntry::new("bob".to_string(), test_public_key(), msg))
+            .await
+            .unwrap();
+    }
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(!contents.contains("hello"));
+    assert!(Outbox::load(&path, WEEK, None).is_err());
+
+    let outbox = Outbox::load(&path, WEEK, Some(vault)).unwrap();
+    assert_eq!(outbox.list().await[0].message.content, "hello");
+}
+
+#[tokio::test]
+async fn test_history_is_sealed() {
+    let dir = tempfile::tempdir().unwrap();
+    let vault = create_vault(&dir);
+    let path = dir.path().join("history.jsonl");
+    let sent = entry("meet at noon");
+
+    {
+        let history = History::load(&path, None, Some(vault.clone())).unwrap();
+        history.record(sent.clone()).await.unwrap();
+        history
+            .set_state(sent.id, DeliveryState::Delivered)
+            .await
+            .unwrap();
+    }
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(!contents.contains("noon"));
+    assert!(!contents.contains("bob"));
+    assert!(History::load(&path, None, None).is_err());
+
+    let history = History::load(&path, None, Some(vault)).unwrap();
+    let conversation = history.conversation("bob", 10).await;
+    assert_eq!(conversation.len(), 1);
+    assert_eq!(conversation[0].content, "meet at noon");
+    assert_eq!(conversation[0].state, DeliveryState::Delivered);
+}
+
+#[tokio::test]
+async fn test_plaintext_stores_are_migrated() {
+    let dir = tempfile::tempdir().unwrap();
+    let history_path = dir.path().join("history.jsonl");
+    let outbox_path = dir.path().join("outbox.json");
+    let msg = TextMessage::new("alice".to_string(), test_public_key(), "queued".to_string());
+
+    {
+        let history = History::load(&history_path, None, None).unwrap();
+        history.record(entry("old news")).await.unwrap();
+        let outbox = Outbox::load(&outbox_path, WEEK, None).unwrap();
+        outbox
+            .push(OutboxEntry::new("bob".to_string(), test_public_key(), msg))
+            .await
+            .unwrap();
+    }
+
+    let vault = create_vault(&dir);
+    let history = History::load(&history_path, None, Some(vault.clone())).unwrap();
+    let outbox = Outbox::load(&outbox_path, WEEK, Some(vault)).unwrap();
+
+    assert_eq!(history.conversation("bob", 10).await[0].content, "old news");
+    assert_eq!(outbox.list().await[0].message.content, "queued");
+    assert!(!std::fs::read_to_string(&history_path)
+        .unwrap()
+        .contains("old news"));
+    assert!(!std::fs::read_to_string(&outbox_path)
+        .unwrap()
+        .contains("queued"));
+}
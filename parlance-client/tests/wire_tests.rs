@@ -0,0 +1,135 @@
+//! Integration tests for the wire envelope and peer frame encoding.
+
+use parlance::core::error::ParlanceError;
+use parlance::core::identity::Identity;
+use parlance::network::messaging::PeerFrame;
+use parlance::network::transfer::{FileChunk, FileOffer, TransferId};
+use parlance::network::wire::{Envelope, FrameType, HEADER_LEN, WIRE_VERSION};
+
+#[test]
+fn test_envelope_roundtrip() {
+    let envelope = Envelope::new(FrameType::Chunk, [7u8; 16], b"payload".to_vec());
+    let data = envelope.encode();
+
+    assert_eq!(data.len(), HEADER_LEN + 7);
+    assert_eq!(data[0], WIRE_VERSION);
+    assert_eq!(data[1], FrameType::Chunk.code());
+    assert_eq!(Envelope::decode(&data).unwrap(), envelope);
+}
+
+#[test]
+fn test_frame_type_codes_are_unique() {
+    for code in 0..=u8::MAX {
+        if let Some(frame_type) = FrameType::from_code(code) {
+            assert_eq!(frame_type.code(), code);
+        }
+    }
+}
+
+#[test]
+fn test_truncated_envelope_is_rejected() {
+    let data = Envelope::new(FrameType::Ack, [1u8; 16], Vec::new()).encode();
+    assert!(matches!(
+        Envelope::decode(&data[..HEADER_LEN - 1]),
+        Err(ParlanceError::InvalidMessage(_))
+    ));
+}
+
+#[test]
+fn test_unsupported_version_is_rejected() {
+    let mut data = Envelope::new(FrameType::Ack, [1u8; 16], Vec::new()).encode();
+    data[0] = WIRE_VERSION + 1;
+    assert!(matches!(
+        Envelope::decode(&data),
+        Err(ParlanceError::InvalidMessage(_))
+    ));
+}
+
+#[test]
+fn test_unknown_frame_type_is_reported() {
+    let mut data = Envelope::new(FrameType::Ack, [1u8; 16], Vec::new()).encode();
+    data[1] = 200;
+    assert!(matches!(
+        PeerFrame::decode(&data),
+        Err(ParlanceError::UnknownFrameType(200))
+    ));
+}
+
+#[test]
+fn test_chunk_carries_raw_bytes() {
+    let id = TransferId::new();
+    let bytes: Vec<u8> = (0..=255).collect();
+    let data = PeerFrame::Chunk(FileChunk::new(id, 4096, &bytes))
+        .encode()
+        .unwrap();
+
+    assert_eq!(data.len(), HEADER_LEN + 8 + bytes.len());
+    match PeerFrame::decode(&data).unwrap() {
+        PeerFrame::Chunk(chunk) => assert_eq!(chunk, FileChunk::new(id, 4096, &bytes)),
+        other => panic!("Unexpected frame: {:?}", other),
+    }
+}
+
+#[test]
+fn test_transfer_frames_roundtrip() {
+    let id = TransferId::new();
+
+    match PeerFrame::decode(&PeerFrame::Accept { id, offset: 65536 }.encode().unwrap()).unwrap() {
+        PeerFrame::Accept { id: parsed, offset } => {
+            assert_eq!(parsed, id);
+            assert_eq!(offset, 65536);
+        }
+        other => panic!("Unexpected frame: {:?}", other),
+    }
+
+    let data = PeerFrame::Done {
+        id,
+        verified: false,
+    }
+    .encode()
+    .unwrap();
+    match PeerFrame::decode(&data).unwrap() {
+        PeerFrame::Done {
+            id: parsed,
+            verified,
+        } => {
+            assert_eq!(parsed, id);
+            assert!(!verified);
+        }
+        other => panic!("Unexpected frame: {:?}", other),
+    }
+
+    let offer = FileOffer {
+        id,
+        from: "alice".to_string(),
+        name: "notes.txt".to_string(),
+        size: 42,
+        sha256: "00".repeat(32),
+    };
+    match PeerFrame::decode(&PeerFrame::Offer(offer.clone()).encode().unwrap()).unwrap() {
+        PeerFrame::Offer(parsed) => assert_eq!(parsed, offer),
+        other => panic!("Unexpected frame: {:?}", other),
+    }
+}
+
+#[test]
+fn test_accept_without_offset_is_rejected() {
+    let id = TransferId::new();
+    let data = Envelope::new(FrameType::Accept, id.to_bytes(), vec![0u8; 4]).encode();
+    assert!(PeerFrame::decode(&data).is_err());
+}
+
+#[test]
+fn test_envelope_id_matches_message_id() {
+    let identity = Identity::generate();
+    let msg = parlance::network::messaging::TextMessage::new(
+        "alice".to_string(),
+        identity.public_key(),
+        "hi".to_string(),
+    );
+    let data = PeerFrame::Message(msg.clone()).encode().unwrap();
+
+    let envelope = Envelope::decode(&data).unwrap();
+    assert_eq!(envelope.frame_type, FrameType::Message);
+    assert_eq!(envelope.id, msg.id.to_bytes());
+}